@@ -18,6 +18,28 @@ pub struct KernelConfig {
 
     /// Interrupts hooked by the application, keyed by IRQ number.
     pub irqs: BTreeMap<u32, InterruptConfig>,
+
+    /// Round-robin time slices, in ticks, keyed by task priority. Tasks at
+    /// priorities not listed here are not time sliced.
+    #[serde(default)]
+    pub time_slices: BTreeMap<u8, u32>,
+
+    /// If set, the kernel stops its periodic tick while the system is idle,
+    /// sleeping this deeply until the next timer deadline.
+    #[serde(default)]
+    pub tickless: Option<SleepDepth>,
+}
+
+/// Processor sleep state used in tickless mode.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum SleepDepth {
+    /// Ordinary sleep: the core stops, but its clocks keep running.
+    #[serde(alias = "sleep")]
+    Sleep,
+    /// The processor's deep sleep state, whose details are chip-specific.
+    /// The kernel needs a `DeepSleepClock` from the application to use it.
+    #[serde(alias = "deep-sleep")]
+    DeepSleep,
 }
 
 /// Configuration for a single hooked interrupt.
@@ -71,6 +93,11 @@ pub struct TaskConfig {
 
     /// Should this task be started automatically on boot?
     pub start_at_boot: bool,
+
+    /// Indices of tasks allowed to send messages to this task, or `None` if
+    /// any task may.
+    #[serde(default)]
+    pub allowed_callers: Option<BTreeSet<usize>>,
 }
 
 /// An address within an owned region of memory.
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::Deserialize;
 use std::collections::BTreeMap;
 
@@ -28,19 +28,37 @@ pub struct NetConfig {
     /// during the `net` build, so it must be present iff the `vlan` feature
     /// is turned on.
     pub vlan: Option<VLanConfig>,
+
+    /// IPv4 configuration, or None. Like `vlan`, this must be present iff the
+    /// `net` task's `ipv4` feature is turned on.
+    pub ipv4: Option<Ipv4Config>,
 }
 
 /// TODO: this type really wants to be an enum, but the toml crate's enum
-/// handling is really, really fragile, and currently it would be an enum with a
-/// single variant anyway.
+/// handling is really, really fragile, so only `kind` is one.
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SocketConfig {
-    pub kind: String,
+    pub kind: SocketKind,
     pub owner: TaskNote,
     pub port: u16,
     pub tx: BufSize,
     pub rx: BufSize,
+    /// Restrictions on who can send packets to the socket. These are checked
+    /// by the `net` task's `filter` feature, which must be enabled iff any
+    /// socket has them.
+    pub filter: Option<FilterConfig>,
+    /// IPv6 multicast groups (e.g. `"ff02::1de:0:2"`) to join for the
+    /// socket, so that the network sends it packets addressed to them
+    #[serde(default)]
+    pub multicast: Vec<String>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SocketKind {
+    Udp,
+    Tcp,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize)]
@@ -52,10 +70,49 @@ pub struct VLanConfig {
     pub count: usize,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Ipv4Config {
+    /// Static address, with the length of its subnet prefix (e.g.
+    /// `"192.168.1.20/24"`). An address in the VPD takes precedence.
+    pub address: Option<String>,
+    /// Default gateway to go with `address`
+    pub gateway: Option<String>,
+    /// Whether to get an address with DHCP if there's no static one
+    #[serde(default)]
+    pub dhcp: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Source prefixes to accept packets from (e.g. `"fe80::/10"`), or empty
+    /// to accept them from anywhere
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// VIDs of the VLANs to accept packets on, or empty to accept them on
+    /// any VLAN
+    #[serde(default)]
+    pub vlans: Vec<u16>,
+    /// Limit on the rate of packets to accept, or None
+    pub rate: Option<RateConfig>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RateConfig {
+    /// Sustained rate, in packets per second
+    pub packets_per_sec: u32,
+    /// Number of packets that can arrive at once, after a quiet period
+    pub burst: u32,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct BufSize {
-    pub packets: usize,
+    /// Number of packets the buffer can hold. UDP sockets must have this;
+    /// TCP sockets must not, since their buffers hold a stream of bytes.
+    pub packets: Option<usize>,
     pub bytes: usize,
 }
 
@@ -79,6 +136,41 @@ pub fn load_net_config() -> Result<NetConfig> {
         _ => (),
     }
 
+    for (name, socket) in &cfg.sockets {
+        for buf in [&socket.tx, &socket.rx] {
+            match (socket.kind, buf.packets) {
+                (SocketKind::Udp, None) => {
+                    bail!("UDP socket {name} is missing a packet count")
+                }
+                (SocketKind::Tcp, Some(_)) => {
+                    bail!("TCP socket {name} can't have a packet count")
+                }
+                _ => (),
+            }
+        }
+        if !socket.multicast.is_empty() && socket.kind != SocketKind::Udp {
+            bail!("socket {name} joins multicast groups, but isn't UDP")
+        }
+        if let Some(filter) = &socket.filter {
+            if socket.kind != SocketKind::Udp {
+                bail!("socket {name} has a filter, but isn't a UDP socket")
+            }
+            for &vid in &filter.vlans {
+                let in_range = cfg.vlan.map(|v| {
+                    (v.start..v.start + v.count).contains(&usize::from(vid))
+                });
+                if in_range != Some(true) {
+                    bail!("socket {name} filters on unknown VLAN {vid:#x}")
+                }
+            }
+            if let Some(rate) = filter.rate {
+                if rate.packets_per_sec == 0 || rate.burst == 0 {
+                    bail!("socket {name} has a rate limit of zero")
+                }
+            }
+        }
+    }
+
     Ok(cfg)
 }
 
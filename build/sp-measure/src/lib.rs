@@ -0,0 +1,115 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The `sp_measure` task's configuration, and the measurements it allows the
+//! SP to have.
+//!
+//! This is shared with `drv-lpc55-swd`, which will only restore the SP from
+//! an image that `sp_measure` would allow.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha3::{Digest, Sha3_256};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// How much of the SP's flash is measured, from the start of its boot bank.
+pub const MEASURED_SIZE: usize = 0x0010_0000;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnMismatch {
+    Halt,
+    Flag,
+    // Only reporting a mismatch is what we did before there was a policy, so
+    // keep that unless an app asks for more.
+    #[default]
+    Alert,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub binary_path: PathBuf,
+    /// Other images the SP may be running, e.g. the previous release.
+    #[serde(default)]
+    pub allowed_binary_paths: Vec<PathBuf>,
+    /// Measurements of other images the SP may be running, as hex strings,
+    /// for images that aren't available at build time.
+    #[serde(default)]
+    pub allowed_measurements: Vec<String>,
+    #[serde(default)]
+    pub on_mismatch: OnMismatch,
+}
+
+/// Measures `bin` the way `sp_measure` measures SP flash: the whole of
+/// `MEASURED_SIZE`, with the space after the image erased.
+pub fn measure(bin: &[u8]) -> [u8; 32] {
+    let mut sha = Sha3_256::new();
+    sha.update(bin);
+
+    let extra: Vec<u8> = vec![0xff; MEASURED_SIZE - bin.len()];
+
+    sha.update(&extra);
+
+    sha.finalize().into()
+}
+
+fn parse_measurement(s: &str) -> Result<[u8; 32]> {
+    if s.len() != 64 {
+        bail!("measurement {s:?} isn't 64 hex digits");
+    }
+    let mut out = [0; 32];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[i * 2..][..2], 16)
+            .with_context(|| format!("bad measurement {s:?}"))?;
+    }
+    Ok(out)
+}
+
+impl Config {
+    /// Returns the measurements that the SP is allowed to have.
+    pub fn allowed(&self) -> Result<Vec<[u8; 32]>> {
+        println!("cargo:rerun-if-changed={:?}", self.binary_path);
+
+        // We intentionally don't error out of the binary path isn't
+        // found. There's no way to have another binary available for CI
+        // unless we check something in which will still be wrong. It's
+        // still useful to calculate a hash to demonstrate the connection
+        // works.
+        let bin = match std::fs::read(&self.binary_path) {
+            Ok(b) => b,
+            Err(_) => vec![0; 256],
+        };
+
+        let mut allowed = vec![measure(&bin)];
+
+        // Unlike `binary_path`, these are only ever listed on purpose, so they
+        // had better exist.
+        for path in &self.allowed_binary_paths {
+            println!("cargo:rerun-if-changed={:?}", path);
+            let bin = std::fs::read(path)
+                .with_context(|| format!("can't read {}", path.display()))?;
+            allowed.push(measure(&bin));
+        }
+        for s in &self.allowed_measurements {
+            allowed.push(parse_measurement(s)?);
+        }
+        Ok(allowed)
+    }
+}
+
+/// Writes the measurements in `allowed` as a `const ALLOWED`.
+pub fn write_allowed(out: &mut impl Write, allowed: &[[u8; 32]]) -> Result<()> {
+    writeln!(out, "const ALLOWED: [[u8; 32]; {}] = [", allowed.len())?;
+    for m in allowed {
+        writeln!(out, "[")?;
+        for b in m {
+            writeln!(out, "0x{:x},", b)?;
+        }
+        writeln!(out, "],")?;
+    }
+    writeln!(out, "];")?;
+    Ok(())
+}
@@ -166,6 +166,17 @@ pub fn other_task_full_config_toml(
     other_task_full_config(name)
 }
 
+/// Pulls the configuration of a different task, or `None` if the task has no
+/// configuration. Unlike `other_task_full_config`, only that task's
+/// configuration needs to fit `T`.
+pub fn other_task_config<T: DeserializeOwned>(name: &str) -> Result<Option<T>> {
+    let task = other_task_full_config::<toml::Value>(name)?;
+    task.config
+        .map(|c| c.try_into())
+        .transpose()
+        .with_context(|| format!("deserializing config of {name}"))
+}
+
 /// Returns a map of task names to their IDs.
 pub fn task_ids() -> TaskIds {
     let tasks = crate::env_var("HUBRIS_TASKS").expect("missing HUBRIS_TASKS");
@@ -218,6 +229,53 @@ impl TaskIds {
     }
 }
 
+/// Works out which tasks may use each operation of this task's Idol interface,
+/// described by the file at `idl`, from the task's `callers` table in
+/// `app.toml`.
+///
+/// The result is in the form `idol::server::build_restricted_server_support`
+/// expects. It's empty if the task doesn't restrict its callers; otherwise it
+/// has an entry for every operation, since the kernel already keeps out
+/// anyone not in the table.
+pub fn task_allowed_callers(idl: &str) -> Result<BTreeMap<String, Vec<usize>>> {
+    /// Just enough of an Idol interface to find its operation names.
+    #[derive(serde::Deserialize)]
+    struct Interface {
+        ops: BTreeMap<String, serde::de::IgnoredAny>,
+    }
+
+    let callers = task_full_config_toml()?.callers;
+    if callers.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let text = std::fs::read_to_string(idl)
+        .with_context(|| format!("reading {idl}"))?;
+    let iface: Interface =
+        ron::from_str(&text).with_context(|| format!("parsing {idl}"))?;
+
+    let ids = task_ids();
+    let mut allowed: BTreeMap<_, _> =
+        iface.ops.keys().map(|op| (op.clone(), vec![])).collect();
+    for (caller, ops) in &callers {
+        ops.check()?;
+        let id = ids
+            .get(caller)
+            .ok_or_else(|| anyhow!("unknown task `{}`", caller))?;
+        if let toml_task::CallerOps::Only(ops) = ops {
+            if let Some(op) = ops.iter().find(|op| !allowed.contains_key(*op)) {
+                bail!("caller {caller} is allowed unknown operation {op}");
+            }
+        }
+        for (op, tasks) in allowed.iter_mut() {
+            if ops.allows(op) {
+                tasks.push(id);
+            }
+        }
+    }
+    Ok(allowed)
+}
+
 /// Parse the contents of an environment variable as toml.
 ///
 /// Returns:
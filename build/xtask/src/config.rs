@@ -62,6 +62,7 @@ struct RawConfig {
     external_images: Vec<String>,
     #[serde(default)]
     signing: Option<RoTMfgSettings>,
+    image_signing: Option<ImageSigning>,
     stacksize: Option<u32>,
     kernel: Kernel,
     tasks: IndexMap<String, Task>,
@@ -83,6 +84,7 @@ pub struct Config {
     pub image_names: Vec<String>,
     pub external_images: Vec<String>,
     pub signing: Option<RoTMfgSettings>,
+    pub image_signing: Option<ImageSigningKey>,
     pub stacksize: Option<u32>,
     pub kernel: Kernel,
     pub outputs: IndexMap<String, Vec<Output>>,
@@ -131,6 +133,53 @@ pub struct CabooseConfig {
     pub default: bool,
 }
 
+/// Settings for appending an `abi::ImageSignature` to the image, which
+/// `stm32h7-update-server` checks when built with `verify-signature`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ImageSigning {
+    /// Path to a file containing a raw 32-byte Ed25519 private key seed,
+    /// relative to the app TOML file
+    private_key: PathBuf,
+}
+
+/// An image signing key, resolved from `ImageSigning`
+#[derive(Clone, Debug)]
+pub struct ImageSigningKey {
+    /// Absolute path to the private key seed, which is only read when signing
+    pub private_key: PathBuf,
+    /// Public half of the key, which is baked into tasks that check images
+    pub public_key: [u8; 32],
+}
+
+impl ImageSigningKey {
+    fn new(private_key: PathBuf) -> Result<Self> {
+        let public_key = *Self::keypair_from(&private_key)?.public.as_bytes();
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+
+    fn keypair_from(private_key: &Path) -> Result<salty::Keypair> {
+        let seed = std::fs::read(private_key).with_context(|| {
+            format!("could not read {}", private_key.display())
+        })?;
+        let seed: [u8; 32] = seed.as_slice().try_into().map_err(|_| {
+            anyhow!(
+                "{} must contain exactly 32 bytes, not {}",
+                private_key.display(),
+                seed.len()
+            )
+        })?;
+        Ok(salty::Keypair::from(&seed))
+    }
+
+    pub fn keypair(&self) -> Result<salty::Keypair> {
+        Self::keypair_from(&self.private_key)
+    }
+}
+
 impl Config {
     pub fn from_file(cfg: &Path) -> Result<Self> {
         Self::from_file_with_hasher(cfg, DefaultHasher::new())
@@ -218,6 +267,13 @@ impl Config {
             None => None,
         };
 
+        let image_signing = match toml.image_signing {
+            Some(s) => Some(ImageSigningKey::new(
+                cfg.parent().unwrap().join(s.private_key),
+            )?),
+            None => None,
+        };
+
         let dice_mfg = match outputs.get("flash") {
             Some(f) => f.iter().find(|&o| o.name == "dice-mfg").cloned(),
             None => None,
@@ -233,6 +289,7 @@ impl Config {
             epoch: toml.epoch,
             version: toml.version,
             signing: toml.signing,
+            image_signing,
             stacksize: toml.stacksize,
             kernel: toml.kernel,
             outputs,
@@ -333,6 +390,13 @@ impl Config {
             }
         }
 
+        if let Some(key) = &self.image_signing {
+            env.insert(
+                "HUBRIS_IMAGE_SIGNING_KEY".to_string(),
+                format!("{:?}", key.public_key),
+            );
+        }
+
         if let Some(app_config) = &self.config {
             let app_config = toml::to_string(&app_config).unwrap();
             env.insert("HUBRIS_APP_CONFIG".to_string(), app_config);
@@ -648,6 +712,12 @@ pub struct Kernel {
     pub stacksize: Option<u32>,
     #[serde(default)]
     pub features: Vec<String>,
+    /// Round-robin time slices, in ticks, keyed by task priority.
+    #[serde(default)]
+    pub time_slices: IndexMap<String, u32>,
+    /// If set, stop the kernel tick while idle and sleep this deeply
+    /// (`"sleep"` or `"deep-sleep"`).
+    pub tickless: Option<build_kconfig::SleepDepth>,
 }
 
 fn default_name() -> String {
@@ -227,6 +227,9 @@ pub fn package(
     // Verify that our dump configuration is correct (or absent)
     check_dump_config(&cfg.toml)?;
 
+    // Verify that IPC permissions name real tasks, and cover task slots.
+    check_task_callers(&cfg.toml)?;
+
     // If we're using filters, we change behavior at the end. Record this in a
     // convenient flag, running other checks as well.
     let (partial_build, tasks_to_build): (bool, BTreeSet<&str>) =
@@ -471,6 +474,37 @@ pub fn package(
             }
         }
 
+        // Post-build modifications: append an image signature if requested.
+        // This covers the caboose, so it must come after the caboose is
+        // written.
+        if let Some(key) = &cfg.toml.image_signing {
+            let archive = hubtools::RawHubrisArchive::load(&archive_name)
+                .context("loading archive with hubtools")?;
+            let image = archive
+                .extract_file("img/final.bin")
+                .context("extracting image from archive")?;
+            let sig = sign_image(&image, key)?;
+
+            let sig_start = flash.start + ((image.len() as u32 + 3) & !3);
+            let sig_end = sig_start + sig.as_bytes().len() as u32;
+            if sig_end > flash.end {
+                bail!(
+                    "no room for the image signature: it would end at \
+                     {sig_end:#x}, but flash ends at {:#x}",
+                    flash.end
+                );
+            }
+            let segments = BTreeMap::from([
+                (flash.start, image),
+                (sig_start, sig.as_bytes().to_vec()),
+            ]);
+            let raw_image = hubtools::RawHubrisImage::from_segments(
+                &segments, kentry, 0xFF,
+            )
+            .context("constructing signed image with hubtools")?;
+            build_archive(&cfg, image_name, raw_image)?;
+        }
+
         // Post-build modifications: sign the image if requested
         if let Some(signing) = &cfg.toml.signing {
             let mut archive = hubtools::RawHubrisArchive::load(&archive_name)
@@ -549,6 +583,42 @@ pub fn package(
     Ok(allocated)
 }
 
+/// Builds the `abi::ImageSignature` for `image`, which must begin at the start
+/// of flash.
+fn sign_image(
+    image: &[u8],
+    key: &crate::config::ImageSigningKey,
+) -> Result<abi::ImageSignature> {
+    use sha2::Digest;
+
+    let keypair = key.keypair()?;
+    let digest: [u8; 32] = sha2::Sha256::digest(image).into();
+    Ok(abi::ImageSignature {
+        magic: abi::SIGNATURE_MAGIC,
+        digest,
+        signature: keypair.sign(&digest).to_bytes(),
+    })
+}
+
+/// Writes an `abi::EpochFloorToken` to `output`, authorizing the update
+/// servers of the app in `cfg` to raise their epoch floor to `epoch`.
+pub fn sign_epoch_floor(cfg: &Path, epoch: u32, output: &Path) -> Result<()> {
+    let toml = Config::from_file(cfg)?;
+    let key = toml.image_signing.as_ref().ok_or_else(|| {
+        anyhow!("{} has no [image-signing] section", cfg.display())
+    })?;
+    let keypair = key.keypair()?;
+    let token = abi::EpochFloorToken {
+        magic: abi::EPOCH_FLOOR_TOKEN_MAGIC,
+        epoch,
+        signature: keypair
+            .sign(&abi::EpochFloorToken::message(epoch))
+            .to_bytes(),
+    };
+    std::fs::write(output, token.as_bytes())
+        .with_context(|| format!("failed to write {}", output.display()))
+}
+
 fn write_gdb_script(cfg: &PackageConfig, image_name: &str) -> Result<()> {
     // Humility doesn't know about images right now. The gdb symbol file
     // paths all assume a flat layout with everything in dist. For now,
@@ -652,7 +722,14 @@ fn build_archive(
 
     let img_dir = PathBuf::from("img");
     archive.binary(img_dir.join("final.elf"), raw_image.to_elf()?)?;
-    archive.binary(img_dir.join("final.bin"), raw_image.to_binary()?)?;
+    let final_bin = raw_image.to_binary()?;
+    if takes_compressed_images(&cfg.toml) {
+        archive.binary(
+            img_dir.join("final.bin.gnarle"),
+            compress_image(&final_bin),
+        )?;
+    }
+    archive.binary(img_dir.join("final.bin"), final_bin)?;
 
     //
     // To allow for the image to be flashed based only on the archive (e.g.,
@@ -1185,6 +1262,43 @@ fn check_dump_config(toml: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Checks the `callers` tables that restrict who may send to each task: every
+/// caller must be a real task. Also warns about tasks holding a task slot for
+/// a restricted task without being one of its callers, since any messages
+/// they send will be refused at runtime. (They may only be posting
+/// notifications, which is allowed.)
+fn check_task_callers(toml: &Config) -> Result<()> {
+    use colored::Colorize;
+
+    for (name, task) in &toml.tasks {
+        for (caller, ops) in &task.callers {
+            if !toml.tasks.contains_key(caller) {
+                bail!("task {name} allows unknown caller {caller}");
+            }
+            ops.check()
+                .with_context(|| format!("in callers of task {name}"))?;
+        }
+    }
+    for (name, task) in &toml.tasks {
+        for callee in task.task_slots.values() {
+            let Some(callee_task) = toml.tasks.get(callee) else {
+                continue;
+            };
+            if callee != name
+                && !callee_task.callers.is_empty()
+                && !callee_task.callers.contains_key(name)
+            {
+                eprintln!(
+                    "{} task {name} has a task slot for {callee}, but isn't \
+                     one of its callers",
+                    "warning:".yellow().bold(),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Prints warning messages about priority inversions
 fn check_task_priorities(toml: &Config) -> Result<()> {
     let idle_priority = toml.tasks["idle"].priority;
@@ -2096,6 +2210,16 @@ pub fn make_kconfig(
             },
             priority: task.priority,
             start_at_boot: task.start,
+            allowed_callers: if task.callers.is_empty() {
+                None
+            } else {
+                Some(
+                    task.callers
+                        .keys()
+                        .map(|c| toml.tasks.get_index_of(c).unwrap())
+                        .collect(),
+                )
+            },
         });
 
         // Interrupts.
@@ -2161,10 +2285,23 @@ pub fn make_kconfig(
     // Pare down the list of shared regions.
     flat_shared.retain(|name, _v| used_shared_regions.contains(name.as_str()));
 
+    let mut time_slices = BTreeMap::new();
+    for (priority, &ticks) in &toml.kernel.time_slices {
+        let priority: u8 = priority.parse().with_context(|| {
+            format!("bad priority `{priority}` in kernel time-slices")
+        })?;
+        if ticks == 0 {
+            bail!("time slice for priority {priority} must be nonzero");
+        }
+        time_slices.insert(priority, ticks);
+    }
+
     Ok(build_kconfig::KernelConfig {
         irqs,
         tasks,
         shared_regions: flat_shared,
+        time_slices,
+        tickless: toml.kernel.tickless,
     })
 }
 
@@ -2343,6 +2480,31 @@ impl Archive {
     }
 }
 
+/// Checks whether the app's update server can take compressed images, which
+/// it needs the `compression` feature for. A compressed image is only worth
+/// making if so.
+fn takes_compressed_images(toml: &Config) -> bool {
+    toml.tasks.values().any(|task| {
+        matches!(
+            task.name.as_str(),
+            "stm32h7-update-server" | "lpc55-update-server"
+        ) && task.features.iter().any(|f| f == "compression")
+    })
+}
+
+/// Compresses an image for update, in the format that the update servers
+/// take through `write_compressed`: a header of a magic number and the size
+/// of the image (both little-endian `u32`s), then the image, compressed with
+/// `gnarle`. This must match the `compressed-image` crate.
+fn compress_image(image: &[u8]) -> Vec<u8> {
+    const COMPRESSED_IMAGE_MAGIC: &[u8; 4] = b"GNLE";
+
+    let mut out = COMPRESSED_IMAGE_MAGIC.to_vec();
+    out.extend_from_slice(&(image.len() as u32).to_le_bytes());
+    out.extend(gnarle::compress_to_vec(image));
+    out
+}
+
 /// Gets the status of a git repository containing the current working
 /// directory. Returns two values:
 ///
@@ -20,6 +20,48 @@ pub fn run(
     if interactive {
         ctrlc::set_handler(|| {}).expect("Error setting Ctrl-C handler");
     }
+
+    let mut humility = command(args, precmd, cmd, image_name)?;
+    let status = humility
+        .status()
+        .with_context(|| format!("failed to run humility ({:?})", humility))?;
+
+    if !status.success() {
+        anyhow::bail!("humility failed");
+    }
+
+    Ok(())
+}
+
+/// Runs humility like `run`, but non-interactively, returning what it printed
+/// to stdout.
+pub fn output(
+    args: &HumilityArgs,
+    precmd: &[&str],
+    cmd: Option<&str>,
+    image_name: &String,
+) -> anyhow::Result<String> {
+    let mut humility = command(args, precmd, cmd, image_name)?;
+    let output = humility
+        .output()
+        .with_context(|| format!("failed to run humility ({:?})", humility))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "humility failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn command(
+    args: &HumilityArgs,
+    precmd: &[&str],
+    cmd: Option<&str>,
+    image_name: &String,
+) -> anyhow::Result<Command> {
     let toml = Config::from_file(&args.cfg)?;
 
     let archive = Path::new("target")
@@ -47,13 +89,5 @@ pub fn run(
         humility.arg(opt);
     }
 
-    let status = humility
-        .status()
-        .with_context(|| format!("failed to run humility ({:?})", humility))?;
-
-    if !status.success() {
-        anyhow::bail!("humility failed");
-    }
-
-    Ok(())
+    Ok(humility)
 }
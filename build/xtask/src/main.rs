@@ -20,6 +20,7 @@ mod humility;
 mod lsp;
 mod print;
 mod sizes;
+mod stacks;
 mod task_slot;
 
 #[derive(Debug, Parser)]
@@ -116,6 +117,18 @@ enum Xtask {
         args: HumilityArgs,
     },
 
+    /// Asks a running target how much of its stack each task has used, and
+    /// warns about tasks that are close to running out
+    Stacks {
+        /// Warn about tasks whose deepest stack usage is at least this
+        /// percentage of their stack size.
+        #[clap(long, default_value_t = 80)]
+        threshold: u32,
+
+        #[clap(flatten)]
+        args: HumilityArgs,
+    },
+
     /// Runs `xtask dist`, `xtask flash` and then `humility test`
     Test {
         /// Do not flash a new image; just run `humility test`
@@ -166,6 +179,21 @@ enum Xtask {
         cfg: PathBuf,
     },
 
+    /// Writes a token authorizing an app's update servers to raise their
+    /// epoch floor (see `raise_epoch_floor` in `update.idol`), signed with
+    /// the app's image signing key
+    SignEpochFloor {
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+
+        /// Epoch to raise the floor to
+        epoch: u32,
+
+        /// File to write the token to
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+
     /// Print out information related to the build.
     ///
     /// Currently only useful to print the archive path, but may grow over time.
@@ -305,6 +333,9 @@ fn run(xtask: Xtask) -> Result<()> {
             };
             humility::run(&args, &[], None, true, image_name)?;
         }
+        Xtask::Stacks { threshold, args } => {
+            stacks::run(&args, threshold)?;
+        }
         Xtask::Gdb { noflash, mut args } => {
             let toml = Config::from_file(&args.cfg)?;
             let image_name = if let Some(ref name) = args.image_name {
@@ -355,6 +386,9 @@ fn run(xtask: Xtask) -> Result<()> {
         Xtask::Graph { output, cfg } => {
             graph::task_graph(&cfg, &output)?;
         }
+        Xtask::SignEpochFloor { cfg, epoch, output } => {
+            dist::sign_epoch_floor(&cfg, epoch, &output)?;
+        }
         Xtask::Print {
             cfg,
             archive,
@@ -0,0 +1,97 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reports how much of its stack each task on a running target has used.
+//!
+//! The kernel paints every task's stack when it starts the task, and `jefe`
+//! will measure how much of the paint has been overwritten on request (the
+//! `get_stack_usage` operation). We ask for each task in turn through
+//! `humility hiffy`, then compare the results against the `stacksize` values
+//! in the app.toml, to help keep those honest.
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+
+use crate::{humility, Config, HumilityArgs};
+
+pub fn run(args: &HumilityArgs, threshold: u32) -> Result<()> {
+    let toml = Config::from_file(&args.cfg)?;
+    let image_name = if let Some(ref name) = args.image_name {
+        if !toml.check_image_name(name) {
+            bail!("Image name {} not declared in TOML", name);
+        }
+        name
+    } else {
+        &toml.image_names[0]
+    };
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>8} {:>6}",
+        "TASK", "SIZE", "DEPTH", "PEAK", "PEAK%"
+    );
+
+    let mut over = vec![];
+    for (index, name) in toml.tasks.keys().enumerate() {
+        let arg = format!("task_index={index}");
+        let out = humility::output(
+            args,
+            &["hiffy", "-c", "Jefe.get_stack_usage", "-a", &arg],
+            None,
+            image_name,
+        )
+        .with_context(|| format!("could not get stack usage of {name}"))?;
+
+        let field = |f| {
+            parse_field(&out, f).with_context(|| {
+                format!("no `{f}` in humility output for {name}:\n{out}")
+            })
+        };
+        let size = field("size")?;
+        let depth = field("depth")?;
+        let high_water = field("high_water")?;
+
+        let percent = if size == 0 {
+            0
+        } else {
+            high_water * 100 / size
+        };
+        let line = format!(
+            "{:<24} {:>8} {:>8} {:>8} {:>5}%",
+            name, size, depth, high_water, percent
+        );
+        if percent >= threshold {
+            println!("{}", line.yellow());
+            over.push(name);
+        } else {
+            println!("{line}");
+        }
+    }
+
+    if !over.is_empty() {
+        println!();
+        for name in over {
+            println!(
+                "{} {name} has used at least {threshold}% of its stack",
+                "warning:".yellow().bold(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds `name: value` in humility's rendering of a struct, where `value` is
+/// an integer in decimal or (with a `0x` prefix) hex.
+fn parse_field(out: &str, name: &str) -> Option<u32> {
+    out.split(|c: char| c == ',' || c == '{' || c == '}' || c == '\n')
+        .filter_map(|s| s.split_once(':'))
+        .find(|(k, _)| k.trim() == name)
+        .and_then(|(_, v)| {
+            let v = v.trim();
+            match v.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => v.parse().ok(),
+            }
+        })
+}
@@ -3,12 +3,16 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::Trace;
+use attest_api::{Attest, MeasurementKind};
 use crc::{Crc, CRC_32_CKSUM};
+use drv_sp_ctrl_api::SpCtrl;
 use drv_sprot_api::{
-    DumpReq, DumpRsp, ReqBody, Request, Response, RotIoStats, RotState,
-    RotStatus, RspBody, SprocketsError, SprotError, SprotProtocolError,
-    UpdateReq, UpdateRsp, CURRENT_VERSION, MIN_VERSION, REQUEST_BUF_SIZE,
-    RESPONSE_BUF_SIZE,
+    AttestRsp, CertReq, CertRsp, DumpReq, DumpRsp, HostMeasurementsReq,
+    HostMeasurementsRsp, ReqBody, Request, Response, RotIoStats, RotState,
+    RotStatus, RspBody, SpRecoveryReq, SpRecoveryRsp, SpRecoveryState,
+    SpRecoveryStatus, SprocketsError, SprotError, SprotProtocolError,
+    UpdateReq, UpdateRsp, CURRENT_VERSION, MAX_BLOB_SIZE, MIN_VERSION,
+    REQUEST_BUF_SIZE, RESPONSE_BUF_SIZE,
 };
 use drv_update_api::{Update, UpdateStatus};
 use dumper_api::Dumper;
@@ -21,6 +25,8 @@ mod sprockets;
 
 task_slot!(UPDATE_SERVER, update_server);
 task_slot!(DUMPER, dumper);
+task_slot!(ATTEST, attest);
+task_slot!(SWD, swd);
 
 pub const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
 
@@ -63,31 +69,43 @@ impl Handler {
         Response::pack(&body, tx_buf)
     }
 
+    /// Handle the request in `rx_buf`, putting the response in `tx_buf`.
+    /// `blob_buf` is scratch space for the response's blob, if any.
     pub fn handle(
         &mut self,
         rx_buf: &[u8],
         tx_buf: &mut [u8; RESPONSE_BUF_SIZE],
+        blob_buf: &mut [u8; MAX_BLOB_SIZE],
         stats: &mut RotIoStats,
     ) -> usize {
         stats.rx_received = stats.rx_received.wrapping_add(1);
-        let rsp_body = match Request::unpack(rx_buf) {
-            Ok(request) => self.handle_request(request, stats),
+        let (rsp_body, blob_len) = match Request::unpack(rx_buf) {
+            Ok(request) => {
+                match self.handle_request(request, stats, blob_buf) {
+                    Ok((body, blob_len)) => (Ok(body), blob_len),
+                    Err(e) => (Err(e), 0),
+                }
+            }
             Err(e) => {
                 ringbuf_entry!(Trace::Err(e));
                 stats.rx_invalid = stats.rx_invalid.wrapping_add(1);
-                Err(e.into())
+                (Err(e.into()), 0)
             }
         };
 
-        Response::pack(&rsp_body, tx_buf)
+        Response::pack_with_slice(&rsp_body, tx_buf, &blob_buf[..blob_len])
     }
 
+    /// Handle `req`, returning the response body and the length of the blob,
+    /// if any, that the response carries in `blob`.
     pub fn handle_request(
         &mut self,
         req: Request,
         stats: &mut RotIoStats,
-    ) -> Result<RspBody, SprotError> {
-        match req.body {
+        blob: &mut [u8; MAX_BLOB_SIZE],
+    ) -> Result<(RspBody, usize), SprotError> {
+        let mut blob_len = 0;
+        let body = match req.body {
             ReqBody::Status => {
                 let status = RotStatus {
                     version: CURRENT_VERSION,
@@ -158,6 +176,104 @@ impl Handler {
                 self.update.reset()?;
                 Ok(RspBody::Ok)
             }
-        }
+            ReqBody::Update(UpdateReq::RaiseEpochFloor) => {
+                self.update.raise_epoch_floor(&req.blob)?;
+                Ok(RspBody::Ok)
+            }
+            ReqBody::Update(UpdateReq::Resume { target, block_num }) => {
+                let block_num = self
+                    .update
+                    .resume_image_update(target, block_num as usize)?;
+                // Block numbers will always fit in a u32 on these MCUs
+                Ok(RspBody::Update(UpdateRsp::ResumeFrom(
+                    block_num.try_into().unwrap_lite(),
+                )))
+            }
+            ReqBody::Update(UpdateReq::WriteCompressed { offset }) => {
+                self.update.write_compressed(offset, &req.blob)?;
+                Ok(RspBody::Ok)
+            }
+            ReqBody::Attest { nonce } => {
+                let attest = Attest::from(ATTEST.get_task_id());
+                let err = match attest.quote(nonce, &mut blob[..]) {
+                    Ok(len) => {
+                        blob_len = len as usize;
+                        None
+                    }
+                    Err(e) => Some(e),
+                };
+                Ok(RspBody::Attestation(AttestRsp::V1 { err }))
+            }
+            ReqBody::HostMeasurements(req) => {
+                let attest = Attest::from(ATTEST.get_task_id());
+                let err = match req {
+                    HostMeasurementsReq::Extend { digest } => {
+                        attest.record(MeasurementKind::Host, &digest).err()
+                    }
+                    HostMeasurementsReq::Read => None,
+                };
+                let measurements = attest.host_measurements();
+                Ok(RspBody::HostMeasurements(HostMeasurementsRsp::V1 {
+                    measurements,
+                    err,
+                }))
+            }
+            ReqBody::Certs(req) => {
+                let attest = Attest::from(ATTEST.get_task_id());
+                let result = match req {
+                    CertReq::ChainLen { chain } => attest.cert_chain_len(chain),
+                    CertReq::Len { chain, index } => {
+                        attest.cert_len(chain, index)
+                    }
+                    CertReq::Read {
+                        chain,
+                        index,
+                        offset,
+                    } => attest.cert(chain, index, offset, &mut blob[..]).map(
+                        |len| {
+                            blob_len = len as usize;
+                            len
+                        },
+                    ),
+                };
+                let (len, err) = match result {
+                    Ok(len) => (len, None),
+                    Err(e) => (0, Some(e)),
+                };
+                Ok(RspBody::Certs(CertRsp::V1 { len, err }))
+            }
+            ReqBody::SpMeasureStatus => {
+                let attest = Attest::from(ATTEST.get_task_id());
+                Ok(RspBody::SpMeasureStatus(attest.sp_measure_status()))
+            }
+            ReqBody::SpRecovery(op) => {
+                let sp_ctrl = SpCtrl::from(SWD.get_task_id());
+                // Staging and restoring start by talking to the SP, so make
+                // sure SWD is set up first.
+                let err = match op {
+                    SpRecoveryReq::Prep { size } => sp_ctrl
+                        .setup()
+                        .and_then(|()| sp_ctrl.recovery_stage_prep(size)),
+                    SpRecoveryReq::Write { offset } => {
+                        sp_ctrl.recovery_stage_write(offset, &req.blob)
+                    }
+                    SpRecoveryReq::Restore { size, digest } => sp_ctrl
+                        .setup()
+                        .and_then(|()| sp_ctrl.recovery_restore(size, digest)),
+                    SpRecoveryReq::Status => Ok(()),
+                }
+                .err();
+                let status = match sp_ctrl.recovery_status() {
+                    Ok(status) => status,
+                    Err(e) => SpRecoveryStatus {
+                        state: SpRecoveryState::Failed(e),
+                        done: 0,
+                        total: 0,
+                    },
+                };
+                Ok(RspBody::SpRecovery(SpRecoveryRsp::V1 { status, err }))
+            }
+        };
+        body.map(|body| (body, blob_len))
     }
 }
@@ -42,8 +42,8 @@ use drv_lpc55_gpio_api::{Direction, Value};
 use drv_lpc55_spi as spi_core;
 use drv_lpc55_syscon_api::{Peripheral, Syscon};
 use drv_sprot_api::{
-    RotIoStats, SprotProtocolError, REQUEST_BUF_SIZE, RESPONSE_BUF_SIZE,
-    ROT_FIFO_SIZE,
+    RotIoStats, SprotProtocolError, MAX_BLOB_SIZE, REQUEST_BUF_SIZE,
+    RESPONSE_BUF_SIZE, ROT_FIFO_SIZE,
 };
 use lpc55_pac as device;
 use ringbuf::{ringbuf, ringbuf_entry};
@@ -159,9 +159,11 @@ enum IoError {
 fn main() -> ! {
     let mut io = configure_spi();
 
-    let (rx_buf, tx_buf) = mutable_statics::mutable_statics! {
+    let (rx_buf, tx_buf, blob_buf) = mutable_statics::mutable_statics! {
         static mut RX_BUF: [u8; REQUEST_BUF_SIZE] = [|| 0; _];
         static mut TX_BUF: [u8; RESPONSE_BUF_SIZE] = [|| 0; _];
+        // Holds the blob of a response while it's being put together.
+        static mut BLOB_BUF: [u8; MAX_BLOB_SIZE] = [|| 0; _];
     };
 
     let mut handler = Handler::new();
@@ -174,9 +176,12 @@ fn main() -> ! {
 
     loop {
         let rsp_len = match io.wait_for_request(rx_buf) {
-            Ok(rx_len) => {
-                handler.handle(&rx_buf[..rx_len], tx_buf, &mut io.stats)
-            }
+            Ok(rx_len) => handler.handle(
+                &rx_buf[..rx_len],
+                tx_buf,
+                blob_buf,
+                &mut io.stats,
+            ),
             Err(IoError::Flush) => {
                 // A flush indicates that the server should de-assert ROT_IRQ
                 // as instructed by the SP. We do that and then proceed to wait
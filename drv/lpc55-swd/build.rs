@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use build_lpc55pins::PinConfig;
 use serde::Deserialize;
 use std::io::Write;
@@ -88,6 +88,26 @@ fn generate_swd_functions(config: &TaskConfig) -> Result<()> {
     Ok(())
 }
 
+/// Generates the measurements of the images we'll restore the SP from, which
+/// are the ones that `sp_measure` allows it to have.
+fn generate_allowed() -> Result<()> {
+    let config =
+        build_util::other_task_config::<build_sp_measure::Config>("sp_measure")
+            .context("SP recovery only restores images that sp_measure allows")?
+            .ok_or_else(|| anyhow!("sp_measure has no config"))?;
+
+    let out_dir = build_util::out_dir();
+    let mut file = std::fs::File::create(out_dir.join("allowed.rs"))?;
+    writeln!(
+        &mut file,
+        "const MEASURED_SIZE: u32 = {};",
+        build_sp_measure::MEASURED_SIZE
+    )?;
+    build_sp_measure::write_allowed(&mut file, &config.allowed()?)?;
+
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     idol::server::build_server_support(
         "../../idl/sp-ctrl.idol",
@@ -96,10 +116,12 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     )?;
 
     build_util::expose_target_board();
+    build_util::build_notifications()?;
 
     let task_config = build_util::task_config::<TaskConfig>()?;
 
     generate_swd_functions(&task_config)?;
+    generate_allowed()?;
     build_lpc55pins::codegen(task_config.pins)?;
 
     Ok(())
@@ -61,9 +61,11 @@
 #![no_std]
 #![no_main]
 
+mod recovery;
+
 use drv_lpc55_spi as spi_core;
 use drv_lpc55_syscon_api::{Peripheral, Syscon};
-use drv_sp_ctrl_api::SpCtrlError;
+use drv_sp_ctrl_api::{SpCtrlError, SpRecoveryState, SpRecoveryStatus};
 use idol_runtime::{
     LeaseBufReader, LeaseBufWriter, Leased, LenLimit, RequestError, R, W,
 };
@@ -85,6 +87,7 @@ enum Trace {
     DongleDetected,
     Dhcsr(u32),
     ParityFail { data: u32, received_parity: u16 },
+    Recovery(SpRecoveryState),
 }
 
 ringbuf!(Trace, 128, Trace::None);
@@ -140,6 +143,12 @@ const DHCSR_S_REGRDY: u32 = 1 << 16;
 const DCRSR: u32 = 0xE000EDF4;
 const DCRDR: u32 = 0xE000EDF8;
 
+// Debug Exception and Monitor Control Register. With VC_CORERESET set, the
+// SP's core halts as soon as it comes out of reset. Only a power-on reset
+// clears it.
+const DEMCR: u32 = 0xE000EDFC;
+const DEMCR_VC_CORERESET: u32 = 1 << 0;
+
 #[derive(Copy, Clone, PartialEq)]
 enum Port {
     DP = 0,
@@ -259,6 +268,7 @@ struct ServerImpl {
     gpio: TaskId,
     init: bool,
     transaction: Option<MemTransaction>,
+    recovery: recovery::Recovery,
 }
 
 impl idl::InOrderSpCtrlImpl for ServerImpl {
@@ -268,6 +278,7 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         start: u32,
         end: u32,
     ) -> Result<(), RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
         if !self.init {
             return Err(SpCtrlError::NeedInit.into());
         }
@@ -280,6 +291,7 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         _: &RecvMessage,
         dest: LenLimit<Leased<W, [u8]>, 4096>,
     ) -> Result<(), RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
         if !self.init {
             return Err(SpCtrlError::NeedInit.into());
         }
@@ -316,6 +328,7 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         dest: LenLimit<Leased<W, [u8]>, 4096>,
     ) -> Result<(), RequestError<SpCtrlError>> {
         ringbuf_entry!(Trace::ReadCmd);
+        self.check_not_restoring()?;
         if !self.init {
             return Err(SpCtrlError::NeedInit.into());
         }
@@ -349,6 +362,7 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         dest: LenLimit<Leased<R, [u8]>, 4096>,
     ) -> Result<(), RequestError<SpCtrlError>> {
         ringbuf_entry!(Trace::WriteCmd);
+        self.check_not_restoring()?;
         if !self.init {
             return Err(SpCtrlError::NeedInit.into());
         }
@@ -384,6 +398,7 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         &mut self,
         _: &RecvMessage,
     ) -> Result<(), RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
         if !self.init {
             self.pin_setup();
         }
@@ -406,33 +421,28 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         &mut self,
         _: &RecvMessage,
     ) -> Result<(), RequestError<SpCtrlError>> {
-        match self.write_single_target_addr(DHCSR, DHCSR_HALT_MAGIC) {
-            Ok(_) => loop {
-                match self.read_single_target_addr(DHCSR) {
-                    Ok(dhcsr) => {
-                        ringbuf_entry!(Trace::Dhcsr(dhcsr));
-
-                        if dhcsr & DHCSR_S_HALT != 0 {
-                            return Ok(());
-                        }
-                    }
-                    Err(_) => {
-                        return Err(SpCtrlError::Fault.into());
-                    }
-                }
-            },
-            Err(_) => Err(SpCtrlError::Fault.into()),
-        }
+        self.check_not_restoring()?;
+        Ok(self.halt_sp()?)
     }
 
     fn resume(
         &mut self,
         _: &RecvMessage,
     ) -> Result<(), RequestError<SpCtrlError>> {
-        match self.write_single_target_addr(DHCSR, DHCSR_RESUME_MAGIC) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(SpCtrlError::Fault.into()),
-        }
+        self.check_not_restoring()?;
+        Ok(self.resume_sp()?)
+    }
+
+    fn is_halted(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<bool, RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
+        let dhcsr = self
+            .read_single_target_addr(DHCSR)
+            .map_err(SpCtrlError::from)?;
+        ringbuf_entry!(Trace::Dhcsr(dhcsr));
+        Ok(dhcsr & DHCSR_S_HALT != 0)
     }
 
     fn read_core_register(
@@ -440,6 +450,7 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
         _: &RecvMessage,
         register: u16,
     ) -> Result<u32, RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
         // C1.6 Debug system registers
         let r = match register {
             // R0-R12
@@ -483,9 +494,91 @@ impl idl::InOrderSpCtrlImpl for ServerImpl {
             Err(_) => Err(SpCtrlError::Fault.into()),
         }
     }
+
+    fn recovery_stage_prep(
+        &mut self,
+        _: &RecvMessage,
+        size: u32,
+    ) -> Result<(), RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
+        if !self.init {
+            return Err(SpCtrlError::NeedInit.into());
+        }
+        Ok(self.stage_prep(size)?)
+    }
+
+    fn recovery_stage_write(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: LenLimit<Leased<R, [u8]>, 512>,
+    ) -> Result<(), RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
+        if !self.init {
+            return Err(SpCtrlError::NeedInit.into());
+        }
+        let mut buf = [0; 512];
+        let buf = &mut buf[..data.len()];
+        data.read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::went_away())?;
+        Ok(self.stage_write(offset, buf)?)
+    }
+
+    fn recovery_restore(
+        &mut self,
+        _: &RecvMessage,
+        size: u32,
+        digest: [u8; drv_sp_ctrl_api::SP_IMAGE_DIGEST_SIZE],
+    ) -> Result<(), RequestError<SpCtrlError>> {
+        self.check_not_restoring()?;
+        if !self.init {
+            return Err(SpCtrlError::NeedInit.into());
+        }
+        Ok(self.start_restore(size, digest)?)
+    }
+
+    fn recovery_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<SpRecoveryStatus, RequestError<SpCtrlError>> {
+        Ok(self.recovery.status())
+    }
+}
+
+impl idol_runtime::NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.recovery_step();
+    }
 }
 
 impl ServerImpl {
+    /// Halts the SP's core, and has it halt again whenever it comes out of
+    /// reset, so it doesn't run until it's resumed.
+    fn halt_sp(&mut self) -> Result<(), SpCtrlError> {
+        let demcr = self.read_single_target_addr(DEMCR)?;
+        self.write_single_target_addr(DEMCR, demcr | DEMCR_VC_CORERESET)?;
+        self.write_single_target_addr(DHCSR, DHCSR_HALT_MAGIC)?;
+        loop {
+            let dhcsr = self.read_single_target_addr(DHCSR)?;
+            ringbuf_entry!(Trace::Dhcsr(dhcsr));
+            if dhcsr & DHCSR_S_HALT != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Lets the SP's core run again, including after a reset.
+    fn resume_sp(&mut self) -> Result<(), SpCtrlError> {
+        let demcr = self.read_single_target_addr(DEMCR)?;
+        self.write_single_target_addr(DEMCR, demcr & !DEMCR_VC_CORERESET)?;
+        self.write_single_target_addr(DHCSR, DHCSR_RESUME_MAGIC)?;
+        Ok(())
+    }
+
     fn io_out(&mut self) {
         self.wait_for_mstidle();
         switch_io_out();
@@ -988,19 +1081,21 @@ fn main() -> ! {
         gpio,
         init: false,
         transaction: None,
+        recovery: recovery::Recovery::default(),
     };
 
     let mut incoming = [0; idl::INCOMING_SIZE];
     loop {
-        idol_runtime::dispatch(&mut incoming, &mut server);
+        idol_runtime::dispatch_n(&mut incoming, &mut server);
     }
 }
 
 mod idl {
-    use drv_sp_ctrl_api::SpCtrlError;
+    use drv_sp_ctrl_api::{SpCtrlError, SpRecoveryStatus};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
 
 include!(concat!(env!("OUT_DIR"), "/pin_config.rs"));
 include!(concat!(env!("OUT_DIR"), "/swd.rs"));
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,604 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recovering the SP by reflashing it over SWD.
+//!
+//! Recovery happens in two steps:
+//!
+//! 1. An image is staged in the SP's inactive flash bank (the one mapped at
+//!    `0x0810_0000`): the sectors it needs are erased, and then it's written
+//!    a chunk at a time. The SP keeps running from its boot bank meanwhile,
+//!    so it can be the one sending the image.
+//! 2. The staged image is checked: it must have a valid image header and the
+//!    expected SHA3-256 digest, and the SP must be allowed to run it, which
+//!    is to say that `sp_measure` would allow the measurement the SP will
+//!    have once it is. With the `sp-epoch-floor` feature, for SPs whose
+//!    update server keeps an epoch floor, its epoch can't be below the
+//!    floor either. If it passes, the SP is halted and the image is copied
+//!    into its boot bank, checked again, and the SP is reset into it.
+//!
+//! Erasing the staging area and the whole of the second step take far too
+//! long to do in one IPC, so they're driven by our timer, a sector or chunk
+//! at a time, and callers poll for progress. While the SP is being restored,
+//! every other operation fails with `RecoveryInProgress`.
+//!
+//! The staging area is the bank the SP's own update server writes updates
+//! to, and there's no room for a copy of an image in our flash, so a staged
+//! image only lasts until the next SP update. If it's been overwritten, it
+//! fails its checks and the SP is left alone, but it does mean an image has
+//! to be staged again after an update to be able to restore from it.
+//!
+//! All of the flash sequences here are from RM0433 Rev 7 section 4.3.
+
+use crate::{notifications, Ack, ServerImpl, Trace};
+use crate::{DEMCR, DEMCR_VC_CORERESET, DHCSR, DHCSR_RESUME_MAGIC};
+use drv_sp_ctrl_api::{
+    SpCtrlError, SpRecoveryState, SpRecoveryStatus, SP_IMAGE_DIGEST_SIZE,
+};
+use ringbuf::ringbuf_entry;
+use sha3::{Digest, Sha3_256};
+use userlib::{sys_get_timer, sys_set_timer, ImageHeader, HEADER_MAGIC};
+use zerocopy::FromBytes;
+
+/// One of the SP's flash banks: where it's mapped, and where its registers
+/// are.
+#[derive(Copy, Clone)]
+struct Bank {
+    base: u32,
+    regs: u32,
+}
+
+const FLASH_REGS: u32 = 0x5200_2000;
+
+/// The bank the SP boots from.
+const BOOT_BANK: Bank = Bank {
+    base: 0x0800_0000,
+    regs: FLASH_REGS,
+};
+
+/// The bank we stage images in.
+const STAGING_BANK: Bank = Bank {
+    base: 0x0810_0000,
+    regs: FLASH_REGS + 0x100,
+};
+
+// Register offsets within a bank's registers
+const KEYR: u32 = 0x04;
+const CR: u32 = 0x0c;
+const SR: u32 = 0x10;
+const CCR: u32 = 0x14;
+
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xcdef_89ab;
+
+const CR_LOCK: u32 = 1 << 0;
+const CR_PG: u32 = 1 << 1;
+const CR_SER: u32 = 1 << 2;
+// 64-bit internal parallelism, as the SP's own update server uses
+const CR_PSIZE_64: u32 = 0b11 << 4;
+const CR_START: u32 = 1 << 7;
+const CR_SNB_SHIFT: u32 = 8;
+
+const SR_BSY: u32 = 1 << 0;
+const SR_QW: u32 = 1 << 2;
+// WRPERR, PGSERR, STRBERR, INCERR, OPERR, RDPERR, RDSERR, SNECCERR, DBECCERR
+const SR_ERRORS: u32 = 0x07ee_0000;
+const SR_EOP: u32 = 1 << 16;
+
+const SECTOR_SIZE: u32 = 128 * 1024;
+const FLASH_WORD_SIZE: usize = 32;
+
+/// Number of sectors of a bank that an image can use: the SP's update server
+/// may keep its epoch floor log and update journal in the last two.
+const IMAGE_SECTORS: u32 = 6;
+
+/// Largest image we can stage.
+pub const MAX_IMAGE_SIZE: u32 = IMAGE_SECTORS * SECTOR_SIZE;
+const _: () = assert!(MAX_IMAGE_SIZE <= MEASURED_SIZE);
+
+/// Where the image header is, after the vector table.
+const HEADER_OFFSET: u32 = 0x298;
+
+/// The SP's epoch floor logs, in the last sector of each bank, if it keeps
+/// them. See `epoch_floor` in `drv-stm32h7-update-server`.
+const EPOCH_LOGS: &[u32] = if cfg!(feature = "sp-epoch-floor") {
+    &[
+        BOOT_BANK.base + 7 * SECTOR_SIZE,
+        STAGING_BANK.base + 7 * SECTOR_SIZE,
+    ]
+} else {
+    &[]
+};
+
+/// How much to hash or copy each time our timer fires. Reads are done in SWD
+/// transactions, which can't cross a 1 KiB boundary.
+const CHUNK_SIZE: u32 = 1024;
+
+// Generated by build.rs from `sp_measure`'s config: `MEASURED_SIZE`, how much
+// of the SP's flash it measures, and `ALLOWED`, the measurements it allows.
+include!(concat!(env!("OUT_DIR"), "/allowed.rs"));
+
+const AIRCR: u32 = 0xe000_ed0c;
+const AIRCR_SYSRESETREQ: u32 = 0x05fa_0004;
+
+pub struct Recovery {
+    state: SpRecoveryState,
+    done: u32,
+    total: u32,
+    /// Size of the image being staged or restored.
+    size: u32,
+    digest: [u8; SP_IMAGE_DIGEST_SIZE],
+    sha: Sha3_256,
+    /// Whether we've started erasing sector `done` of the bank being erased.
+    erasing: bool,
+    /// Epoch of the staged image, and the SP's epoch floor.
+    epoch: u32,
+    floor: u32,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self {
+            state: SpRecoveryState::Idle,
+            done: 0,
+            total: 0,
+            size: 0,
+            digest: [0; SP_IMAGE_DIGEST_SIZE],
+            sha: Sha3_256::new(),
+            erasing: false,
+            epoch: 0,
+            floor: 0,
+        }
+    }
+}
+
+impl Recovery {
+    pub fn status(&self) -> SpRecoveryStatus {
+        SpRecoveryStatus {
+            state: self.state,
+            done: self.done,
+            total: self.total,
+        }
+    }
+
+    /// Checks whether the SP is being restored, which no other operation may
+    /// interrupt.
+    pub fn is_restoring(&self) -> bool {
+        matches!(
+            self.state,
+            SpRecoveryState::VerifyingStaged
+                | SpRecoveryState::Erasing
+                | SpRecoveryState::Copying
+                | SpRecoveryState::Verifying
+        )
+    }
+
+    /// Checks whether recovery is in a state that's driven by our timer.
+    fn is_timed(&self) -> bool {
+        self.state == SpRecoveryState::ErasingStaging || self.is_restoring()
+    }
+
+    fn set_state(&mut self, state: SpRecoveryState, total: u32) {
+        ringbuf_entry!(Trace::Recovery(state));
+        self.state = state;
+        self.done = 0;
+        self.total = total;
+        self.erasing = false;
+    }
+}
+
+impl From<Ack> for SpCtrlError {
+    fn from(_: Ack) -> Self {
+        SpCtrlError::Fault
+    }
+}
+
+impl ServerImpl {
+    /// Starts staging an image of `size` bytes, by erasing the sectors of the
+    /// staging area that it needs.
+    pub(crate) fn stage_prep(&mut self, size: u32) -> Result<(), SpCtrlError> {
+        // An erase may be under way, which we'd lose track of.
+        if self.recovery.state == SpRecoveryState::ErasingStaging {
+            return Err(SpCtrlError::NotReady);
+        }
+        if size == 0 {
+            return Err(SpCtrlError::BadLen);
+        }
+        if size > MAX_IMAGE_SIZE {
+            return Err(SpCtrlError::ImageTooBig);
+        }
+        self.unlock(STAGING_BANK)?;
+        let sectors = (size + SECTOR_SIZE - 1) / SECTOR_SIZE;
+        self.recovery.size = size;
+        self.recovery
+            .set_state(SpRecoveryState::ErasingStaging, sectors);
+
+        sys_set_timer(Some(sys_get_timer().now), notifications::TIMER_MASK);
+        Ok(())
+    }
+
+    /// Writes the next chunk of the staged image, which starts at `offset`.
+    /// Every chunk but the last must be a whole number of flash words.
+    pub(crate) fn stage_write(
+        &mut self,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), SpCtrlError> {
+        let len = data.len();
+        let r = &self.recovery;
+        // A repeat of the last chunk means our reply to it was lost, so
+        // there's nothing more to do.
+        if r.state == SpRecoveryState::Staging
+            && offset < r.done
+            && offset + len as u32 == r.done
+        {
+            return Ok(());
+        }
+        if r.state == SpRecoveryState::ErasingStaging {
+            return Err(SpCtrlError::NotReady);
+        }
+        if r.state != SpRecoveryState::Staging || offset != r.done {
+            return Err(SpCtrlError::BadOffset);
+        }
+        let end = offset + len as u32;
+        if end > r.total {
+            return Err(SpCtrlError::ImageTooBig);
+        }
+        if end < r.total && len % FLASH_WORD_SIZE != 0 {
+            return Err(SpCtrlError::BadLen);
+        }
+
+        let result = (|| -> Result<(), SpCtrlError> {
+            self.unlock(STAGING_BANK)?;
+            for start in (0..len).step_by(FLASH_WORD_SIZE) {
+                let addr = STAGING_BANK.base + offset + start as u32;
+
+                // Pad the end of the last flash word as if it were erased.
+                let mut word = [0xff; FLASH_WORD_SIZE];
+                let n = (len - start).min(FLASH_WORD_SIZE);
+                word[..n].copy_from_slice(&data[start..][..n]);
+                self.program_word(STAGING_BANK, addr, &word)?;
+            }
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => {
+                self.recovery.done = end;
+                if end == self.recovery.total {
+                    self.lock(STAGING_BANK)?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.recovery_failed(e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts restoring the SP from the staged image, which must be `size`
+    /// bytes with a SHA3-256 of `digest`.
+    pub(crate) fn start_restore(
+        &mut self,
+        size: u32,
+        digest: [u8; SP_IMAGE_DIGEST_SIZE],
+    ) -> Result<(), SpCtrlError> {
+        if self.recovery.state == SpRecoveryState::ErasingStaging {
+            return Err(SpCtrlError::NotReady);
+        }
+        if size == 0 {
+            return Err(SpCtrlError::BadLen);
+        }
+        if size > MAX_IMAGE_SIZE {
+            return Err(SpCtrlError::ImageTooBig);
+        }
+        let r = &mut self.recovery;
+        r.size = size;
+        r.digest = digest;
+        r.sha = Sha3_256::new();
+        let total = MEASURED_SIZE + EPOCH_LOGS.len() as u32 * SECTOR_SIZE;
+        r.set_state(SpRecoveryState::VerifyingStaged, total);
+
+        sys_set_timer(Some(sys_get_timer().now), notifications::TIMER_MASK);
+        Ok(())
+    }
+
+    /// Does the next sector or chunk of erasing the staging area or
+    /// restoring the SP. Called when our timer fires.
+    pub(crate) fn recovery_step(&mut self) {
+        if !self.recovery.is_timed() {
+            return;
+        }
+        match self.try_recovery_step() {
+            Ok(()) => {
+                if self.recovery.is_timed() {
+                    sys_set_timer(
+                        Some(sys_get_timer().now + 1),
+                        notifications::TIMER_MASK,
+                    );
+                }
+            }
+            Err(e) => self.recovery_failed(e),
+        }
+    }
+
+    fn try_recovery_step(&mut self) -> Result<(), SpCtrlError> {
+        let size = self.recovery.size;
+        match self.recovery.state {
+            SpRecoveryState::ErasingStaging => {
+                if self.erase_step(STAGING_BANK)? {
+                    self.recovery.set_state(SpRecoveryState::Staging, size);
+                }
+            }
+            SpRecoveryState::VerifyingStaged => {
+                // Nothing's been touched yet, so if the staged image isn't
+                // one we can restore from, the SP is left running.
+                if self.verify_staged_step()? {
+                    // The SP stays halted through any reset, so it can't run
+                    // a half-written boot bank.
+                    self.halt_sp()?;
+                    self.unlock(BOOT_BANK)?;
+                    self.recovery
+                        .set_state(SpRecoveryState::Erasing, IMAGE_SECTORS);
+                }
+            }
+            SpRecoveryState::Erasing => {
+                // All the sectors an image can use are erased, not just the
+                // ones this one does, so that the SP measures the same as
+                // the image did.
+                if self.erase_step(BOOT_BANK)? {
+                    self.recovery.set_state(SpRecoveryState::Copying, size);
+                }
+            }
+            SpRecoveryState::Copying => {
+                let start = self.recovery.done;
+                let end = (start + CHUNK_SIZE).min(size);
+                // The last flash word of the staged image was padded when it
+                // was written, so we can copy whole words.
+                for offset in (start..end).step_by(FLASH_WORD_SIZE) {
+                    let mut word = [0; FLASH_WORD_SIZE];
+                    self.read_flash(STAGING_BANK.base + offset, &mut word)?;
+                    self.program_word(
+                        BOOT_BANK,
+                        BOOT_BANK.base + offset,
+                        &word,
+                    )?;
+                }
+                self.recovery.done = end;
+                if end == size {
+                    self.lock(BOOT_BANK)?;
+                    self.recovery.sha = Sha3_256::new();
+                    self.recovery.set_state(SpRecoveryState::Verifying, size);
+                }
+            }
+            SpRecoveryState::Verifying => {
+                if self.hash_chunk(BOOT_BANK)? {
+                    // If this fails, the SP stays halted: it's better off
+                    // not running whatever's in its boot bank.
+                    self.check_digest()?;
+                    self.reset_sp()?;
+                    self.recovery.set_state(SpRecoveryState::Done, 0);
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Fails with `RecoveryInProgress` if the SP is being restored.
+    pub(crate) fn check_not_restoring(&self) -> Result<(), SpCtrlError> {
+        if self.recovery.is_restoring() {
+            Err(SpCtrlError::RecoveryInProgress)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn recovery_failed(&mut self, e: SpCtrlError) {
+        self.recovery.set_state(SpRecoveryState::Failed(e), 0);
+    }
+
+    /// Does the next step of erasing the first `total` sectors of `bank`,
+    /// returning `true` once they've all been erased.
+    fn erase_step(&mut self, bank: Bank) -> Result<bool, SpCtrlError> {
+        if !self.recovery.erasing {
+            self.start_erase(bank, self.recovery.done)?;
+            self.recovery.erasing = true;
+        } else if self.flash_done(bank)? {
+            self.recovery.erasing = false;
+            self.recovery.done += 1;
+        }
+        Ok(self.recovery.done == self.recovery.total)
+    }
+
+    /// Does the next step of checking the staged image, returning `true` once
+    /// it's passed.
+    ///
+    /// The image is hashed and checked against the expected digest, and then
+    /// the hash is carried on over erased flash, as `sp_measure` does, to get
+    /// the measurement the SP will have once it's running the image. After
+    /// that, we read the SP's epoch floor logs, if it has any.
+    fn verify_staged_step(&mut self) -> Result<bool, SpCtrlError> {
+        let r = &self.recovery;
+        let (done, size, total) = (r.done, r.size, r.total);
+        if done == 0 {
+            self.recovery.epoch = self.staged_header()?.epoch;
+            self.recovery.floor = 0;
+        }
+
+        if done < size {
+            if self.hash_chunk(STAGING_BANK)? {
+                self.check_digest()?;
+            }
+        } else if done < MEASURED_SIZE {
+            let end = (done + CHUNK_SIZE).min(MEASURED_SIZE);
+            let erased = [0xff; CHUNK_SIZE as usize];
+            self.recovery.sha.update(&erased[..(end - done) as usize]);
+            self.recovery.done = end;
+        } else if done < total {
+            self.scan_epoch_logs()?;
+        } else {
+            let sha =
+                core::mem::replace(&mut self.recovery.sha, Sha3_256::new());
+            let measurement = sha.finalize();
+            if !ALLOWED.iter().any(|m| m == measurement.as_slice()) {
+                return Err(SpCtrlError::NotAllowed);
+            }
+            if self.recovery.epoch < self.recovery.floor {
+                return Err(SpCtrlError::EpochTooOld);
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Reads the header of the staged image, if it has a valid one.
+    fn staged_header(&mut self) -> Result<ImageHeader, SpCtrlError> {
+        let mut buf = [0; core::mem::size_of::<ImageHeader>()];
+        self.read_flash(STAGING_BANK.base + HEADER_OFFSET, &mut buf)?;
+        let header =
+            ImageHeader::read_from(&buf[..]).ok_or(SpCtrlError::BadImage)?;
+        // The image may be followed by a signature, which isn't counted in
+        // its length.
+        if header.magic != HEADER_MAGIC
+            || header.total_image_len > self.recovery.size
+        {
+            return Err(SpCtrlError::BadImage);
+        }
+        Ok(header)
+    }
+
+    /// Reads the next chunk of the SP's epoch floor logs, raising `floor` to
+    /// the highest epoch in it. Each entry is a flash word holding an epoch
+    /// and its complement, and unused entries are erased.
+    fn scan_epoch_logs(&mut self) -> Result<(), SpCtrlError> {
+        let offset = self.recovery.done - MEASURED_SIZE;
+        let log = EPOCH_LOGS[(offset / SECTOR_SIZE) as usize];
+        let mut buf = [0; CHUNK_SIZE as usize];
+        self.read_flash(log + offset % SECTOR_SIZE, &mut buf)?;
+
+        let mut next = self.recovery.done + CHUNK_SIZE;
+        for entry in buf.chunks_exact(FLASH_WORD_SIZE) {
+            if entry.iter().all(|&b| b == 0xff) {
+                // That's the end of this log.
+                next = MEASURED_SIZE + (offset / SECTOR_SIZE + 1) * SECTOR_SIZE;
+                break;
+            }
+            let epoch = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+            let check = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            if check == !epoch {
+                self.recovery.floor = self.recovery.floor.max(epoch);
+            }
+        }
+        self.recovery.done = next;
+        Ok(())
+    }
+
+    /// Hashes the next chunk of the image in `bank`, returning `true` once
+    /// the whole image has been hashed.
+    fn hash_chunk(&mut self, bank: Bank) -> Result<bool, SpCtrlError> {
+        let start = self.recovery.done;
+        let end = (start + CHUNK_SIZE).min(self.recovery.size);
+        let mut buf = [0; CHUNK_SIZE as usize];
+        let buf = &mut buf[..(end - start) as usize];
+        self.read_flash(bank.base + start, buf)?;
+        self.recovery.sha.update(&*buf);
+        self.recovery.done = end;
+        Ok(end == self.recovery.size)
+    }
+
+    /// Checks the hash so far against the expected digest. The hash can be
+    /// carried on afterwards.
+    fn check_digest(&mut self) -> Result<(), SpCtrlError> {
+        if self.recovery.sha.clone().finalize().as_slice()
+            == self.recovery.digest
+        {
+            Ok(())
+        } else {
+            Err(SpCtrlError::DigestMismatch)
+        }
+    }
+
+    /// Reads SP memory at `addr` into `buf`, which must not cross a 1 KiB
+    /// boundary.
+    fn read_flash(
+        &mut self,
+        addr: u32,
+        buf: &mut [u8],
+    ) -> Result<(), SpCtrlError> {
+        let words = (buf.len() + 3) / 4;
+        self.start_read_transaction(addr, words)?;
+        for chunk in buf.chunks_mut(4) {
+            let word = self.read_transaction_word()?.ok_or(Ack::Fault)?;
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn reset_sp(&mut self) -> Result<(), SpCtrlError> {
+        let demcr = self.read_single_target_addr(DEMCR)?;
+        self.write_single_target_addr(DEMCR, demcr & !DEMCR_VC_CORERESET)?;
+        self.write_single_target_addr(AIRCR, AIRCR_SYSRESETREQ)?;
+        // The SP's reset doesn't reset its debug logic, so make sure it
+        // isn't still being told to halt.
+        self.write_single_target_addr(DHCSR, DHCSR_RESUME_MAGIC)?;
+        Ok(())
+    }
+
+    fn unlock(&mut self, bank: Bank) -> Result<(), SpCtrlError> {
+        let cr = self.read_single_target_addr(bank.regs + CR)?;
+        if cr & CR_LOCK != 0 {
+            self.write_single_target_addr(bank.regs + KEYR, FLASH_KEY1)?;
+            self.write_single_target_addr(bank.regs + KEYR, FLASH_KEY2)?;
+        }
+        Ok(())
+    }
+
+    fn lock(&mut self, bank: Bank) -> Result<(), SpCtrlError> {
+        self.write_single_target_addr(bank.regs + CR, CR_LOCK)?;
+        Ok(())
+    }
+
+    fn start_erase(
+        &mut self,
+        bank: Bank,
+        sector: u32,
+    ) -> Result<(), SpCtrlError> {
+        self.write_single_target_addr(bank.regs + CCR, SR_ERRORS | SR_EOP)?;
+        let cr = CR_PSIZE_64 | CR_SER | (sector << CR_SNB_SHIFT);
+        self.write_single_target_addr(bank.regs + CR, cr)?;
+        self.write_single_target_addr(bank.regs + CR, cr | CR_START)?;
+        Ok(())
+    }
+
+    /// Checks whether the last erase or program of `bank` is done.
+    fn flash_done(&mut self, bank: Bank) -> Result<bool, SpCtrlError> {
+        let sr = self.read_single_target_addr(bank.regs + SR)?;
+        if sr & (SR_BSY | SR_QW) != 0 {
+            return Ok(false);
+        }
+        if sr & SR_ERRORS != 0 {
+            self.write_single_target_addr(bank.regs + CCR, SR_ERRORS)?;
+            return Err(SpCtrlError::FlashError);
+        }
+        Ok(true)
+    }
+
+    fn program_word(
+        &mut self,
+        bank: Bank,
+        addr: u32,
+        word: &[u8; FLASH_WORD_SIZE],
+    ) -> Result<(), SpCtrlError> {
+        self.write_single_target_addr(bank.regs + CR, CR_PSIZE_64 | CR_PG)?;
+        for (i, w) in word.chunks_exact(4).enumerate() {
+            let w = u32::from_le_bytes(w.try_into().unwrap());
+            self.write_single_target_addr(addr + 4 * i as u32, w)?;
+        }
+        while !self.flash_done(bank)? {}
+        self.write_single_target_addr(bank.regs + CR, 0)?;
+        Ok(())
+    }
+}
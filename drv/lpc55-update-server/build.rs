@@ -9,10 +9,12 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
 
-    idol::server::build_server_support(
-        "../../idl/update.idol",
+    const IDL: &str = "../../idl/update.idol";
+    idol::server::build_restricted_server_support(
+        IDL,
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+        &build_util::task_allowed_callers(IDL)?,
     )?;
 
     let out = build_util::out_dir();
@@ -24,5 +26,19 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     writeln!(ver_file, "const HUBRIS_BUILD_VERSION: u32 = {};", version)?;
     writeln!(ver_file, "const HUBRIS_BUILD_EPOCH: u32 = {};", epoch)?;
 
+    // Epoch floor tokens are checked against the image signing key, if the
+    // app has one.
+    match build_util::env_var("HUBRIS_IMAGE_SIGNING_KEY") {
+        Ok(key) => writeln!(
+            ver_file,
+            "const IMAGE_SIGNING_KEY: Option<[u8; 32]> = Some({});",
+            key
+        )?,
+        Err(_) => writeln!(
+            ver_file,
+            "const IMAGE_SIGNING_KEY: Option<[u8; 32]> = None;"
+        )?,
+    }
+
     Ok(())
 }
@@ -13,13 +13,16 @@ use core::convert::Infallible;
 use core::mem::MaybeUninit;
 use drv_caboose::CabooseError;
 use drv_lpc55_flash::{BYTES_PER_FLASH_PAGE, BYTES_PER_FLASH_WORD};
+#[cfg(feature = "compression")]
+use drv_update_api::compressed::Inflater;
 use drv_update_api::{
     SlotId, SwitchDuration, UpdateError, UpdateStatus, UpdateTarget,
+    JOURNAL_RECORD_BYTES,
 };
-use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
 use stage0_handoff::{HandoffData, ImageVersion, RotBootState};
 use userlib::*;
-use zerocopy::AsBytes;
+use zerocopy::{AsBytes, FromBytes};
 
 // We shouldn't actually dereference these. The types are not correct.
 // They are just here to allow a mechanism for getting the addresses.
@@ -51,6 +54,8 @@ struct ServerImpl<'a> {
     header_block: Option<[u8; BLOCK_SIZE_BYTES]>,
     state: UpdateState,
     image: Option<UpdateTarget>,
+    #[cfg(feature = "compression")]
+    inflater: Option<Inflater<BLOCK_SIZE_BYTES>>,
 
     flash: drv_lpc55_flash::Flash<'a>,
     hashcrypt: &'a lpc55_pac::hashcrypt::RegisterBlock,
@@ -67,6 +72,209 @@ const BLOCK_SIZE_BYTES: usize = BYTES_PER_FLASH_PAGE;
 const MAX_LEASE: usize = 1024;
 const HEADER_BLOCK: usize = 0;
 
+/// The anti-rollback epoch floor is kept in the customer data area of the
+/// CFPA, in the 32-bit word at (byte) offset 0x104, just after the boot
+/// setting. This is (flash word, `u32` index) within the CFPA.
+const EPOCH_FLOOR_CFPA_WORD: (usize, usize) = (0x10, 1);
+
+// There are two "official" copies of the CFPA, referred to as ping and pong.
+// One of them will supercede the other, based on a monotonic version field at
+// offset 4. Changes are made by writing a _third_ copy, called the scratch
+// page, with a higher version; at reset, the boot ROM will inspect the scratch
+// page, check invariants, and copy it to overwrite the older of the ping and
+// pong pages if it approves.
+//
+// The addresses of these pages are as follows (see Figure 13, "Protected
+// Flash Region," in UM11126 rev 2.4, or the NXP flash layout spreadsheet):
+//
+// Page     Addr        16-byte word number
+// Scratch  0x9_DE00    0x9DE0
+// Ping     0x9_E000    0x9E00
+// Pong     0x9_E200    0x9E20
+const CFPA_SCRATCH_WORD: u32 = 0x9DE0;
+const CFPA_PING_WORD: u32 = 0x9E00;
+const CFPA_PONG_WORD: u32 = 0x9E20;
+
+type Cfpa = [[u32; 4]; 512 / 16];
+
+impl ServerImpl<'_> {
+    /// Writes `flash_page` as block `block_num` of the image being updated.
+    /// A short block should be padded out with zeros.
+    fn write_block(
+        &mut self,
+        block_num: usize,
+        flash_page: &mut [u8; BLOCK_SIZE_BYTES],
+    ) -> Result<(), UpdateError> {
+        let target = self.image.unwrap_lite();
+
+        if block_num == HEADER_BLOCK {
+            let floor = self.epoch_floor()?;
+            if let Err(e) = validate_header_block(target, flash_page, floor) {
+                self.header_block = None;
+                return Err(e);
+            }
+            self.header_block = Some(*flash_page);
+
+            // The header block is written properly by `finish_image_update`,
+            // but we write it now with its magic cleared -- which keeps the
+            // image from booting -- so that `resume_image_update` can recover
+            // it after a reset.
+            flash_page[MAGIC_OFFSET..][..4].fill(0);
+        } else {
+            // The header block is currently block 0. We should ensure
+            // we've seen and cached it before proceeding with other
+            // blocks. Otherwise, we won't be able to complete the update in
+            // `finish_image_update`.
+            if self.header_block.is_none() {
+                return Err(UpdateError::MissingHeaderBlock);
+            }
+        }
+
+        do_block_write(&mut self.flash, target, block_num, flash_page)
+    }
+
+    /// Decompresses the chunk of a compressed image in `data`, which starts
+    /// at `offset`, writing each block as it's filled.
+    #[cfg(feature = "compression")]
+    fn inflate(
+        &mut self,
+        inflater: &mut Inflater<BLOCK_SIZE_BYTES>,
+        offset: u32,
+        data: &Leased<R, [u8]>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        if !inflater.start_chunk(offset, data.len())? {
+            return Ok(());
+        }
+
+        // We read the lease in small pieces, since we need a block's worth
+        // of stack to write each block.
+        let mut buf = [0u8; 64];
+        for start in (0..data.len()).step_by(buf.len()) {
+            let n = (data.len() - start).min(buf.len());
+            let buf = &mut buf[..n];
+            data.read_range(start..start + buf.len(), buf)
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+            inflater.feed(buf, |block_num, block| {
+                let mut flash_page = [0u8; BLOCK_SIZE_BYTES];
+                flash_page[..block.len()].copy_from_slice(block);
+                self.write_block(block_num, &mut flash_page)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reads the most recent contents of the CFPA. This includes changes that
+    /// are waiting in the scratch page to be applied at the next reset, so
+    /// that making several changes before a reset doesn't lose any of them.
+    fn read_cfpa(&mut self) -> Result<Cfpa, UpdateError> {
+        let cfpa_word_number = {
+            // Read the versions. We do this with smaller buffers so we don't
+            // need several 512B buffers to read the entire CFPAs.
+            let mut ping_header = [0u32; 4];
+            let mut pong_header = [0u32; 4];
+            let mut scratch_header = [0u32; 4];
+
+            indirect_flash_read(
+                &mut self.flash,
+                CFPA_PING_WORD,
+                core::slice::from_mut(&mut ping_header),
+            )?;
+            indirect_flash_read(
+                &mut self.flash,
+                CFPA_PONG_WORD,
+                core::slice::from_mut(&mut pong_header),
+            )?;
+
+            // Work out where to read the authoritative contents from.
+            let (word, version) = if ping_header[1] >= pong_header[1] {
+                (CFPA_PING_WORD, ping_header[1])
+            } else {
+                (CFPA_PONG_WORD, pong_header[1])
+            };
+
+            // The scratch page is only pending if it's newer. It may also be
+            // erased, which reads as an error; that means nothing's pending.
+            match indirect_flash_read(
+                &mut self.flash,
+                CFPA_SCRATCH_WORD,
+                core::slice::from_mut(&mut scratch_header),
+            ) {
+                Ok(()) if scratch_header[1] > version => CFPA_SCRATCH_WORD,
+                _ => word,
+            }
+        };
+
+        let mut cfpa = [[0u32; 4]; 512 / 16];
+        indirect_flash_read(&mut self.flash, cfpa_word_number, &mut cfpa)?;
+        Ok(cfpa)
+    }
+
+    /// Writes `cfpa` into the scratch page, with a new version and hash, to
+    /// be applied at the next reset.
+    fn write_cfpa(&mut self, mut cfpa: Cfpa) -> Result<(), UpdateError> {
+        // Increment the monotonic version. The manual doesn't specify how the
+        // version numbers are compared or what happens if they wrap, so, we'll
+        // treat wrapping as an error and report it for now. (Note that getting
+        // this version to wrap _should_ require more write cycles than the
+        // flash can take.)
+        let new_version =
+            cfpa[0][1].checked_add(1).ok_or(UpdateError::SecureErr)?;
+        cfpa[0][1] = new_version;
+
+        // The last two flash words are a SHA256 hash of the preceding data.
+        // This means we need to compute a SHA256 hash of the preceding data --
+        // meaning flash words 0 thru 29 inclusive.
+        let cfpa_hash = {
+            // We leave the hashcrypt unit in reset when unused, starting in
+            // the `main` function, so we only need to bring it _out of_ reset
+            // here.
+            self.syscon
+                .leave_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
+            let mut h = drv_lpc55_sha256::Hasher::begin(
+                self.hashcrypt,
+                notifications::HASHCRYPT_IRQ_MASK,
+            );
+            for chunk in &cfpa[..30] {
+                h.update(chunk, 0);
+            }
+            let hash = h.finish();
+
+            // Put it back.
+            self.syscon
+                .enter_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
+
+            hash
+        };
+        cfpa[30] = cfpa_hash[..4].try_into().unwrap_lite();
+        cfpa[31] = cfpa_hash[4..].try_into().unwrap_lite();
+
+        // Recast that as a page-sized byte array because that's what the
+        // update side of the machinery wants. The try_into on the second line
+        // can't fail at runtime, but there's no good support for casting
+        // between fixed-size arrays in zerocopy yet.
+        let cfpa_bytes: &[u8] = cfpa.as_bytes();
+        let cfpa_bytes: &[u8; BLOCK_SIZE_BYTES] =
+            cfpa_bytes.try_into().unwrap_lite();
+
+        // Erase and program the scratch page. Note that because the scratch
+        // page is _not_ the authoritative copy, and because the ROM will check
+        // its contents before making it authoritative, we can fail during this
+        // operation without corrupting anything permanent. Yay!
+        //
+        // Note that the page write machinery uses page numbers. This should
+        // probably change. But, for now, we must divide our word number by
+        // 32.
+        do_raw_page_write(&mut self.flash, CFPA_SCRATCH_WORD / 32, cfpa_bytes)
+    }
+
+    /// Returns the lowest image epoch that we'll accept for update, including
+    /// any increase that's waiting to be applied.
+    fn epoch_floor(&mut self) -> Result<u32, UpdateError> {
+        let (word, index) = EPOCH_FLOOR_CFPA_WORD;
+        Ok(self.read_cfpa()?[word][index])
+    }
+}
+
 impl idl::InOrderUpdateImpl for ServerImpl<'_> {
     fn prep_image_update(
         &mut self,
@@ -87,6 +295,10 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
 
         self.image = Some(image_type);
         self.state = UpdateState::InProgress;
+        #[cfg(feature = "compression")]
+        {
+            self.inflater = None;
+        }
         Ok(())
     }
 
@@ -105,6 +317,70 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         Ok(())
     }
 
+    fn resume_image_update(
+        &mut self,
+        _: &RecvMessage,
+        image_type: UpdateTarget,
+        block_num: usize,
+    ) -> Result<usize, RequestError<UpdateError>> {
+        match self.state {
+            // If only the SP was reset, we may still be in the middle of the
+            // update that it's resuming.
+            UpdateState::InProgress if self.image == Some(image_type) => (),
+            UpdateState::InProgress => {
+                return Err(UpdateError::UpdateInProgress.into())
+            }
+            UpdateState::Finished => {
+                return Err(UpdateError::UpdateAlreadyFinished.into())
+            }
+            UpdateState::NoUpdate => (),
+        }
+
+        match image_type {
+            UpdateTarget::ImageA
+            | UpdateTarget::ImageB
+            | UpdateTarget::Bootloader => (),
+            _ => return Err(UpdateError::BadImageType.into()),
+        }
+        if same_image(image_type) {
+            return Err(UpdateError::RunningImage.into());
+        }
+
+        // Every block write erases the page first, so we can pick up from
+        // any block. If that's past the header block, we need the header
+        // block back, which `write_one_block` left in flash without its magic.
+        if block_num != HEADER_BLOCK {
+            let addr = target_addr(image_type, HEADER_BLOCK as u32)
+                .ok_or(UpdateError::OutOfBounds)?;
+            let mut header_block =
+                [[0u32; 4]; BLOCK_SIZE_BYTES / BYTES_PER_FLASH_WORD];
+            indirect_flash_read(
+                &mut self.flash,
+                addr / BYTES_PER_FLASH_WORD as u32,
+                &mut header_block,
+            )?;
+            let mut header_block: [u8; BLOCK_SIZE_BYTES] =
+                header_block.as_bytes().try_into().unwrap_lite();
+            header_block[MAGIC_OFFSET..][..4]
+                .copy_from_slice(&abi::HEADER_MAGIC.to_le_bytes());
+            validate_header_block(
+                image_type,
+                &header_block,
+                self.epoch_floor()?,
+            )?;
+            self.header_block = Some(header_block);
+        }
+
+        self.image = Some(image_type);
+        self.state = UpdateState::InProgress;
+        #[cfg(feature = "compression")]
+        {
+            // We can't pick up decompression where we left off.
+            self.inflater = None;
+        }
+        Ok(block_num)
+    }
+
     fn write_one_block(
         &mut self,
         _: &RecvMessage,
@@ -131,35 +407,11 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         }
 
         let mut flash_page = [0u8; BLOCK_SIZE_BYTES];
-        let target = self.image.unwrap_lite();
-
-        if block_num == HEADER_BLOCK {
-            let header_block =
-                self.header_block.get_or_insert([0u8; BLOCK_SIZE_BYTES]);
-            block
-                .read_range(0..len, &mut header_block[..])
-                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
-            header_block[len..].fill(0);
-            if let Err(e) = validate_header_block(target, header_block) {
-                self.header_block = None;
-                return Err(e.into());
-            }
-        } else {
-            // The header block is currently block 0. We should ensure
-            // we've seen and cached it before proceeding with other
-            // blocks. Otherwise, we won't be able to complete the update in
-            // `finish_image_update`.
-            if self.header_block.is_none() {
-                return Err(UpdateError::MissingHeaderBlock.into());
-            }
-            block
-                .read_range(0..len, &mut flash_page)
-                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        block
+            .read_range(0..len, &mut flash_page[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
 
-            flash_page[len..].fill(0);
-        }
-
-        do_block_write(&mut self.flash, target, block_num, &flash_page)?;
+        self.write_block(block_num, &mut flash_page)?;
 
         Ok(())
     }
@@ -182,6 +434,14 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             return Err(UpdateError::MissingHeaderBlock.into());
         }
 
+        // If we were sent a compressed image, make sure we got all of it.
+        #[cfg(feature = "compression")]
+        if let Some(inflater) = self.inflater.take() {
+            if !inflater.is_done() {
+                return Err(UpdateError::BadCompressedImage.into());
+            }
+        }
+
         do_block_write(
             &mut self.flash,
             self.image.unwrap_lite(),
@@ -247,131 +507,129 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
                 return Err(UpdateError::NotImplemented.into());
             }
             SwitchDuration::Forever => {
-                // There are two "official" copies of the CFPA, referred to as
-                // ping and pong. One of them will supercede the other, based on
-                // a monotonic version field at offset 4. We'll take the
-                // contents of whichever one is most recent, alter them, and
-                // then write them into the _third_ copy, called the scratch
-                // page.
-                //
-                // At reset, the boot ROM will inspect the scratch page, check
-                // invariants, and copy it to overwrite the older of the ping
-                // and pong pages if it approves.
-                //
-                // That means you can apply this operation several times before
-                // resetting without burning many monotonic versions, if you
-                // want to do that for some reason.
-                //
-                // The addresses of these pages are as follows (see Figure 13,
-                // "Protected Flash Region," in UM11126 rev 2.4, or the NXP
-                // flash layout spreadsheet):
-                //
-                // Page     Addr        16-byte word number
-                // Scratch  0x9_DE00    0x9DE0
-                // Ping     0x9_E000    0x9E00
-                // Pong     0x9_E200    0x9E20
-
-                let cfpa_word_number = {
-                    // Read the two versions. We do this with smaller buffers so
-                    // we don't need 2x 512B buffers to read the entire CFPAs.
-                    let mut ping_header = [0u32; 4];
-                    let mut pong_header = [0u32; 4];
-
-                    indirect_flash_read(
-                        &mut self.flash,
-                        0x9E00,
-                        core::slice::from_mut(&mut ping_header),
-                    )?;
-                    indirect_flash_read(
-                        &mut self.flash,
-                        0x9E20,
-                        core::slice::from_mut(&mut pong_header),
-                    )?;
-
-                    // Work out where to read the authoritative contents from.
-                    if ping_header[1] >= pong_header[1] {
-                        0x9E00
-                    } else {
-                        0x9E20
-                    }
+                // Don't let the boot setting sidestep the epoch floor: the
+                // other slot most likely holds the image we updated from.
+                let target = match slot {
+                    SlotId::A => UpdateTarget::ImageA,
+                    SlotId::B => UpdateTarget::ImageB,
                 };
-
-                // Read current CFPA contents.
-                let mut cfpa = [[0u32; 4]; 512 / 16];
+                let mut header_block =
+                    [[0u32; 4]; BLOCK_SIZE_BYTES / BYTES_PER_FLASH_WORD];
                 indirect_flash_read(
                     &mut self.flash,
-                    cfpa_word_number,
-                    &mut cfpa,
+                    get_base(target) / BYTES_PER_FLASH_WORD as u32,
+                    &mut header_block,
+                )?;
+                validate_header_block(
+                    target,
+                    header_block.as_bytes().try_into().unwrap_lite(),
+                    self.epoch_floor()?,
                 )?;
 
-                // Increment the monotonic version. The manual doesn't specify
-                // how the version numbers are compared or what happens if they
-                // wrap, so, we'll treat wrapping as an error and report it for
-                // now. (Note that getting this version to wrap _should_ require
-                // more write cycles than the flash can take.)
-                let new_version =
-                    cfpa[0][1].checked_add(1).ok_or(UpdateError::SecureErr)?;
-                cfpa[0][1] = new_version;
                 // Alter the boot setting. The boot setting (per RFD 374) is in
                 // the lowest bit of the 32-bit word starting at (byte) offset
                 // 0x100. This is flash word offset 0x10.
                 //
                 // Leave remaining bits undisturbed; they are currently
                 // reserved.
+                let mut cfpa = self.read_cfpa()?;
                 cfpa[0x10][0] &= !1;
                 cfpa[0x10][0] |= if slot == SlotId::A { 0 } else { 1 };
-                // The last two flash words are a SHA256 hash of the preceding
-                // data. This means we need to compute a SHA256 hash of the
-                // preceding data -- meaning flash words 0 thru 29 inclusive.
-                let cfpa_hash = {
-                    // We leave the hashcrypt unit in reset when unused,
-                    // starting in the `main` function, so we only need to bring
-                    // it _out of_ reset here.
-                    self.syscon
-                        .leave_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
-                    let mut h = drv_lpc55_sha256::Hasher::begin(
-                        self.hashcrypt,
-                        notifications::HASHCRYPT_IRQ_MASK,
-                    );
-                    for chunk in &cfpa[..30] {
-                        h.update(chunk, 0);
-                    }
-                    let hash = h.finish();
+                self.write_cfpa(cfpa)?;
+            }
+        }
 
-                    // Put it back.
-                    self.syscon
-                        .enter_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
+        Ok(())
+    }
 
-                    hash
-                };
-                cfpa[30] = cfpa_hash[..4].try_into().unwrap_lite();
-                cfpa[31] = cfpa_hash[4..].try_into().unwrap_lite();
-
-                // Recast that as a page-sized byte array because that's what
-                // the update side of the machinery wants. The try_into on the
-                // second line can't fail at runtime, but there's no good
-                // support for casting between fixed-size arrays in zerocopy
-                // yet.
-                let cfpa_bytes: &[u8] = cfpa.as_bytes();
-                let cfpa_bytes: &[u8; BLOCK_SIZE_BYTES] =
-                    cfpa_bytes.try_into().unwrap_lite();
-
-                // Erase and program the scratch page. Note that because the
-                // scratch page is _not_ the authoritative copy, and because the
-                // ROM will check its contents before making it authoritative,
-                // we can fail during this operation without corrupting anything
-                // permanent. Yay!
-                //
-                // Note that the page write machinery uses page numbers. This
-                // should probably change. But, for now, we must divide our word
-                // number by 32.
-                do_raw_page_write(&mut self.flash, 0x9DE0 / 32, &cfpa_bytes)?;
-            }
+    fn raise_epoch_floor(
+        &mut self,
+        _: &RecvMessage,
+        token: LenLimit<Leased<R, [u8]>, 72>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        // Tokens are signed with the same key as SP images, so without one
+        // there's no way to authorize a raise.
+        let key = IMAGE_SIGNING_KEY.ok_or(UpdateError::NotImplemented)?;
+        let mut buf = [0; core::mem::size_of::<EpochFloorToken>()];
+        let buf = buf.get_mut(..token.len()).ok_or(UpdateError::BadLength)?;
+        token
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        let epoch = drv_update_api::epoch_floor::check_token(&key, buf)?;
+
+        // Only epochs that we've been allowed to boot can be made the floor,
+        // so that this can't lock out every image that's been released.
+        if epoch > HUBRIS_BUILD_EPOCH {
+            return Err(UpdateError::EpochTooNew.into());
         }
 
+        let mut cfpa = self.read_cfpa()?;
+        let (word, index) = EPOCH_FLOOR_CFPA_WORD;
+        if cfpa[word][index] >= epoch {
+            return Ok(());
+        }
+        cfpa[word][index] = epoch;
+        self.write_cfpa(cfpa)?;
         Ok(())
     }
 
+    // We leave update journals to the SP.
+    fn read_update_journal(
+        &mut self,
+        _: &RecvMessage,
+        _back: u32,
+        _data: LenLimit<Leased<W, [u8]>, JOURNAL_RECORD_BYTES>,
+    ) -> Result<bool, RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
+    fn write_update_journal(
+        &mut self,
+        _: &RecvMessage,
+        _data: LenLimit<Leased<R, [u8]>, JOURNAL_RECORD_BYTES>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
+    #[cfg(feature = "compression")]
+    fn write_compressed(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: LenLimit<Leased<R, [u8]>, MAX_LEASE>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        match self.state {
+            UpdateState::NoUpdate => {
+                return Err(UpdateError::UpdateNotStarted.into())
+            }
+            UpdateState::Finished => {
+                return Err(UpdateError::UpdateAlreadyFinished.into())
+            }
+            UpdateState::InProgress => (),
+        }
+
+        // We take the inflater out of `self` so that we can write blocks
+        // while it's in use, and put it back when we're done -- unless we
+        // failed partway through the chunk, and it's lost its place in the
+        // image, in which case the image has to be sent again from the start.
+        let mut inflater = self.inflater.take().unwrap_or_default();
+        let result = self.inflate(&mut inflater, offset, &data);
+        if !inflater.is_unfinished() {
+            self.inflater = Some(inflater);
+        }
+        result
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn write_compressed(
+        &mut self,
+        _: &RecvMessage,
+        _offset: u32,
+        _data: LenLimit<Leased<R, [u8]>, MAX_LEASE>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
     /// Reset.
     fn reset(
         &mut self,
@@ -450,10 +708,12 @@ fn indirect_flash_read(
     Ok(())
 }
 
-// Perform some sanity checking on the header block.
+// Perform some sanity checking on the header block, including that the image
+// isn't from an epoch older than `epoch_floor`.
 fn validate_header_block(
     target: UpdateTarget,
     block: &[u8; BLOCK_SIZE_BYTES],
+    epoch_floor: u32,
 ) -> Result<(), UpdateError> {
     // TODO: Do some actual checks for stage0. This will likely change
     // with Cliff's bootloader.
@@ -496,6 +756,12 @@ fn validate_header_block(
         return Err(UpdateError::InvalidHeaderBlock);
     }
 
+    let header = abi::ImageHeader::read_from_prefix(&block[MAGIC_OFFSET..])
+        .ok_or(UpdateError::InvalidHeaderBlock)?;
+    if header.epoch < epoch_floor {
+        return Err(UpdateError::EpochTooOld);
+    }
+
     Ok(())
 }
 
@@ -662,6 +928,8 @@ fn main() -> ! {
         header_block: None,
         state: UpdateState::NoUpdate,
         image: None,
+        #[cfg(feature = "compression")]
+        inflater: None,
 
         flash: drv_lpc55_flash::Flash::new(unsafe {
             &*lpc55_pac::FLASH::ptr()
@@ -9,7 +9,9 @@
 use drv_local_vpd::LocalVpdError;
 use oxide_barcode::ParseError as BarcodeParseError;
 use ringbuf::{ringbuf, ringbuf_entry};
-use task_packrat_api::{CacheSetError, MacAddressBlock, VpdIdentity};
+use task_packrat_api::{
+    CacheSetError, Ipv4Config, MacAddressBlock, VpdIdentity,
+};
 use userlib::{hl, TaskId};
 
 pub use task_packrat_api::Packrat;
@@ -20,8 +22,10 @@ enum Trace {
     MacLocalVpdError(LocalVpdError),
     BarcodeLocalVpdError(LocalVpdError),
     BarcodeParseError(BarcodeParseError),
+    Ipv4LocalVpdError(LocalVpdError),
     MacsAlreadySet(MacAddressBlock),
     IdentityAlreadySet(VpdIdentity),
+    Ipv4ConfigAlreadySet(Ipv4Config),
 }
 
 ringbuf!(Trace, 16, Trace::None);
@@ -34,6 +38,7 @@ pub fn read_vpd_and_load_packrat(packrat: &Packrat, i2c_task: TaskId) {
 
     let mut read_macs = false;
     let mut read_identity = false;
+    let mut read_ipv4 = false;
 
     for _ in 0..MAX_ATTEMPTS {
         if !read_macs {
@@ -73,7 +78,28 @@ pub fn read_vpd_and_load_packrat(packrat: &Packrat, i2c_task: TaskId) {
             }
         }
 
-        if read_macs && read_identity {
+        if !read_ipv4 {
+            match drv_local_vpd::read_config(i2c_task, *b"IPV4") {
+                Ok(config) => {
+                    match packrat.set_ipv4_config(config) {
+                        Ok(()) => (),
+                        Err(CacheSetError::ValueAlreadySet) => {
+                            ringbuf_entry!(Trace::Ipv4ConfigAlreadySet(config));
+                        }
+                    }
+                    read_ipv4 = true;
+                }
+                // Most boards don't have a static IPv4 address, which is fine.
+                Err(LocalVpdError::NoSuchChunk) => {
+                    read_ipv4 = true;
+                }
+                Err(err) => {
+                    ringbuf_entry!(Trace::Ipv4LocalVpdError(err));
+                }
+            }
+        }
+
+        if read_macs && read_identity && read_ipv4 {
             break;
         }
 
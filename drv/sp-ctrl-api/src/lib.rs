@@ -7,9 +7,26 @@
 #![no_std]
 
 use derive_idol_err::IdolError;
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
 use userlib::*;
 
-#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+/// Size of the SHA3-256 digest that an SP image is checked against during
+/// recovery.
+pub const SP_IMAGE_DIGEST_SIZE: usize = 32;
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    IdolError,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
 #[repr(u32)]
 pub enum SpCtrlError {
     BadLen = 1,
@@ -17,9 +34,65 @@ pub enum SpCtrlError {
     Fault,
     InvalidCoreRegister,
     DongleDetected,
+    /// The SP is being restored from its staged image, and can't be touched
+    /// until that's done.
+    RecoveryInProgress,
+    /// A chunk of a staged image wasn't the next one.
+    BadOffset,
+    /// The image doesn't fit in the staging area.
+    ImageTooBig,
+    /// The SP's flash controller reported an error.
+    FlashError,
+    /// The image's SHA3-256 wasn't the expected one.
+    DigestMismatch,
+    /// The staging area is still being erased: poll `recovery_status` until
+    /// it's `Staging`.
+    NotReady,
+    /// The staged image doesn't start with a valid image header.
+    BadImage,
+    /// The staged image isn't one that `sp_measure` allows the SP to run.
+    NotAllowed,
+    /// The staged image's epoch is below the SP's epoch floor.
+    EpochTooOld,
 
     #[idol(server_death)]
     ServerRestarted,
 }
 
+/// Where SP recovery is up to.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum SpRecoveryState {
+    Idle,
+    /// The staging area is being erased, before an image can be written to
+    /// it.
+    ErasingStaging,
+    /// An image is being written to the staging area.
+    Staging,
+    /// The staged image is being checked against the expected digest, and
+    /// against what the SP is allowed to run.
+    VerifyingStaged,
+    /// The SP is halted, and its boot bank is being erased.
+    Erasing,
+    /// The staged image is being copied into the boot bank.
+    Copying,
+    /// The boot bank is being checked against the expected digest.
+    Verifying,
+    /// The SP has been restored and reset.
+    Done,
+    Failed(SpCtrlError),
+}
+
+/// Progress of SP recovery: `done` out of `total` bytes (or, for
+/// `ErasingStaging` and `Erasing`, sectors) of the current state.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct SpRecoveryStatus {
+    pub state: SpRecoveryState,
+    pub done: u32,
+    pub total: u32,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -4,7 +4,9 @@
 
 //! Errors for the sprot API
 
+use attest_api::AttestError;
 use derive_more::From;
+use drv_sp_ctrl_api::SpCtrlError;
 use drv_spi_api::SpiError;
 use drv_update_api::UpdateError;
 use dumper_api::DumperError;
@@ -184,3 +186,43 @@ impl From<DumpOrSprotError> for Result<(), RequestError<DumpOrSprotError>> {
         Err(RequestError::Runtime(err.into()))
     }
 }
+
+impl From<SprotError> for RequestError<AttestOrSprotError> {
+    fn from(err: SprotError) -> Self {
+        AttestOrSprotError::from(err).into()
+    }
+}
+
+#[derive(
+    Copy, Clone, Debug, From, Deserialize, Serialize, SerializedSize, PartialEq,
+)]
+pub enum AttestOrSprotError {
+    Attest(AttestError),
+    Sprot(SprotError),
+}
+
+impl From<idol_runtime::ServerDeath> for AttestOrSprotError {
+    fn from(err: idol_runtime::ServerDeath) -> Self {
+        SprotError::from(err).into()
+    }
+}
+
+impl From<SprotError> for RequestError<SpRecoveryOrSprotError> {
+    fn from(err: SprotError) -> Self {
+        SpRecoveryOrSprotError::from(err).into()
+    }
+}
+
+#[derive(
+    Copy, Clone, Debug, From, Deserialize, Serialize, SerializedSize, PartialEq,
+)]
+pub enum SpRecoveryOrSprotError {
+    SpCtrl(SpCtrlError),
+    Sprot(SprotError),
+}
+
+impl From<idol_runtime::ServerDeath> for SpRecoveryOrSprotError {
+    fn from(err: idol_runtime::ServerDeath) -> Self {
+        SprotError::from(err).into()
+    }
+}
@@ -9,9 +9,17 @@
 extern crate memoffset;
 
 mod error;
+pub use attest_api::{
+    AttestError, CertChain, HostMeasurements, Quote, SpMeasureStatus,
+    SpMismatchAction, DIGEST_SIZE, NONCE_SIZE,
+};
+pub use drv_sp_ctrl_api::{
+    SpCtrlError, SpRecoveryState, SpRecoveryStatus, SP_IMAGE_DIGEST_SIZE,
+};
 use dumper_api::DumperError;
 pub use error::{
-    DumpOrSprotError, SprocketsError, SprotError, SprotProtocolError,
+    AttestOrSprotError, DumpOrSprotError, SpRecoveryOrSprotError,
+    SprocketsError, SprotError, SprotProtocolError,
 };
 
 use crc::{Crc, CRC_16_XMODEM};
@@ -62,6 +70,9 @@ const_assert!(
         >= Header::MAX_SIZE + RspBody::MAX_SIZE + MAX_BLOB_SIZE + CRC_SIZE + 1
 );
 
+// Quotes are returned as a blob.
+const_assert!(Quote::MAX_SIZE <= MAX_BLOB_SIZE);
+
 // For simplicity we want to be able to retrieve the header
 // in a maximum of 1 FIFO size read.
 const_assert!(Header::MAX_SIZE <= ROT_FIFO_SIZE);
@@ -245,6 +256,31 @@ where
         Ok(size)
     }
 
+    /// Serialize a `Header` followed by a `ReqBody` or `RspBody` and a blob
+    /// that's already in our own memory, compute a CRC, serialize the CRC, and
+    /// return the total size of the serialized message.
+    pub fn pack_with_slice(body: &T, buf: &mut [u8; N], blob: &[u8]) -> usize {
+        // Serialize `body`
+        let mut size = hubpack::serialize(&mut buf[Header::MAX_SIZE..], body)
+            .unwrap_lite();
+
+        // Copy the blob into the buffer after the serialized body
+        buf[Header::MAX_SIZE + size..][..blob.len()].copy_from_slice(blob);
+        size += blob.len();
+
+        // Create a header, now that we know the size of the body
+        let header = Header::new(size.try_into().unwrap_lite());
+
+        // Serialize the header
+        size += hubpack::serialize(buf, &header).unwrap_lite();
+
+        // Compute and serialize the CRC
+        let crc = CRC16.checksum(&buf[..size]);
+        size += hubpack::serialize(&mut buf[size..], &crc).unwrap_lite();
+
+        size
+    }
+
     // Deserialize and return a `Msg`
     pub fn unpack(buf: &'a [u8]) -> Result<Msg<'a, T, N>, SprotProtocolError> {
         let (header, rest) = hubpack::deserialize::<Header>(buf)?;
@@ -308,6 +344,60 @@ pub enum ReqBody {
     Update(UpdateReq),
     Sprockets(SprocketsReq),
     Dump(DumpReq),
+    Attest { nonce: [u8; NONCE_SIZE] },
+    HostMeasurements(HostMeasurementsReq),
+    Certs(CertReq),
+    SpMeasureStatus,
+    SpRecovery(SpRecoveryReq),
+}
+
+/// A request for the RoT's host measurement register
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum HostMeasurementsReq {
+    /// Extend the register with a measurement. This isn't idempotent, so it
+    /// mustn't be retried.
+    Extend {
+        digest: [u8; DIGEST_SIZE],
+    },
+    Read,
+}
+
+/// A request for one of the RoT's cert chains, which is read a chunk at a
+/// time
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum CertReq {
+    ChainLen {
+        chain: CertChain,
+    },
+    Len {
+        chain: CertChain,
+        index: u32,
+    },
+    Read {
+        chain: CertChain,
+        index: u32,
+        offset: u32,
+    },
+}
+
+/// A request to recover the SP by reflashing it via SWD
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum SpRecoveryReq {
+    /// Start staging an image of `size` bytes.
+    Prep {
+        size: u32,
+    },
+    /// Followed by the chunk of the image starting at `offset` as a blob.
+    /// This isn't idempotent, so it mustn't be retried.
+    Write {
+        offset: u32,
+    },
+    /// Restore the SP from the staged image.
+    Restore {
+        size: u32,
+        digest: [u8; SP_IMAGE_DIGEST_SIZE],
+    },
+    Status,
 }
 
 /// Instruct the RoT to take a dump of the SP via SWD
@@ -333,12 +423,22 @@ pub enum UpdateReq {
     Finish,
     Abort,
     Reset,
+    /// Followed by an `abi::EpochFloorToken` as a blob.
+    RaiseEpochFloor,
+    Resume {
+        target: UpdateTarget,
+        block_num: u32,
+    },
+    WriteCompressed {
+        offset: u32,
+    },
 }
 
 /// A response used for RoT updates
 #[derive(Clone, Serialize, Deserialize, SerializedSize)]
 pub enum UpdateRsp {
     BlockSize(u32),
+    ResumeFrom(u32),
 }
 
 /// The body of a sprot response.
@@ -362,6 +462,13 @@ pub enum RspBody {
     Update(UpdateRsp),
     Sprockets(SprocketsRsp),
     Dump(DumpRsp),
+    // On success, followed by the hubpack-encoded `Quote` as a blob
+    Attestation(AttestRsp),
+    HostMeasurements(HostMeasurementsRsp),
+    // For `CertReq::Read`, followed by the chunk of the cert as a blob
+    Certs(CertRsp),
+    SpMeasureStatus(SpMeasureStatus),
+    SpRecovery(SpRecoveryRsp),
 }
 
 /// A response from the Dumper
@@ -372,6 +479,46 @@ pub enum DumpRsp {
     V1 { err: Option<DumperError> },
 }
 
+/// A response from the attestation task
+//
+// Separate this into its own enum to allow better extensibility
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum AttestRsp {
+    V1 { err: Option<AttestError> },
+}
+
+/// The state of the host measurement register, after any extend
+//
+// Separate this into its own enum to allow better extensibility
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum HostMeasurementsRsp {
+    V1 {
+        measurements: HostMeasurements,
+        err: Option<AttestError>,
+    },
+}
+
+/// A response to a `CertReq`. `len` is the chain length, the cert length, or
+/// the length of the chunk that was read, depending on the request.
+//
+// Separate this into its own enum to allow better extensibility
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum CertRsp {
+    V1 { len: u32, err: Option<AttestError> },
+}
+
+/// A response to a `SpRecoveryReq`, with the state of recovery after the
+/// request
+//
+// Separate this into its own enum to allow better extensibility
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum SpRecoveryRsp {
+    V1 {
+        status: SpRecoveryStatus,
+        err: Option<SpCtrlError>,
+    },
+}
+
 /// The successful result of pulsing the active low chip-select line
 #[derive(Copy, Clone, Serialize, Deserialize, SerializedSize)]
 pub struct PulseStatus {
@@ -264,6 +264,12 @@ impl Ethernet {
         self.tx_ring.len()
     }
 
+    /// Returns the number of frames that the MAC has dropped because of CRC
+    /// errors, from its management counters. This wraps around on overflow.
+    pub fn rx_crc_errors(&self) -> u32 {
+        self.mac.rx_crc_error_packets.read().rxcrcerr().bits()
+    }
+
     // This function is identical in the VLAN and non-VLAN cases, so it lives
     // in the main impl block
     pub fn can_send(&self) -> bool {
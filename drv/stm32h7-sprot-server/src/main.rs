@@ -92,6 +92,24 @@ const MAX_UPDATE_ATTEMPTS: u16 = 3;
 // On the flipside, we have learned via unintended experiment that 5ms is too short!
 const DUMP_TIMEOUT: u32 = 1000;
 
+// Time to wait for a quote. The RoT signs it with ed25519 before replying,
+// which takes a while compared to most requests.
+const TIMEOUT_ATTEST: u32 = 100;
+
+// Time to wait for the RoT to decompress a chunk of a compressed image and
+// write the blocks that it fills. Each run in the chunk takes 3 bytes and can
+// stand for up to 256 bytes of image, so a full chunk can fill up to
+// `MAX_BLOB_SIZE / 3 * 256` bytes of blocks (one block being `MAX_BLOB_SIZE`),
+// plus the one it started partway through and the one it finishes in. Each
+// gets as long as a single `write_one_block`.
+const TIMEOUT_WRITE_COMPRESSED: u32 = TIMEOUT_WRITE_ONE_BLOCK
+    * ((MAX_BLOB_SIZE / 3 * 256 / MAX_BLOB_SIZE) as u32 + 2);
+
+// Time to wait for the RoT to write a chunk of an SP recovery image into our
+// flash over SWD. The sectors were erased by `sp_recovery_prep`, but each
+// flash word still takes a dozen or so SWD transactions.
+const TIMEOUT_SP_RECOVERY_WRITE: u32 = 100;
+
 // ROT_IRQ comes from app.toml
 // We use spi3 on gimletlet and spi4 on gemini and gimlet.
 // You should be able to move the RoT board between SPI3, SPI4, and SPI6
@@ -460,6 +478,94 @@ impl<S: SpiServer> ServerImpl<S> {
             hl::sleep_for(RETRY_TIMEOUT);
         }
     }
+
+    fn host_measurements_request(
+        &mut self,
+        body: ReqBody,
+        attempts: u16,
+    ) -> Result<HostMeasurements, idol_runtime::RequestError<AttestOrSprotError>>
+    {
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp =
+            self.do_send_recv_retries(tx_size, TIMEOUT_QUICK, attempts)?;
+        if let RspBody::HostMeasurements(HostMeasurementsRsp::V1 {
+            measurements,
+            err,
+        }) = rsp.body?
+        {
+            match err {
+                Some(e) => Err(AttestOrSprotError::Attest(e).into()),
+                None => Ok(measurements),
+            }
+        } else {
+            Err(SprotError::Protocol(SprotProtocolError::UnexpectedResponse))?
+        }
+    }
+
+    /// Sends a `CertReq`, returning the `len` from the response. For
+    /// `CertReq::Read`, the chunk that was read is copied into `dest`.
+    fn cert_request(
+        &mut self,
+        req: CertReq,
+        dest: Option<&idol_runtime::Leased<idol_runtime::W, [u8]>>,
+    ) -> Result<u32, idol_runtime::RequestError<AttestOrSprotError>> {
+        let body = ReqBody::Certs(req);
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        if let RspBody::Certs(CertRsp::V1 { len, err }) = rsp.body? {
+            if let Some(e) = err {
+                return Err(AttestOrSprotError::Attest(e).into());
+            }
+            if let Some(dest) = dest {
+                let n = rsp.blob.len().min(dest.len());
+                dest.write_range(0..n, &rsp.blob[..n])
+                    .map_err(|_| idol_runtime::RequestError::went_away())?;
+                return Ok(n as u32);
+            }
+            Ok(len)
+        } else {
+            Err(SprotError::Protocol(SprotProtocolError::UnexpectedResponse))?
+        }
+    }
+
+    /// Sends a `SpRecoveryReq`, with `data` as its blob if there is one,
+    /// returning the state of recovery afterwards.
+    fn sp_recovery_request(
+        &mut self,
+        req: SpRecoveryReq,
+        data: Option<
+            idol_runtime::LenLimit<
+                idol_runtime::Leased<idol_runtime::R, [u8]>,
+                MAX_BLOB_SIZE,
+            >,
+        >,
+        timeout: u32,
+    ) -> Result<SpRecoveryStatus, RequestError<SpRecoveryOrSprotError>> {
+        let body = ReqBody::SpRecovery(req);
+        let tx_size = match data {
+            Some(data) => {
+                Request::pack_with_blob(&body, &mut self.tx_buf, data)
+                    .map_err(SprotError::from)?
+            }
+            None => Request::pack(&body, &mut self.tx_buf),
+        };
+        let rsp =
+            self.do_send_recv_retries(tx_size, timeout, DEFAULT_ATTEMPTS)?;
+        if let RspBody::SpRecovery(SpRecoveryRsp::V1 { status, err }) =
+            rsp.body?
+        {
+            match err {
+                Some(e) => Err(SpRecoveryOrSprotError::SpCtrl(e).into()),
+                None => Ok(status),
+            }
+        } else {
+            Err(SprotError::Protocol(SprotProtocolError::UnexpectedResponse))?
+        }
+    }
 }
 
 impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
@@ -672,6 +778,81 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
         }
     }
 
+    fn raise_epoch_floor(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        token: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            72,
+        >,
+    ) -> Result<(), idol_runtime::RequestError<SprotError>> {
+        let mut buf = [0; 72];
+        let buf = &mut buf[..token.len()];
+        token
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| SprotProtocolError::TaskRestarted)?;
+        let body = ReqBody::Update(UpdateReq::RaiseEpochFloor);
+        let tx_size = Request::pack_with_slice(&body, &mut self.tx_buf, buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        if let RspBody::Ok = rsp.body? {
+            Ok(())
+        } else {
+            Err(SprotProtocolError::UnexpectedResponse)?
+        }
+    }
+
+    fn resume_image_update(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        target: UpdateTarget,
+        block_num: u32,
+    ) -> Result<u32, idol_runtime::RequestError<SprotError>> {
+        let body = ReqBody::Update(UpdateReq::Resume { target, block_num });
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        if let RspBody::Update(UpdateRsp::ResumeFrom(block_num)) = rsp.body? {
+            Ok(block_num)
+        } else {
+            Err(SprotProtocolError::UnexpectedResponse)?
+        }
+    }
+
+    /// Write a chunk of a compressed image to the update server
+    fn write_compressed(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        offset: u32,
+        data: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<(), idol_runtime::RequestError<SprotError>> {
+        let body = ReqBody::Update(UpdateReq::WriteCompressed { offset });
+        let tx_size = Request::pack_with_blob(&body, &mut self.tx_buf, data)?;
+
+        // The update server ignores a repeat of the last chunk, so retrying
+        // is safe even if only our reply was lost.
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_WRITE_COMPRESSED,
+            MAX_UPDATE_ATTEMPTS,
+        )?;
+
+        if let RspBody::Ok = rsp.body? {
+            Ok(())
+        } else {
+            Err(SprotProtocolError::UnexpectedResponse)?
+        }
+    }
+
     /// Reset the RoT
     fn reset(
         &mut self,
@@ -702,12 +883,168 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
             Err(SprotError::Protocol(SprotProtocolError::UnexpectedResponse))?
         }
     }
+
+    fn attest(
+        &mut self,
+        _: &userlib::RecvMessage,
+        nonce: [u8; NONCE_SIZE],
+        quote: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, idol_runtime::RequestError<AttestOrSprotError>> {
+        let body = ReqBody::Attest { nonce };
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_ATTEST,
+            DEFAULT_ATTEMPTS,
+        )?;
+        if let RspBody::Attestation(AttestRsp::V1 { err }) = rsp.body? {
+            if let Some(e) = err {
+                return Err(AttestOrSprotError::Attest(e).into());
+            }
+            if quote.len() < rsp.blob.len() {
+                return Err(AttestOrSprotError::Attest(
+                    AttestError::QuoteBufferTooSmall,
+                )
+                .into());
+            }
+            quote
+                .write_range(0..rsp.blob.len(), rsp.blob)
+                .map_err(|_| idol_runtime::RequestError::went_away())?;
+            Ok(rsp.blob.len() as u32)
+        } else {
+            Err(SprotError::Protocol(SprotProtocolError::UnexpectedResponse))?
+        }
+    }
+
+    fn extend_host_measurements(
+        &mut self,
+        _: &userlib::RecvMessage,
+        digest: [u8; DIGEST_SIZE],
+    ) -> Result<HostMeasurements, idol_runtime::RequestError<AttestOrSprotError>>
+    {
+        // Extending the register twice isn't the same as extending it once,
+        // so this can't be retried.
+        let body =
+            ReqBody::HostMeasurements(HostMeasurementsReq::Extend { digest });
+        self.host_measurements_request(body, 1)
+    }
+
+    fn read_host_measurements(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<HostMeasurements, idol_runtime::RequestError<AttestOrSprotError>>
+    {
+        let body = ReqBody::HostMeasurements(HostMeasurementsReq::Read);
+        self.host_measurements_request(body, DEFAULT_ATTEMPTS)
+    }
+
+    fn cert_chain_len(
+        &mut self,
+        _: &userlib::RecvMessage,
+        chain: CertChain,
+    ) -> Result<u32, idol_runtime::RequestError<AttestOrSprotError>> {
+        self.cert_request(CertReq::ChainLen { chain }, None)
+    }
+
+    fn cert_len(
+        &mut self,
+        _: &userlib::RecvMessage,
+        chain: CertChain,
+        index: u32,
+    ) -> Result<u32, idol_runtime::RequestError<AttestOrSprotError>> {
+        self.cert_request(CertReq::Len { chain, index }, None)
+    }
+
+    fn cert(
+        &mut self,
+        _: &userlib::RecvMessage,
+        chain: CertChain,
+        index: u32,
+        offset: u32,
+        dest: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::W, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<u32, idol_runtime::RequestError<AttestOrSprotError>> {
+        self.cert_request(
+            CertReq::Read {
+                chain,
+                index,
+                offset,
+            },
+            Some(&*dest),
+        )
+    }
+
+    fn sp_measure_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<SpMeasureStatus, RequestError<SprotError>> {
+        let tx_size =
+            Request::pack(&ReqBody::SpMeasureStatus, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        if let RspBody::SpMeasureStatus(status) = rsp.body? {
+            Ok(status)
+        } else {
+            Err(SprotProtocolError::UnexpectedResponse)?
+        }
+    }
+
+    fn sp_recovery_prep(
+        &mut self,
+        _: &RecvMessage,
+        size: u32,
+    ) -> Result<(), RequestError<SpRecoveryOrSprotError>> {
+        let req = SpRecoveryReq::Prep { size };
+        self.sp_recovery_request(req, None, TIMEOUT_WRITE_ONE_BLOCK)?;
+        Ok(())
+    }
+
+    fn sp_recovery_write(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<(), RequestError<SpRecoveryOrSprotError>> {
+        // The RoT ignores a repeat of the last chunk, so retrying is safe
+        // even if only our reply was lost.
+        let req = SpRecoveryReq::Write { offset };
+        self.sp_recovery_request(req, Some(data), TIMEOUT_SP_RECOVERY_WRITE)?;
+        Ok(())
+    }
+
+    fn sp_recovery_restore(
+        &mut self,
+        _: &RecvMessage,
+        size: u32,
+        digest: [u8; SP_IMAGE_DIGEST_SIZE],
+    ) -> Result<(), RequestError<SpRecoveryOrSprotError>> {
+        let req = SpRecoveryReq::Restore { size, digest };
+        self.sp_recovery_request(req, None, TIMEOUT_WRITE_ONE_BLOCK)?;
+        Ok(())
+    }
+
+    fn sp_recovery_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<SpRecoveryStatus, RequestError<SpRecoveryOrSprotError>> {
+        self.sp_recovery_request(SpRecoveryReq::Status, None, TIMEOUT_QUICK)
+    }
 }
 
 mod idl {
     use super::{
-        DumpOrSprotError, PulseStatus, RotState, SlotId, SprotError,
-        SprotIoStats, SprotStatus, SwitchDuration, UpdateTarget,
+        AttestOrSprotError, DumpOrSprotError, HostMeasurements, PulseStatus,
+        RotState, SlotId, SpMeasureStatus, SpRecoveryOrSprotError,
+        SpRecoveryStatus, SprotError, SprotIoStats, SprotStatus,
+        SwitchDuration, UpdateTarget,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::Write;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // An image we fall back to must be signed with the same key as the
+    // update server checks updates against.
+    if build_util::has_feature("verify-signature") {
+        let out = build_util::out_dir();
+        let mut file = File::create(out.join("consts.rs"))?;
+        match build_util::env_var("HUBRIS_IMAGE_SIGNING_KEY") {
+            Ok(key) => {
+                writeln!(file, "const IMAGE_SIGNING_KEY: [u8; 32] = {};", key)?
+            }
+            Err(e) => panic!(
+                "Could not find HUBRIS_IMAGE_SIGNING_KEY in environment. \
+                 The verify-signature feature requires an [image-signing] \
+                 section in the app.\n\
+                 {e:?}",
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Counts attempts to boot the image, and falls back to the image in the
+//! other flash bank once the current one has used them up. See the
+//! `boot-attempts` crate.
+
+use super::device;
+use abi::{ImageHeader, HEADER_MAGIC};
+use boot_attempts::BootRecord;
+
+/// The record lives at the start of backup SRAM, which keeps its contents
+/// across resets. Jefe finds it there through the `bkpsram` region.
+const RECORD: *mut BootRecord = 0x3880_0000 as *mut BootRecord;
+
+/// Whichever bank we booted from, the other one is mapped here.
+const OTHER_BANK: u32 = 0x0810_0000;
+const BANK_SIZE: u32 = 0x0010_0000;
+const SECTOR_SIZE: u32 = 0x0002_0000;
+
+/// Where the image header is, after the vector table.
+const HEADER_OFFSET: u32 = 0x298;
+
+/// How much of a bank an image can use: with `epoch-floor`, the last sector
+/// holds the update server's epoch floor log.
+const IMAGE_SPACE: u32 =
+    BANK_SIZE - SECTOR_SIZE * cfg!(feature = "epoch-floor") as u32;
+
+#[cfg(feature = "verify-signature")]
+include!(concat!(env!("OUT_DIR"), "/consts.rs"));
+
+// See RM0433 Rev 7 section 4.9.3
+const FLASH_OPT_KEY1: u32 = 0x0819_2a3b;
+const FLASH_OPT_KEY2: u32 = 0x4c5d_6e7f;
+
+pub(crate) fn count_boot(p: &device::Peripherals) {
+    // Backup SRAM is in the backup domain, which is write protected out of
+    // reset. We leave it writable so that Jefe can update the record.
+    p.RCC.ahb4enr.modify(|_, w| w.bkpramen().set_bit());
+    p.PWR.cr1.modify(|_, w| w.dbp().set_bit());
+    cortex_m::asm::dsb();
+
+    // Safety: backup SRAM is ours, and nothing else is running yet.
+    let mut record = unsafe { BootRecord::load(RECORD) };
+
+    // If there's nothing to fall back to, keep counting, so that Jefe can
+    // at least report how badly this image is doing.
+    if record.count_boot() && other_image_present() {
+        record.fall_back();
+        unsafe { record.store(RECORD) };
+        swap_banks(&p.FLASH);
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+    unsafe { record.store(RECORD) };
+}
+
+/// Checks that the other bank holds an image we can fall back to: it must
+/// start with something that looks like a vector table, and have a valid
+/// image header. With `verify-signature`, it must also have a valid
+/// signature, which is the only way to tell that all of it was written; with
+/// `epoch-floor`, its epoch can't be below the floor.
+fn other_image_present() -> bool {
+    let vectors = OTHER_BANK as *const u32;
+    // Safety: the other bank is always mapped, and reads of erased flash
+    // return all ones.
+    let (sp, reset) =
+        unsafe { (vectors.read_volatile(), vectors.add(1).read_volatile()) };
+    if !(0x2000_0000..0x4000_0000).contains(&sp)
+        || reset & 1 != 1
+        || !(0x0800_0000..OTHER_BANK).contains(&reset)
+    {
+        return false;
+    }
+
+    // Safety: the header is within the other bank.
+    let header: ImageHeader = unsafe {
+        core::ptr::read_volatile((OTHER_BANK + HEADER_OFFSET) as *const _)
+    };
+    if header.magic != HEADER_MAGIC
+        || header.total_image_len <= HEADER_OFFSET
+        || header.total_image_len > IMAGE_SPACE
+    {
+        return false;
+    }
+
+    #[cfg(feature = "verify-signature")]
+    if !signature_valid(header.total_image_len) {
+        return false;
+    }
+
+    #[cfg(feature = "epoch-floor")]
+    if header.epoch < epoch_floor() {
+        return false;
+    }
+
+    true
+}
+
+/// Checks that the image of `len` bytes in the other bank is followed by a
+/// valid signature from `IMAGE_SIGNING_KEY`. See `abi::ImageSignature` for
+/// the format, and `verify_signature` in `drv-stm32h7-update-server`.
+#[cfg(feature = "verify-signature")]
+fn signature_valid(len: u32) -> bool {
+    use abi::{ImageSignature, SIGNATURE_MAGIC};
+    use sha2::{Digest, Sha256};
+
+    let sig_offset = (len + 3) & !3;
+    if sig_offset + core::mem::size_of::<ImageSignature>() as u32 > IMAGE_SPACE
+    {
+        return false;
+    }
+    // Safety: the signature block and the image are both within the other
+    // bank, as checked above.
+    let (sig, image): (ImageSignature, &[u8]) = unsafe {
+        (
+            core::ptr::read_volatile((OTHER_BANK + sig_offset) as *const _),
+            core::slice::from_raw_parts(OTHER_BANK as *const u8, len as usize),
+        )
+    };
+    if sig.magic != SIGNATURE_MAGIC {
+        return false;
+    }
+
+    let digest: [u8; 32] = Sha256::digest(image).into();
+    if digest != sig.digest {
+        return false;
+    }
+    // The key is derived by xtask from a private key seed, so it should
+    // always decode; if not, no signature can match it.
+    match salty::PublicKey::try_from(&IMAGE_SIGNING_KEY) {
+        Ok(key) => key
+            .verify(&digest, &salty::Signature::from(&sig.signature))
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Returns the epoch floor: the highest epoch in the update server's logs, in
+/// the last sector of each bank. See `epoch_floor` in
+/// `drv-stm32h7-update-server`.
+#[cfg(feature = "epoch-floor")]
+fn epoch_floor() -> u32 {
+    let logs = [
+        0x0800_0000 + BANK_SIZE - SECTOR_SIZE,
+        OTHER_BANK + IMAGE_SPACE,
+    ];
+    let mut floor = 0;
+    for log in logs {
+        for addr in (log..log + SECTOR_SIZE).step_by(32) {
+            // Safety: the logs are within flash, which is always mapped.
+            let entry: [u32; 8] =
+                unsafe { core::ptr::read_volatile(addr as *const _) };
+            if entry == [!0; 8] {
+                // An erased entry, so that's the end of this log.
+                break;
+            }
+            if entry[1] == !entry[0] {
+                floor = floor.max(entry[0]);
+            }
+        }
+    }
+    floor
+}
+
+// This is the same sequence as the update server uses to switch to a new
+// image, except that we're privileged and nothing else is using the flash.
+fn swap_banks(flash: &device::FLASH) {
+    flash
+        .optkeyr()
+        .write(|w| unsafe { w.optkeyr().bits(FLASH_OPT_KEY1) });
+    flash
+        .optkeyr()
+        .write(|w| unsafe { w.optkeyr().bits(FLASH_OPT_KEY2) });
+
+    let swapped = flash.optsr_cur().read().swap_bank_opt().bit();
+    flash
+        .optsr_prg()
+        .modify(|_, w| w.swap_bank_opt().bit(!swapped));
+    flash.optcr().modify(|_, w| w.optstart().set_bit());
+    while flash.optsr_cur().read().opt_busy().bit() {
+        // spin
+    }
+}
@@ -4,6 +4,9 @@
 
 #![no_std]
 
+#[cfg(feature = "boot-attempts")]
+mod boot_attempts;
+
 use cortex_m_rt::pre_init;
 
 #[cfg(feature = "h743")]
@@ -319,6 +322,10 @@ pub fn system_init_custom(
 
     // Hello from target speed!
 
+    // This may not return, if it decides to reset into the other image.
+    #[cfg(feature = "boot-attempts")]
+    boot_attempts::count_boot(&p);
+
     // Hand the peripherals back in case the board-specific setup code needs to
     // do anything.
     p
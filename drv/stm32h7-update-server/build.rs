@@ -7,10 +7,12 @@ use std::io::Write;
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::build_notifications()?;
-    idol::server::build_server_support(
-        "../../idl/update.idol",
+    const IDL: &str = "../../idl/update.idol";
+    idol::server::build_restricted_server_support(
+        IDL,
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+        &build_util::task_allowed_callers(IDL)?,
     )?;
 
     let out = build_util::out_dir();
@@ -22,5 +24,25 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     writeln!(ver_file, "const HUBRIS_BUILD_VERSION: u32 = {};", version)?;
     writeln!(ver_file, "const HUBRIS_BUILD_EPOCH: u32 = {};", epoch)?;
 
+    // Signed images and epoch floor tokens are both checked against the
+    // image signing key.
+    if build_util::has_feature("verify-signature")
+        || build_util::has_feature("epoch-floor")
+    {
+        match build_util::env_var("HUBRIS_IMAGE_SIGNING_KEY") {
+            Ok(key) => writeln!(
+                ver_file,
+                "const IMAGE_SIGNING_KEY: [u8; 32] = {};",
+                key
+            )?,
+            Err(e) => panic!(
+                "Could not find HUBRIS_IMAGE_SIGNING_KEY in environment. \
+                 The verify-signature and epoch-floor features require an \
+                 [image-signing] section in the app.\n\
+                 {e:?}",
+            ),
+        }
+    }
+
     Ok(())
 }
@@ -10,23 +10,33 @@
 #![no_main]
 
 use core::convert::Infallible;
+use core::ops::Range;
 use drv_caboose::{CabooseError, CabooseReader};
+#[cfg(feature = "compression")]
+use drv_update_api::compressed::Inflater;
 use drv_update_api::stm32h7::{
     BLOCK_SIZE_BYTES, FLASH_WORDS_PER_BLOCK, FLASH_WORD_BYTES,
+    SECTOR_SIZE_BYTES,
 };
 use drv_update_api::{
     ImageVersion, SlotId, SwitchDuration, UpdateError, UpdateStatus,
-    UpdateTarget,
+    UpdateTarget, JOURNAL_RECORD_BYTES,
 };
-use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
 use ringbuf::*;
 use stm32h7::stm32h753 as device;
 use userlib::*;
 use zerocopy::AsBytes;
 
+#[cfg(feature = "verify-signature")]
+task_slot!(HASH, hash_driver);
+
 // Internally we deal with flash blocks in groups of u32 words.
 const FLASH_WORD_WORDS: usize = FLASH_WORD_BYTES / 4;
 
+// A block, as an array of flash words.
+type FlashPage = [[u32; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK];
+
 // Keys constants are defined in RM0433 Rev 7
 // Section 4.9.2
 const FLASH_KEY1: u32 = 0x4567_0123;
@@ -45,6 +55,31 @@ extern "C" {
     pub static mut __REGION_BANK2_END: [u32; 0];
 }
 
+#[cfg(feature = "epoch-floor")]
+extern "C" {
+    // The last sector of bank 1, which (like the last sector of bank 2) is
+    // kept out of the image to hold an epoch floor log. See `epoch_floor`.
+    //
+    // This requires adding `extern-regions = ["bank2", "epoch_log1"]` to the
+    // task config, and a memory map that defines `epoch_log1`, such as
+    // `memory-large-epoch-floor.toml`.
+    pub static mut __REGION_EPOCH_LOG1_BASE: [u32; 0];
+}
+
+// Sectors at the end of bank 2 can be kept out of the image for our own use:
+// with `epoch-floor`, the last sector holds the epoch floor log, and with
+// `update-journal`, the sector below that (or the last sector, without
+// `epoch-floor`) holds the update journal.
+const EPOCH_LOG_SECTORS: usize = cfg!(feature = "epoch-floor") as usize;
+const JOURNAL_SECTORS: usize = cfg!(feature = "update-journal") as usize;
+
+#[cfg(feature = "update-journal")]
+const JOURNAL_RECORD_WORDS: usize = JOURNAL_RECORD_BYTES / FLASH_WORD_BYTES;
+#[cfg(feature = "update-journal")]
+const JOURNAL_SLOTS: usize = SECTOR_SIZE_BYTES / JOURNAL_RECORD_BYTES;
+#[cfg(feature = "update-journal")]
+const _: () = assert!(JOURNAL_RECORD_BYTES % FLASH_WORD_BYTES == 0);
+
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
     EraseStart,
@@ -54,6 +89,15 @@ enum Trace {
     FinishStart,
     FinishEnd,
     WriteBlock(usize),
+    ResumeFrom(usize),
+    #[cfg(feature = "update-journal")]
+    JournalCompact,
+    #[cfg(feature = "epoch-floor")]
+    RaiseEpochFloor(u32),
+    #[cfg(feature = "verify-signature")]
+    VerifyStart,
+    #[cfg(feature = "verify-signature")]
+    VerifyEnd,
     None,
 }
 
@@ -68,6 +112,8 @@ ringbuf!(Trace, 64, Trace::None);
 struct ServerImpl<'a> {
     flash: &'a device::flash::RegisterBlock,
     state: UpdateState,
+    #[cfg(feature = "compression")]
+    inflater: Option<Inflater<BLOCK_SIZE_BYTES>>,
 }
 
 impl<'a> ServerImpl<'a> {
@@ -96,6 +142,86 @@ impl<'a> ServerImpl<'a> {
         Ok(())
     }
 
+    /// Checks that the image in bank 2 is followed by a valid signature from
+    /// `IMAGE_SIGNING_KEY`. See `abi::ImageSignature` for the format.
+    #[cfg(feature = "verify-signature")]
+    fn verify_signature(&self) -> Result<(), UpdateError> {
+        ringbuf_entry!(Trace::VerifyStart);
+
+        // SAFETY: populated by the linker, so this should be valid
+        let image_start = unsafe { __REGION_BANK2_BASE.as_ptr() } as usize;
+        let header =
+            bank2_image_header().ok_or(UpdateError::MissingHeaderBlock)?;
+
+        // The signature block follows the image, aligned to a word boundary,
+        // and both must fit in the part of bank 2 that holds images. The length
+        // comes from whoever wrote the image, so don't trust it to be sane.
+        let max_sig_offset = bank2_image_end()
+            .checked_sub(image_start)
+            .and_then(|n| n.checked_sub(core::mem::size_of::<ImageSignature>()))
+            .ok_or(UpdateError::InvalidHeaderBlock)?;
+        let image_len = header.total_image_len as usize;
+        let sig_offset = image_len
+            .checked_add(3)
+            .map(|n| n & !3)
+            .filter(|&n| n <= max_sig_offset)
+            .ok_or(UpdateError::InvalidHeaderBlock)?;
+        let sig_start = image_start + sig_offset;
+        // SAFETY: we know this is within the bank2 flash region, since it's
+        // checked above.
+        let sig: ImageSignature = unsafe {
+            core::ptr::read_volatile(sig_start as *const ImageSignature)
+        };
+        if sig.magic != SIGNATURE_MAGIC {
+            return Err(UpdateError::MissingSignature);
+        }
+
+        // SAFETY: this is a slice within the bank2 flash, since the signature
+        // block that follows it is.
+        let image: &[u8] = unsafe {
+            core::slice::from_raw_parts(image_start as *const u8, image_len)
+        };
+
+        // As in `read_image_caboose`, we can't lease the bank2 region to the
+        // hash driver directly, so it's copied through a buffer.
+        let hash = drv_hash_api::Hash::from(HASH.get_task_id());
+        hash.init_sha256().map_err(|_| UpdateError::HashError)?;
+        let mut buf = [0u8; 256];
+        for c in image.chunks(buf.len()) {
+            let buf = &mut buf[..c.len()];
+            buf.copy_from_slice(c);
+            hash.update(buf.len() as u32, buf)
+                .map_err(|_| UpdateError::HashError)?;
+        }
+        let digest =
+            hash.finalize_sha256().map_err(|_| UpdateError::HashError)?;
+        if digest != sig.digest {
+            return Err(UpdateError::ImageDigestMismatch);
+        }
+
+        // The key is derived by xtask from a private key seed, so it should
+        // always decode; if not, no signature can match it.
+        let key = salty::PublicKey::try_from(&IMAGE_SIGNING_KEY)
+            .map_err(|_| UpdateError::BadSignature)?;
+        let signature = salty::Signature::from(&sig.signature);
+        key.verify(&digest, &signature)
+            .map_err(|_| UpdateError::BadSignature)?;
+
+        ringbuf_entry!(Trace::VerifyEnd);
+        Ok(())
+    }
+
+    /// Checks that the image in bank 2 isn't from an epoch below the floor.
+    #[cfg(feature = "epoch-floor")]
+    fn check_epoch(&self) -> Result<(), UpdateError> {
+        let header =
+            bank2_image_header().ok_or(UpdateError::MissingHeaderBlock)?;
+        if header.epoch < epoch_floor() {
+            return Err(UpdateError::EpochTooOld);
+        }
+        Ok(())
+    }
+
     fn poll_flash_done(&mut self) -> Result<(), RequestError<UpdateError>> {
         // This method should implement step 5 of the Single Write Sequence from
         // RM0433 Rev 7 section 4.3.9, which states
@@ -160,6 +286,49 @@ impl<'a> ServerImpl<'a> {
 
     // RM0433 Rev 7 section 4.3.9
     // Following Single write sequence
+    fn write_block(
+        &mut self,
+        block_num: usize,
+        flash_page: &FlashPage,
+    ) -> Result<(), RequestError<UpdateError>> {
+        ringbuf_entry!(Trace::WriteBlock(block_num));
+        for (i, fw) in flash_page.iter().enumerate() {
+            self.write_word(block_num * FLASH_WORDS_PER_BLOCK + i, fw)?;
+        }
+        Ok(())
+    }
+
+    /// Decompresses the chunk of a compressed image in `data`, which starts
+    /// at `offset`, writing each block as it's filled.
+    #[cfg(feature = "compression")]
+    fn inflate(
+        &mut self,
+        inflater: &mut Inflater<BLOCK_SIZE_BYTES>,
+        offset: u32,
+        data: &Leased<R, [u8]>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        if !inflater.start_chunk(offset, data.len())? {
+            return Ok(());
+        }
+
+        // We read the lease in small pieces, since we need a block's worth
+        // of stack to write each block.
+        let mut buf = [0u8; 64];
+        for start in (0..data.len()).step_by(buf.len()) {
+            let n = (data.len() - start).min(buf.len());
+            let buf = &mut buf[..n];
+            data.read_range(start..start + n, buf)
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+            inflater.feed(buf, |block_num, block| {
+                let mut flash_page: FlashPage =
+                    [[0; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK];
+                flash_page.as_bytes_mut()[..block.len()].copy_from_slice(block);
+                self.write_block(block_num, &flash_page)
+            })?;
+        }
+        Ok(())
+    }
+
     fn write_word(
         &mut self,
         word_number: usize,
@@ -173,7 +342,7 @@ impl<'a> ServerImpl<'a> {
         //
         // SAFETY: these are symbols populated by the linker.
         let bank_addr = unsafe { __REGION_BANK2_BASE.as_ptr() } as usize;
-        let bank_end = unsafe { __REGION_BANK2_END.as_ptr() } as usize;
+        let bank_end = bank2_image_end();
         let bank_word_limit =
             (bank_end - bank_addr) as usize / FLASH_WORD_BYTES;
 
@@ -187,6 +356,17 @@ impl<'a> ServerImpl<'a> {
             return Err(UpdateError::BadLength.into());
         }
 
+        let b = self.program_word(start, words);
+        ringbuf_entry!(Trace::WriteEnd);
+        b
+    }
+
+    /// Programs the flash word at `start`, which must be within bank 2.
+    fn program_word(
+        &mut self,
+        start: usize,
+        words: &[u32; FLASH_WORD_WORDS],
+    ) -> Result<(), RequestError<UpdateError>> {
         let addresses = (start..start + FLASH_WORD_BYTES).step_by(4);
 
         self.flash.bank2().cr.write(|w| {
@@ -210,9 +390,7 @@ impl<'a> ServerImpl<'a> {
             }
         }
 
-        let b = self.poll_flash_done();
-        ringbuf_entry!(Trace::WriteEnd);
-        b
+        self.poll_flash_done()
     }
 
     // All sequences can be found in RM0433 Rev 7
@@ -241,6 +419,38 @@ impl<'a> ServerImpl<'a> {
     fn bank_erase(&mut self) -> Result<(), RequestError<UpdateError>> {
         ringbuf_entry!(Trace::EraseStart);
 
+        // With `epoch-floor`, the last sector of the bank holds the epoch floor
+        // log, so we erase the others one at a time rather than the whole
+        // bank. That includes the update journal, if there is one: it can
+        // only describe an update that this one is replacing.
+        let b = if EPOCH_LOG_SECTORS == 0 {
+            self.erase(None)
+        } else {
+            let sectors = bank2_len() / SECTOR_SIZE_BYTES - EPOCH_LOG_SECTORS;
+            self.erase_sectors(0..sectors)
+        };
+
+        ringbuf_entry!(Trace::EraseEnd);
+        b
+    }
+
+    /// Erases the sectors of bank 2 in `sectors`, one at a time.
+    fn erase_sectors(
+        &mut self,
+        sectors: Range<usize>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        for s in sectors {
+            self.erase(Some(s as u8))?;
+        }
+        Ok(())
+    }
+
+    /// Erases the given sector of bank 2, or the whole bank if `sector` is
+    /// `None`.
+    fn erase(
+        &mut self,
+        sector: Option<u8>,
+    ) -> Result<(), RequestError<UpdateError>> {
         // Enable relevant interrupts for completion (or failure) of erasing
         // bank2.
         sys_irq_control(notifications::FLASH_IRQ_MASK, true);
@@ -259,10 +469,23 @@ impl<'a> ServerImpl<'a> {
                 .set_bit()
         });
 
-        self.flash
-            .bank2()
-            .cr
-            .modify(|_, w| w.start().set_bit().ber().set_bit());
+        // Clear any end-of-operation flag left over from an earlier erase, so
+        // that we wait for this one.
+        self.flash.bank2().ccr.write(|w| w.clr_eop().set_bit());
+
+        match sector {
+            // SAFETY: `snb` is marked unsafe because it allows arbitrary bit
+            // patterns; sector numbers are checked by the hardware, which
+            // will set an error flag if they're out of range.
+            Some(s) => self.flash.bank2().cr.modify(|_, w| {
+                unsafe { w.snb().bits(s) }.ser().set_bit().start().set_bit()
+            }),
+            None => self
+                .flash
+                .bank2()
+                .cr
+                .modify(|_, w| w.start().set_bit().ber().set_bit()),
+        }
 
         // Wait for EOP notification via interrupt.
         loop {
@@ -279,10 +502,135 @@ impl<'a> ServerImpl<'a> {
             }
         }
 
-        let b = self.bank2_status();
-        ringbuf_entry!(Trace::EraseEnd);
-        b
+        self.bank2_status()
+    }
+}
+
+/// Returns the size of bank 2, in bytes.
+fn bank2_len() -> usize {
+    // SAFETY: populated by the linker, so these should be valid
+    let bank_addr = unsafe { __REGION_BANK2_BASE.as_ptr() } as usize;
+    let bank_end = unsafe { __REGION_BANK2_END.as_ptr() } as usize;
+    bank_end - bank_addr
+}
+
+/// Returns the end of the part of bank 2 that can hold an image. This excludes
+/// the sectors holding the epoch floor log and update journal, if we have
+/// them.
+fn bank2_image_end() -> usize {
+    // SAFETY: populated by the linker, so this should be valid
+    let bank_end = unsafe { __REGION_BANK2_END.as_ptr() } as usize;
+    bank_end - SECTOR_SIZE_BYTES * (EPOCH_LOG_SECTORS + JOURNAL_SECTORS)
+}
+
+/// Returns the number of sectors of bank 2 that can hold an image.
+fn bank2_image_sectors() -> usize {
+    bank2_len() / SECTOR_SIZE_BYTES - EPOCH_LOG_SECTORS - JOURNAL_SECTORS
+}
+
+/// Returns the start of the epoch floor log in bank 2, which is its last
+/// sector.
+#[cfg(feature = "epoch-floor")]
+fn bank2_epoch_log() -> usize {
+    // SAFETY: populated by the linker, so this should be valid
+    let bank_end = unsafe { __REGION_BANK2_END.as_ptr() } as usize;
+    bank_end - SECTOR_SIZE_BYTES
+}
+
+/// Reads the header of the image in bank 2, if there is one.
+#[cfg(any(feature = "verify-signature", feature = "epoch-floor"))]
+fn bank2_image_header() -> Option<ImageHeader> {
+    // SAFETY: populated by the linker, so this should be valid
+    let image_start = unsafe { __REGION_BANK2_BASE.as_ptr() } as usize;
+
+    // The header is at a fixed location at the end of the vector table; see
+    // `read_image_caboose`.
+    const HEADER_OFFSET: usize = 0x298;
+    // SAFETY: this is within the bank2 flash region.
+    let header: ImageHeader = unsafe {
+        core::ptr::read_volatile(
+            (image_start + HEADER_OFFSET) as *const ImageHeader,
+        )
+    };
+    if header.magic == HEADER_MAGIC {
+        Some(header)
+    } else {
+        None
+    }
+}
+
+// The anti-rollback epoch floor is kept in a log in the last sector of each
+// flash bank, which is appended to rather than rewritten, so that raising the
+// floor doesn't need a sector erase. Each entry is a flash word holding the
+// epoch and its complement, so that a partly-programmed entry can be ignored;
+// unused entries are erased, and so read as all ones.
+//
+// Since the banks swap on update, the log we can write to (in bank 2) isn't
+// always the same physical sector, so the floor is the highest epoch in
+// either log.
+
+/// Returns the lowest image epoch that we'll accept for update.
+#[cfg(feature = "epoch-floor")]
+fn epoch_floor() -> u32 {
+    // SAFETY: populated by the linker, so this should be valid
+    let log1 = unsafe { __REGION_EPOCH_LOG1_BASE.as_ptr() } as usize;
+    scan_epoch_log(log1)
+        .0
+        .max(scan_epoch_log(bank2_epoch_log()).0)
+}
+
+/// Scans the epoch floor log in the sector starting at `base`, returning the
+/// highest epoch recorded in it, and the address of the first unused entry if
+/// there is one.
+#[cfg(feature = "epoch-floor")]
+fn scan_epoch_log(base: usize) -> (u32, Option<usize>) {
+    let mut floor = 0;
+    for addr in (base..base + SECTOR_SIZE_BYTES).step_by(FLASH_WORD_BYTES) {
+        // SAFETY: the log sectors are within our extern regions.
+        let entry: [u32; FLASH_WORD_WORDS] = unsafe {
+            core::ptr::read_volatile(addr as *const [u32; FLASH_WORD_WORDS])
+        };
+        if entry == [!0; FLASH_WORD_WORDS] {
+            return (floor, Some(addr));
+        }
+        if entry[1] == !entry[0] {
+            floor = floor.max(entry[0]);
+        }
     }
+    (floor, None)
+}
+
+// The update journal is a store of fixed-size records on behalf of our
+// clients, which use it to keep track of updates in a way that survives a
+// reset. Like the epoch floor log, it's appended to, and erased only when it
+// fills up; only the most recent records matter. Unused slots are erased, and
+// so read as all ones.
+
+/// Returns the start of the update journal slot with index `slot`.
+#[cfg(feature = "update-journal")]
+fn journal_slot(slot: usize) -> usize {
+    bank2_image_end() + slot * JOURNAL_RECORD_BYTES
+}
+
+/// Reads the update journal slot with index `slot`.
+#[cfg(feature = "update-journal")]
+fn read_journal_slot(slot: usize) -> [u8; JOURNAL_RECORD_BYTES] {
+    // SAFETY: the journal sector is within the bank2 flash region.
+    unsafe {
+        core::ptr::read_volatile(
+            journal_slot(slot) as *const [u8; JOURNAL_RECORD_BYTES]
+        )
+    }
+}
+
+/// Returns the number of records in the update journal. A record that was
+/// only partly programmed still counts; it's up to the client to notice that
+/// it's garbled.
+#[cfg(feature = "update-journal")]
+fn journal_len() -> usize {
+    (0..JOURNAL_SLOTS)
+        .find(|&slot| read_journal_slot(slot).iter().all(|&b| b == 0xff))
+        .unwrap_or(JOURNAL_SLOTS)
 }
 
 impl idl::InOrderUpdateImpl for ServerImpl<'_> {
@@ -309,6 +657,10 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         self.unlock();
         self.bank_erase()?;
         self.state = UpdateState::InProgress;
+        #[cfg(feature = "compression")]
+        {
+            self.inflater = None;
+        }
         Ok(())
     }
 
@@ -330,6 +682,53 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         Ok(())
     }
 
+    fn resume_image_update(
+        &mut self,
+        _: &RecvMessage,
+        img_type: UpdateTarget,
+        block_num: usize,
+    ) -> Result<usize, RequestError<UpdateError>> {
+        match self.state {
+            // If only the client was restarted, we're still in the middle of
+            // the update that it's resuming; there's only one place that an
+            // update can go, so it must be the same one.
+            UpdateState::InProgress | UpdateState::NoUpdate => (),
+            UpdateState::Finished => {
+                return Err(UpdateError::UpdateAlreadyFinished.into())
+            }
+        }
+
+        match img_type {
+            UpdateTarget::Alternate => (),
+            _ => return Err(UpdateError::BadImageType.into()),
+        }
+
+        // The reset may have interrupted programming of any block after those
+        // the client knows were written, and a flash word can't be programmed
+        // again without erasing it. So, we erase everything from the start of
+        // the sector holding `block_num`, and have the client resume there.
+        let image_sectors = bank2_image_sectors();
+        let sector = block_num
+            .checked_mul(BLOCK_SIZE_BYTES)
+            .map(|offset| offset / SECTOR_SIZE_BYTES)
+            .filter(|&sector| sector < image_sectors)
+            .ok_or(UpdateError::OutOfBounds)?;
+        let block_num = sector * SECTOR_SIZE_BYTES / BLOCK_SIZE_BYTES;
+
+        ringbuf_entry!(Trace::ResumeFrom(block_num));
+        self.unlock();
+        ringbuf_entry!(Trace::EraseStart);
+        self.erase_sectors(sector..image_sectors)?;
+        ringbuf_entry!(Trace::EraseEnd);
+        self.state = UpdateState::InProgress;
+        #[cfg(feature = "compression")]
+        {
+            // We can't pick up decompression where we left off.
+            self.inflater = None;
+        }
+        Ok(block_num)
+    }
+
     fn write_one_block(
         &mut self,
         _: &RecvMessage,
@@ -353,7 +752,7 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         // hardware's perspective, it is actually an array of flash words,
         // grouped (by our arbitrary choice) into units of
         // FLASH_WORDS_PER_BLOCK.
-        let mut flash_page: [[u32; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK] =
+        let mut flash_page: FlashPage =
             [[0; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK];
 
         {
@@ -371,10 +770,7 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             }
         }
 
-        ringbuf_entry!(Trace::WriteBlock(block_num));
-        for (i, fw) in flash_page.iter().enumerate() {
-            self.write_word(block_num * FLASH_WORDS_PER_BLOCK + i, fw)?;
-        }
+        self.write_block(block_num, &flash_page)?;
 
         Ok(())
     }
@@ -393,6 +789,21 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             UpdateState::InProgress => (),
         }
 
+        // If we were sent a compressed image, make sure we got all of it.
+        #[cfg(feature = "compression")]
+        if let Some(inflater) = &self.inflater {
+            if !inflater.is_done() {
+                return Err(UpdateError::BadCompressedImage.into());
+            }
+        }
+
+        // If the image doesn't check out, the update stays in progress, so
+        // that it can be aborted or the image rewritten.
+        #[cfg(feature = "verify-signature")]
+        self.verify_signature()?;
+        #[cfg(feature = "epoch-floor")]
+        self.check_epoch()?;
+
         self.swap_banks()?;
         self.state = UpdateState::Finished;
         Ok(())
@@ -536,6 +947,163 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         Err(UpdateError::NotImplemented.into())
     }
 
+    #[cfg(feature = "epoch-floor")]
+    fn raise_epoch_floor(
+        &mut self,
+        _: &RecvMessage,
+        token: LenLimit<Leased<R, [u8]>, 72>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        let mut buf = [0; core::mem::size_of::<EpochFloorToken>()];
+        let buf = buf.get_mut(..token.len()).ok_or(UpdateError::BadLength)?;
+        token
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        let epoch =
+            drv_update_api::epoch_floor::check_token(&IMAGE_SIGNING_KEY, buf)?;
+
+        // Only epochs that we've been allowed to boot can be made the floor,
+        // so that this can't lock out every image that's been released.
+        if epoch > HUBRIS_BUILD_EPOCH {
+            return Err(UpdateError::EpochTooNew.into());
+        }
+        if epoch <= epoch_floor() {
+            return Ok(());
+        }
+
+        // The log would take thousands of raises to fill, but if it somehow
+        // does, it can only be reset by erasing flash with a debugger.
+        let addr = match scan_epoch_log(bank2_epoch_log()) {
+            (_, Some(addr)) => addr,
+            (_, None) => return Err(UpdateError::OutOfBounds.into()),
+        };
+
+        ringbuf_entry!(Trace::RaiseEpochFloor(epoch));
+        let mut entry = [0; FLASH_WORD_WORDS];
+        entry[0] = epoch;
+        entry[1] = !epoch;
+        self.unlock();
+        self.program_word(addr, &entry)
+    }
+
+    #[cfg(not(feature = "epoch-floor"))]
+    fn raise_epoch_floor(
+        &mut self,
+        _: &RecvMessage,
+        _token: LenLimit<Leased<R, [u8]>, 72>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
+    #[cfg(feature = "update-journal")]
+    fn read_update_journal(
+        &mut self,
+        _: &RecvMessage,
+        back: u32,
+        data: LenLimit<Leased<W, [u8]>, JOURNAL_RECORD_BYTES>,
+    ) -> Result<bool, RequestError<UpdateError>> {
+        let slot = match journal_len().checked_sub(1 + back as usize) {
+            Some(slot) => slot,
+            None => return Ok(false),
+        };
+
+        // As in `read_image_caboose`, we can't lease the bank2 region
+        // directly, so the record is copied out first.
+        let record = read_journal_slot(slot);
+        let len = data.len();
+        data.write_range(0..len, &record[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(true)
+    }
+
+    #[cfg(feature = "update-journal")]
+    fn write_update_journal(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<R, [u8]>, JOURNAL_RECORD_BYTES>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        if data.len() != JOURNAL_RECORD_BYTES {
+            return Err(UpdateError::BadLength.into());
+        }
+        let mut record = [[0u32; FLASH_WORD_WORDS]; JOURNAL_RECORD_WORDS];
+        data.read_range(0..JOURNAL_RECORD_BYTES, record.as_bytes_mut())
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.unlock();
+        let mut slot = journal_len();
+        if slot == JOURNAL_SLOTS {
+            // Start over. If we're reset before the new record is written, the
+            // journal is left empty, which only costs the client its progress.
+            ringbuf_entry!(Trace::JournalCompact);
+            // The journal is the first sector after the image.
+            self.erase(Some(bank2_image_sectors() as u8))?;
+            slot = 0;
+        }
+
+        let addr = journal_slot(slot);
+        for (i, words) in record.iter().enumerate() {
+            self.program_word(addr + i * FLASH_WORD_BYTES, words)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "update-journal"))]
+    fn read_update_journal(
+        &mut self,
+        _: &RecvMessage,
+        _back: u32,
+        _data: LenLimit<Leased<W, [u8]>, JOURNAL_RECORD_BYTES>,
+    ) -> Result<bool, RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
+    #[cfg(not(feature = "update-journal"))]
+    fn write_update_journal(
+        &mut self,
+        _: &RecvMessage,
+        _data: LenLimit<Leased<R, [u8]>, JOURNAL_RECORD_BYTES>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
+    #[cfg(feature = "compression")]
+    fn write_compressed(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: LenLimit<Leased<R, [u8]>, BLOCK_SIZE_BYTES>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        match self.state {
+            UpdateState::NoUpdate => {
+                return Err(UpdateError::UpdateNotStarted.into())
+            }
+            UpdateState::Finished => {
+                return Err(UpdateError::UpdateAlreadyFinished.into())
+            }
+            UpdateState::InProgress => (),
+        }
+
+        // We take the inflater out of `self` so that we can write blocks
+        // while it's in use, and put it back when we're done -- unless we
+        // failed partway through the chunk, and it's lost its place in the
+        // image, in which case the image has to be sent again from the start.
+        let mut inflater = self.inflater.take().unwrap_or_default();
+        let result = self.inflate(&mut inflater, offset, &data);
+        if !inflater.is_unfinished() {
+            self.inflater = Some(inflater);
+        }
+        result
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn write_compressed(
+        &mut self,
+        _: &RecvMessage,
+        _offset: u32,
+        _data: LenLimit<Leased<R, [u8]>, BLOCK_SIZE_BYTES>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
     fn reset(
         &mut self,
         _: &RecvMessage,
@@ -556,6 +1124,8 @@ fn main() -> ! {
     let mut server = ServerImpl {
         flash,
         state: UpdateState::NoUpdate,
+        #[cfg(feature = "compression")]
+        inflater: None,
     };
     let mut incoming = [0u8; idl::INCOMING_SIZE];
 
@@ -3,10 +3,12 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::server::build_server_support(
-        "../../idl/stm32xx-sys.idol",
+    const IDL: &str = "../../idl/stm32xx-sys.idol";
+    idol::server::build_restricted_server_support(
+        IDL,
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+        &build_util::task_allowed_callers(IDL)?,
     )?;
 
     Ok(())
@@ -0,0 +1,82 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Compressed update images.
+//!
+//! The format and decompression are in the `compressed-image` crate; this
+//! reports its errors as `UpdateError`s.
+//!
+//! Update servers that support them take compressed images through
+//! `write_compressed`, and decompress them with an `Inflater`.
+
+use crate::UpdateError;
+
+pub use compressed_image::{
+    is_compressed, CompressedImageHeader, COMPRESSED_IMAGE_MAGIC,
+};
+
+impl From<compressed_image::Error> for UpdateError {
+    fn from(e: compressed_image::Error) -> Self {
+        match e {
+            compressed_image::Error::OutOfBounds => UpdateError::OutOfBounds,
+            // Like a chunk that doesn't follow on, this can only be fixed by
+            // starting over.
+            compressed_image::Error::Unfinished => UpdateError::OutOfBounds,
+            compressed_image::Error::BadImage => {
+                UpdateError::BadCompressedImage
+            }
+        }
+    }
+}
+
+/// Carries an error from the `write` function passed to `Inflater::feed`
+/// through `compressed_image::Inflater::feed`.
+struct Failed<E>(E);
+
+impl<E: From<UpdateError>> From<compressed_image::Error> for Failed<E> {
+    fn from(e: compressed_image::Error) -> Self {
+        Failed(UpdateError::from(e).into())
+    }
+}
+
+/// Decompresses a compressed image, a chunk at a time, into blocks of `N`
+/// bytes. See `compressed_image::Inflater`.
+#[derive(Default)]
+pub struct Inflater<const N: usize>(compressed_image::Inflater<N>);
+
+impl<const N: usize> Inflater<N> {
+    /// Starts a chunk of `len` bytes at `offset` in the compressed image,
+    /// which must follow on from the last chunk. Returns `false` if it's a
+    /// repeat of the last chunk, and should be skipped.
+    pub fn start_chunk(
+        &mut self,
+        offset: u32,
+        len: usize,
+    ) -> Result<bool, UpdateError> {
+        Ok(self.0.start_chunk(offset, len)?)
+    }
+
+    /// Decompresses `data`, the next part of the current chunk, calling
+    /// `write` with the number and contents of each block as it's filled.
+    pub fn feed<E: From<UpdateError>>(
+        &mut self,
+        data: &[u8],
+        mut write: impl FnMut(usize, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.0
+            .feed(data, |num, block| write(num, block).map_err(Failed))
+            .map_err(|Failed(e)| e)
+    }
+
+    /// Checks whether the whole image has been decompressed and written.
+    pub fn is_done(&self) -> bool {
+        self.0.is_done()
+    }
+
+    /// Checks whether a chunk wasn't decompressed in full, in which case
+    /// every chunk after it is refused.
+    pub fn is_unfinished(&self) -> bool {
+        self.0.is_unfinished()
+    }
+}
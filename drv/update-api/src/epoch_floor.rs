@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checking the tokens that authorize raising the epoch floor.
+
+use crate::UpdateError;
+use userlib::{EpochFloorToken, EPOCH_FLOOR_TOKEN_MAGIC};
+use zerocopy::FromBytes;
+
+/// Checks that `token` is an `EpochFloorToken` signed with `key`, the image
+/// signing key, and returns the epoch that it authorizes.
+pub fn check_token(key: &[u8; 32], token: &[u8]) -> Result<u32, UpdateError> {
+    let token =
+        EpochFloorToken::read_from(token).ok_or(UpdateError::BadLength)?;
+    if token.magic != EPOCH_FLOOR_TOKEN_MAGIC {
+        return Err(UpdateError::MissingSignature);
+    }
+
+    // The key is derived by xtask from a private key seed, so it should
+    // always decode; if not, no signature can match it.
+    let key = salty::PublicKey::try_from(key)
+        .map_err(|_| UpdateError::BadSignature)?;
+    let signature = salty::Signature::from(&token.signature);
+    key.verify(&EpochFloorToken::message(token.epoch), &signature)
+        .map_err(|_| UpdateError::BadSignature)?;
+    Ok(token.epoch)
+}
@@ -12,6 +12,10 @@ use serde::{Deserialize, Serialize};
 use userlib::{sys_send, FromPrimitive};
 use zerocopy::AsBytes;
 
+pub mod compressed;
+#[cfg(feature = "epoch-floor-token")]
+pub mod epoch_floor;
+
 // Re-export
 pub use stage0_handoff::{
     HandoffDataLoadError, ImageVersion, RotBootState, RotImageDetails, RotSlot,
@@ -98,6 +102,18 @@ pub enum UpdateError {
     TaskRestarted,
 
     NotImplemented,
+
+    // Signature checks
+    MissingSignature,
+    ImageDigestMismatch,
+    BadSignature,
+    HashError,
+
+    // Anti-rollback checks
+    EpochTooOld,
+    EpochTooNew,
+
+    BadCompressedImage,
 }
 
 impl From<UpdateError> for GwUpdateError {
@@ -128,10 +144,23 @@ impl From<UpdateError> for GwUpdateError {
             UpdateError::ImageBoardUnknown => Self::ImageBoardUnknown,
             UpdateError::TaskRestarted => Self::TaskRestarted,
             UpdateError::NotImplemented => Self::NotImplemented,
+            // The gateway protocol predates image signatures, so these are
+            // reported as the closest existing errors.
+            UpdateError::MissingSignature => Self::MissingHeaderBlock,
+            UpdateError::ImageDigestMismatch => Self::InvalidHeaderBlock,
+            UpdateError::BadSignature => Self::InvalidHeaderBlock,
+            UpdateError::HashError => Self::FlashError,
+            UpdateError::EpochTooOld => Self::InvalidHeaderBlock,
+            UpdateError::EpochTooNew => Self::OutOfBounds,
+            UpdateError::BadCompressedImage => Self::InvalidHeaderBlock,
         }
     }
 }
 
+/// Size of a record in the update journal store (see `write_update_journal`).
+/// The store treats records as opaque; their format belongs to the client.
+pub const JOURNAL_RECORD_BYTES: usize = 96;
+
 /// When booting into an alternate image, specifies how "sticky" that decision
 /// is.
 #[derive(
@@ -191,6 +220,9 @@ pub mod stm32h7 {
         FLASH_WORD_BYTES * FLASH_WORDS_PER_BLOCK;
 
     pub const BLOCK_SIZE_WORDS: usize = BLOCK_SIZE_BYTES / 4;
+
+    // Flash sectors are the unit of erasure. See RM0433 Rev 7 section 4.2.
+    pub const SECTOR_SIZE_BYTES: usize = 128 * 1024;
 }
 
 pub mod lpc55 {
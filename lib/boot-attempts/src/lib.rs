@@ -0,0 +1,158 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A count of attempts to boot the current image, kept in memory that
+//! survives a reset.
+//!
+//! Startup code counts each boot with [`BootRecord::count_boot`]. Once the
+//! image is up and has declared itself healthy, the supervisor clears the
+//! count with [`BootRecord::mark_healthy`]. If the count goes past
+//! `max_attempts` first, the image keeps failing before it gets that far, and
+//! startup should fall back to the other image, recording that it did so
+//! with [`BootRecord::fall_back`] so that the next image can report it.
+//!
+//! The record is checked on load, so whatever the memory holds after a power
+//! cycle reads as a fresh record. That means a power cycle forgets failed
+//! boots, which is fine: they're meant to catch an image that resets itself.
+
+#![cfg_attr(not(test), no_std)]
+
+const MAGIC: u32 = 0xb007_c0de;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct BootRecord {
+    magic: u32,
+    /// Boots of the current image since it last declared itself healthy,
+    /// including this one.
+    pub attempts: u32,
+    /// How many attempts the image gets before startup falls back to the
+    /// other one, or 0 to never fall back. The supervisor sets this from its
+    /// config, so it's whatever the last image to get that far wanted.
+    pub max_attempts: u32,
+    /// If startup fell back to this image, how many attempts the other image
+    /// had; otherwise, 0.
+    pub fell_back_after: u32,
+    check: u32,
+}
+
+impl BootRecord {
+    /// Reads the record at `addr`, or returns a fresh one if it isn't valid.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be valid for reads of a `BootRecord`.
+    pub unsafe fn load(addr: *const BootRecord) -> Self {
+        let record = core::ptr::read_volatile(addr);
+        if record.magic == MAGIC && record.check == record.checksum() {
+            record
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Writes the record to `addr`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be valid for writes of a `BootRecord`.
+    pub unsafe fn store(mut self, addr: *mut BootRecord) {
+        self.magic = MAGIC;
+        self.check = self.checksum();
+        core::ptr::write_volatile(addr, self);
+    }
+
+    fn checksum(&self) -> u32 {
+        !(MAGIC
+            ^ self.attempts
+            ^ self.max_attempts.rotate_left(8)
+            ^ self.fell_back_after.rotate_left(16))
+    }
+
+    /// Counts a boot, returning `true` if the image has used up its attempts
+    /// and startup should fall back to the other image.
+    pub fn count_boot(&mut self) -> bool {
+        self.attempts = self.attempts.saturating_add(1);
+        self.max_attempts != 0 && self.attempts > self.max_attempts
+    }
+
+    /// Records that startup is falling back to the other image, which starts
+    /// with a clean count.
+    pub fn fall_back(&mut self) {
+        self.fell_back_after = self.attempts;
+        self.attempts = 0;
+    }
+
+    pub fn mark_healthy(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_loads_fresh() {
+        let garbage = BootRecord {
+            magic: MAGIC,
+            attempts: 7,
+            max_attempts: 3,
+            fell_back_after: 0,
+            check: 0x1234_5678,
+        };
+        let record = unsafe { BootRecord::load(&garbage) };
+        assert_eq!(record, BootRecord::default());
+    }
+
+    #[test]
+    fn store_then_load() {
+        let mut mem = BootRecord::default();
+        let mut record = BootRecord {
+            max_attempts: 3,
+            ..Default::default()
+        };
+        record.count_boot();
+        unsafe { record.store(&mut mem) };
+        let loaded = unsafe { BootRecord::load(&mem) };
+        assert_eq!(loaded.attempts, 1);
+        assert_eq!(loaded.max_attempts, 3);
+    }
+
+    #[test]
+    fn falls_back_after_max_attempts() {
+        let mut record = BootRecord {
+            max_attempts: 2,
+            ..Default::default()
+        };
+        assert!(!record.count_boot());
+        assert!(!record.count_boot());
+        assert!(record.count_boot());
+
+        record.fall_back();
+        assert_eq!(record.attempts, 0);
+        assert_eq!(record.fell_back_after, 3);
+        assert!(!record.count_boot());
+    }
+
+    #[test]
+    fn healthy_image_never_falls_back() {
+        let mut record = BootRecord {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            assert!(!record.count_boot());
+            record.mark_healthy();
+        }
+    }
+
+    #[test]
+    fn no_max_never_falls_back() {
+        let mut record = BootRecord::default();
+        for _ in 0..10 {
+            assert!(!record.count_boot());
+        }
+    }
+}
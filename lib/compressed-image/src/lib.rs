@@ -0,0 +1,492 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Compressed update images.
+//!
+//! A compressed image is a `CompressedImageHeader` followed by the image,
+//! compressed with `gnarle`. `xtask dist` can write one alongside
+//! `final.bin`; the header format is duplicated there, so keep the two in
+//! sync.
+//!
+//! Update servers decompress them with an `Inflater`, through
+//! `drv_update_api::compressed`, which reports its errors as `UpdateError`s.
+
+#![cfg_attr(not(test), no_std)]
+
+use gnarle::Decompressor;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Magic number at the start of a compressed image.
+pub const COMPRESSED_IMAGE_MAGIC: u32 = u32::from_le_bytes(*b"GNLE");
+
+/// Header of a compressed image. Both fields are little-endian.
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct CompressedImageHeader {
+    pub magic: u32,
+    /// Size of the image once it's decompressed.
+    pub image_size: u32,
+}
+
+const HEADER_BYTES: usize = core::mem::size_of::<CompressedImageHeader>();
+
+/// Checks whether `data`, from the start of an update image, is the start of
+/// a compressed image.
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&COMPRESSED_IMAGE_MAGIC.to_le_bytes())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// A chunk didn't follow on from the last one.
+    OutOfBounds,
+    /// The image isn't a compressed image, or decompresses to more than its
+    /// header says.
+    BadImage,
+    /// An earlier chunk wasn't decompressed in full -- writing a block
+    /// failed partway through it, say -- so there's no picking up where it
+    /// left off.
+    Unfinished,
+}
+
+/// Decompresses a compressed image, a chunk at a time, into blocks of `N`
+/// bytes.
+pub struct Inflater<const N: usize> {
+    header: [u8; HEADER_BYTES],
+    header_len: usize,
+    /// Offsets in the compressed image of the last chunk we took, and of the
+    /// next one we expect.
+    last_offset: u32,
+    next_offset: u32,
+    /// How much of the last chunk is still to be decompressed.
+    chunk_left: usize,
+    /// Set when decompressing fails, after which nothing can follow.
+    failed: bool,
+    state: Decompressor,
+    block: [u8; N],
+    block_len: usize,
+    /// How much of the image has been written.
+    written: usize,
+}
+
+impl<const N: usize> Default for Inflater<N> {
+    fn default() -> Self {
+        Self {
+            header: [0; HEADER_BYTES],
+            header_len: 0,
+            last_offset: 0,
+            next_offset: 0,
+            chunk_left: 0,
+            failed: false,
+            state: Decompressor::default(),
+            block: [0; N],
+            block_len: 0,
+            written: 0,
+        }
+    }
+}
+
+impl<const N: usize> Inflater<N> {
+    fn image_size(&self) -> Option<usize> {
+        if self.header_len < HEADER_BYTES {
+            return None;
+        }
+        let header = CompressedImageHeader::read_from(&self.header[..])?;
+        Some(header.image_size as usize)
+    }
+
+    /// Returns how much of the image has been decompressed.
+    fn image_len(&self) -> usize {
+        self.written + self.block_len
+    }
+
+    /// Starts a chunk of `len` bytes at `offset` in the compressed image,
+    /// which must follow on from the last chunk. Returns `false` if it's a
+    /// repeat of the last chunk -- which a client that didn't hear back about
+    /// it may send again -- and should be skipped. All of the last chunk must
+    /// have been fed without error: if it wasn't, a repeat of it isn't
+    /// skipped, but refused, along with everything else.
+    pub fn start_chunk(
+        &mut self,
+        offset: u32,
+        len: usize,
+    ) -> Result<bool, Error> {
+        let end = u32::try_from(len)
+            .ok()
+            .and_then(|len| offset.checked_add(len))
+            .ok_or(Error::OutOfBounds)?;
+        if self.is_unfinished() {
+            return Err(Error::Unfinished);
+        }
+        if offset == self.last_offset && end == self.next_offset {
+            return Ok(false);
+        }
+        if offset != self.next_offset {
+            return Err(Error::OutOfBounds);
+        }
+        self.last_offset = offset;
+        self.next_offset = end;
+        self.chunk_left = len;
+        Ok(true)
+    }
+
+    /// Decompresses `data`, the next part of the current chunk. `write` is
+    /// called with the number and contents of each block as it's filled; the
+    /// last block of the image, which may be short, is written as soon as
+    /// it's complete.
+    pub fn feed<E: From<Error>>(
+        &mut self,
+        data: &[u8],
+        write: impl FnMut(usize, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.failed {
+            return Err(Error::Unfinished.into());
+        }
+        if data.len() > self.chunk_left {
+            return Err(Error::OutOfBounds.into());
+        }
+        let result = self.decompress(data, write);
+        if result.is_ok() {
+            self.chunk_left -= data.len();
+        } else {
+            self.failed = true;
+        }
+        result
+    }
+
+    fn decompress<E: From<Error>>(
+        &mut self,
+        mut data: &[u8],
+        mut write: impl FnMut(usize, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        // Collect the header, which may be split across chunks.
+        let n = data.len().min(HEADER_BYTES - self.header_len);
+        self.header[self.header_len..][..n].copy_from_slice(&data[..n]);
+        self.header_len += n;
+        data = &data[n..];
+
+        let image_size = match self.image_size() {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        if !is_compressed(&self.header) {
+            return Err(Error::BadImage.into());
+        }
+
+        loop {
+            let out = gnarle::decompress(
+                &mut self.state,
+                &mut data,
+                &mut self.block[self.block_len..],
+            );
+            self.block_len += out.len();
+
+            if self.image_len() > image_size {
+                return Err(Error::BadImage.into());
+            }
+            if self.block_len == N
+                || (self.block_len > 0 && self.image_len() == image_size)
+            {
+                write(self.written / N, &self.block[..self.block_len])?;
+                self.written += self.block_len;
+                self.block_len = 0;
+            } else {
+                // `decompress` only stops short of filling the block when
+                // it's run out of input.
+                return Ok(());
+            }
+        }
+    }
+
+    /// Checks whether the whole image has been decompressed and written.
+    pub fn is_done(&self) -> bool {
+        self.image_size() == Some(self.image_len()) && self.state.is_idle()
+    }
+
+    /// Checks whether a chunk wasn't decompressed in full, in which case
+    /// every chunk after it is refused, and the image has to be sent again.
+    pub fn is_unfinished(&self) -> bool {
+        self.failed || self.chunk_left != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 16;
+
+    /// Returns `image` as a compressed image, whose header claims it's
+    /// `image_size` bytes.
+    fn compress_as(image: &[u8], image_size: u32) -> Vec<u8> {
+        let header = CompressedImageHeader {
+            magic: COMPRESSED_IMAGE_MAGIC,
+            image_size,
+        };
+        let mut out = header.as_bytes().to_vec();
+        gnarle::compress::<core::convert::Infallible>(image, |data| {
+            out.extend_from_slice(data);
+            Ok(())
+        })
+        .unwrap();
+        out
+    }
+
+    fn compress(image: &[u8]) -> Vec<u8> {
+        compress_as(image, image.len() as u32)
+    }
+
+    /// An image with some runs in it, that doesn't fill its last block.
+    fn image() -> Vec<u8> {
+        let mut image = Vec::new();
+        for i in 0..10u8 {
+            image.extend(0..i * 3);
+            image.extend([i; 40]);
+        }
+        image.push(1);
+        assert!(image.len() % N != 0);
+        image
+    }
+
+    /// Collects the blocks written by an `Inflater`.
+    #[derive(Default)]
+    struct Blocks {
+        data: Vec<u8>,
+        /// Number of a block that fails to be written, if any.
+        fail: Option<usize>,
+    }
+
+    impl Blocks {
+        fn write(&mut self, num: usize, block: &[u8]) -> Result<(), Error> {
+            assert_eq!(num * N, self.data.len(), "blocks written out of order");
+            assert!(block.len() <= N);
+            if self.fail == Some(num) {
+                // The error doesn't matter, so long as it's passed on.
+                return Err(Error::OutOfBounds);
+            }
+            self.data.extend_from_slice(block);
+            Ok(())
+        }
+    }
+
+    /// Feeds `chunk`, at `offset` in the compressed image, to `inflater`.
+    fn feed(
+        inflater: &mut Inflater<N>,
+        blocks: &mut Blocks,
+        offset: usize,
+        chunk: &[u8],
+    ) -> Result<bool, Error> {
+        if !inflater.start_chunk(offset as u32, chunk.len())? {
+            return Ok(false);
+        }
+        inflater.feed(chunk, |num, block| blocks.write(num, block))?;
+        Ok(true)
+    }
+
+    /// Inflates `compressed`, split at each offset in `splits`.
+    fn inflate_split(compressed: &[u8], splits: &[usize]) -> Vec<u8> {
+        let mut inflater = Inflater::<N>::default();
+        let mut blocks = Blocks::default();
+        let mut start = 0;
+        for &end in splits.iter().chain([compressed.len()].iter()) {
+            assert!(!inflater.is_done());
+            let chunk = &compressed[start..end];
+            assert_eq!(
+                feed(&mut inflater, &mut blocks, start, chunk),
+                Ok(true)
+            );
+            start = end;
+        }
+        assert!(inflater.is_done());
+        blocks.data
+    }
+
+    #[test]
+    fn whole_image() {
+        let image = image();
+        let compressed = compress(&image);
+        assert!(compressed.len() < image.len());
+        assert_eq!(inflate_split(&compressed, &[]), image);
+    }
+
+    #[test]
+    fn any_chunk_size() {
+        let image = image();
+        let compressed = compress(&image);
+        for size in 1..compressed.len() {
+            let splits: Vec<_> =
+                (size..compressed.len()).step_by(size).collect();
+            assert_eq!(inflate_split(&compressed, &splits), image, "{size}");
+        }
+    }
+
+    #[test]
+    fn header_split_across_chunks() {
+        let image = image();
+        let compressed = compress(&image);
+        assert_eq!(inflate_split(&compressed, &[3]), image);
+        assert_eq!(inflate_split(&compressed, &[1, 5, 7]), image);
+        assert_eq!(inflate_split(&compressed, &[2, 6]), image);
+    }
+
+    #[test]
+    fn escape_split_across_chunks() {
+        let image = image();
+        let compressed = compress(&image);
+
+        // Split right after an escape byte, and between the byte and count
+        // that follow it.
+        let esc = HEADER_BYTES
+            + compressed[HEADER_BYTES..]
+                .iter()
+                .position(|&b| b == 0xba)
+                .unwrap();
+        assert_eq!(inflate_split(&compressed, &[esc + 1]), image);
+        assert_eq!(inflate_split(&compressed, &[esc + 2]), image);
+        assert_eq!(inflate_split(&compressed, &[esc + 1, esc + 2]), image);
+    }
+
+    #[test]
+    fn duplicate_chunk() {
+        let image = image();
+        let compressed = compress(&image);
+        let (a, b) = compressed.split_at(40);
+
+        let mut inflater = Inflater::<N>::default();
+        let mut blocks = Blocks::default();
+        assert_eq!(feed(&mut inflater, &mut blocks, 0, a), Ok(true));
+        let written = blocks.data.len();
+
+        // A repeat of the last chunk is skipped...
+        assert_eq!(feed(&mut inflater, &mut blocks, 0, a), Ok(false));
+        assert_eq!(blocks.data.len(), written);
+
+        // ...but any other chunk that doesn't follow on is refused.
+        assert_eq!(
+            feed(&mut inflater, &mut blocks, 0, &a[..39]),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(
+            feed(&mut inflater, &mut blocks, 41, &b[1..]),
+            Err(Error::OutOfBounds)
+        );
+
+        assert_eq!(feed(&mut inflater, &mut blocks, 40, b), Ok(true));
+        assert_eq!(feed(&mut inflater, &mut blocks, 40, b), Ok(false));
+        assert!(inflater.is_done());
+        assert_eq!(blocks.data, image);
+    }
+
+    #[test]
+    fn write_fails_mid_chunk() {
+        let image = image();
+        let compressed = compress(&image);
+        let (a, b) = compressed.split_at(40);
+
+        let mut inflater = Inflater::<N>::default();
+        let mut blocks = Blocks::default();
+        assert_eq!(feed(&mut inflater, &mut blocks, 0, a), Ok(true));
+        assert!(!inflater.is_unfinished());
+
+        // Fail the second block that the next chunk writes, so that some of
+        // it's been decompressed and written, but not all.
+        let num = blocks.data.len() / N;
+        blocks.fail = Some(num + 1);
+        assert_eq!(
+            feed(&mut inflater, &mut blocks, 40, b),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(blocks.data.len(), (num + 1) * N);
+
+        // Sending the chunk again mustn't look like a repeat of a chunk
+        // that went through, nor can anything else carry on from here.
+        blocks.fail = None;
+        assert_eq!(
+            feed(&mut inflater, &mut blocks, 40, b),
+            Err(Error::Unfinished)
+        );
+        assert_eq!(
+            feed(&mut inflater, &mut blocks, 40 + b.len(), &[0]),
+            Err(Error::Unfinished)
+        );
+        assert!(!inflater.is_done());
+        assert!(inflater.is_unfinished());
+    }
+
+    #[test]
+    fn chunk_not_fed_in_full() {
+        let image = image();
+        let compressed = compress(&image);
+        let (a, b) = compressed.split_at(40);
+
+        // As happens if the lease holding a chunk goes away partway
+        // through reading it.
+        let mut inflater = Inflater::<N>::default();
+        let mut blocks = Blocks::default();
+        assert_eq!(inflater.start_chunk(0, a.len()), Ok(true));
+        inflater
+            .feed(&a[..20], |num, block| blocks.write(num, block))
+            .unwrap();
+        assert_eq!(inflater.start_chunk(0, a.len()), Err(Error::Unfinished));
+        assert_eq!(inflater.start_chunk(40, b.len()), Err(Error::Unfinished));
+
+        // Nor can a chunk be fed more than it holds.
+        let mut inflater = Inflater::<N>::default();
+        assert_eq!(inflater.start_chunk(0, 20), Ok(true));
+        assert_eq!(
+            inflater.feed(a, |num, block| blocks.write(num, block)),
+            Err(Error::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn chunk_end_overflow() {
+        let mut inflater = Inflater::<N>::default();
+        assert_eq!(inflater.start_chunk(0, 8), Ok(true));
+        inflater.chunk_left = 0;
+        inflater.next_offset = u32::MAX - 4;
+        assert_eq!(
+            inflater.start_chunk(u32::MAX - 4, 8),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(inflater.start_chunk(u32::MAX - 4, 4), Ok(true));
+    }
+
+    #[test]
+    fn overrun() {
+        let image = image();
+
+        // An image that's longer than its header says, whether or not the
+        // excess is in a run.
+        for image in [&image[..], &image[..image.len() - 1]] {
+            let compressed = compress_as(image, image.len() as u32 - 5);
+            let mut inflater = Inflater::<N>::default();
+            let mut blocks = Blocks::default();
+            assert_eq!(
+                feed(&mut inflater, &mut blocks, 0, &compressed),
+                Err(Error::BadImage)
+            );
+            assert!(!inflater.is_done());
+            assert!(blocks.data.len() <= image.len() - 5);
+        }
+
+        // One that's shorter is never done.
+        let compressed = compress_as(&image, image.len() as u32 + 1);
+        let mut inflater = Inflater::<N>::default();
+        let mut blocks = Blocks::default();
+        assert_eq!(feed(&mut inflater, &mut blocks, 0, &compressed), Ok(true));
+        assert!(!inflater.is_done());
+    }
+
+    #[test]
+    fn not_compressed() {
+        let mut inflater = Inflater::<N>::default();
+        let mut blocks = Blocks::default();
+        assert_eq!(
+            feed(&mut inflater, &mut blocks, 0, &image()),
+            Err(Error::BadImage)
+        );
+        assert!(blocks.data.is_empty());
+    }
+}
@@ -299,5 +299,19 @@ impl RngSeed {
 #[derive(Deserialize, Serialize, SerializedSize)]
 pub struct PersistIdCert(SizedBlob);
 
+impl PersistIdCert {
+    /// Returns the DER-encoded cert.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
 #[derive(Deserialize, Serialize, SerializedSize)]
 pub struct IntermediateCert(SizedBlob);
+
+impl IntermediateCert {
+    /// Returns the DER-encoded cert.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
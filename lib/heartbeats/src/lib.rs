@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Heartbeat deadlines for supervised tasks.
+//!
+//! Each task with a heartbeat must check in at least once every `timeout`
+//! milliseconds. The supervisor records check-ins with
+//! [`Heartbeats::check_in`], and periodically asks
+//! [`Heartbeats::next_missed`] for tasks that are overdue. A task counts as
+//! healthy once it has checked in, and stops counting as healthy when it
+//! misses a deadline or is restarted, until it checks in again.
+//!
+//! Every missed deadline is followed by a fresh one, whatever the supervisor
+//! does about it. That way a task that wasn't running when it missed its
+//! deadline -- stopped, faulted, or held for inspection -- is supervised
+//! again as soon as it's back, however it was restarted.
+
+#![cfg_attr(not(test), no_std)]
+
+/// A task's heartbeat requirement.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Heartbeat {
+    /// Index of the task.
+    pub task: usize,
+    /// Longest the task may go between check-ins, in milliseconds.
+    pub timeout: u64,
+    /// Whether to dump the task before restarting it.
+    pub dump: bool,
+    /// Whether the hardware watchdog depends on this task.
+    pub critical: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Status {
+    deadline: u64,
+    checked_in: bool,
+}
+
+/// Heartbeat state for every task that has one.
+pub struct Heartbeats<const N: usize> {
+    table: [Heartbeat; N],
+    status: [Status; N],
+}
+
+impl<const N: usize> Heartbeats<N> {
+    /// Starts every task's first deadline at `now`.
+    pub fn new(table: [Heartbeat; N], now: u64) -> Self {
+        let mut status = [Status::default(); N];
+        for (s, hb) in status.iter_mut().zip(&table) {
+            s.deadline = now + hb.timeout;
+        }
+        Self { table, status }
+    }
+
+    fn find(&mut self, task: usize) -> Option<(Heartbeat, &mut Status)> {
+        self.table
+            .iter()
+            .zip(self.status.iter_mut())
+            .find(|(hb, _)| hb.task == task)
+            .map(|(hb, s)| (*hb, s))
+    }
+
+    /// Records a check-in from the task at index `task`, if it has a
+    /// heartbeat.
+    pub fn check_in(&mut self, task: usize, now: u64) {
+        if let Some((hb, s)) = self.find(task) {
+            s.deadline = now + hb.timeout;
+            s.checked_in = true;
+        }
+    }
+
+    /// Gives the task at index `task`, which has just been restarted, a fresh
+    /// deadline. It doesn't count as healthy until it checks in.
+    pub fn restarted(&mut self, task: usize, now: u64) {
+        if let Some((hb, s)) = self.find(task) {
+            s.deadline = now + hb.timeout;
+            s.checked_in = false;
+        }
+    }
+
+    /// Finds a task that has missed its deadline as of `now`. The task no
+    /// longer counts as healthy, and gets a fresh deadline starting at `now`,
+    /// so each missed deadline is only reported once.
+    pub fn next_missed(&mut self, now: u64) -> Option<Heartbeat> {
+        self.table
+            .iter()
+            .zip(self.status.iter_mut())
+            .find(|(_, s)| now >= s.deadline)
+            .map(|(hb, s)| {
+                s.deadline = now + hb.timeout;
+                s.checked_in = false;
+                *hb
+            })
+    }
+
+    /// Checks whether every critical task is healthy.
+    pub fn critical_tasks_healthy(&self) -> bool {
+        self.table
+            .iter()
+            .zip(&self.status)
+            .all(|(hb, s)| !hb.critical || s.checked_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: [Heartbeat; 2] = [
+        Heartbeat {
+            task: 3,
+            timeout: 100,
+            dump: false,
+            critical: true,
+        },
+        Heartbeat {
+            task: 5,
+            timeout: 50,
+            dump: true,
+            critical: false,
+        },
+    ];
+
+    #[test]
+    fn check_ins_keep_deadline_moving() {
+        let mut hbs = Heartbeats::new(TABLE, 0);
+        for now in (0..1000).step_by(40) {
+            hbs.check_in(3, now);
+            hbs.check_in(5, now);
+            assert_eq!(hbs.next_missed(now), None);
+        }
+    }
+
+    #[test]
+    fn miss_is_reported_once_per_deadline() {
+        let mut hbs = Heartbeats::new(TABLE, 0);
+        hbs.check_in(5, 60);
+        assert_eq!(hbs.next_missed(99), None);
+        assert_eq!(hbs.next_missed(100), Some(TABLE[0]));
+        assert_eq!(hbs.next_missed(100), None);
+        assert_eq!(hbs.next_missed(110), Some(TABLE[1]));
+        assert_eq!(hbs.next_missed(110), None);
+        assert_eq!(hbs.next_missed(159), None);
+        assert_eq!(hbs.next_missed(160), Some(TABLE[1]));
+    }
+
+    #[test]
+    fn missed_task_is_supervised_again() {
+        // A task that misses its deadline while it isn't running (so the
+        // supervisor leaves it alone) still gets fresh deadlines, so it's
+        // caught again if it comes back and never checks in.
+        let mut hbs = Heartbeats::new(TABLE, 0);
+        hbs.check_in(5, 90);
+        assert_eq!(hbs.next_missed(100), Some(TABLE[0]));
+        assert_eq!(hbs.next_missed(100), None);
+        hbs.check_in(5, 160);
+        assert_eq!(hbs.next_missed(199), None);
+        assert_eq!(hbs.next_missed(200), Some(TABLE[0]));
+
+        // Once it checks in, it's back to normal.
+        hbs.check_in(3, 250);
+        hbs.check_in(5, 250);
+        assert_eq!(hbs.next_missed(299), None);
+        assert_eq!(hbs.next_missed(350), Some(TABLE[0]));
+    }
+
+    #[test]
+    fn restart_gives_fresh_deadline() {
+        let mut hbs = Heartbeats::new(TABLE, 0);
+        hbs.check_in(5, 0);
+        hbs.restarted(3, 90);
+        assert_eq!(hbs.next_missed(150), Some(TABLE[1]));
+        assert_eq!(hbs.next_missed(150), None);
+        assert_eq!(hbs.next_missed(190), Some(TABLE[0]));
+    }
+
+    #[test]
+    fn critical_health() {
+        let mut hbs = Heartbeats::new(TABLE, 0);
+        // Not healthy until it has checked in.
+        assert!(!hbs.critical_tasks_healthy());
+        hbs.check_in(3, 10);
+        assert!(hbs.critical_tasks_healthy());
+
+        // A non-critical task missing its deadline doesn't matter.
+        assert_eq!(hbs.next_missed(60), Some(TABLE[1]));
+        assert!(hbs.critical_tasks_healthy());
+
+        // A critical one missing its deadline does, until it checks in.
+        assert_eq!(hbs.next_missed(110), Some(TABLE[0]));
+        assert!(!hbs.critical_tasks_healthy());
+        hbs.check_in(3, 120);
+        assert!(hbs.critical_tasks_healthy());
+
+        // As does a restart.
+        hbs.restarted(3, 130);
+        assert!(!hbs.critical_tasks_healthy());
+    }
+
+    #[test]
+    fn unknown_tasks_are_ignored() {
+        let mut hbs = Heartbeats::new(TABLE, 0);
+        hbs.check_in(4, 0);
+        hbs.restarted(4, 0);
+        assert!(!hbs.critical_tasks_healthy());
+    }
+}
@@ -89,8 +89,12 @@ pub enum HostToSp {
     // Host ack'ing SP task startup.
     AckSpStart,
     GetAlert,
-    RotRequest, // Followed by a binary data blob (the request)
-    RotAddHostMeasurements, // Followed by a binary data blob?
+    // Followed by a binary data blob: the hubpack-encoded `RotRequest`.
+    RotRequest,
+    // Followed by a binary data blob: one or more measurements, each
+    // `HOST_MEASUREMENT_SIZE` bytes, to extend the RoT's host measurement
+    // register with, in order (see `host_measurements`).
+    RotAddHostMeasurements,
     /// Get as much phase 2 data as we can from the image identified by `hash`
     /// starting at `offset`.
     GetPhase2Data {
@@ -107,6 +111,11 @@ pub enum HostToSp {
         key: u8,
         max_response_len: u16,
     },
+    /// Get a quote from the RoT: its measurement log, signed along with
+    /// `nonce`.
+    GetRotAttestation {
+        nonce: [u8; 32],
+    },
 }
 
 /// The order of these cases is critical! We are relying on hubpack's encoding
@@ -144,7 +153,7 @@ pub enum SpToHost {
         // details TBD
         action: u8,
     },
-    // Followed by a binary data blob (the response)
+    // Followed by a binary data blob: the hubpack-encoded `RotResponse`.
     RotResponse,
     // Followed by a binary data blob (the data)
     Phase2Data,
@@ -152,6 +161,10 @@ pub enum SpToHost {
     // blob of length at most `max_response_len` from the corresponding request.
     // For any other result, there is no subsequent binary blob.
     KeyLookupResult(KeyLookupResult),
+    // If `result` is `RotAttestationResult::Ok`, this will be followed by a
+    // binary blob: the hubpack-encoded quote (see `attest-api`). For any other
+    // result, there is no subsequent binary blob.
+    RotAttestation(RotAttestationResult),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, num_derive::FromPrimitive)]
@@ -175,6 +188,87 @@ pub enum KeyLookupResult {
     MaxResponseLenTooShort,
 }
 
+/// Size of a host measurement: a SHA3-256 digest.
+pub const HOST_MEASUREMENT_SIZE: usize = 32;
+
+/// Splits the data blob following `HostToSp::RotAddHostMeasurements` into
+/// measurements.
+///
+/// # Errors
+///
+/// Returns [`DecodeFailureReason::DataLengthInvalid`] if `data` is empty or
+/// isn't a whole number of measurements.
+pub fn host_measurements(
+    data: &[u8],
+) -> Result<
+    impl Iterator<Item = &[u8; HOST_MEASUREMENT_SIZE]>,
+    DecodeFailureReason,
+> {
+    if data.is_empty() || data.len() % HOST_MEASUREMENT_SIZE != 0 {
+        return Err(DecodeFailureReason::DataLengthInvalid);
+    }
+    Ok(data
+        .chunks_exact(HOST_MEASUREMENT_SIZE)
+        .map(|m| m.try_into().unwrap_lite()))
+}
+
+/// A request for the RoT, carried in the data blob following
+/// `HostToSp::RotRequest`.
+///
+/// Like `HostToSp`, this is hubpack-encoded, so variants may only be added at
+/// the end.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum RotRequest {
+    /// Read the RoT's host measurement register.
+    ReadHostMeasurements,
+}
+
+/// The RoT's response to a `HostToSp::RotRequest` or
+/// `HostToSp::RotAddHostMeasurements`, carried in the data blob following
+/// `SpToHost::RotResponse`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum RotResponse {
+    /// The state of the host measurement register: its value, and the number
+    /// of measurements it's been extended with. For
+    /// `RotAddHostMeasurements`, this is after all of them.
+    HostMeasurements {
+        value: [u8; 32],
+        count: u32,
+    },
+    Error(RotRequestError),
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum RotRequestError {
+    /// The host measurement register can't be extended any further. For
+    /// `RotAddHostMeasurements`, the measurements before the one that didn't
+    /// fit were still added.
+    RegisterFull,
+    /// The RoT failed the request.
+    Failed,
+    /// We couldn't get a response from the RoT. For `RotAddHostMeasurements`,
+    /// the measurement we were sending may or may not have been added: read
+    /// the register to find out.
+    RotUnavailable,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum RotAttestationResult {
+    Ok,
+    /// The RoT couldn't produce a quote (e.g., it has no alias key).
+    Failed,
+    /// We couldn't get a response from the RoT.
+    RotUnavailable,
+}
+
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
 )]
@@ -464,6 +558,7 @@ mod tests {
                     max_response_len: 0,
                 },
             ),
+            (0x0f, HostToSp::GetRotAttestation { nonce: [0; 32] }),
         ] {
             let n = hubpack::serialize(&mut buf[..], &variant).unwrap();
             assert!(n >= 1);
@@ -502,6 +597,7 @@ mod tests {
             (0x08, SpToHost::RotResponse),
             (0x09, SpToHost::Phase2Data),
             (0x0a, SpToHost::KeyLookupResult(KeyLookupResult::Ok)),
+            (0x0b, SpToHost::RotAttestation(RotAttestationResult::Ok)),
         ] {
             let n = hubpack::serialize(&mut buf[..], &variant).unwrap();
             assert!(n >= 1);
@@ -509,6 +605,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rot_add_host_measurements_roundtrip() {
+        let header = Header {
+            magic: MAGIC,
+            version: 123,
+            sequence: 456,
+        };
+        let mut blob = [0; 3 * HOST_MEASUREMENT_SIZE];
+        for (i, m) in blob.chunks_exact_mut(HOST_MEASUREMENT_SIZE).enumerate() {
+            m.fill(i as u8 + 1);
+        }
+
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let n = serialize(
+            &mut buf,
+            &header,
+            &HostToSp::RotAddHostMeasurements,
+            |out| {
+                out[..blob.len()].copy_from_slice(&blob);
+                blob.len()
+            },
+        )
+        .unwrap();
+
+        let (_, command, data) = deserialize::<HostToSp>(&buf[..n]).unwrap();
+        assert_eq!(command, HostToSp::RotAddHostMeasurements);
+
+        let measurements = host_measurements(data).unwrap().collect::<Vec<_>>();
+        assert_eq!(
+            measurements,
+            [
+                &[1; HOST_MEASUREMENT_SIZE],
+                &[2; HOST_MEASUREMENT_SIZE],
+                &[3; HOST_MEASUREMENT_SIZE],
+            ]
+        );
+    }
+
+    #[test]
+    fn host_measurements_bad_length() {
+        for len in [0, 1, HOST_MEASUREMENT_SIZE - 1, HOST_MEASUREMENT_SIZE + 1]
+        {
+            let data = vec![0; len];
+            assert_eq!(
+                host_measurements(&data).err(),
+                Some(DecodeFailureReason::DataLengthInvalid),
+                "length {len}",
+            );
+        }
+    }
+
+    #[test]
+    fn rot_request_roundtrip() {
+        let header = Header {
+            magic: MAGIC,
+            version: 123,
+            sequence: 456,
+        };
+        let mut request = [0; RotRequest::MAX_SIZE];
+        let request_len =
+            hubpack::serialize(&mut request, &RotRequest::ReadHostMeasurements)
+                .unwrap();
+
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let n = serialize(&mut buf, &header, &HostToSp::RotRequest, |out| {
+            out[..request_len].copy_from_slice(&request[..request_len]);
+            request_len
+        })
+        .unwrap();
+
+        let (_, command, data) = deserialize::<HostToSp>(&buf[..n]).unwrap();
+        assert_eq!(command, HostToSp::RotRequest);
+        let (request, _) = hubpack::deserialize::<RotRequest>(data).unwrap();
+        assert_eq!(request, RotRequest::ReadHostMeasurements);
+    }
+
+    #[test]
+    fn rot_response_roundtrip() {
+        let header = Header {
+            magic: MAGIC,
+            version: 123,
+            sequence: 456,
+        };
+        for response in [
+            RotResponse::HostMeasurements {
+                value: [7; 32],
+                count: 3,
+            },
+            RotResponse::Error(RotRequestError::RegisterFull),
+        ] {
+            let mut buf = [0; MAX_MESSAGE_SIZE];
+            let n =
+                serialize(&mut buf, &header, &SpToHost::RotResponse, |out| {
+                    hubpack::serialize(out, &response).unwrap()
+                })
+                .unwrap();
+
+            let (_, command, data) =
+                deserialize::<SpToHost>(&buf[..n]).unwrap();
+            assert_eq!(command, SpToHost::RotResponse);
+            let (deserialized, rest) =
+                hubpack::deserialize::<RotResponse>(data).unwrap();
+            assert_eq!(deserialized, response);
+            assert!(rest.is_empty());
+        }
+    }
+
     #[test]
     fn roundtrip() {
         let header = Header {
@@ -60,6 +60,15 @@ fn apply_memory_protection(mpu: &MPU) {
     }
 }
 
+/// Turns on the handoff memory, runs DICE if it's enabled, and records which
+/// image we booted from and what's in each slot.
+///
+/// Unlike `drv-stm32h7-startup`, this doesn't count boot attempts or fall
+/// back to the other image (see the `boot-attempts` crate). The image has
+/// already been chosen by stage0, from the persistent boot preference in the
+/// CFPA; the only way for us to pick the other one would be a CFPA write,
+/// which is permanent rather than a fallback, and we have no memory that's
+/// known to survive stage0 to keep a count in.
 pub fn startup(
     core_peripherals: &cortex_m::Peripherals,
     peripherals: &lpc55_pac::Peripherals,
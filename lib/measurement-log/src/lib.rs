@@ -0,0 +1,194 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A bounded log of measurements, for attestation.
+//!
+//! The log holds up to `N` entries, in the order they were added, followed by
+//! empty slots. A new entry can take the place of an earlier one that it
+//! supersedes -- say, a fresh measurement of something that's been measured
+//! before -- keeping that entry's position. So a log where each thing that's
+//! measured supersedes its own earlier measurements never fills up, however
+//! many times things are measured again, and never shows a stale
+//! measurement.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Returned when there's no room in the log for a new entry.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LogFull;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MeasurementLog<T, const N: usize> {
+    entries: [Option<T>; N],
+}
+
+impl<T: Copy, const N: usize> Default for MeasurementLog<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> MeasurementLog<T, N> {
+    pub const fn new() -> Self {
+        Self { entries: [None; N] }
+    }
+
+    /// Returns the entries, in order, followed by `None`s.
+    pub fn entries(&self) -> &[Option<T>; N] {
+        &self.entries
+    }
+
+    /// Returns the first entry that `supersedes` returns `true` for, or if
+    /// there isn't one, the first empty slot, so that the caller can fill it.
+    /// A slot that's left empty is still at the end of the log.
+    pub fn slot(
+        &mut self,
+        supersedes: impl Fn(&T) -> bool,
+    ) -> Result<&mut Option<T>, LogFull> {
+        // Entries are packed at the start, so any match comes before the
+        // first empty slot.
+        self.entries
+            .iter_mut()
+            .find(|e| match e {
+                Some(e) => supersedes(e),
+                None => true,
+            })
+            .ok_or(LogFull)
+    }
+
+    /// Puts `entry` in place of the first entry that `supersedes` returns
+    /// `true` for, or appends it if there isn't one.
+    pub fn put(
+        &mut self,
+        entry: T,
+        supersedes: impl Fn(&T) -> bool,
+    ) -> Result<(), LogFull> {
+        *self.slot(supersedes)? = Some(entry);
+        Ok(())
+    }
+
+    /// Removes every entry that `matches` returns `true` for, moving later
+    /// entries up to close the gaps.
+    pub fn remove(&mut self, matches: impl Fn(&T) -> bool) {
+        let old = core::mem::replace(&mut self.entries, [None; N]);
+        let kept = old.into_iter().flatten().filter(|e| !matches(e));
+        for (slot, e) in self.entries.iter_mut().zip(kept) {
+            *slot = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    enum Kind {
+        Fwid,
+        Sp,
+        Mismatch,
+    }
+
+    type Entry = (Kind, u8);
+
+    fn of(kind: Kind) -> impl Fn(&Entry) -> bool {
+        move |e| e.0 == kind
+    }
+
+    #[test]
+    fn starts_empty() {
+        let log = MeasurementLog::<Entry, 4>::new();
+        assert_eq!(log.entries(), &[None; 4]);
+    }
+
+    #[test]
+    fn appends_in_order() {
+        let mut log = MeasurementLog::<Entry, 4>::new();
+        log.put((Kind::Fwid, 1), of(Kind::Fwid)).unwrap();
+        log.put((Kind::Sp, 2), of(Kind::Sp)).unwrap();
+        assert_eq!(
+            log.entries(),
+            &[Some((Kind::Fwid, 1)), Some((Kind::Sp, 2)), None, None]
+        );
+    }
+
+    #[test]
+    fn full_log_is_unchanged() {
+        let mut log = MeasurementLog::<Entry, 2>::new();
+        log.put((Kind::Fwid, 1), of(Kind::Fwid)).unwrap();
+        log.put((Kind::Sp, 2), of(Kind::Sp)).unwrap();
+        let full = log;
+
+        assert_eq!(
+            log.put((Kind::Mismatch, 3), of(Kind::Mismatch)),
+            Err(LogFull)
+        );
+        assert_eq!(log, full);
+    }
+
+    #[test]
+    fn remeasuring_never_fills_log() {
+        let mut log = MeasurementLog::<Entry, 3>::new();
+        log.put((Kind::Fwid, 0), of(Kind::Fwid)).unwrap();
+
+        // Record far more measurements than the log has room for, as an SP
+        // that's measured every time it's released from a halt would.
+        for n in 1..=20 {
+            log.remove(of(Kind::Mismatch));
+            log.put((Kind::Sp, n), of(Kind::Sp)).unwrap();
+            if n % 2 == 1 {
+                log.put((Kind::Mismatch, n), of(Kind::Mismatch)).unwrap();
+            }
+        }
+
+        // Only the latest measurement shows, and the mismatch flagged
+        // against an earlier one is gone.
+        assert_eq!(
+            log.entries(),
+            &[Some((Kind::Fwid, 0)), Some((Kind::Sp, 20)), None]
+        );
+
+        log.put((Kind::Sp, 21), of(Kind::Sp)).unwrap();
+        log.put((Kind::Mismatch, 21), of(Kind::Mismatch)).unwrap();
+        assert_eq!(
+            log.entries(),
+            &[
+                Some((Kind::Fwid, 0)),
+                Some((Kind::Sp, 21)),
+                Some((Kind::Mismatch, 21)),
+            ]
+        );
+    }
+
+    #[test]
+    fn put_keeps_position() {
+        let mut log = MeasurementLog::<Entry, 3>::new();
+        log.put((Kind::Sp, 1), of(Kind::Sp)).unwrap();
+        log.put((Kind::Fwid, 2), of(Kind::Fwid)).unwrap();
+        log.put((Kind::Sp, 3), of(Kind::Sp)).unwrap();
+        assert_eq!(
+            log.entries(),
+            &[Some((Kind::Sp, 3)), Some((Kind::Fwid, 2)), None]
+        );
+    }
+
+    #[test]
+    fn remove_closes_gaps() {
+        let mut log = MeasurementLog::<Entry, 4>::new();
+        log.put((Kind::Fwid, 1), |_| false).unwrap();
+        log.put((Kind::Mismatch, 2), |_| false).unwrap();
+        log.put((Kind::Sp, 3), |_| false).unwrap();
+        log.put((Kind::Mismatch, 4), |_| false).unwrap();
+
+        log.remove(of(Kind::Mismatch));
+        assert_eq!(
+            log.entries(),
+            &[Some((Kind::Fwid, 1)), Some((Kind::Sp, 3)), None, None]
+        );
+
+        // Freed slots can be used again.
+        log.put((Kind::Mismatch, 5), of(Kind::Mismatch)).unwrap();
+        assert_eq!(log.entries()[2], Some((Kind::Mismatch, 5)));
+    }
+}
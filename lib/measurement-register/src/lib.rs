@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A measurement register with PCR-like extend semantics.
+//!
+//! A register starts out all zeros. Extending it with a measurement replaces
+//! its value with the SHA3-256 of the old value followed by the measurement,
+//! so its value commits to every measurement it's been extended with, in
+//! order, and can't be rolled back or set to a chosen value. Someone who has
+//! the list of measurements (e.g., from the host's event log) can check it by
+//! replaying the extends and comparing the result.
+//!
+//! Registers are append-only -- there's no way to reset one -- and bounded:
+//! after `MAX_EXTENDS` measurements, further extends are refused, rather
+//! than letting the list that a verifier has to replay grow without limit.
+
+#![cfg_attr(not(test), no_std)]
+
+use sha3::{Digest, Sha3_256};
+
+/// Size of a measurement, and of the register: a SHA3-256 digest.
+pub const DIGEST_SIZE: usize = 32;
+
+/// Maximum number of measurements a register can be extended with.
+pub const MAX_EXTENDS: u32 = 64;
+
+/// Returned when a register has already been extended `MAX_EXTENDS` times.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterFull;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MeasurementRegister {
+    value: [u8; DIGEST_SIZE],
+    count: u32,
+}
+
+impl Default for MeasurementRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeasurementRegister {
+    pub const fn new() -> Self {
+        Self {
+            value: [0; DIGEST_SIZE],
+            count: 0,
+        }
+    }
+
+    /// Extends the register with `measurement`.
+    pub fn extend(
+        &mut self,
+        measurement: &[u8; DIGEST_SIZE],
+    ) -> Result<(), RegisterFull> {
+        if self.count >= MAX_EXTENDS {
+            return Err(RegisterFull);
+        }
+        let mut sha = Sha3_256::new();
+        sha.update(self.value);
+        sha.update(measurement);
+        self.value.copy_from_slice(&sha.finalize());
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns the register's value.
+    pub fn value(&self) -> [u8; DIGEST_SIZE] {
+        self.value
+    }
+
+    /// Returns how many measurements the register has been extended with.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(n: u8) -> [u8; DIGEST_SIZE] {
+        [n; DIGEST_SIZE]
+    }
+
+    #[test]
+    fn starts_zeroed() {
+        let reg = MeasurementRegister::new();
+        assert_eq!(reg.value(), [0; DIGEST_SIZE]);
+        assert_eq!(reg.count(), 0);
+    }
+
+    #[test]
+    fn extend_hashes_old_value_and_measurement() {
+        let mut reg = MeasurementRegister::new();
+        reg.extend(&measurement(1)).unwrap();
+
+        let mut sha = Sha3_256::new();
+        sha.update([0; DIGEST_SIZE]);
+        sha.update(measurement(1));
+        let expected = sha.finalize();
+
+        assert_eq!(reg.value()[..], expected[..]);
+        assert_eq!(reg.count(), 1);
+
+        reg.extend(&measurement(2)).unwrap();
+
+        let mut sha = Sha3_256::new();
+        sha.update(expected);
+        sha.update(measurement(2));
+        assert_eq!(reg.value()[..], sha.finalize()[..]);
+        assert_eq!(reg.count(), 2);
+    }
+
+    #[test]
+    fn order_matters() {
+        let mut a = MeasurementRegister::new();
+        a.extend(&measurement(1)).unwrap();
+        a.extend(&measurement(2)).unwrap();
+
+        let mut b = MeasurementRegister::new();
+        b.extend(&measurement(2)).unwrap();
+        b.extend(&measurement(1)).unwrap();
+
+        assert_ne!(a.value(), b.value());
+        assert_eq!(a.count(), b.count());
+    }
+
+    #[test]
+    fn full_register_is_unchanged() {
+        let mut reg = MeasurementRegister::new();
+        for n in 0..MAX_EXTENDS {
+            reg.extend(&measurement(n as u8)).unwrap();
+        }
+        let full = reg;
+
+        assert_eq!(reg.extend(&measurement(0)), Err(RegisterFull));
+        assert_eq!(reg, full);
+        assert_eq!(reg.count(), MAX_EXTENDS);
+    }
+}
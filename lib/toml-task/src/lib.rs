@@ -58,6 +58,50 @@ pub struct Task<T = ordered_toml::Value> {
     pub sections: IndexMap<String, String>,
     #[serde(default)]
     pub max_sizes: IndexMap<String, u32>,
+
+    /// Tasks allowed to send messages to this one, keyed by name, with the
+    /// operations each may use. If this is empty, any task may send to this
+    /// one; otherwise the kernel refuses messages from anyone else.
+    #[serde(default)]
+    pub callers: IndexMap<String, CallerOps>,
+}
+
+/// Operations that a task listed in another task's `callers` may use.
+///
+/// In `app.toml`, this is either `"*"` for any operation, or a list of
+/// operation names:
+/// ```toml
+/// [tasks.update_server.callers]
+/// control_plane_agent = "*"
+/// hiffy = ["block_size", "prep_image_update"]
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CallerOps {
+    /// Any operation; the only valid value is `"*"`.
+    Any(String),
+    /// Only the operations named.
+    Only(Vec<String>),
+}
+
+impl CallerOps {
+    /// Checks that this is well-formed, i.e. not some string other than `"*"`.
+    pub fn check(&self) -> Result<()> {
+        match self {
+            CallerOps::Any(s) if s != "*" => {
+                bail!("expected \"*\" or a list of operations, found {s:?}")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether operation `op` is allowed.
+    pub fn allows(&self, op: &str) -> bool {
+        match self {
+            CallerOps::Any(_) => true,
+            CallerOps::Only(ops) => ops.iter().any(|o| o == op),
+        }
+    }
 }
 
 impl<T> Task<T> {
@@ -0,0 +1,366 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Records for the update progress journal kept by `control-plane-agent`.
+//!
+//! A [`Record`] describes an update in progress: its ID and size, a bitmap of
+//! the segments of the image that have been written, and a running hash of
+//! the data in them. This crate only deals with the contents of records;
+//! storing them is up to the caller.
+//!
+//! The running hash is FNV-1a, which is there to catch mistakes -- such as a
+//! target not holding what we think we wrote to it -- rather than attacks.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::mem::size_of;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Number of segments that an image is divided into for tracking.
+pub const SEGMENTS: usize = 256;
+
+/// Length of a component ID (`gateway_messages::SpComponent::MAX_ID_LENGTH`).
+pub const COMPONENT_ID_LEN: usize = 16;
+
+const JOURNAL_MAGIC: u32 = 0x6a72_6e6c;
+
+/// Initial value of a running hash.
+pub const HASH_INIT: u32 = 0x811c_9dc5;
+
+/// Continues the running hash `hash` over `data`.
+pub fn hash(mut hash: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        hash = (hash ^ u32::from(b)).wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// A journal record, as saved.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Record {
+    magic: u32,
+    /// The component being updated, or all zeros if there's no update.
+    pub component: [u8; COMPONENT_ID_LEN],
+    pub id: [u8; 16],
+    pub slot: u16,
+    _pad: u16,
+    /// Size of the part of the update that comes before the journaled image:
+    /// for the SP, that's the aux flash image, which isn't journaled.
+    pub aux_flash_size: u32,
+    pub total_size: u32,
+    pub segment_size: u32,
+    /// Running hash of the data in the written segments.
+    pub hash: u32,
+    /// Bitmap of the written segments.
+    written: [u8; SEGMENTS / 8],
+    _reserved: u32,
+    /// Hash of the rest of the record.
+    checksum: u32,
+}
+
+impl Record {
+    /// Returns a record of no update.
+    pub fn empty() -> Self {
+        Self {
+            magic: JOURNAL_MAGIC,
+            ..Self::new_zeroed()
+        }
+    }
+
+    /// Returns a record of an update that's just starting. The segments are
+    /// a multiple of `unit` bytes, which should be the granularity at which
+    /// the target can resume, and are as small as they can be while fitting
+    /// the image into `SEGMENTS` of them.
+    pub fn new(
+        component: [u8; COMPONENT_ID_LEN],
+        id: [u8; 16],
+        slot: u16,
+        aux_flash_size: u32,
+        total_size: u32,
+        unit: u32,
+    ) -> Self {
+        let segment_size = (total_size + SEGMENTS as u32 - 1) / SEGMENTS as u32;
+        let segment_size = (segment_size.max(1) + unit - 1) / unit * unit;
+        Self {
+            component,
+            id,
+            slot,
+            aux_flash_size,
+            total_size,
+            segment_size,
+            hash: HASH_INIT,
+            ..Self::empty()
+        }
+    }
+
+    /// Checks whether this is the record of an update, rather than of none.
+    pub fn is_update(&self) -> bool {
+        self.component != [0; COMPONENT_ID_LEN]
+    }
+
+    fn expected_checksum(&self) -> u32 {
+        let bytes = self.as_bytes();
+        hash(HASH_INIT, &bytes[..bytes.len() - size_of::<u32>()])
+    }
+
+    /// Fills in the checksum, which should be done just before saving.
+    pub fn seal(&mut self) {
+        self.checksum = self.expected_checksum();
+    }
+
+    /// Checks that this was sealed, and hasn't been garbled since.
+    pub fn is_valid(&self) -> bool {
+        self.magic == JOURNAL_MAGIC && self.checksum == self.expected_checksum()
+    }
+
+    /// Records that the image has been written up to `offset`, with running
+    /// hash `hash`, if that's the end of a segment or of the image. Returns
+    /// true if so, in which case the record should be saved.
+    pub fn finish_segment(&mut self, offset: u32, hash: u32) -> bool {
+        if offset == 0
+            || (offset % self.segment_size != 0 && offset != self.total_size)
+        {
+            return false;
+        }
+        let segment = ((offset - 1) / self.segment_size) as usize;
+        self.written[segment / 8] |= 1 << (segment % 8);
+        self.hash = hash;
+        true
+    }
+
+    /// Returns the number of segments written, if they're all at the start of
+    /// the image; since updates are written in order, they always should be.
+    pub fn written_segments(&self) -> Option<u32> {
+        let mut n = 0;
+        for (i, &b) in self.written.iter().enumerate() {
+            if b != 0xff {
+                n = i as u32 * 8 + b.trailing_ones();
+                break;
+            }
+            n = (i as u32 + 1) * 8;
+        }
+        let tail = (n as usize..SEGMENTS)
+            .any(|s| self.written[s / 8] & (1 << (s % 8)) != 0);
+        if tail {
+            None
+        } else {
+            Some(n)
+        }
+    }
+
+    /// Returns how much of the image had been written when this was saved,
+    /// if the update can be resumed from there: that is, if some but not all
+    /// of the image had been written.
+    pub fn resume_offset(&self) -> Option<u32> {
+        let offset = self
+            .written_segments()?
+            .checked_mul(self.segment_size)?
+            .min(self.total_size);
+        if offset == 0 || offset == self.total_size {
+            None
+        } else {
+            Some(offset)
+        }
+    }
+}
+
+/// Picks the record of the update to resume, if there is one, given `read`,
+/// which reads the record saved `back` records before the newest, if there
+/// is one.
+///
+/// If the newest record is garbled, we were reset while saving it, and the
+/// one before it is still good.
+pub fn select(mut read: impl FnMut(u32) -> Option<Record>) -> Option<Record> {
+    for back in 0..2 {
+        let record = read(back)?;
+        if !record.is_valid() {
+            continue;
+        }
+        if !record.is_update() {
+            return None;
+        }
+        record.resume_offset()?;
+        return Some(record);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SP: [u8; COMPONENT_ID_LEN] = *b"sp\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+    fn update(total_size: u32, unit: u32) -> Record {
+        Record::new(SP, [7; 16], 1, 0, total_size, unit)
+    }
+
+    /// Returns a sealed record of an update of 256 KiB, in 1 KiB segments,
+    /// with the first `segments` written.
+    fn written(segments: u32) -> Record {
+        let mut r = update(256 * 1024, 1024);
+        for s in 1..=segments {
+            assert!(r.finish_segment(s * 1024, s));
+        }
+        r.seal();
+        r
+    }
+
+    #[test]
+    fn record_size() {
+        assert_eq!(size_of::<Record>(), 96);
+    }
+
+    #[test]
+    fn segment_size() {
+        // Big enough to fit the image into `SEGMENTS`, rounded up to `unit`.
+        assert_eq!(update(256 * 1024, 1024).segment_size, 1024);
+        assert_eq!(update(256 * 1024 + 1, 1024).segment_size, 2048);
+        assert_eq!(
+            update(2 * 1024 * 1024, 128 * 1024).segment_size,
+            128 * 1024
+        );
+        assert_eq!(update(30 * 1024 * 1024, 1024).segment_size, 120 * 1024);
+
+        // Small images still get whole units.
+        assert_eq!(update(100, 32).segment_size, 32);
+        assert_eq!(update(0, 32).segment_size, 32);
+
+        for total in [1, 255, 256, 257, 1_000_000, 33_554_431] {
+            let r = update(total, 512);
+            assert_eq!(r.segment_size % 512, 0);
+            assert!(r.segment_size as u64 * SEGMENTS as u64 >= total as u64);
+        }
+    }
+
+    #[test]
+    fn written_segments() {
+        let mut r = update(256 * 1024, 1024);
+        assert_eq!(r.written_segments(), Some(0));
+
+        r.written[0] = 0xff;
+        r.written[1] = 0x07;
+        assert_eq!(r.written_segments(), Some(11));
+
+        // A segment written after a gap means something's wrong.
+        r.written[5] = 0x10;
+        assert_eq!(r.written_segments(), None);
+        r.written[5] = 0;
+        r.written[1] = 0x0b;
+        assert_eq!(r.written_segments(), None);
+
+        r.written = [0xff; SEGMENTS / 8];
+        assert_eq!(r.written_segments(), Some(SEGMENTS as u32));
+    }
+
+    #[test]
+    fn finish_segment() {
+        let mut r = update(2500, 1000);
+        assert_eq!(r.segment_size, 1000);
+        assert!(!r.finish_segment(0, 1));
+        assert!(!r.finish_segment(999, 2));
+        assert!(r.finish_segment(1000, 3));
+        assert_eq!((r.written_segments(), r.hash), (Some(1), 3));
+        assert!(!r.finish_segment(1500, 4));
+        assert!(r.finish_segment(2000, 5));
+        // The last segment is short.
+        assert!(r.finish_segment(2500, 6));
+        assert_eq!((r.written_segments(), r.hash), (Some(3), 6));
+    }
+
+    #[test]
+    fn resume_offset() {
+        assert_eq!(written(0).resume_offset(), None);
+        assert_eq!(written(1).resume_offset(), Some(1024));
+        assert_eq!(written(255).resume_offset(), Some(255 * 1024));
+        assert_eq!(written(256).resume_offset(), None);
+
+        // A short last segment counts as the whole rest of the image.
+        let mut r = update(2500, 1000);
+        assert!(r.finish_segment(1000, 0));
+        assert!(r.finish_segment(2000, 0));
+        assert_eq!(r.resume_offset(), Some(2000));
+        assert!(r.finish_segment(2500, 0));
+        assert_eq!(r.resume_offset(), None);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut r = written(3);
+        assert!(r.is_valid());
+
+        // Any change to the contents is caught...
+        for i in 0..size_of::<Record>() - size_of::<u32>() {
+            let mut garbled = r;
+            garbled.as_bytes_mut()[i] ^= 0x20;
+            assert!(!garbled.is_valid(), "byte {i}");
+        }
+
+        // ...as is a record that was never sealed, or a blank one.
+        r.slot = 0;
+        assert!(!r.is_valid());
+        r.seal();
+        assert!(r.is_valid());
+        assert!(!Record::new_zeroed().is_valid());
+        assert!(!Record::empty().is_valid());
+    }
+
+    /// Returns a `read` function for `select` over `records`, newest first.
+    fn reader(records: &[Record]) -> impl FnMut(u32) -> Option<Record> + '_ {
+        |back| records.get(back as usize).copied()
+    }
+
+    fn garbled(mut r: Record) -> Record {
+        r.hash ^= 1;
+        r
+    }
+
+    fn selected(records: &[Record]) -> Option<u32> {
+        select(reader(records)).map(|r| r.resume_offset().unwrap())
+    }
+
+    #[test]
+    fn select_newest() {
+        assert_eq!(selected(&[written(5), written(4)]), Some(5 * 1024));
+        assert_eq!(selected(&[written(5)]), Some(5 * 1024));
+    }
+
+    #[test]
+    fn select_skips_garbled() {
+        assert_eq!(
+            selected(&[garbled(written(5)), written(4)]),
+            Some(4 * 1024)
+        );
+        assert_eq!(selected(&[garbled(written(5)), garbled(written(4))]), None);
+        assert_eq!(selected(&[garbled(written(5))]), None);
+
+        // Only the newest may be garbled; anything older is stale.
+        assert_eq!(
+            selected(&[garbled(written(5)), garbled(written(4)), written(3)]),
+            None
+        );
+    }
+
+    #[test]
+    fn select_nothing_to_resume() {
+        let mut empty = Record::empty();
+        empty.seal();
+
+        // Nothing saved, or the journal was cleared.
+        assert_eq!(selected(&[]), None);
+        assert_eq!(selected(&[empty, written(4)]), None);
+        assert_eq!(selected(&[garbled(written(5)), empty]), None);
+
+        // Nothing written yet, or everything was.
+        assert_eq!(selected(&[written(0)]), None);
+        assert_eq!(selected(&[written(256), written(255)]), None);
+
+        // A newest record that doesn't make sense isn't second-guessed.
+        let mut gap = written(5);
+        gap.written[10] = 1;
+        gap.seal();
+        assert_eq!(selected(&[gap, written(4)]), None);
+    }
+}
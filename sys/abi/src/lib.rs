@@ -216,6 +216,30 @@ pub const fn extract_new_generation(code: u32) -> Option<Generation> {
 /// Response code returned by the kernel if a lender has defected.
 pub const DEFECT: u32 = 1;
 
+/// Response code returned by the kernel from `SEND_WITH_TIMEOUT` if the
+/// sender's timer fired before a reply arrived (or wasn't armed to begin
+/// with). Also returned by either kind of send to a recipient that has yet to
+/// reply to a message that the sender gave up on this way.
+///
+/// This is chosen to sit just below the range of dead codes, where it won't
+/// collide with small application-defined error codes.
+pub const TIMED_OUT: u32 = 0xffff_fe00;
+
+/// Response code returned by the kernel from `SEND` if the application's IPC
+/// permission table doesn't allow the sender to message the recipient. The
+/// message is not delivered.
+///
+/// Like `TIMED_OUT`, this sits below the range of dead codes.
+pub const ACCESS_DENIED: u32 = 0xffff_fd00;
+
+/// Response code returned by the kernel from `BORROW_MAP` if the lease can't
+/// be granted: it isn't suitably sized and aligned for the memory protection
+/// unit, it isn't in the lender's normal memory, or the borrower has no room
+/// for another grant. The lease can still be accessed by copying.
+///
+/// Like `TIMED_OUT`, this sits below the range of dead codes.
+pub const UNGRANTABLE: u32 = 0xffff_fc00;
+
 /// State used to make scheduling decisions.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub enum TaskState {
@@ -444,6 +468,9 @@ pub enum Sysnum {
     RefreshTaskId = 10,
     Post = 11,
     ReplyFault = 12,
+    SendWithTimeout = 13,
+    Idle = 14,
+    BorrowMap = 15,
 }
 
 /// We're using an explicit `TryFrom` impl for `Sysnum` instead of
@@ -467,6 +494,9 @@ impl core::convert::TryFrom<u32> for Sysnum {
             10 => Ok(Self::RefreshTaskId),
             11 => Ok(Self::Post),
             12 => Ok(Self::ReplyFault),
+            13 => Ok(Self::SendWithTimeout),
+            14 => Ok(Self::Idle),
+            15 => Ok(Self::BorrowMap),
             _ => Err(()),
         }
     }
@@ -479,6 +509,105 @@ pub struct TaskDumpRegion {
     pub size: u32,
 }
 
+/// Execution statistics the kernel keeps for each task, as returned by
+/// `Kipcnum::ReadTaskStats`.
+///
+/// These are cumulative since boot and are _not_ reset when the task restarts,
+/// so that a monitor can compute utilization from the difference between two
+/// samples. The counters wrap on overflow.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize,
+)]
+pub struct TaskStats {
+    /// Number of kernel timer ticks that interrupted this task while it was
+    /// running. This is a statistical measure of CPU time (the way Unix has
+    /// traditionally done it): a task that runs for less than a tick at a time
+    /// may be over- or undercounted, but over many ticks it converges on the
+    /// task's share of the CPU.
+    pub ticks: u64,
+    /// Number of times the kernel switched to this task from another one.
+    pub schedules: u32,
+    /// Number of syscalls this task has made.
+    pub syscalls: u32,
+    /// Number of hardware interrupts delivered to this task as notifications.
+    pub irqs: u32,
+}
+
+/// A task's stack usage, as returned by `Kipcnum::ReadStackUsage`.
+///
+/// The kernel paints each task's stack with a known pattern whenever it starts
+/// the task, and measures usage by looking for the deepest word that no longer
+/// holds the pattern. This can undercount if the task happens to write the
+/// pattern itself, or skips over part of its stack without writing to it (e.g.
+/// with a large, mostly unused local array).
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize,
+)]
+pub struct StackUsage {
+    /// Size of the task's stack, in bytes.
+    pub size: u32,
+    /// Deepest stack usage since the task was last started, in bytes.
+    pub depth: u32,
+    /// Deepest stack usage by any incarnation of the task since boot, in
+    /// bytes.
+    pub high_water: u32,
+}
+
+/// Kind of event recorded in the kernel's trace ring. See `TraceRecord`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TraceEvent {
+    /// Unused slot; the ring hasn't wrapped yet.
+    None = 0,
+    /// The kernel switched to `task`. `arg` is 0.
+    ContextSwitch = 1,
+    /// `task` entered the kernel with syscall number `arg`.
+    SyscallEnter = 2,
+    /// The kernel finished syscall number `arg` on behalf of `task`. The
+    /// kernel may switch to a different task before returning.
+    SyscallExit = 3,
+    /// Hardware interrupt number `arg` was dispatched to `task`.
+    Irq = 4,
+    /// Notification bits `arg` were posted to `task`, by another task, an
+    /// interrupt, or a timer.
+    Post = 5,
+    /// `task` faulted. `arg` is the `FaultInfo` variant, numbered from 0 in
+    /// declaration order.
+    Fault = 6,
+}
+
+/// One entry in the kernel's trace ring, the `HUBRIS_TRACE` symbol in kernels
+/// built with the `trace` feature.
+///
+/// The ring is a `u32` count of all records ever written, followed by an
+/// array of records; the next record goes in slot `count % len`, so the
+/// oldest record is the one in that slot unless the count is smaller than the
+/// array.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// Kernel timestamp when the event happened, in ticks.
+    pub timestamp: u64,
+    /// Event-specific argument; see `TraceEvent`.
+    pub arg: u32,
+    /// Index of the task the event concerns.
+    pub task: u16,
+    /// What happened.
+    pub event: TraceEvent,
+    pub _pad: u8,
+}
+
+impl TraceRecord {
+    /// Contents of an unused slot.
+    pub const EMPTY: Self = Self {
+        timestamp: 0,
+        arg: 0,
+        task: 0,
+        event: TraceEvent::None,
+        _pad: 0,
+    };
+}
+
 /// Representation of kipc numbers
 pub enum Kipcnum {
     ReadTaskStatus = 1,
@@ -489,6 +618,9 @@ pub enum Kipcnum {
     ReadCaboosePos = 6,
     GetTaskDumpRegion = 7,
     ReadTaskDumpRegion = 8,
+    ReadTaskStats = 9,
+    ReadStackUsage = 10,
+    GetTraceRegion = 11,
 }
 
 impl core::convert::TryFrom<u16> for Kipcnum {
@@ -504,6 +636,9 @@ impl core::convert::TryFrom<u16> for Kipcnum {
             6 => Ok(Self::ReadCaboosePos),
             7 => Ok(Self::GetTaskDumpRegion),
             8 => Ok(Self::ReadTaskDumpRegion),
+            9 => Ok(Self::ReadTaskStats),
+            10 => Ok(Self::ReadStackUsage),
+            11 => Ok(Self::GetTraceRegion),
             _ => Err(()),
         }
     }
@@ -511,9 +646,13 @@ impl core::convert::TryFrom<u16> for Kipcnum {
 
 pub const HEADER_MAGIC: u32 = 0x64_CE_D6_CA;
 pub const CABOOSE_MAGIC: u32 = 0xCAB0_005E;
+pub const SIGNATURE_MAGIC: u32 = 0x5167_B10C;
+pub const EPOCH_FLOOR_TOKEN_MAGIC: u32 = 0xF100_2E90;
 
-/// TODO: Add hash for integrity check
-/// Later this will also be a signature block
+/// Image header, found at a fixed offset after the vector table.
+///
+/// The image may be followed by an `ImageSignature`, which isn't counted in
+/// `total_image_len`.
 #[repr(C)]
 #[derive(Default, AsBytes, FromBytes)]
 pub struct ImageHeader {
@@ -524,6 +663,48 @@ pub struct ImageHeader {
     pub epoch: u32,
 }
 
+/// Signature block for an image, stored in flash immediately after the image:
+/// that is, `total_image_len` bytes from the start of the image, rounded up to
+/// a multiple of 4.
+///
+/// `digest` is the SHA-256 of the image's first `total_image_len` bytes, which
+/// includes the header and the caboose, and `signature` is an Ed25519
+/// signature of `digest`. Because the caboose is covered, it must be written
+/// before the image is signed.
+#[repr(C)]
+#[derive(AsBytes, FromBytes)]
+pub struct ImageSignature {
+    pub magic: u32,
+    pub digest: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+/// Authorization to raise the epoch floor (see `raise_epoch_floor` in
+/// `update.idol`) to `epoch`.
+///
+/// `signature` is an Ed25519 signature of `EpochFloorToken::message(epoch)`,
+/// made with the image signing key. That message is shorter than an image
+/// digest, so neither kind of signature can pass for the other. Tokens don't
+/// expire: replaying one can only raise the floor to where it's been already.
+#[repr(C)]
+#[derive(AsBytes, FromBytes)]
+pub struct EpochFloorToken {
+    pub magic: u32,
+    pub epoch: u32,
+    pub signature: [u8; 64],
+}
+
+impl EpochFloorToken {
+    /// Returns the message that's signed to authorize raising the floor to
+    /// `epoch`.
+    pub fn message(epoch: u32) -> [u8; 8] {
+        let mut msg = [0; 8];
+        msg[..4].copy_from_slice(&EPOCH_FLOOR_TOKEN_MAGIC.to_le_bytes());
+        msg[4..].copy_from_slice(&epoch.to_le_bytes());
+        msg
+    }
+}
+
 // Corresponds to the ARM vector table, limited to what we need
 // see ARMv8m B3.30 and B1.5.3 ARMv7m for the full description
 #[repr(C)]
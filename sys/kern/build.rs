@@ -9,13 +9,16 @@ use std::io::Write;
 use anyhow::{bail, Context, Result};
 use build_kconfig::{
     InterruptConfig, KernelConfig, OwnedAddress, RegionAttributes,
-    RegionConfig, SpecialRole,
+    RegionConfig, SleepDepth, SpecialRole,
 };
 use indexmap::IndexMap;
 use proc_macro2::TokenStream;
 
 fn main() -> Result<()> {
-    build_util::expose_m_profile();
+    // Hosted builds are for the simulator, which has no M-profile to speak of.
+    if !is_hosted() {
+        build_util::expose_m_profile();
+    }
 
     let g = process_config()?;
     generate_statics(&g)?;
@@ -27,8 +30,14 @@ struct Generated {
     tasks: Vec<TokenStream>,
     regions: Vec<TokenStream>,
     irq_code: TokenStream,
+    time_slices: Vec<u32>,
+    tickless: TokenStream,
 }
 
+/// Number of priority levels supported by the scheduler; must match
+/// `sched::PRIORITY_LEVELS`.
+const PRIORITY_LEVELS: usize = 32;
+
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 enum RegionKey {
     Null,
@@ -36,10 +45,44 @@ enum RegionKey {
     Owned(usize, String),
 }
 
+/// Checks whether we're building for a host OS (i.e. for the simulator) rather
+/// than bare metal.
+fn is_hosted() -> bool {
+    build_util::target_os() != "none"
+}
+
 fn process_config() -> Result<Generated> {
-    let kconfig: KernelConfig =
-        ron::de::from_str(&build_util::env_var("HUBRIS_KCONFIG")?)
-            .context("parsing kconfig from HUBRIS_KCONFIG")?;
+    let kconfig: KernelConfig = match build_util::env_var("HUBRIS_KCONFIG") {
+        Ok(text) => ron::de::from_str(&text)
+            .context("parsing kconfig from HUBRIS_KCONFIG")?,
+        // The simulator builds its task table at runtime, so when built for
+        // the host outside of an app, an empty config is fine.
+        Err(_) if is_hosted() => KernelConfig {
+            tasks: vec![],
+            shared_regions: Default::default(),
+            irqs: Default::default(),
+            time_slices: Default::default(),
+            tickless: None,
+        },
+        Err(e) => return Err(e),
+    };
+
+    for (i, task) in kconfig.tasks.iter().enumerate() {
+        if usize::from(task.priority) >= PRIORITY_LEVELS {
+            bail!(
+                "task {i} has priority {}, but the scheduler only supports \
+                 priorities below {PRIORITY_LEVELS}",
+                task.priority
+            );
+        }
+    }
+    let mut time_slices = vec![0; PRIORITY_LEVELS];
+    for (&priority, &ticks) in &kconfig.time_slices {
+        match time_slices.get_mut(usize::from(priority)) {
+            Some(slot) => *slot = ticks,
+            None => bail!("time slice given for bad priority {priority}"),
+        }
+    }
 
     // The kconfig data structure keeps things somewhat abstract to give us, the
     // kernel, more freedom about our internal implementation choices. However,
@@ -132,6 +175,21 @@ fn process_config() -> Result<Generated> {
         } else {
             quote::quote! { TaskFlags::empty() }
         };
+        let allowed_callers = match &task.allowed_callers {
+            None => quote::quote! { None },
+            Some(callers) => {
+                let callers = callers
+                    .iter()
+                    .map(|&c| {
+                        if c >= kconfig.tasks.len() {
+                            bail!("task {i} allows unknown caller {c}");
+                        }
+                        Ok(c as u16)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                quote::quote! { Some(&[#(#callers),*]) }
+            }
+        };
         task_descs.push(quote::quote! {
             TaskDesc {
                 regions: [#(&HUBRIS_REGION_DESCS[#regions]),*],
@@ -140,6 +198,7 @@ fn process_config() -> Result<Generated> {
                 priority: #priority,
                 index: #index,
                 flags: #flags,
+                allowed_callers: #allowed_callers,
             }
         });
     }
@@ -179,7 +238,7 @@ fn process_config() -> Result<Generated> {
     let task_irq_map = per_task_irqs.into_iter().collect::<Vec<_>>();
 
     let target = build_util::target();
-    let irq_code = if target.starts_with("thumbv6m") {
+    let irq_code = if target.starts_with("thumbv6m") || is_hosted() {
         // On ARMv6-M we have no hardware division, which the perfect hash table
         // relies on (to get efficient integer remainder). Fall back to a good
         // old sorted list with binary search instead.
@@ -187,6 +246,8 @@ fn process_config() -> Result<Generated> {
         // This means our dispatch time for interrupts on ARMv6-M is O(log N)
         // instead of O(1), but these parts also tend to have few interrupts,
         // so, not the end of the world.
+        //
+        // The simulator uses the same representation, since it's the simplest.
 
         let task_irq_map = phash_gen::OwnedSortedList::build(task_irq_map)
             .context("building task-to-IRQ map")?;
@@ -280,10 +341,22 @@ fn process_config() -> Result<Generated> {
         panic!("Don't know the target {target}");
     };
 
+    let tickless = match kconfig.tickless {
+        None => quote::quote! { None },
+        Some(SleepDepth::Sleep) => {
+            quote::quote! { Some(crate::time::SleepDepth::Sleep) }
+        }
+        Some(SleepDepth::DeepSleep) => {
+            quote::quote! { Some(crate::time::SleepDepth::DeepSleep) }
+        }
+    };
+
     Ok(Generated {
         tasks: task_descs,
         regions: region_descs,
         irq_code,
+        time_slices,
+        tickless,
     })
 }
 
@@ -345,9 +418,11 @@ fn fmt_region(region: &RegionConfig) -> TokenStream {
 }
 
 fn generate_statics(gen: &Generated) -> Result<()> {
-    let image_id: u64 = build_util::env_var("HUBRIS_IMAGE_ID")?
-        .parse()
-        .context("parsing HUBRIS_IMAGE_ID")?;
+    let image_id: u64 = match build_util::env_var("HUBRIS_IMAGE_ID") {
+        Ok(id) => id.parse().context("parsing HUBRIS_IMAGE_ID")?,
+        Err(_) if is_hosted() => 0,
+        Err(e) => return Err(e),
+    };
 
     let out = build_util::out_dir();
     let kconfig_path = out.join("kconfig.rs");
@@ -364,7 +439,7 @@ fn generate_statics(gen: &Generated) -> Result<()> {
         file,
         "{}",
         quote::quote! {
-            const HUBRIS_TASK_COUNT: usize = #task_count;
+            pub(crate) const HUBRIS_TASK_COUNT: usize = #task_count;
             #[no_mangle]
             pub static HUBRIS_IMAGE_ID: u64 = #image_id;
 
@@ -389,6 +464,29 @@ fn generate_statics(gen: &Generated) -> Result<()> {
         },
     )?;
 
+    /////////////////////////////////////////////////////////
+    // Scheduler time slices
+
+    let time_slices = &gen.time_slices;
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            const HUBRIS_TIME_SLICES: [u32; crate::sched::PRIORITY_LEVELS] = [
+                #(#time_slices,)*
+            ];
+        },
+    )?;
+
+    let tickless = &gen.tickless;
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            const HUBRIS_TICKLESS: Option<crate::time::SleepDepth> = #tickless;
+        },
+    )?;
+
     /////////////////////////////////////////////////////////
     // Region descriptors
 
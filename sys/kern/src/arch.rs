@@ -19,8 +19,15 @@ cfg_if::cfg_if! {
     // Note: cfg_if! is slightly touchy about ordering and expression
     // complexity; this chain seems to be the best compromise.
 
-    if #[cfg(not(target_pointer_width = "32"))] {
-        compile_error!("non-32-bit targets not supported (even for simulation)");
+    if #[cfg(not(target_os = "none"))] {
+        // Hosted simulation. This is the one case where we tolerate 64-bit
+        // pointers; the simulator keeps task memory below 4 GiB so that
+        // addresses still fit in the 32-bit syscall ABI.
+        #[macro_use]
+        pub mod hosted;
+        pub use hosted::*;
+    } else if #[cfg(not(target_pointer_width = "32"))] {
+        compile_error!("non-32-bit targets not supported");
     } else if #[cfg(target_arch = "arm")] {
         #[macro_use]
         pub mod arm_m;
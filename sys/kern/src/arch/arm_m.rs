@@ -13,6 +13,13 @@
 //! interrupts to maintain `TICKS`, but has the upside that we don't need
 //! special SoC support for timing.
 //!
+//! In tickless mode, when the idle task is about to sleep, we stretch the
+//! timer's period to cover all the ticks until the next timer deadline (see
+//! `idle_sleep`), and account for them all at once when it expires or some
+//! other interrupt wakes us (see `wake_from_idle_sleep`). SysTick stops in
+//! deep sleep, so for that we pause it, and rely on the board's
+//! `DeepSleepClock` to wake us and say how long we slept.
+//!
 //! # Notes on ARM-M interrupts
 //!
 //! For performance and (believe it or not) simplicity, this implementation uses
@@ -79,7 +86,7 @@ use crate::atomic::AtomicExt;
 use crate::descs::RegionAttributes;
 use crate::startup::with_task_table;
 use crate::task;
-use crate::time::Timestamp;
+use crate::time::{DeepSleepClock, SleepDepth, Timestamp};
 use crate::umem::USlice;
 use abi::FaultInfo;
 #[cfg(any(armv7m, armv8m))]
@@ -294,25 +301,9 @@ pub fn reinitialize(task: &mut task::Task) {
     // Ok. Generate a uslice for the task's starting stack frame.
     let mut frame_uslice: USlice<ExtendedExceptionFrame> =
         USlice::from_raw(initial_stack - frame_size, 1).unwrap_lite();
-    // Before we set our frame, find the region that contains our initial stack
-    // pointer, and zap the region from the base to the stack pointer with a
-    // distinct (and storied) pattern.
-    if let Some(region) = task
-        .region_table()
-        .iter()
-        .find(|region| region.contains(initial_stack))
-    {
-        let mut uslice: USlice<u32> = USlice::from_raw(
-            region.base as usize,
-            (initial_stack - frame_size - region.base as usize) >> 2,
-        )
-        .unwrap_lite();
-
-        let zap = task.try_write(&mut uslice).unwrap_lite();
-        for word in zap.iter_mut() {
-            *word = 0xbaddcafe;
-        }
-    }
+    // Before we set our frame, zap the rest of the stack with a distinct (and
+    // storied) pattern, which also lets us measure stack usage later.
+    task.paint_stack(initial_stack - frame_size);
 
     let descriptor = task.descriptor();
     let frame = &mut task.try_write(&mut frame_uslice).unwrap_lite()[0];
@@ -336,6 +327,23 @@ pub fn reinitialize(task: &mut task::Task) {
     task.save_mut().exc_return = EXC_RETURN_CONST;
 }
 
+/// Checks whether memory at `base` of `size` bytes can be granted to a task
+/// (see `crate::grant`): it has to be expressible as a single MPU region.
+pub fn is_valid_grant(base: u32, size: u32) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(any(armv6m, armv7m))] {
+            // A power of two of at least 32 bytes, naturally aligned.
+            size >= 32 && size.is_power_of_two() && base & (size - 1) == 0
+        } else if #[cfg(armv8m)] {
+            // Any multiple of 32 bytes, 32-byte aligned.
+            size >= 32 && size % 32 == 0 && base % 32 == 0
+                && base.checked_add(size).is_some()
+        } else {
+            compile_error!("missing grant check for ARM profile");
+        }
+    }
+}
+
 #[cfg(any(armv6m, armv7m))]
 pub fn apply_memory_protection(task: &task::Task) {
     // We are manufacturing authority to interact with the MPU here, because we
@@ -347,7 +355,7 @@ pub fn apply_memory_protection(task: &task::Task) {
         &*cortex_m::peripheral::MPU::PTR
     };
 
-    for (i, region) in task.region_table().iter().enumerate() {
+    for (i, region) in task.mpu_regions().enumerate() {
         let ratts = region.attributes;
         let xn = !ratts.contains(RegionAttributes::EXECUTE);
         // These AP encodings are chosen such that we never deny *privileged*
@@ -438,7 +446,7 @@ pub fn apply_memory_protection(task: &task::Task) {
         disable_mpu(mpu);
     }
 
-    for (i, region) in task.region_table().iter().enumerate() {
+    for (i, region) in task.mpu_regions().enumerate() {
         // This MPU requires that all regions are 32-byte aligned...in part
         // because it stuffs extra stuff into the bottom five bits.
         debug_assert_eq!(region.base & 0x1F, 0);
@@ -487,13 +495,19 @@ pub fn apply_memory_protection(task: &task::Task) {
         unsafe {
             mpu.rnr.write(rnr);
             mpu.rlar.write(rlar); // configure but leave disabled
+
+            // Replace (rather than accumulate into) this region's attribute
+            // byte, since the same slot can hold different kinds of memory
+            // from one task -- or one grant -- to the next.
             if rnr < 4 {
-                let mut mair0 = mpu.mair[0].read();
-                mair0 |= (mair as u32) << (rnr * 8);
+                let shift = rnr * 8;
+                let mut mair0 = mpu.mair[0].read() & !(0xFF << shift);
+                mair0 |= (mair as u32) << shift;
                 mpu.mair[0].write(mair0);
             } else {
-                let mut mair1 = mpu.mair[1].read();
-                mair1 |= (mair as u32) << ((rnr - 4) * 8);
+                let shift = (rnr - 4) * 8;
+                let mut mair1 = mpu.mair[1].read() & !(0xFF << shift);
+                mair1 |= (mair as u32) << shift;
                 mpu.mair[1].write(mair1);
             }
             mpu.rbar.write(rbar);
@@ -641,6 +655,8 @@ pub fn start_first_task(tick_divisor: u32, task: &mut task::Task) -> ! {
         mpu.ctrl.write(ENABLE | PRIVDEFENA);
     }
 
+    task.note_scheduled();
+    crate::sched::start_slice(task.priority());
     CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
 
     extern "C" {
@@ -890,6 +906,11 @@ pub unsafe extern "C" fn SVCall() {
 /// pointer while you have access to `task`, and as long as the `task` being
 /// stored is actually in the task table, you'll be okay.
 pub unsafe fn set_current_task(task: &mut task::Task) {
+    if !core::ptr::eq(CURRENT_TASK_PTR.load(Ordering::Relaxed), task) {
+        task.note_scheduled();
+        crate::sched::start_slice(task.priority());
+        crate::trace::context_switch(usize::from(task.descriptor().index));
+    }
     CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
     crate::profiling::event_context_switch(task as *mut _ as usize);
 }
@@ -918,39 +939,267 @@ static TICKS: [AtomicU32; 2] = {
     [ZERO; 2]
 };
 
+/// Advances the kernel's notion of time by `ticks`, returning the new time.
+fn advance_ticks(ticks: u32) -> Timestamp {
+    // Load the time before this tick event.
+    let t0 = TICKS[0].load(Ordering::Relaxed);
+    let t1 = TICKS[1].load(Ordering::Relaxed);
+
+    // Advance the kernel's notion of time. Laboriously.
+    let (t0, t1) = if let Some(t0p) = t0.checked_add(ticks) {
+        // Adding to t0 did not roll over, no need to update t1.
+        TICKS[0].store(t0p, Ordering::Relaxed);
+        (t0p, t1)
+    } else {
+        // Adding to t0 overflowed. We need to also increment t1. We use
+        // normal checked addition for this, not wrapping, because this should
+        // not be able to overflow under normal operation, and would almost
+        // certainly indicate state corruption that we'd like to discover.
+        let t0p = t0.wrapping_add(ticks);
+        TICKS[0].store(t0p, Ordering::Relaxed);
+        TICKS[1].store(t1 + 1, Ordering::Relaxed);
+        (t0p, t1 + 1)
+    };
+    Timestamp::from([t0, t1])
+}
+
+/// Number of ticks the SysTick timer has been stretched to cover while the
+/// idle task sleeps, or 0 if it's ticking normally.
+static SLEEP_TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// Set while the idle task deep sleeps, when the SysTick timer is paused, and
+/// the `DeepSleepClock` keeps time instead.
+static DEEP_SLEEP: AtomicBool = AtomicBool::new(false);
+
+/// Reading of the `DeepSleepClock` when we went into deep sleep.
+static DEEP_SLEEP_START: AtomicU32 = AtomicU32::new(0);
+
+/// SysTick Control and Status Register bit that enables the counter.
+const SYST_CSR_ENABLE: u32 = 1 << 0;
+
+/// SysTick Control and Status Register bit set when the counter has reached
+/// zero since it was last read.
+const SYST_CSR_COUNTFLAG: u32 = 1 << 16;
+
+/// System Control Register bit selecting deep sleep.
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+
+/// Shortest sleep we're willing to program, in ticks. Less than this doesn't
+/// save enough to be worth the trouble.
+const MIN_SLEEP_TICKS: u64 = 2;
+
+/// Reprograms the SysTick timer to interrupt after `cycles` processor cycles
+/// from now, then resume its normal period of `tick_divisor` cycles.
+///
+/// Safety: this must only be called from kernel context, and `cycles` must fit
+/// in the 24-bit reload register.
+unsafe fn restart_systick(cycles: u32, tick_divisor: u32) {
+    let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+    // Writing the current value register clears it, and the counter reloads
+    // from the reload register on the next cycle -- without an interrupt. By
+    // the time we've written the reload register again, it's too late for
+    // that write to affect this period, only the ones after.
+    unsafe {
+        syst.rvr.write(cycles - 1);
+        syst.cvr.write(0);
+        syst.rvr.write(tick_divisor - 1);
+    }
+}
+
+/// Stops the periodic kernel tick until `deadline` (or for as long as the timer
+/// can manage, if there's no deadline), and arranges for the processor to
+/// sleep `depth` deep the next time the current task, `current`, waits for an
+/// interrupt.
+///
+/// SysTick stops counting in deep sleep on the parts we support (LPC55 and
+/// STM32H7), so for deep sleep we pause it, and use the `DeepSleepClock`
+/// instead, which startup makes sure we have.
+///
+/// If the deadline is too close to be worth it, this does nothing.
+pub fn idle_sleep(
+    _tasks: &mut [task::Task],
+    _current: usize,
+    deadline: Option<Timestamp>,
+    depth: SleepDepth,
+) -> task::NextTask {
+    let clock = match depth {
+        SleepDepth::Sleep => None,
+        SleepDepth::DeepSleep => crate::time::deep_sleep_clock(),
+    };
+    let tick_divisor = CLOCK_FREQ_KHZ.load(Ordering::Relaxed);
+    let max_ticks = match clock {
+        Some(_) => u64::from(u32::MAX),
+        // The reload register is 24 bits.
+        None => u64::from(0xFF_FFFF / tick_divisor),
+    };
+    let ticks = match deadline {
+        Some(deadline) => u64::from(deadline)
+            .saturating_sub(u64::from(now()))
+            .min(max_ticks),
+        None => max_ticks,
+    };
+    if ticks < MIN_SLEEP_TICKS {
+        return task::NextTask::Same;
+    }
+    let ticks = ticks as u32;
+
+    if let Some(clock) = clock {
+        // Pausing SysTick keeps the rest of the current tick for when we
+        // wake; the clock counts the ticks in between.
+        let start = (clock.now)();
+        DEEP_SLEEP_START.store(start, Ordering::Relaxed);
+        (clock.wake_at)(start.wrapping_add(ticks));
+        enable_irq(clock.irq);
+        // Safety: we're in kernel context, so nothing else is touching these.
+        unsafe {
+            let syst = &*cortex_m::peripheral::SYST::PTR;
+            syst.csr.modify(|x| x & !SYST_CSR_ENABLE);
+            let scb = &*cortex_m::peripheral::SCB::PTR;
+            scb.scr.modify(|x| x | SCB_SCR_SLEEPDEEP);
+        }
+        DEEP_SLEEP.store(true, Ordering::Relaxed);
+    } else {
+        // Safety: we're in kernel context, and have made sure the number of
+        // cycles fits: the rest of the current tick is at most tick_divisor
+        // cycles.
+        unsafe {
+            let syst = &*cortex_m::peripheral::SYST::PTR;
+            let left = syst.cvr.read();
+            restart_systick(left + (ticks - 1) * tick_divisor, tick_divisor);
+        }
+    }
+    SLEEP_TICKS.store(ticks, Ordering::Relaxed);
+    task::NextTask::Same
+}
+
+/// Brings the kernel's notion of time up to date if the processor was woken
+/// early from an `idle_sleep` by something other than the SysTick timer, and
+/// restarts the periodic tick. Any ticks that have passed are charged to
+/// `current`.
+///
+/// This does nothing if we weren't sleeping.
+pub fn wake_from_idle_sleep(tasks: &mut [task::Task], current: Option<usize>) {
+    let ticks = SLEEP_TICKS.load(Ordering::Relaxed);
+    if ticks == 0 {
+        return;
+    }
+    if DEEP_SLEEP.load(Ordering::Relaxed) {
+        // We only deep sleep with a clock.
+        let clock = crate::time::deep_sleep_clock().unwrap_lite();
+        wake_from_deep_sleep(tasks, current, clock, ticks);
+        return;
+    }
+
+    // Safety: we're in kernel context, so nothing else is touching these.
+    let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+    if syst.csr.read() & SYST_CSR_COUNTFLAG != 0 {
+        // The sleep is over, and the SysTick interrupt is pending. It will
+        // take care of everything.
+        return;
+    }
+
+    let tick_divisor = CLOCK_FREQ_KHZ.load(Ordering::Relaxed);
+    let left = syst.cvr.read();
+    // Tick boundaries fall every tick_divisor cycles before the end of the
+    // sleep; the ones that haven't happened yet haven't elapsed.
+    let elapsed = ticks - (left + tick_divisor - 1) / tick_divisor;
+    let next = match left % tick_divisor {
+        // Reloading with 0 would stop the timer; lose a cycle instead.
+        0 | 1 => tick_divisor,
+        n => n,
+    };
+    // Safety: next is no more than tick_divisor, which fits.
+    unsafe {
+        restart_systick(next, tick_divisor);
+    }
+    finish_idle_sleep();
+
+    advance_ticks(elapsed);
+    if let Some(current) = current {
+        tasks[current].note_ticks(elapsed);
+    }
+}
+
+/// Does the work of `wake_from_idle_sleep` after a deep sleep that was meant
+/// to last `ticks`, going by `clock`.
+fn wake_from_deep_sleep(
+    tasks: &mut [task::Task],
+    current: Option<usize>,
+    clock: &DeepSleepClock,
+    ticks: u32,
+) {
+    let start = DEEP_SLEEP_START.load(Ordering::Relaxed);
+    let elapsed = (clock.now)().wrapping_sub(start);
+    (clock.cancel)();
+    disable_irq(clock.irq);
+    DEEP_SLEEP.store(false, Ordering::Relaxed);
+    // Safety: we're in kernel context, so nothing else is touching this.
+    unsafe {
+        let syst = &*cortex_m::peripheral::SYST::PTR;
+        syst.csr.modify(|x| x | SYST_CSR_ENABLE);
+    }
+
+    if elapsed >= ticks {
+        // We slept to the deadline, so there are timers to fire. The SysTick
+        // handler does that, and takes the ticks that passed from here.
+        SLEEP_TICKS.store(elapsed, Ordering::Relaxed);
+        cortex_m::peripheral::SCB::set_pendst();
+    } else {
+        finish_idle_sleep();
+        advance_ticks(elapsed);
+        if let Some(current) = current {
+            tasks[current].note_ticks(elapsed);
+        }
+    }
+}
+
+/// Clears the sleep state set up by `idle_sleep`, returning the number of ticks
+/// it covered.
+fn finish_idle_sleep() -> u32 {
+    // Safety: we're in kernel context, so nothing else is touching this.
+    unsafe {
+        let scb = &*cortex_m::peripheral::SCB::PTR;
+        scb.scr.modify(|x| x & !SCB_SCR_SLEEPDEEP);
+    }
+    let ticks = SLEEP_TICKS.load(Ordering::Relaxed);
+    SLEEP_TICKS.store(0, Ordering::Relaxed);
+    ticks
+}
+
 /// Handler that gets linked into the vector table for the System Tick Timer
 /// overflow interrupt. (Name is dictated by the `cortex_m` crate.)
 #[allow(non_snake_case)]
 #[no_mangle]
 pub unsafe extern "C" fn SysTick() {
     crate::profiling::event_timer_isr_enter();
+
+    // The timer starts a moment before the first task does, so it's possible
+    // for a tick to arrive with no current task.
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    let current = if current.is_null() {
+        None
+    } else {
+        // Safety: we're dereferencing the current task pointer, which we're
+        // trusting the rest of this module to maintain correctly.
+        Some(usize::from(unsafe { (*current).descriptor().index }))
+    };
+
     with_task_table(|tasks| {
-        // Load the time before this tick event.
-        let t0 = TICKS[0].load(Ordering::Relaxed);
-        let t1 = TICKS[1].load(Ordering::Relaxed);
-
-        // Advance the kernel's notion of time by adding 1. Laboriously.
-        let (t0, t1) = if let Some(t0p) = t0.checked_add(1) {
-            // Incrementing t0 did not roll over, no need to update t1.
-            TICKS[0].store(t0p, Ordering::Relaxed);
-            (t0p, t1)
+        // Normally a tick is a tick, but if the idle task was sleeping, this
+        // interrupt marks the end of all the ticks we skipped.
+        let elapsed = if SLEEP_TICKS.load(Ordering::Relaxed) != 0 {
+            finish_idle_sleep()
         } else {
-            // Incrementing t0 overflowed. We need to also increment t1. We use
-            // normal checked addition for this, not wrapping, because this
-            // should not be able to overflow under normal operation, and would
-            // almost certainly indicate state corruption that we'd like to
-            // discover.
-            TICKS[0].store(0, Ordering::Relaxed);
-            TICKS[1].store(t1 + 1, Ordering::Relaxed);
-            (0, t1 + 1)
+            1
         };
+        let now = advance_ticks(elapsed);
 
-        // Process any timers.
-        let now = Timestamp::from([t0, t1]);
-        let switch = task::process_timers(tasks, now);
+        // Process any timers, and charge the time to whoever it interrupted.
+        let switch = task::tick(tasks, current, elapsed, now);
 
-        // If any timers fired, we need to defer a context switch, because the entry
-        // sequence to this ISR doesn't save state correctly for efficiency.
+        // If any timers fired, or the current task's time slice ran out, we
+        // need to defer a context switch, because the entry sequence to this
+        // ISR doesn't save state correctly for efficiency.
         if switch != task::NextTask::Same {
             pend_context_switch_from_isr();
         }
@@ -1118,16 +1367,41 @@ pub unsafe extern "C" fn DefaultHandler() {
             // Hardware interrupt
             let irq_num = exception_num - 16;
             let owner = crate::startup::HUBRIS_IRQ_TASK_LOOKUP
-                .get(abi::InterruptNum(irq_num))
-                .unwrap_or_else(|| panic!("unhandled IRQ {irq_num}"));
+                .get(abi::InterruptNum(irq_num));
+            // The deep sleep clock's interrupt is the kernel's own, and only
+            // serves to wake us up, which happens below.
+            let is_wakeup = matches!(
+                crate::time::deep_sleep_clock(),
+                Some(clock) if clock.irq == irq_num
+            );
+            if owner.is_none() && !is_wakeup {
+                panic!("unhandled IRQ {irq_num}");
+            }
 
             let switch = with_task_table(|tasks| {
+                // If this woke us from a tickless sleep, catch up on the time
+                // first, since the task we notify will likely want to know.
+                let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+                if !current.is_null() {
+                    // Safety: we're trusting the rest of this module to
+                    // maintain the current task pointer correctly.
+                    let index = unsafe { (*current).descriptor().index };
+                    wake_from_idle_sleep(tasks, Some(usize::from(index)));
+                }
+                let owner = match owner {
+                    Some(owner) => owner,
+                    None => return false,
+                };
+
                 disable_irq(irq_num);
 
                 // Now, post the notification and return the
                 // scheduling hint.
                 let n = task::NotificationSet(owner.notification);
-                tasks[owner.task as usize].post(n)
+                crate::trace::irq(owner.task as usize, irq_num);
+                let task = &mut tasks[owner.task as usize];
+                task.note_irq();
+                task.post(n)
             });
             if switch {
                 pend_context_switch_from_isr()
@@ -0,0 +1,323 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Architecture support for running the kernel as an ordinary host program.
+//!
+//! This backend exists so that the portable parts of the kernel -- IPC, timers,
+//! fault delivery, kipc -- can be exercised under `cargo test` without a board.
+//! It is not a real port: there's no user mode, no memory protection, and no
+//! interrupt controller. Instead, the simulator in `crate::sim` runs each task
+//! as a host thread and enters the kernel on its behalf, one syscall at a time.
+//!
+//! Task state lives entirely in `SavedState`. We model the same register file
+//! that the ARM port uses for syscalls (r4-r11), so that the quirks of the
+//! syscall ABI -- like return values overwriting the argument registers -- are
+//! preserved.
+//!
+//! All of the statics in this module are shared by whatever simulation is
+//! currently running. The simulator takes a process-wide lock for its entire
+//! lifetime, so only one simulation can exist at a time.
+
+use core::sync::atomic::{
+    AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering,
+};
+
+use crate::atomic::AtomicExt;
+use crate::task;
+use crate::time::{SleepDepth, Timestamp};
+
+macro_rules! uassert {
+    ($cond : expr) => {
+        if !$cond {
+            panic!("Assertion failed!");
+        }
+    };
+}
+
+/// Pointer to the task most recently passed to `set_current_task`. Nothing in
+/// the hosted backend dereferences this; it's kept for parity with the ARM
+/// port and for inspection from a debugger.
+static CURRENT_TASK_PTR: AtomicPtr<task::Task> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// Recorded clock frequency, for parity with the ARM port.
+static CLOCK_FREQ_KHZ: AtomicU32 = AtomicU32::new(0);
+
+/// Simulated kernel time, in ticks. Unlike hardware, this only moves when the
+/// simulator tells it to, via `advance_time`.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Simulated interrupt enable bits, one per IRQ number, for the first 128
+/// IRQs. Higher-numbered IRQs are accepted but not tracked.
+static IRQ_ENABLED: [AtomicU32; 4] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 4]
+};
+
+/// Simulated task registers.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SavedState {
+    /// The registers used by the syscall ABI, in the same arrangement as
+    /// r4-r11 on ARM: arguments in 0..=6, syscall number in 7, and return
+    /// values in 0..=5.
+    regs: [u32; 8],
+    /// Simulated stack pointer.
+    sp: u32,
+    /// Simulated program counter. The simulator never executes code at this
+    /// address, but it records where the task would begin.
+    pc: u32,
+    /// Number of times this task has been (re)initialized. The simulator uses
+    /// this to notice that a task was restarted out from under its thread.
+    restarts: u32,
+}
+
+impl SavedState {
+    /// Loads syscall number `nr` and its arguments, as a task would just before
+    /// trapping into the kernel.
+    pub fn load_syscall(&mut self, nr: u32, args: [u32; 7]) {
+        self.regs[..7].copy_from_slice(&args);
+        self.regs[7] = nr;
+    }
+
+    /// Reads back the six syscall return registers.
+    pub fn syscall_results(&self) -> [u32; 6] {
+        let mut rets = [0; 6];
+        rets.copy_from_slice(&self.regs[..6]);
+        rets
+    }
+
+    /// Returns the number of times this task has been initialized.
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Returns the address the task would begin executing at.
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+}
+
+/// Map the simulated registers to (architecture-independent) syscall argument
+/// and return slots.
+impl task::ArchState for SavedState {
+    fn stack_pointer(&self) -> u32 {
+        self.sp
+    }
+
+    /// Reads syscall argument register 0.
+    fn arg0(&self) -> u32 {
+        self.regs[0]
+    }
+    fn arg1(&self) -> u32 {
+        self.regs[1]
+    }
+    fn arg2(&self) -> u32 {
+        self.regs[2]
+    }
+    fn arg3(&self) -> u32 {
+        self.regs[3]
+    }
+    fn arg4(&self) -> u32 {
+        self.regs[4]
+    }
+    fn arg5(&self) -> u32 {
+        self.regs[5]
+    }
+    fn arg6(&self) -> u32 {
+        self.regs[6]
+    }
+
+    fn syscall_descriptor(&self) -> u32 {
+        self.regs[7]
+    }
+
+    /// Writes syscall return argument 0.
+    fn ret0(&mut self, x: u32) {
+        self.regs[0] = x
+    }
+    fn ret1(&mut self, x: u32) {
+        self.regs[1] = x
+    }
+    fn ret2(&mut self, x: u32) {
+        self.regs[2] = x
+    }
+    fn ret3(&mut self, x: u32) {
+        self.regs[3] = x
+    }
+    fn ret4(&mut self, x: u32) {
+        self.regs[4] = x
+    }
+    fn ret5(&mut self, x: u32) {
+        self.regs[5] = x
+    }
+}
+
+/// # Safety
+///
+/// This is unsafe only for signature compatibility with the ARM port.
+pub unsafe fn set_clock_freq(tick_divisor: u32) {
+    CLOCK_FREQ_KHZ.store(tick_divisor, Ordering::Relaxed);
+}
+
+pub fn reinitialize(task: &mut task::Task) {
+    let restarts = task.save().restarts.wrapping_add(1);
+    let initial_stack = task.descriptor().initial_stack;
+
+    // Keep the same alignment requirement as the ARM port, so that a task
+    // table that works here doesn't fall over on hardware.
+    uassert!(initial_stack & 0x7 == 0);
+
+    *task.save_mut() = SavedState {
+        sp: initial_stack,
+        pc: task.descriptor().entry_point,
+        restarts,
+        ..SavedState::default()
+    };
+
+    // There's no initial frame here, so paint the whole stack.
+    task.paint_stack(initial_stack as usize);
+}
+
+/// Checks whether memory at `base` of `size` bytes can be granted to a task
+/// (see `crate::grant`). There's no memory protection unit here, so we use the
+/// ARMv8-M rules, which are the most permissive: any multiple of 32 bytes,
+/// 32-byte aligned.
+pub fn is_valid_grant(base: u32, size: u32) -> bool {
+    size >= 32
+        && size % 32 == 0
+        && base % 32 == 0
+        && base.checked_add(size).is_some()
+}
+
+/// There is no memory protection unit to program; every simulated task can
+/// scribble on every other. The kernel's own access checks (`Task::can_access`
+/// and friends) still apply, which is what we're interested in testing.
+pub fn apply_memory_protection(_task: &task::Task) {}
+
+/// The hosted backend can't enter a task the way hardware does; use
+/// `crate::sim` to run tasks instead.
+pub fn start_first_task(_tick_divisor: u32, _task: &mut task::Task) -> ! {
+    panic!("start_kernel is not supported on hosted targets; use sim");
+}
+
+/// Records the address of `task` as the current user task.
+///
+/// # Safety
+///
+/// This records a pointer that aliases `task`, for signature compatibility
+/// with the ARM port. The hosted backend never dereferences it.
+pub unsafe fn set_current_task(task: &mut task::Task) {
+    if !core::ptr::eq(CURRENT_TASK_PTR.load(Ordering::Relaxed), task) {
+        task.note_scheduled();
+        crate::sched::start_slice(task.priority());
+        crate::trace::context_switch(usize::from(task.descriptor().index));
+    }
+    CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
+    crate::profiling::event_context_switch(task as *mut _ as usize);
+}
+
+/// Reads the simulated tick counter.
+pub fn now() -> Timestamp {
+    Timestamp::from(TICKS.load(Ordering::Relaxed))
+}
+
+/// Advances simulated time by `ticks` and processes any timers that have
+/// expired as a result, as though the SysTick handler had fired `ticks` times
+/// in a row.
+///
+/// The simulator only advances time when every task is blocked, so unlike the
+/// SysTick handler, this doesn't charge the ticks to any task.
+pub fn advance_time(tasks: &mut [task::Task], ticks: u64) -> task::NextTask {
+    let t = TICKS.load(Ordering::Relaxed).checked_add(ticks).unwrap();
+    TICKS.store(t, Ordering::Relaxed);
+    task::process_timers(tasks, Timestamp::from(t))
+}
+
+/// Advances simulated time by one tick while task `current` is running, as
+/// the SysTick handler would: the tick is charged to `current`, and may end
+/// its time slice.
+pub fn tick(tasks: &mut [task::Task], current: usize) -> task::NextTask {
+    let t = TICKS.load(Ordering::Relaxed).checked_add(1).unwrap();
+    TICKS.store(t, Ordering::Relaxed);
+    task::tick(tasks, Some(current), 1, Timestamp::from(t))
+}
+
+/// Simulates a tickless sleep by task `current`: nothing can interrupt the
+/// simulated processor, so it sleeps right through to `deadline`, and the
+/// ticks are charged to `current` all at once, as the SysTick handler would on
+/// waking. With no deadline, the processor would sleep until some interrupt
+/// arrived, which in simulation is never, so this does nothing.
+pub fn idle_sleep(
+    tasks: &mut [task::Task],
+    current: usize,
+    deadline: Option<Timestamp>,
+    _depth: SleepDepth,
+) -> task::NextTask {
+    let now = TICKS.load(Ordering::Relaxed);
+    match deadline.map(u64::from) {
+        Some(t) if t > now => {
+            TICKS.store(t, Ordering::Relaxed);
+            let elapsed = u32::try_from(t - now).unwrap_or(u32::MAX);
+            task::tick(tasks, Some(current), elapsed, Timestamp::from(t))
+        }
+        _ => task::NextTask::Same,
+    }
+}
+
+/// Simulated sleeps never get interrupted, so there's nothing to do here.
+pub fn wake_from_idle_sleep(
+    _tasks: &mut [task::Task],
+    _current: Option<usize>,
+) {
+}
+
+/// Puts the simulated clock and interrupt controller back into their reset
+/// state.
+pub fn reset_machine() {
+    TICKS.store(0, Ordering::Relaxed);
+    for word in &IRQ_ENABLED {
+        word.store(0, Ordering::Relaxed);
+    }
+    CURRENT_TASK_PTR.store(core::ptr::null_mut(), Ordering::Relaxed);
+}
+
+pub fn disable_irq(n: u32) {
+    if let Some(word) = IRQ_ENABLED.get((n / 32) as usize) {
+        word.fetch_and(!(1 << (n % 32)), Ordering::Relaxed);
+    }
+}
+
+pub fn enable_irq(n: u32) {
+    if let Some(word) = IRQ_ENABLED.get((n / 32) as usize) {
+        word.fetch_or(1 << (n % 32), Ordering::Relaxed);
+    }
+}
+
+/// Checks whether IRQ `n` is currently enabled in the simulated interrupt
+/// controller.
+pub fn irq_enabled(n: u32) -> bool {
+    IRQ_ENABLED
+        .get((n / 32) as usize)
+        .map(|word| word.load(Ordering::Relaxed) & (1 << (n % 32)) != 0)
+        .unwrap_or(false)
+}
+
+pub fn reset() -> ! {
+    panic!("system reset requested");
+}
+
+impl AtomicExt for AtomicBool {
+    type Primitive = bool;
+
+    #[inline(always)]
+    fn swap_polyfill(
+        &self,
+        value: Self::Primitive,
+        ordering: Ordering,
+    ) -> Self::Primitive {
+        self.swap(value, ordering)
+    }
+}
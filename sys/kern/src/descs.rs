@@ -67,6 +67,9 @@ pub struct TaskDesc {
     /// The index is a u16 to save space in the `TaskDesc` struct; in practice
     /// other factors limit us to fewer than `2**16` tasks.
     pub index: u16,
+    /// Indices of the tasks allowed to send messages to this one, in
+    /// ascending order, or `None` if any task may.
+    pub allowed_callers: Option<&'static [u16]>,
 }
 
 bitflags::bitflags! {
@@ -88,6 +88,8 @@ impl Write for Eulogist {
     }
 }
 
+// Hosted builds link std, which brings its own panic handler.
+#[cfg(target_os = "none")]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
     die(info)
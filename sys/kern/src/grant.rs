@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Grants: leases mapped directly into a server's memory.
+//!
+//! Normally a server gets at memory a client has leased it by asking the kernel
+//! to copy it, with `BORROW_READ` and `BORROW_WRITE`. That costs a syscall and
+//! a copy through the kernel per access, which adds up on bulk data paths.
+//! Instead, a server can ask for a lease to be *granted* to it with
+//! `BORROW_MAP`. The kernel then adds the leased memory to the server's memory
+//! protection configuration, in place of one of its unused regions, and the
+//! server can use it like its own memory for as long as the client stays
+//! blocked waiting for its reply -- across as many other messages as the
+//! server cares to handle in the meantime.
+//!
+//! The grant is revoked as soon as the client stops waiting for the server:
+//! because the server replied, the client's `SEND_WITH_TIMEOUT` timed out, or
+//! the client was restarted or faulted. It's also revoked if the server is
+//! restarted. A server that touches the memory after that will fault, so
+//! servers should only map leases from clients they trust to stay put.
+//!
+//! # Revocation
+//!
+//! Rather than chasing down grants whenever a client stops waiting for a
+//! reply, we count how many times each task has done so: its _lend epoch_. A
+//! grant records the lender's epoch at the time it was made, and is only
+//! honored while the two match. `Task` bumps the epoch when the task's state
+//! leaves `InReply`, and since that's the only way a task's state can leave
+//! `InReply`, no grant can outlive the SEND that made it.
+//!
+//! Grants are checked every time memory protection is applied to the server,
+//! and every time the kernel checks the server's access to memory. If a grant
+//! is revoked by a timer while the server is running, the server keeps access
+//! until it next enters the kernel or is switched out -- but the lender can't
+//! run in the meantime, so it can't tell the difference. Syscalls that revoke
+//! one of the caller's grants, such as a `REPLY` to the lender, reapply memory
+//! protection before returning.
+//!
+//! # Concurrency
+//!
+//! Like the scheduler's ready set, the epochs are kernel globals, accessed only
+//! from kernel context, which can't be preempted or reentered. We use atomics
+//! for interior mutability, but only loads and stores, since ARMv6-M doesn't
+//! have anything fancier.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::descs::RegionDesc;
+use crate::sched::MAX_TASKS;
+
+/// Lend epoch for each task, by index.
+static EPOCHS: [AtomicU32; MAX_TASKS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; MAX_TASKS]
+};
+
+/// Revokes any grant made from the leases of the task at `index`. Called when
+/// it stops waiting for a reply.
+pub fn revoke_lent(index: usize) {
+    let epoch = &EPOCHS[index];
+    epoch.store(
+        epoch.load(Ordering::Relaxed).wrapping_add(1),
+        Ordering::Relaxed,
+    );
+}
+
+/// A lease granted to a server, recorded in the server's `Task`.
+#[derive(Copy, Clone, Debug)]
+pub struct Grant {
+    /// Index of the lending task.
+    lender: usize,
+    /// Lender's epoch when the grant was made.
+    epoch: u32,
+    /// Index of the server's region table entry the grant replaces.
+    slot: usize,
+    /// The granted memory.
+    region: RegionDesc,
+}
+
+impl Grant {
+    /// Grants `region` from the task at index `lender`, which must be waiting
+    /// for a reply from the server, in place of the server's region table
+    /// entry `slot`.
+    pub fn new(lender: usize, slot: usize, region: RegionDesc) -> Self {
+        Self {
+            lender,
+            epoch: EPOCHS[lender].load(Ordering::Relaxed),
+            slot,
+            region,
+        }
+    }
+
+    /// Checks whether the grant is still in force.
+    pub fn is_live(&self) -> bool {
+        EPOCHS[self.lender].load(Ordering::Relaxed) == self.epoch
+    }
+
+    /// Index of the server's region table entry the grant replaces.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// The granted memory.
+    pub fn region(&self) -> &RegionDesc {
+        &self.region
+    }
+}
@@ -34,6 +34,12 @@ pub fn handle_kernel_message(
         Ok(Kipcnum::ReadCaboosePos) => {
             read_caboose_pos(tasks, caller, args.response?)
         }
+        Ok(Kipcnum::ReadTaskStats) => {
+            read_task_stats(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::ReadStackUsage) => {
+            read_stack_usage(tasks, caller, args.message?, args.response?)
+        }
         #[cfg(feature = "dump")]
         Ok(Kipcnum::GetTaskDumpRegion) => {
             get_task_dump_region(tasks, caller, args.message?, args.response?)
@@ -42,6 +48,10 @@ pub fn handle_kernel_message(
         Ok(Kipcnum::ReadTaskDumpRegion) => {
             read_task_dump_region(tasks, caller, args.message?, args.response?)
         }
+        #[cfg(feature = "dump")]
+        Ok(Kipcnum::GetTraceRegion) => {
+            get_trace_region(tasks, caller, args.response?)
+        }
 
         _ => {
             // Task has sent an unknown message to the kernel. That's bad.
@@ -111,6 +121,50 @@ fn read_task_status(
     Ok(NextTask::Same)
 }
 
+fn read_task_stats(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    let stats = *tasks[index as usize].stats();
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &stats)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_stack_usage(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    let usage = tasks[index as usize].stack_usage();
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &usage)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
 fn restart_task(
     tasks: &mut [Task],
     caller: usize,
@@ -135,6 +189,10 @@ fn restart_task(
     // example. So, make a pass over the task table and unblock anyone who was
     // expecting useful work from the now-defunct task.
     for (i, task) in tasks.iter_mut().enumerate() {
+        // Any message a task abandoned with the restarted task will never be
+        // replied to, so it no longer stands in the way of new ones.
+        task.forget_abandoned_reply(index);
+
         // Just to make this a little easier to think about, don't check either
         // of the tasks involved in the restart operation. Neither should be
         // affected anyway.
@@ -231,6 +289,18 @@ fn read_caboose_pos(
     caller: usize,
     response: USlice<u8>,
 ) -> Result<NextTask, UserError> {
+    let out = caboose_bounds();
+    let response_len = serialize_response(&mut tasks[caller], response, &out)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+/// Locates the caboose at the end of our image, returning its start and end
+/// addresses, or `(0, 0)` if there isn't one.
+#[cfg(target_os = "none")]
+fn caboose_bounds() -> (u32, u32) {
     // SAFETY: populated by the linker + build system
     let header = unsafe { &crate::header::HEADER };
 
@@ -254,7 +324,7 @@ fn read_caboose_pos(
     // then we expect a random value (or 0xFFFFFFFF) as its size, which we can
     // catch because it will give us an obviously invalid start location.
     let caboose_start = image_end.saturating_sub(caboose_size);
-    let out = if caboose_start <= image_start {
+    if caboose_start <= image_start {
         (0, 0)
     } else {
         // SAFETY: we know this pointer is within the image flash region
@@ -265,13 +335,13 @@ fn read_caboose_pos(
         } else {
             (0, 0)
         }
-    };
+    }
+}
 
-    let response_len = serialize_response(&mut tasks[caller], response, &out)?;
-    tasks[caller]
-        .save_mut()
-        .set_send_response_and_length(0, response_len);
-    Ok(NextTask::Same)
+/// The hosted simulator doesn't have an image, let alone a caboose.
+#[cfg(not(target_os = "none"))]
+fn caboose_bounds() -> (u32, u32) {
+    (0, 0)
 }
 
 #[cfg(feature = "dump")]
@@ -290,6 +360,9 @@ fn get_task_dump_region(
     }
 
     let rval = if rindex == 0 {
+        // Refresh the stack high-water mark, so that it's current in the copy
+        // of the `Task` that's about to be dumped.
+        let _ = tasks[index as usize].stack_usage();
         Some(abi::TaskDumpRegion {
             base: &tasks[index as usize] as *const _ as u32,
             size: size_of::<Task>() as u32,
@@ -313,6 +386,24 @@ fn get_task_dump_region(
     Ok(NextTask::Same)
 }
 
+#[cfg(feature = "dump")]
+fn get_trace_region(
+    tasks: &mut [Task],
+    caller: usize,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let rval = crate::trace::region().map(|(base, size)| abi::TaskDumpRegion {
+        base: base as u32,
+        size: size as u32,
+    });
+
+    let response_len = serialize_response(&mut tasks[caller], response, &rval)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
 #[cfg(feature = "dump")]
 fn read_task_dump_region(
     tasks: &mut [Task],
@@ -361,6 +452,24 @@ fn read_task_dump_region(
         USlice::<u8>::from_raw(region.base as usize, region.size as usize)
             .map_err(FaultInfo::SyscallUsage)?;
 
+    // The kernel trace doesn't belong to any task, but the supervisor can
+    // include it in any task's dump (see `get_trace_region`).
+    #[cfg(feature = "trace")]
+    if let Some((base, size)) = crate::trace::region() {
+        if from.base_addr() >= base && from.end_addr() <= base + size {
+            let to = caller_task
+                .try_write(&mut response)
+                .map_err(UserError::Unrecoverable)?;
+            let len = to.len().min(from.len());
+            let copy_len =
+                crate::trace::read(from.base_addr() - base, &mut to[..len]);
+            caller_task
+                .save_mut()
+                .set_send_response_and_length(0, copy_len);
+            return Ok(NextTask::Same);
+        }
+    }
+
     //
     // If we are being asked to copy out the target task structure (and only
     // a part of the target task structure), we will copy that directly.  (If
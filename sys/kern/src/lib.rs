@@ -40,12 +40,17 @@ pub mod atomic;
 mod descs;
 pub mod err;
 pub mod fail;
+pub mod grant;
 pub mod header;
 pub mod kipc;
 pub mod profiling;
+pub mod sched;
+#[cfg(not(target_os = "none"))]
+pub mod sim;
 pub mod startup;
 pub mod syscalls;
 pub mod task;
 pub mod time;
+pub mod trace;
 pub mod umem;
 pub mod util;
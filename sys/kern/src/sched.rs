@@ -0,0 +1,192 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Scheduler bookkeeping: the ready set and time slices.
+//!
+//! # Ready set
+//!
+//! Rather than scanning the whole task table on every context switch, the
+//! scheduler keeps a bitmap of runnable tasks for each priority level, plus a
+//! summary word with one bit per level that has any runnable tasks. Picking
+//! the next task is then a matter of finding the lowest set bit in the summary
+//! (the most important level), and the next set bit after the previous task
+//! in that level's bitmap. That's a handful of instructions per word of
+//! bitmap, no matter how many tasks there are.
+//!
+//! The bitmaps are only correct if every change to a task's state is reported
+//! with `set_ready`. `Task` takes care of that -- its state can't be changed
+//! any other way -- so code outside this module and `task` shouldn't need to
+//! think about it.
+//!
+//! This is designed to make exactly the same choice as `task::priority_scan`
+//! looking for runnable tasks, which remains the reference implementation.
+//!
+//! # Time slices
+//!
+//! Hubris is normally purely priority-driven: a task runs until it blocks or
+//! something more important becomes runnable. Applications can opt in to time
+//! slicing for particular priority levels, in which case a task at that level
+//! that has run for its full slice without blocking will yield to the next
+//! runnable task at the same level, if there is one. This keeps CPU-bound tasks
+//! at equal priority from starving one another. It never allows a less
+//! important task to run.
+//!
+//! # Concurrency
+//!
+//! These are kernel globals, accessed only from kernel context, which can't be
+//! preempted or reentered. We use atomics for interior mutability, but only
+//! loads and stores, since ARMv6-M doesn't have anything fancier.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::descs::Priority;
+
+/// Number of distinct priority levels the scheduler supports. Priorities must
+/// be numerically less than this; the build checks.
+pub const PRIORITY_LEVELS: usize = 32;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "none")] {
+        /// Words in each level's bitmap: enough for one bit per task.
+        const WORDS: usize = (crate::startup::HUBRIS_TASK_COUNT + 31) / 32;
+    } else {
+        /// Words in each level's bitmap. The simulator builds its task table
+        /// at runtime, so this is its limit on task count.
+        const WORDS: usize = 2;
+    }
+}
+
+/// Maximum number of tasks the ready set can track.
+pub const MAX_TASKS: usize = WORDS * 32;
+
+/// Bit `p` is set if any task at priority `p` is runnable.
+static LEVELS: AtomicU32 = AtomicU32::new(0);
+
+/// For each priority level, a bitmap of runnable tasks by index.
+static READY: [[AtomicU32; WORDS]; PRIORITY_LEVELS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ROW: [AtomicU32; WORDS] = [ZERO; WORDS];
+    [ROW; PRIORITY_LEVELS]
+};
+
+/// Time slice for each priority level, in ticks, or 0 if tasks at that level
+/// aren't time sliced.
+static SLICES: [AtomicU32; PRIORITY_LEVELS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; PRIORITY_LEVELS]
+};
+
+/// Ticks left in the current task's time slice. Only meaningful if its
+/// priority level is time sliced.
+static SLICE_LEFT: AtomicU32 = AtomicU32::new(0);
+
+/// Resets all scheduler state, then records which of `tasks` are runnable and
+/// the time slice for each priority level.
+///
+/// # Panics
+///
+/// If there are more than `MAX_TASKS` tasks.
+pub fn reset(tasks: &[crate::task::Task], slices: &[u32; PRIORITY_LEVELS]) {
+    uassert!(tasks.len() <= MAX_TASKS);
+
+    LEVELS.store(0, Ordering::Relaxed);
+    for row in &READY {
+        for word in row {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+    for (slice, &ticks) in SLICES.iter().zip(slices) {
+        slice.store(ticks, Ordering::Relaxed);
+    }
+    SLICE_LEFT.store(0, Ordering::Relaxed);
+
+    for (i, task) in tasks.iter().enumerate() {
+        if task.is_runnable() {
+            set_ready(i, task.priority(), true);
+        }
+    }
+}
+
+/// Records whether the task at `index`, which has priority `priority`, is
+/// runnable.
+pub fn set_ready(index: usize, priority: Priority, ready: bool) {
+    let level = usize::from(priority.0);
+    let row = &READY[level];
+    let (w, bit) = (index / 32, 1 << (index % 32));
+
+    let word = row[w].load(Ordering::Relaxed);
+    let levels = LEVELS.load(Ordering::Relaxed);
+    if ready {
+        row[w].store(word | bit, Ordering::Relaxed);
+        LEVELS.store(levels | 1 << level, Ordering::Relaxed);
+    } else {
+        row[w].store(word & !bit, Ordering::Relaxed);
+        if row.iter().all(|w| w.load(Ordering::Relaxed) == 0) {
+            LEVELS.store(levels & !(1 << level), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Picks the next task to run, after `previous`: the most important runnable
+/// task, preferring the first in order after `previous` (mod the number of
+/// tasks) if several are equally important. Returns `None` if nothing is
+/// runnable.
+pub fn select(previous: usize) -> Option<usize> {
+    let levels = LEVELS.load(Ordering::Relaxed);
+    if levels == 0 {
+        return None;
+    }
+    let row = &READY[levels.trailing_zeros() as usize];
+    first_ready(row, previous + 1).or_else(|| first_ready(row, 0))
+}
+
+/// Finds the lowest-numbered runnable task in `row` at or above `from`.
+fn first_ready(row: &[AtomicU32; WORDS], from: usize) -> Option<usize> {
+    for w in from / 32..WORDS {
+        let mut bits = row[w].load(Ordering::Relaxed);
+        if w == from / 32 {
+            bits &= !0 << (from % 32);
+        }
+        if bits != 0 {
+            return Some(w * 32 + bits.trailing_zeros() as usize);
+        }
+    }
+    None
+}
+
+/// Starts a fresh time slice for a task at `priority`, which is about to be
+/// switched to.
+pub fn start_slice(priority: Priority) {
+    let ticks = SLICES[usize::from(priority.0)].load(Ordering::Relaxed);
+    SLICE_LEFT.store(ticks, Ordering::Relaxed);
+}
+
+/// Charges a tick against the time slice of the current task, `current`, which
+/// has priority `priority`. Returns `true` if the slice has run out and some
+/// other task at the same level is waiting to run.
+///
+/// If the slice runs out with nobody waiting, the task gets a fresh one.
+pub fn charge_slice(current: usize, priority: Priority) -> bool {
+    let level = usize::from(priority.0);
+    let ticks = SLICES[level].load(Ordering::Relaxed);
+    if ticks == 0 {
+        return false;
+    }
+
+    let left = SLICE_LEFT.load(Ordering::Relaxed).saturating_sub(1);
+    if left != 0 {
+        SLICE_LEFT.store(left, Ordering::Relaxed);
+        return false;
+    }
+    SLICE_LEFT.store(ticks, Ordering::Relaxed);
+
+    let (w, bit) = (current / 32, 1 << (current % 32));
+    READY[level].iter().enumerate().any(|(i, word)| {
+        let others = if i == w { !bit } else { !0 };
+        word.load(Ordering::Relaxed) & others != 0
+    })
+}
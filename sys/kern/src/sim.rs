@@ -0,0 +1,915 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hosted kernel simulator.
+//!
+//! This runs the real, architecture-independent kernel -- the syscall
+//! implementations, the scheduler, timers, kipc -- against a task table built
+//! at runtime, with each task's code supplied as a Rust closure running on its
+//! own host thread.
+//!
+//! Only one thread runs at a time. A task thread runs until it makes a
+//! syscall; the simulator then enters the kernel on its behalf, lets the kernel
+//! pick the next task, and hands control to that task's thread. From the
+//! kernel's perspective, this looks like a machine where preemption only ever
+//! happens at syscalls, which is enough to exercise the IPC and fault paths
+//! deterministically.
+//!
+//! When no task is runnable, the simulated clock jumps straight to the next
+//! timer deadline. If there isn't one, the system can make no further progress
+//! and the simulation ends. A task whose closure returns is simply stopped.
+//!
+//! Task memory is real memory, allocated below 4 GiB so that addresses survive
+//! the trip through the 32-bit syscall ABI. Each task gets a single read-write
+//! region; tasks obtain buffers in it using `Ctx::buf`.
+//!
+//! Tasks can also pretend to compute for a while with `Ctx::spin`, which lets
+//! the clock tick underneath them as the SysTick handler would, so that timer
+//! preemption and time slicing (see `Sim::time_slice`) can be exercised. A
+//! task can also make the IDLE syscall with `Ctx::idle`; in tickless mode (see
+//! `Sim::tickless`), the simulated processor sleeps right through to the next
+//! timer deadline.
+//!
+//! If a task is restarted (e.g. by a supervisor using kipc), the next time its
+//! thread is scheduled its closure is unwound and started over from the top,
+//! just as restarting a real task would.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use abi::{
+    LeaseAttributes, ReplyFaultReason, Sysnum, TaskId, TaskState, ULease,
+};
+
+use crate::arch;
+use crate::descs::{
+    RegionAttributes, RegionDesc, TaskDesc, TaskFlags, REGIONS_PER_TASK,
+};
+use crate::sched;
+use crate::task::{self, NextTask, Task};
+use crate::time::SleepDepth;
+
+/// Size of the RAM region given to each simulated task.
+pub const TASK_RAM_SIZE: u32 = 16 * 1024;
+
+/// Size of the stack at the bottom of each task's RAM, as on hardware. Task
+/// closures run on host threads, so nothing uses this unless a test asks for
+/// it with `Ctx::use_stack`.
+pub const TASK_STACK_SIZE: u32 = 1024;
+
+/// Space just above the stack reserved for the lease table passed to SEND.
+/// This limits a single send to `LEASE_SCRATCH / 12` leases.
+const LEASE_SCRATCH: u32 = 256;
+
+/// Serializes simulations, since the hosted arch backend (clock, IRQ state)
+/// is global.
+static SIM_LOCK: Mutex<()> = Mutex::new(());
+
+type Body = Arc<dyn Fn(&Ctx<'_>) + Send + Sync>;
+
+struct TaskSpec {
+    priority: u8,
+    start_at_boot: bool,
+    allowed_callers: Option<Vec<u16>>,
+    body: Body,
+}
+
+/// A simulated system, under construction.
+///
+/// Add tasks with `task` (and friends) and then call `run`. As on hardware,
+/// task 0 is the supervisor and receives fault notifications.
+pub struct Sim {
+    specs: Vec<TaskSpec>,
+    slices: [u32; sched::PRIORITY_LEVELS],
+    tickless: Option<SleepDepth>,
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sim {
+    pub fn new() -> Self {
+        Self {
+            specs: vec![],
+            slices: [0; sched::PRIORITY_LEVELS],
+            tickless: None,
+        }
+    }
+
+    /// Only allows `callers` to send messages to `task`, as its `callers` table
+    /// in `app.toml` would.
+    pub fn allow_callers(&mut self, task: TaskId, callers: &[TaskId]) {
+        let mut callers: Vec<u16> =
+            callers.iter().map(|c| c.index() as u16).collect();
+        callers.sort_unstable();
+        self.specs[task.index()].allowed_callers = Some(callers);
+    }
+
+    /// Time slices tasks at `priority` to `ticks`, as `time-slices` in the
+    /// kernel section of `app.toml` would.
+    pub fn time_slice(&mut self, priority: u8, ticks: u32) {
+        self.slices[usize::from(priority)] = ticks;
+    }
+
+    /// Enables tickless idle with the given sleep depth, as `tickless` in the
+    /// kernel section of `app.toml` would.
+    pub fn tickless(&mut self, depth: SleepDepth) {
+        self.tickless = Some(depth);
+    }
+
+    /// Adds a task at `priority` that starts at boot, returning its initial
+    /// `TaskId`.
+    pub fn task(
+        &mut self,
+        priority: u8,
+        body: impl Fn(&Ctx<'_>) + Send + Sync + 'static,
+    ) -> TaskId {
+        self.add(priority, true, Arc::new(body))
+    }
+
+    /// Adds a task at `priority` that does *not* start at boot, returning its
+    /// initial `TaskId`.
+    pub fn stopped_task(
+        &mut self,
+        priority: u8,
+        body: impl Fn(&Ctx<'_>) + Send + Sync + 'static,
+    ) -> TaskId {
+        self.add(priority, false, Arc::new(body))
+    }
+
+    fn add(&mut self, priority: u8, start_at_boot: bool, body: Body) -> TaskId {
+        let index = self.specs.len();
+        self.specs.push(TaskSpec {
+            priority,
+            start_at_boot,
+            allowed_callers: None,
+            body,
+        });
+        TaskId::for_index_and_gen(index, Default::default())
+    }
+
+    /// Boots the system and runs it until no task can make further progress.
+    ///
+    /// If any task's closure panics, the panic is propagated out of `run`.
+    pub fn run(self) -> Outcome {
+        let _serialize = SIM_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        arch::reset_machine();
+
+        let tasks = build_task_table(&self.specs);
+        let shared = Arc::new(Shared {
+            machine: Mutex::new(Machine {
+                tasks,
+                current: None,
+                failure: None,
+            }),
+            turn: Condvar::new(),
+        });
+
+        // Do the moral equivalent of `start_kernel`.
+        {
+            let mut m = shared.lock();
+            for task in m.tasks.iter_mut() {
+                arch::reinitialize(task);
+            }
+            sched::reset(&m.tasks, &self.slices);
+            crate::time::set_tickless(self.tickless);
+            crate::trace::reset();
+            let last = m.tasks.len() - 1;
+            m.switch(last, NextTask::Other);
+        }
+
+        let threads = self
+            .specs
+            .iter()
+            .enumerate()
+            .map(|(index, spec)| {
+                let shared = shared.clone();
+                let body = spec.body.clone();
+                thread::Builder::new()
+                    .name(format!("sim-task-{index}"))
+                    .spawn(move || task_thread(&shared, index, &body))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut m = shared.lock();
+        while m.current.is_some() {
+            m = shared.turn.wait(m).unwrap();
+        }
+        let failure = m.failure.take();
+        let outcome = Outcome {
+            states: m.tasks.iter().map(|t| *t.state()).collect(),
+            generations: m
+                .tasks
+                .iter()
+                .enumerate()
+                .map(|(i, _)| task::current_id(&m.tasks, i))
+                .collect(),
+            now: u64::from(arch::now()),
+        };
+        drop(m);
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        if let Some(payload) = failure {
+            panic::resume_unwind(payload);
+        }
+        outcome
+    }
+}
+
+/// Final state of a simulated system.
+#[derive(Debug)]
+pub struct Outcome {
+    states: Vec<TaskState>,
+    generations: Vec<TaskId>,
+    now: u64,
+}
+
+impl Outcome {
+    /// State of task `index` when the system stopped.
+    pub fn state(&self, index: usize) -> TaskState {
+        self.states[index]
+    }
+
+    /// Current `TaskId` of task `index` when the system stopped.
+    pub fn task_id(&self, index: usize) -> TaskId {
+        self.generations[index]
+    }
+
+    /// Simulated time when the system stopped.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+}
+
+/// Allocates task memory and produces a task table for `specs`. The memory
+/// and descriptors are leaked, since the kernel wants `'static` descriptors.
+fn build_task_table(specs: &[TaskSpec]) -> Vec<Task> {
+    assert!(!specs.is_empty(), "simulation needs at least one task");
+    assert!(specs.len() <= sched::MAX_TASKS, "too many tasks");
+    let arena = alloc_low_memory(specs.len() * TASK_RAM_SIZE as usize);
+
+    let null: &'static RegionDesc = Box::leak(Box::new(RegionDesc {
+        base: 0,
+        size: 32,
+        attributes: RegionAttributes::empty(),
+    }));
+
+    specs
+        .iter()
+        .enumerate()
+        .map(|(i, spec)| {
+            let base = arena + i as u32 * TASK_RAM_SIZE;
+            let ram: &'static RegionDesc = Box::leak(Box::new(RegionDesc {
+                base,
+                size: TASK_RAM_SIZE,
+                attributes: RegionAttributes::READ | RegionAttributes::WRITE,
+            }));
+            let mut regions = [null; REGIONS_PER_TASK];
+            regions[1] = ram;
+            let desc: &'static TaskDesc = Box::leak(Box::new(TaskDesc {
+                regions,
+                entry_point: base,
+                initial_stack: base + TASK_STACK_SIZE,
+                priority: spec.priority,
+                flags: if spec.start_at_boot {
+                    TaskFlags::START_AT_BOOT
+                } else {
+                    TaskFlags::empty()
+                },
+                index: u16::try_from(i).unwrap(),
+                allowed_callers: spec
+                    .allowed_callers
+                    .as_ref()
+                    .map(|c| &*Box::leak(c.clone().into_boxed_slice())),
+            }));
+            Task::from_descriptor(desc)
+        })
+        .collect()
+}
+
+/// Allocates `size` bytes of zeroed memory whose addresses fit in a `u32`.
+fn alloc_low_memory(size: usize) -> u32 {
+    cfg_if::cfg_if! {
+        if #[cfg(target_pointer_width = "32")] {
+            let mem = Box::leak(vec![0u64; (size + 7) / 8].into_boxed_slice());
+            mem.as_mut_ptr() as u32
+        } else if #[cfg(all(target_os = "linux", target_arch = "x86_64"))] {
+            // Safety: we're asking for fresh anonymous memory, which can't
+            // alias anything.
+            let p = unsafe {
+                libc::mmap(
+                    core::ptr::null_mut(),
+                    size,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_32BIT,
+                    -1,
+                    0,
+                )
+            };
+            assert_ne!(p, libc::MAP_FAILED, "can't allocate task memory");
+            u32::try_from(p as usize).unwrap()
+        } else {
+            compile_error!("don't know how to get memory below 4 GiB here");
+        }
+    }
+}
+
+struct Shared {
+    machine: Mutex<Machine>,
+    turn: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Machine> {
+        self.machine.lock().unwrap()
+    }
+}
+
+struct Machine {
+    tasks: Vec<Task>,
+    /// Index of the task whose thread may run, or `None` once the simulation
+    /// has stopped.
+    current: Option<usize>,
+    /// Panic payload from a task closure, to be rethrown by `Sim::run`.
+    failure: Option<Box<dyn Any + Send>>,
+}
+
+impl Machine {
+    /// Acts on a scheduling hint from the kernel, as the tail end of the ARM
+    /// syscall and PendSV handlers would. If nothing is runnable, idles until
+    /// a timer fires, or stops the simulation if none is pending.
+    fn switch(&mut self, previous: usize, mut hint: NextTask) {
+        loop {
+            let next = match hint {
+                NextTask::Same => Some(previous),
+                NextTask::Specific(i) => Some(i),
+                NextTask::Other => {
+                    let next = sched::select(previous);
+                    // Hold the ready set to the reference implementation on
+                    // every decision.
+                    assert_eq!(
+                        next,
+                        task::priority_scan(previous, &self.tasks, |t| {
+                            t.is_runnable()
+                        }),
+                        "ready set disagrees with task table",
+                    );
+                    next
+                }
+            };
+            if let Some(next) = next {
+                let task = &mut self.tasks[next];
+                arch::apply_memory_protection(task);
+                // Safety: the hosted backend never dereferences this.
+                unsafe {
+                    arch::set_current_task(task);
+                }
+                self.current = Some(next);
+                return;
+            }
+
+            let deadline = self
+                .tasks
+                .iter()
+                .filter_map(|t| t.timer().0)
+                .map(u64::from)
+                .min();
+            match deadline {
+                Some(d) => {
+                    let now = u64::from(arch::now());
+                    let _ = arch::advance_time(
+                        &mut self.tasks,
+                        d.saturating_sub(now),
+                    );
+                    hint = NextTask::Other;
+                }
+                None => {
+                    self.current = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Unwind payload used to abandon a task's closure when it's restarted.
+struct Restarted;
+
+/// Unwind payload used to abandon a task's closure when the simulation stops.
+struct Shutdown;
+
+fn task_thread(shared: &Shared, index: usize, body: &Body) {
+    loop {
+        let (restarts, ram) = {
+            let m = match wait_for_turn(shared, shared.lock(), index) {
+                Some(m) => m,
+                None => return,
+            };
+            let task = &m.tasks[index];
+            (task.save().restarts(), *task.region_table()[1])
+        };
+
+        let ctx = Ctx {
+            shared,
+            index,
+            restarts,
+            ram,
+            next_free: Cell::new(ram.base + TASK_STACK_SIZE + LEASE_SCRATCH),
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| body(&ctx)));
+
+        let mut m = shared.lock();
+        match result {
+            Ok(()) => {
+                // Running off the end of a task is not possible on hardware;
+                // here we treat it as the task stopping itself.
+                m.tasks[index].set_healthy_state(abi::SchedState::Stopped);
+                m.switch(index, NextTask::Other);
+                shared.turn.notify_all();
+            }
+            Err(p) if p.is::<Restarted>() => (),
+            Err(p) if p.is::<Shutdown>() => return,
+            Err(p) => {
+                m.failure = Some(p);
+                m.current = None;
+                shared.turn.notify_all();
+                return;
+            }
+        }
+    }
+}
+
+/// Blocks until it's `index`'s turn to run, returning `None` if the
+/// simulation stops first.
+fn wait_for_turn<'a>(
+    shared: &'a Shared,
+    mut m: MutexGuard<'a, Machine>,
+    index: usize,
+) -> Option<MutexGuard<'a, Machine>> {
+    loop {
+        match m.current {
+            Some(i) if i == index => return Some(m),
+            None => return None,
+            _ => m = shared.turn.wait(m).unwrap(),
+        }
+    }
+}
+
+/// A buffer in simulated task memory.
+#[derive(Copy, Clone, Debug)]
+pub struct Buf {
+    addr: u32,
+    len: u32,
+}
+
+impl Buf {
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies the buffer's contents out.
+    pub fn get(&self) -> Vec<u8> {
+        let mut v = vec![0; self.len()];
+        // Safety: task memory is only touched by the running task's thread or
+        // the kernel running on its behalf, and we're the former.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.addr as usize as *const u8,
+                v.as_mut_ptr(),
+                v.len(),
+            );
+        }
+        v
+    }
+
+    /// Copies `data` into the start of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// If `data` is longer than the buffer.
+    pub fn set(&self, data: &[u8]) {
+        assert!(data.len() <= self.len());
+        // Safety: as in `get`.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.addr as usize as *mut u8,
+                data.len(),
+            );
+        }
+    }
+}
+
+/// Results of a successful RECV.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RecvMessage {
+    pub sender: TaskId,
+    pub operation: u32,
+    pub message_len: usize,
+    pub response_capacity: usize,
+    pub lease_count: usize,
+}
+
+/// A simulated task's view of the world, passed to its closure.
+///
+/// The methods here mirror the raw syscalls in `userlib`, but take `Buf`s in
+/// place of slices.
+pub struct Ctx<'a> {
+    shared: &'a Shared,
+    index: usize,
+    restarts: u32,
+    ram: RegionDesc,
+    next_free: Cell<u32>,
+}
+
+impl Ctx<'_> {
+    /// Index of this task in the task table.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Produces the current `TaskId` for task `index`, as the build system
+    /// would for a task slot.
+    pub fn task_id(&self, index: usize) -> TaskId {
+        task::current_id(&self.shared.lock().tasks, index)
+    }
+
+    /// Allocates a zeroed buffer of `len` bytes in this task's memory. Buffers
+    /// are freed when the task restarts.
+    ///
+    /// # Panics
+    ///
+    /// If task memory is exhausted.
+    pub fn buf(&self, len: usize) -> Buf {
+        let addr = self.next_free.get();
+        let len = u32::try_from(len).unwrap();
+        // Keep everything word-aligned, so any buffer can hold a ULease.
+        let end = addr + ((len + 3) & !3);
+        assert!(end <= self.ram.base + self.ram.size, "task out of memory");
+        self.next_free.set(end);
+        let buf = Buf { addr, len };
+        buf.set(&vec![0; buf.len()]);
+        buf
+    }
+
+    /// Like `buf`, but aligned to `align` bytes, which must be a power of two.
+    pub fn aligned_buf(&self, len: usize, align: u32) -> Buf {
+        let addr = self.next_free.get();
+        self.next_free.set((addr + align - 1) & !(align - 1));
+        self.buf(len)
+    }
+
+    /// Scribbles over the top `depth` bytes of this task's stack, as though it
+    /// had made calls that deep.
+    pub fn use_stack(&self, depth: u32) {
+        assert!(depth <= TASK_STACK_SIZE);
+        Buf {
+            addr: self.ram.base + TASK_STACK_SIZE - depth,
+            len: depth,
+        }
+        .set(&vec![0x55; depth as usize]);
+    }
+
+    /// Allocates a buffer holding a copy of `data`.
+    pub fn buf_from(&self, data: &[u8]) -> Buf {
+        let buf = self.buf(data.len());
+        buf.set(data);
+        buf
+    }
+
+    /// Performs syscall `nr` with raw arguments, returning the raw results.
+    ///
+    /// This doesn't return if the syscall leaves this task unable to run
+    /// (e.g. it faults) until the task is next scheduled, which may be never.
+    pub fn syscall(&self, nr: Sysnum, args: [u32; 7]) -> [u32; 6] {
+        let nr = nr as u32;
+        let mut m = self.shared.lock();
+        assert_eq!(m.current, Some(self.index));
+
+        m.tasks[self.index].save_mut().load_syscall(nr, args);
+        crate::profiling::event_syscall_enter(nr);
+        let hint =
+            crate::syscalls::safe_syscall_entry(nr, self.index, &mut m.tasks);
+        crate::profiling::event_syscall_exit();
+
+        let m = self.leave_kernel(m, hint);
+        m.tasks[self.index].save().syscall_results()
+    }
+
+    /// Computes without making syscalls for `ticks` ticks of the clock. Each
+    /// tick is handled as the SysTick handler would, so this task may be
+    /// preempted, and only resumes spinning once it's scheduled again.
+    pub fn spin(&self, ticks: u64) {
+        for _ in 0..ticks {
+            let mut m = self.shared.lock();
+            assert_eq!(m.current, Some(self.index));
+            let hint = arch::tick(&mut m.tasks, self.index);
+            drop(self.leave_kernel(m, hint));
+        }
+    }
+
+    /// Acts on a scheduling `hint` from the kernel and waits for this task to
+    /// be scheduled again. Unwinds if the task was restarted in the meantime,
+    /// or if the simulation stops.
+    fn leave_kernel<'a>(
+        &'a self,
+        mut m: MutexGuard<'a, Machine>,
+        hint: NextTask,
+    ) -> MutexGuard<'a, Machine> {
+        m.switch(self.index, hint);
+        self.shared.turn.notify_all();
+
+        let m = match wait_for_turn(self.shared, m, self.index) {
+            Some(m) => m,
+            None => panic::resume_unwind(Box::new(Shutdown)),
+        };
+        if m.tasks[self.index].save().restarts() != self.restarts {
+            drop(m);
+            panic::resume_unwind(Box::new(Restarted));
+        }
+        m
+    }
+
+    /// SEND: returns the response code and response length.
+    pub fn send(
+        &self,
+        callee: TaskId,
+        operation: u16,
+        message: &Buf,
+        response: &Buf,
+        leases: &[(LeaseAttributes, Buf)],
+    ) -> (u32, usize) {
+        self.send_common(
+            Sysnum::Send,
+            callee,
+            operation,
+            message,
+            response,
+            leases,
+        )
+    }
+
+    /// SEND_WITH_TIMEOUT, wrapped in the same timer juggling as
+    /// `userlib::sys_send_with_timeout`.
+    pub fn send_with_timeout(
+        &self,
+        callee: TaskId,
+        operation: u16,
+        message: &Buf,
+        response: &Buf,
+        leases: &[(LeaseAttributes, Buf)],
+        deadline: u64,
+    ) -> (u32, usize) {
+        let (now, previous) = self.timer();
+        if deadline <= now {
+            return (abi::TIMED_OUT, 0);
+        }
+        self.set_timer(Some(deadline), 0);
+        let r = self.send_common(
+            Sysnum::SendWithTimeout,
+            callee,
+            operation,
+            message,
+            response,
+            leases,
+        );
+        self.set_timer(previous.0, previous.1);
+        r
+    }
+
+    fn send_common(
+        &self,
+        nr: Sysnum,
+        callee: TaskId,
+        operation: u16,
+        message: &Buf,
+        response: &Buf,
+        leases: &[(LeaseAttributes, Buf)],
+    ) -> (u32, usize) {
+        let table = Buf {
+            addr: self.ram.base + TASK_STACK_SIZE,
+            len: LEASE_SCRATCH,
+        };
+        let mut bytes = vec![];
+        for (atts, buf) in leases {
+            bytes.extend_from_slice(&atts.bits().to_le_bytes());
+            bytes.extend_from_slice(&buf.addr.to_le_bytes());
+            bytes.extend_from_slice(&buf.len.to_le_bytes());
+        }
+        assert_eq!(bytes.len(), leases.len() * core::mem::size_of::<ULease>());
+        table.set(&bytes);
+
+        let r = self.syscall(
+            nr,
+            [
+                u32::from(callee.0) << 16 | u32::from(operation),
+                message.addr,
+                message.len,
+                response.addr,
+                response.len,
+                table.addr,
+                leases.len() as u32,
+            ],
+        );
+        (r[0], r[1] as usize)
+    }
+
+    /// Open RECV, or closed RECV if `from` is given.
+    pub fn recv(
+        &self,
+        buffer: &Buf,
+        notification_mask: u32,
+        from: Option<TaskId>,
+    ) -> Result<RecvMessage, u32> {
+        let r = self.syscall(
+            Sysnum::Recv,
+            [
+                buffer.addr,
+                buffer.len,
+                notification_mask,
+                from.map(|id| 1 << 31 | u32::from(id.0)).unwrap_or(0),
+                0,
+                0,
+                0,
+            ],
+        );
+        if r[0] != 0 {
+            return Err(r[0]);
+        }
+        Ok(RecvMessage {
+            sender: TaskId(r[1] as u16),
+            operation: r[2],
+            message_len: r[3] as usize,
+            response_capacity: r[4] as usize,
+            lease_count: r[5] as usize,
+        })
+    }
+
+    pub fn reply(&self, peer: TaskId, code: u32, message: &Buf) {
+        self.syscall(
+            Sysnum::Reply,
+            [u32::from(peer.0), code, message.addr, message.len, 0, 0, 0],
+        );
+    }
+
+    pub fn reply_fault(&self, peer: TaskId, reason: ReplyFaultReason) {
+        self.syscall(
+            Sysnum::ReplyFault,
+            [u32::from(peer.0), reason as u32, 0, 0, 0, 0, 0],
+        );
+    }
+
+    /// BORROW_READ: returns the response code and number of bytes read.
+    pub fn borrow_read(
+        &self,
+        lender: TaskId,
+        lease: usize,
+        offset: usize,
+        dest: &Buf,
+    ) -> (u32, usize) {
+        self.borrow(Sysnum::BorrowRead, lender, lease, offset, dest)
+    }
+
+    /// BORROW_WRITE: returns the response code and number of bytes written.
+    pub fn borrow_write(
+        &self,
+        lender: TaskId,
+        lease: usize,
+        offset: usize,
+        src: &Buf,
+    ) -> (u32, usize) {
+        self.borrow(Sysnum::BorrowWrite, lender, lease, offset, src)
+    }
+
+    fn borrow(
+        &self,
+        nr: Sysnum,
+        lender: TaskId,
+        lease: usize,
+        offset: usize,
+        buf: &Buf,
+    ) -> (u32, usize) {
+        let r = self.syscall(
+            nr,
+            [
+                u32::from(lender.0),
+                lease as u32,
+                offset as u32,
+                buf.addr,
+                buf.len,
+                0,
+                0,
+            ],
+        );
+        (r[0], r[1] as usize)
+    }
+
+    /// BORROW_INFO: returns the lease attributes and length, or the error code
+    /// if the lender has gone away.
+    pub fn borrow_info(
+        &self,
+        lender: TaskId,
+        lease: usize,
+    ) -> Result<(LeaseAttributes, usize), u32> {
+        let r = self.syscall(
+            Sysnum::BorrowInfo,
+            [u32::from(lender.0), lease as u32, 0, 0, 0, 0, 0],
+        );
+        if r[0] != 0 {
+            return Err(r[0]);
+        }
+        Ok((LeaseAttributes::from_bits_truncate(r[1]), r[2] as usize))
+    }
+
+    /// BORROW_MAP: returns the granted memory, or the response code.
+    pub fn borrow_map(&self, lender: TaskId, lease: usize) -> Result<Buf, u32> {
+        let r = self.syscall(
+            Sysnum::BorrowMap,
+            [u32::from(lender.0), lease as u32, 0, 0, 0, 0, 0],
+        );
+        if r[0] != 0 {
+            return Err(r[0]);
+        }
+        Ok(Buf {
+            addr: r[1],
+            len: r[2],
+        })
+    }
+
+    /// POST: returns the response code.
+    pub fn post(&self, peer: TaskId, bits: u32) -> u32 {
+        self.syscall(Sysnum::Post, [u32::from(peer.0), bits, 0, 0, 0, 0, 0])[0]
+    }
+
+    pub fn set_timer(&self, deadline: Option<u64>, notification: u32) {
+        let d = deadline.unwrap_or(0);
+        self.syscall(
+            Sysnum::SetTimer,
+            [
+                deadline.is_some() as u32,
+                d as u32,
+                (d >> 32) as u32,
+                notification,
+                0,
+                0,
+                0,
+            ],
+        );
+    }
+
+    /// GET_TIMER: returns the current time.
+    pub fn now(&self) -> u64 {
+        self.timer().0
+    }
+
+    /// GET_TIMER: returns the current time, and the timer's deadline and
+    /// notification bits.
+    pub fn timer(&self) -> (u64, (Option<u64>, u32)) {
+        let r = self.syscall(Sysnum::GetTimer, [0; 7]);
+        let now = u64::from(r[0]) | u64::from(r[1]) << 32;
+        let deadline = u64::from(r[3]) | u64::from(r[4]) << 32;
+        (now, (if r[2] != 0 { Some(deadline) } else { None }, r[5]))
+    }
+
+    pub fn refresh_task_id(&self, id: TaskId) -> TaskId {
+        let r = self.syscall(
+            Sysnum::RefreshTaskId,
+            [u32::from(id.0), 0, 0, 0, 0, 0, 0],
+        );
+        TaskId(r[0] as u16)
+    }
+
+    /// IDLE: lets the kernel sleep until the next timer deadline, if this is
+    /// the only runnable task and tickless mode is on.
+    pub fn idle(&self) {
+        self.syscall(Sysnum::Idle, [0; 7]);
+    }
+
+    /// PANIC: faults this task. Doesn't return.
+    pub fn panic(&self, message: &[u8]) -> ! {
+        let msg = self.buf_from(message);
+        self.syscall(Sysnum::Panic, [msg.addr, msg.len, 0, 0, 0, 0, 0]);
+        unreachable!("task resumed after panic");
+    }
+}
+
+#[cfg(test)]
+mod tests;
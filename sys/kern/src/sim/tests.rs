@@ -0,0 +1,1044 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Kernel tests, run in the simulator.
+//!
+//! By convention, task 0 is a supervisor that just parks in RECV unless a test
+//! needs it to do more.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use abi::{
+    FaultInfo, Generation, Kipcnum, LeaseAttributes, ReplyFaultReason,
+    SchedState, TaskId, TaskState, UsageError,
+};
+
+use super::*;
+use crate::startup::HUBRIS_FAULT_NOTIFICATION;
+
+fn idle_supervisor(ctx: &Ctx<'_>) {
+    let buf = ctx.buf(0);
+    loop {
+        ctx.recv(&buf, 0, None).unwrap();
+    }
+}
+
+/// A server that echoes each message back, reversed.
+fn reversing_server(ctx: &Ctx<'_>) {
+    let buf = ctx.buf(16);
+    loop {
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        let mut data = buf.get();
+        data.truncate(msg.message_len);
+        data.reverse();
+        ctx.reply(msg.sender, 0, &ctx.buf_from(&data));
+    }
+}
+
+#[test]
+fn send_recv_reply() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, reversing_server);
+    sim.task(2, move |ctx| {
+        let msg = ctx.buf_from(b"hubris");
+        let resp = ctx.buf(16);
+        let (rc, len) = ctx.send(server, 1, &msg, &resp, &[]);
+        assert_eq!(rc, 0);
+        assert_eq!(&resp.get()[..len], b"sirbuh");
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+    assert_eq!(
+        outcome.state(1),
+        TaskState::Healthy(SchedState::InRecv(None))
+    );
+}
+
+#[test]
+fn recv_reports_operation_and_capacity() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, |ctx| {
+        let buf = ctx.buf(8);
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        assert_eq!(msg.sender, ctx.task_id(2));
+        assert_eq!(msg.operation, 0x1234);
+        assert_eq!(msg.message_len, 3);
+        assert_eq!(msg.response_capacity, 5);
+        assert_eq!(msg.lease_count, 0);
+        ctx.reply(msg.sender, 7, &ctx.buf(0));
+    });
+    sim.task(2, move |ctx| {
+        let (rc, len) = ctx.send(server, 0x1234, &ctx.buf(3), &ctx.buf(5), &[]);
+        assert_eq!((rc, len), (7, 0));
+    });
+    sim.run();
+}
+
+#[test]
+fn reply_fault_faults_client_and_notifies_supervisor() {
+    let notified = Arc::new(AtomicU32::new(0));
+    let n = notified.clone();
+
+    let mut sim = Sim::new();
+    sim.task(0, move |ctx| {
+        let buf = ctx.buf(0);
+        let msg = ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        assert_eq!(msg.sender, TaskId::KERNEL);
+        n.store(msg.operation, Ordering::Relaxed);
+    });
+    let server = sim.task(1, |ctx| {
+        let buf = ctx.buf(0);
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        ctx.reply_fault(msg.sender, ReplyFaultReason::UndefinedOperation);
+    });
+    sim.task(2, move |ctx| {
+        ctx.send(server, 99, &ctx.buf(0), &ctx.buf(0), &[]);
+        unreachable!("client resumed after fault");
+    });
+    let outcome = sim.run();
+
+    assert_eq!(notified.load(Ordering::Relaxed), HUBRIS_FAULT_NOTIFICATION);
+    assert_eq!(
+        outcome.state(2),
+        TaskState::Faulted {
+            fault: FaultInfo::FromServer(
+                server,
+                ReplyFaultReason::UndefinedOperation
+            ),
+            original_state: SchedState::InReply(server),
+        }
+    );
+}
+
+#[test]
+fn send_to_out_of_range_task_faults() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        let bogus = TaskId::for_index_and_gen(9, Generation::default());
+        ctx.send(bogus, 0, &ctx.buf(0), &ctx.buf(0), &[]);
+    });
+    let outcome = sim.run();
+    assert_eq!(
+        outcome.state(1),
+        TaskState::Faulted {
+            fault: FaultInfo::SyscallUsage(UsageError::TaskOutOfRange),
+            original_state: SchedState::Runnable,
+        }
+    );
+}
+
+#[test]
+fn borrow_read_and_write() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, |ctx| {
+        let buf = ctx.buf(0);
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        assert_eq!(msg.lease_count, 2);
+
+        let (atts, len) = ctx.borrow_info(msg.sender, 0).unwrap();
+        assert_eq!(atts, LeaseAttributes::READ);
+        assert_eq!(len, 4);
+        let (atts, len) = ctx.borrow_info(msg.sender, 1).unwrap();
+        assert_eq!(atts, LeaseAttributes::WRITE);
+        assert_eq!(len, 4);
+
+        // Read from the second half of the input lease...
+        let tmp = ctx.buf(2);
+        assert_eq!(ctx.borrow_read(msg.sender, 0, 2, &tmp), (0, 2));
+        assert_eq!(tmp.get(), b"cd");
+        // ...and write it into the first half of the output lease.
+        assert_eq!(ctx.borrow_write(msg.sender, 1, 0, &tmp), (0, 2));
+
+        // Attempting to write through the read-only lease is a defect.
+        assert_eq!(ctx.borrow_write(msg.sender, 0, 0, &tmp), (abi::DEFECT, 0));
+
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+    });
+    sim.task(2, move |ctx| {
+        let input = ctx.buf_from(b"abcd");
+        let output = ctx.buf_from(b"wxyz");
+        let (rc, _) = ctx.send(
+            server,
+            0,
+            &ctx.buf(0),
+            &ctx.buf(0),
+            &[
+                (LeaseAttributes::READ, input),
+                (LeaseAttributes::WRITE, output),
+            ],
+        );
+        assert_eq!(rc, 0);
+        assert_eq!(output.get(), b"cdyz");
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn borrow_out_of_range_lease_faults_server() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, |ctx| {
+        let buf = ctx.buf(0);
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        ctx.borrow_read(msg.sender, 3, 0, &ctx.buf(1));
+    });
+    sim.task(2, move |ctx| {
+        ctx.send(server, 0, &ctx.buf(0), &ctx.buf(0), &[]);
+    });
+    let outcome = sim.run();
+    assert!(matches!(
+        outcome.state(1),
+        TaskState::Faulted {
+            fault: FaultInfo::SyscallUsage(UsageError::LeaseOutOfRange),
+            ..
+        }
+    ));
+    // The client is left waiting on a reply that will never come.
+    assert_eq!(
+        outcome.state(2),
+        TaskState::Healthy(SchedState::InReply(server))
+    );
+}
+
+#[test]
+fn post_wakes_notification_recv() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let waiter = sim.task(1, |ctx| {
+        let buf = ctx.buf(0);
+        let msg = ctx.recv(&buf, 0b0110, Some(TaskId::KERNEL)).unwrap();
+        assert_eq!(msg.sender, TaskId::KERNEL);
+        // Bit 0 isn't in our mask, so we should only see bit 2.
+        assert_eq!(msg.operation, 0b0100);
+    });
+    sim.task(2, move |ctx| {
+        assert_eq!(ctx.post(waiter, 0b0101), 0);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(1), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn timer_fires_while_idle() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        let start = ctx.now();
+        ctx.set_timer(Some(start + 100), 1);
+        let buf = ctx.buf(0);
+        let msg = ctx.recv(&buf, 1, Some(TaskId::KERNEL)).unwrap();
+        assert_eq!(msg.operation, 1);
+        assert_eq!(ctx.now(), start + 100);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.now(), 100);
+}
+
+/// Sends a kipc `RestartTask` from the supervisor.
+fn restart(ctx: &Ctx<'_>, index: usize) {
+    let mut msg = [0; 8];
+    let n = ssmarshal::serialize(&mut msg, &(index as u32, true)).unwrap();
+    let (rc, _) = ctx.send(
+        TaskId::KERNEL,
+        Kipcnum::RestartTask as u16,
+        &ctx.buf_from(&msg[..n]),
+        &ctx.buf(0),
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+#[test]
+fn restart_bumps_generation_and_reruns_task() {
+    let starts = Arc::new(AtomicU32::new(0));
+    let s = starts.clone();
+
+    let mut sim = Sim::new();
+    sim.task(0, |ctx| {
+        let buf = ctx.buf(0);
+        // Restart the client once, after its first fault.
+        ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        restart(ctx, 1);
+        loop {
+            ctx.recv(&buf, 0, None).unwrap();
+        }
+    });
+    sim.task(1, move |ctx| {
+        if s.fetch_add(1, Ordering::Relaxed) == 0 {
+            ctx.panic(b"first time");
+        }
+    });
+    let outcome = sim.run();
+
+    assert_eq!(starts.load(Ordering::Relaxed), 2);
+    assert_eq!(outcome.state(1), TaskState::Healthy(SchedState::Stopped));
+    assert_eq!(outcome.task_id(1).generation(), Generation::from(1));
+}
+
+#[test]
+fn send_to_stale_generation_gets_dead_code() {
+    let mut sim = Sim::new();
+    sim.task(0, |ctx| {
+        let buf = ctx.buf(0);
+        // Restart the server when the client asks, then get out of the way.
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        restart(ctx, 1);
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+    });
+    let stale = sim.task(1, reversing_server);
+    sim.task(2, move |ctx| {
+        let (rc, _) =
+            ctx.send(ctx.task_id(0), 0, &ctx.buf(0), &ctx.buf(0), &[]);
+        assert_eq!(rc, 0);
+        let (rc, _) = ctx.send(stale, 0, &ctx.buf(0), &ctx.buf(0), &[]);
+        assert_eq!(rc, abi::dead_response_code(Generation::from(1)));
+        let fresh = ctx.refresh_task_id(stale);
+        assert_eq!(fresh.generation(), Generation::from(1));
+    });
+    sim.run();
+}
+
+/// A server that receives one message and then never replies to anything.
+fn unresponsive_server(ctx: &Ctx<'_>) {
+    let buf = ctx.buf(4);
+    loop {
+        ctx.recv(&buf, 0, None).unwrap();
+    }
+}
+
+#[test]
+fn send_with_timeout_gives_up_waiting_for_reply() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, unresponsive_server);
+    sim.task(2, move |ctx| {
+        let (rc, len) =
+            ctx.send_with_timeout(server, 0, &ctx.buf(0), &ctx.buf(4), &[], 50);
+        assert_eq!((rc, len), (abi::TIMED_OUT, 0));
+        assert_eq!(ctx.now(), 50);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn send_with_timeout_gives_up_waiting_for_recv() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    // This server is stuck waiting on a closed receive from the supervisor.
+    let server = sim.task(1, |ctx| {
+        ctx.recv(&ctx.buf(0), 0, Some(ctx.task_id(0))).unwrap();
+    });
+    sim.task(2, move |ctx| {
+        let (rc, _) =
+            ctx.send_with_timeout(server, 0, &ctx.buf(0), &ctx.buf(0), &[], 5);
+        assert_eq!(rc, abi::TIMED_OUT);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+    assert_eq!(
+        outcome.state(1),
+        TaskState::Healthy(SchedState::InRecv(Some(outcome.task_id(0))))
+    );
+}
+
+#[test]
+fn send_with_timeout_completes_and_restores_timer() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, reversing_server);
+    sim.task(2, move |ctx| {
+        ctx.set_timer(Some(1000), 0b10);
+        let resp = ctx.buf(2);
+        let (rc, len) = ctx.send_with_timeout(
+            server,
+            0,
+            &ctx.buf_from(b"ab"),
+            &resp,
+            &[],
+            10,
+        );
+        assert_eq!((rc, len), (0, 2));
+        assert_eq!(resp.get(), b"ba");
+        assert_eq!(ctx.timer().1, (Some(1000), 0b10));
+
+        // Make sure the timer, once restored, no longer cancels sends.
+        let msg = ctx.recv(&ctx.buf(0), 0b10, Some(TaskId::KERNEL)).unwrap();
+        assert_eq!(msg.operation, 0b10);
+        assert_eq!(ctx.now(), 1000);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn send_with_timeout_needs_armed_timer() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, reversing_server);
+    sim.task(2, move |ctx| {
+        // Bypass the wrapper to issue the raw syscall with no timer set.
+        let r = ctx.syscall(
+            Sysnum::SendWithTimeout,
+            [u32::from(server.0) << 16, 0, 0, 0, 0, 0, 0],
+        );
+        assert_eq!(r[0], abi::TIMED_OUT);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn borrow_after_send_timeout_is_defect() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    // A slow server, which sleeps on its timer before touching the lease.
+    let server = sim.task(1, |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        assert_eq!(msg.lease_count, 1);
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        let (rc, _) = ctx.borrow_read(msg.sender, 0, 0, &ctx.buf(4));
+        assert_eq!(rc, abi::DEFECT);
+        // The reply is silently discarded.
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+    });
+    sim.task(2, move |ctx| {
+        let lease = ctx.buf(4);
+        let (rc, _) = ctx.send_with_timeout(
+            server,
+            0,
+            &ctx.buf(0),
+            &ctx.buf(0),
+            &[(LeaseAttributes::READ, lease)],
+            10,
+        );
+        assert_eq!(rc, abi::TIMED_OUT);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.now(), 100);
+    assert_eq!(outcome.state(1), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn send_after_timeout_waits_for_late_reply() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    // A slow server, which replies to its first message late.
+    let server = sim.task(1, |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        ctx.reply(msg.sender, 0, &ctx.buf_from(b"late"));
+        reversing_server(ctx);
+    });
+    sim.task(2, move |ctx| {
+        let resp = ctx.buf(4);
+        let (rc, _) =
+            ctx.send_with_timeout(server, 0, &ctx.buf(0), &resp, &[], 10);
+        assert_eq!(rc, abi::TIMED_OUT);
+
+        // The server still owes us a reply, so we can't send it anything new.
+        let (rc, _) = ctx.send(server, 0, &ctx.buf_from(b"ab"), &resp, &[]);
+        assert_eq!(rc, abi::TIMED_OUT);
+        assert_eq!(ctx.now(), 10);
+
+        // Once it has replied (into the void), we can.
+        ctx.set_timer(Some(200), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        let (rc, len) = ctx.send(server, 0, &ctx.buf_from(b"ab"), &resp, &[]);
+        assert_eq!((rc, len), (0, 2));
+        assert_eq!(&resp.get()[..2], b"ba");
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn send_waits_for_every_late_reply() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    // Two slow servers, which reply to their first messages late, the first
+    // well after the second.
+    let first = sim.task(1, |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        ctx.set_timer(Some(200), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        ctx.reply(msg.sender, 0, &ctx.buf_from(b"late"));
+        reversing_server(ctx);
+    });
+    let second = sim.task(1, |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        ctx.reply(msg.sender, 0, &ctx.buf_from(b"late"));
+        reversing_server(ctx);
+    });
+    sim.task(2, move |ctx| {
+        let resp = ctx.buf(4);
+        for (server, deadline) in [(first, 10), (second, 20)] {
+            let (rc, _) = ctx.send_with_timeout(
+                server,
+                0,
+                &ctx.buf(0),
+                &resp,
+                &[],
+                deadline,
+            );
+            assert_eq!(rc, abi::TIMED_OUT);
+            assert_eq!(ctx.now(), deadline);
+        }
+
+        // Giving up on the second server doesn't let us forget the first.
+        let (rc, _) = ctx.send(first, 0, &ctx.buf_from(b"ab"), &resp, &[]);
+        assert_eq!(rc, abi::TIMED_OUT);
+
+        // Once the second has replied, we can send to it, but still not to
+        // the first.
+        ctx.set_timer(Some(150), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        let (rc, len) = ctx.send(second, 0, &ctx.buf_from(b"cd"), &resp, &[]);
+        assert_eq!((rc, len), (0, 2));
+        assert_eq!(&resp.get()[..2], b"dc");
+        let (rc, _) = ctx.send(first, 0, &ctx.buf_from(b"ab"), &resp, &[]);
+        assert_eq!(rc, abi::TIMED_OUT);
+
+        // Once the first has replied too, we get the reply to our new message,
+        // not the late one.
+        ctx.set_timer(Some(250), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        let (rc, len) = ctx.send(first, 0, &ctx.buf_from(b"ab"), &resp, &[]);
+        assert_eq!((rc, len), (0, 2));
+        assert_eq!(&resp.get()[..2], b"ba");
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(3), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn restart_forgets_abandoned_reply() {
+    let mut sim = Sim::new();
+    sim.task(0, |ctx| {
+        // Restart the server when the client asks, then get out of the way.
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        restart(ctx, 1);
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+    });
+    let server = sim.task(1, unresponsive_server);
+    sim.task(2, move |ctx| {
+        let resp = ctx.buf(4);
+        let (rc, _) =
+            ctx.send_with_timeout(server, 0, &ctx.buf(0), &resp, &[], 10);
+        assert_eq!(rc, abi::TIMED_OUT);
+
+        // Once the server has been restarted, the reply we were owed will
+        // never come, so it no longer holds us up: the new instance receives
+        // our message, rather than it being refused straight away.
+        let (rc, _) = ctx.send(ctx.task_id(0), 0, &ctx.buf(0), &resp, &[]);
+        assert_eq!(rc, 0);
+        let server = ctx.refresh_task_id(server);
+        let (rc, _) =
+            ctx.send_with_timeout(server, 0, &ctx.buf(0), &resp, &[], 30);
+        assert_eq!(rc, abi::TIMED_OUT);
+        assert_eq!(ctx.now(), 30);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+/// Reads a task's statistics with the kipc `ReadTaskStats`.
+fn read_task_stats(ctx: &Ctx<'_>, index: usize) -> abi::TaskStats {
+    let resp = ctx.buf(core::mem::size_of::<abi::TaskStats>());
+    let (rc, len) = ctx.send(
+        TaskId::KERNEL,
+        Kipcnum::ReadTaskStats as u16,
+        &ctx.buf_from(&(index as u32).to_le_bytes()),
+        &resp,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&resp.get()[..len]).unwrap().0
+}
+
+#[test]
+fn task_stats_count_syscalls_and_switches() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, reversing_server);
+    sim.task(2, move |ctx| {
+        for _ in 0..3 {
+            let (rc, _) = ctx.send(server, 0, &ctx.buf(0), &ctx.buf(0), &[]);
+            assert_eq!(rc, 0);
+        }
+
+        // Our three sends, plus the one carrying this request.
+        let ours = read_task_stats(ctx, 2);
+        assert_eq!(ours.syscalls, 4);
+        assert_eq!(ours.schedules, 4);
+
+        // The server's first RECV, then a REPLY and RECV for each message.
+        let theirs = read_task_stats(ctx, 1);
+        assert_eq!(theirs.syscalls, 7);
+        assert_eq!(theirs.schedules, 4);
+        assert_eq!(theirs.ticks, 0);
+        assert_eq!(theirs.irqs, 0);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn task_stats_survive_restart() {
+    let mut sim = Sim::new();
+    sim.task(0, |ctx| {
+        let buf = ctx.buf(0);
+        ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        let before = read_task_stats(ctx, 1);
+        restart(ctx, 1);
+        ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        let after = read_task_stats(ctx, 1);
+        assert_eq!(after.syscalls, before.syscalls * 2);
+    });
+    sim.task(1, |ctx| ctx.panic(b"again"));
+    let outcome = sim.run();
+    assert_eq!(outcome.state(0), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn read_task_stats_out_of_range_faults_caller() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        read_task_stats(ctx, 2);
+    });
+    let outcome = sim.run();
+    assert_eq!(
+        outcome.state(1),
+        TaskState::Faulted {
+            fault: FaultInfo::SyscallUsage(UsageError::TaskOutOfRange),
+            original_state: SchedState::Runnable,
+        }
+    );
+}
+
+/// Reads a task's stack usage with the kipc `ReadStackUsage`.
+fn read_stack_usage(ctx: &Ctx<'_>, index: usize) -> abi::StackUsage {
+    let resp = ctx.buf(core::mem::size_of::<abi::StackUsage>());
+    let (rc, len) = ctx.send(
+        TaskId::KERNEL,
+        Kipcnum::ReadStackUsage as u16,
+        &ctx.buf_from(&(index as u32).to_le_bytes()),
+        &resp,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&resp.get()[..len]).unwrap().0
+}
+
+#[test]
+fn stack_usage_tracks_deepest_point() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        let usage = read_stack_usage(ctx, 1);
+        assert_eq!(usage.size, TASK_STACK_SIZE);
+        assert_eq!(usage.depth, 0);
+
+        ctx.use_stack(200);
+        ctx.use_stack(40);
+        let usage = read_stack_usage(ctx, 1);
+        assert_eq!(usage.depth, 200);
+        assert_eq!(usage.high_water, 200);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(1), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn stack_high_water_survives_restart() {
+    let mut sim = Sim::new();
+    sim.task(0, |ctx| {
+        let buf = ctx.buf(0);
+        ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        restart(ctx, 1);
+        ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        let usage = read_stack_usage(ctx, 1);
+        assert_eq!(usage.depth, 64);
+        assert_eq!(usage.high_water, 512);
+    });
+    let runs = Arc::new(AtomicU32::new(0));
+    sim.task(1, move |ctx| {
+        if runs.fetch_add(1, Ordering::Relaxed) == 0 {
+            ctx.use_stack(512);
+        } else {
+            ctx.use_stack(64);
+        }
+        ctx.panic(b"done");
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(0), TaskState::Healthy(SchedState::Stopped));
+}
+
+type Log = Arc<std::sync::Mutex<Vec<usize>>>;
+
+/// A task that spins for `count` ticks, one at a time, noting its index in
+/// `log` each time it gets to go.
+fn spinner(log: &Log, count: usize) -> impl Fn(&Ctx<'_>) + Send + Sync {
+    let log = log.clone();
+    move |ctx| {
+        for _ in 0..count {
+            log.lock().unwrap().push(ctx.index());
+            ctx.spin(1);
+        }
+    }
+}
+
+#[test]
+fn spinning_task_starves_peer_without_slicing() {
+    let log = Log::default();
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(1, spinner(&log, 4));
+    sim.task(1, spinner(&log, 4));
+    let outcome = sim.run();
+    assert_eq!(*log.lock().unwrap(), [1, 1, 1, 1, 2, 2, 2, 2]);
+    assert_eq!(outcome.now(), 8);
+}
+
+#[test]
+fn time_slice_alternates_equal_priority_spinners() {
+    let log = Log::default();
+    let mut sim = Sim::new();
+    sim.time_slice(1, 2);
+    sim.task(0, idle_supervisor);
+    sim.task(1, spinner(&log, 4));
+    sim.task(1, spinner(&log, 4));
+    sim.run();
+    assert_eq!(*log.lock().unwrap(), [1, 1, 2, 2, 1, 1, 2, 2]);
+}
+
+#[test]
+fn time_slice_never_runs_less_important_task() {
+    let log = Log::default();
+    let mut sim = Sim::new();
+    sim.time_slice(1, 1);
+    sim.time_slice(2, 1);
+    sim.task(0, idle_supervisor);
+    sim.task(2, spinner(&log, 2));
+    sim.task(1, spinner(&log, 3));
+    sim.run();
+    assert_eq!(*log.lock().unwrap(), [2, 2, 2, 1, 1]);
+}
+
+#[test]
+fn timer_preempts_spinning_task() {
+    let log = Log::default();
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(2, spinner(&log, 6));
+    let waker = log.clone();
+    sim.task(1, move |ctx| {
+        ctx.set_timer(Some(3), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        assert_eq!(ctx.now(), 3);
+        waker.lock().unwrap().push(ctx.index());
+    });
+    sim.run();
+    assert_eq!(*log.lock().unwrap(), [1, 1, 1, 2, 1, 1, 1]);
+}
+
+#[test]
+fn send_from_disallowed_caller_is_refused() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, |ctx| {
+        let buf = ctx.buf(16);
+        let msg = ctx.recv(&buf, 0, None).unwrap();
+        // Only the allowed client's message ever arrives.
+        assert_eq!(msg.sender.index(), 2);
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+    });
+    let allowed = sim.task(2, move |ctx| {
+        let (rc, _) = ctx.send(server, 0, &ctx.buf(0), &ctx.buf(0), &[]);
+        assert_eq!(rc, 0);
+    });
+    sim.task(3, move |ctx| {
+        let (rc, len) = ctx.send(server, 0, &ctx.buf(0), &ctx.buf(0), &[]);
+        assert_eq!(rc, abi::ACCESS_DENIED);
+        assert_eq!(len, 0);
+    });
+    sim.allow_callers(server, &[allowed]);
+    let outcome = sim.run();
+    assert_eq!(outcome.state(1), TaskState::Healthy(SchedState::Stopped));
+    assert_eq!(outcome.state(3), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn disallowed_caller_can_still_use_kernel() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, idle_supervisor);
+    sim.task(2, |ctx| {
+        read_task_stats(ctx, 1);
+    });
+    sim.allow_callers(server, &[]);
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn tickless_idle_sleeps_until_next_deadline() {
+    let mut sim = Sim::new();
+    sim.tickless(SleepDepth::Sleep);
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        assert_eq!(ctx.now(), 100);
+    });
+    sim.task(2, |ctx| {
+        ctx.idle();
+        // The sleep counts as time spent in the idle task.
+        assert_eq!(ctx.now(), 100);
+        assert_eq!(read_task_stats(ctx, 2).ticks, 100);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(1), TaskState::Healthy(SchedState::Stopped));
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn idle_without_tickless_does_nothing() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+    });
+    sim.task(2, |ctx| {
+        ctx.idle();
+        assert_eq!(ctx.now(), 0);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.now(), 100);
+}
+
+#[test]
+fn tickless_idle_stays_awake_for_runnable_peer() {
+    let mut sim = Sim::new();
+    sim.tickless(SleepDepth::Sleep);
+    sim.task(0, idle_supervisor);
+    sim.task(1, |ctx| {
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+    });
+    sim.task(2, |ctx| {
+        ctx.idle();
+        assert_eq!(ctx.now(), 0);
+    });
+    sim.task(2, |ctx| {
+        assert_eq!(ctx.now(), 0);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+    assert_eq!(outcome.state(3), TaskState::Healthy(SchedState::Stopped));
+}
+
+/// Sends the first four bytes of `granted` to `echo` (a `reversing_server`),
+/// which only works while we can access them, returning the reply.
+fn send_from_grant(ctx: &Ctx<'_>, echo: TaskId, granted: &Buf) -> Vec<u8> {
+    let message = Buf {
+        addr: granted.addr(),
+        len: 4,
+    };
+    let response = ctx.buf(4);
+    let (rc, len) = ctx.send(echo, 0, &message, &response, &[]);
+    assert_eq!((rc, len), (0, 4));
+    response.get()
+}
+
+#[test]
+fn borrow_map_grants_lease_until_reply() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let echo = sim.task(1, reversing_server);
+    let server = sim.task(2, move |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        let granted = ctx.borrow_map(msg.sender, 0).unwrap();
+        assert_eq!(granted.len(), 64);
+        assert_eq!(send_from_grant(ctx, echo, &granted), b"dcba");
+
+        // Only one grant at a time.
+        assert_eq!(
+            ctx.borrow_map(msg.sender, 0).unwrap_err(),
+            abi::UNGRANTABLE
+        );
+
+        // Once we've replied, the memory is no longer ours, and trying to use
+        // it is a fault.
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+        send_from_grant(ctx, echo, &granted);
+    });
+    sim.task(3, move |ctx| {
+        let buf = ctx.aligned_buf(64, 64);
+        buf.set(&[b"abcd".as_slice(), &[0; 60]].concat());
+        let (rc, _) = ctx.send(
+            server,
+            0,
+            &ctx.buf(0),
+            &ctx.buf(0),
+            &[(LeaseAttributes::READ, buf)],
+        );
+        assert_eq!(rc, 0);
+    });
+    let outcome = sim.run();
+    assert!(matches!(
+        outcome.state(2),
+        TaskState::Faulted {
+            fault: FaultInfo::MemoryAccess { .. },
+            ..
+        }
+    ));
+    assert_eq!(outcome.state(3), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn borrow_map_refuses_misaligned_lease() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        assert_eq!(
+            ctx.borrow_map(msg.sender, 0).unwrap_err(),
+            abi::UNGRANTABLE
+        );
+        // It can still be copied, though.
+        let tmp = ctx.buf(4);
+        assert_eq!(ctx.borrow_read(msg.sender, 0, 0, &tmp), (0, 4));
+        assert_eq!(tmp.get(), b"abcd");
+        ctx.reply(msg.sender, 0, &ctx.buf(0));
+    });
+    sim.task(2, move |ctx| {
+        let buf = ctx.aligned_buf(36, 32);
+        buf.set(&[b"abcd".as_slice(), &[0; 32]].concat());
+        let (rc, _) = ctx.send(
+            server,
+            0,
+            &ctx.buf(0),
+            &ctx.buf(0),
+            &[(LeaseAttributes::READ, buf)],
+        );
+        assert_eq!(rc, 0);
+    });
+    let outcome = sim.run();
+    assert_eq!(outcome.state(2), TaskState::Healthy(SchedState::Stopped));
+}
+
+#[test]
+fn grant_revoked_when_lender_times_out() {
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let echo = sim.task(1, reversing_server);
+    let server = sim.task(2, move |ctx| {
+        let msg = ctx.recv(&ctx.buf(0), 0, None).unwrap();
+        let granted = ctx.borrow_map(msg.sender, 0).unwrap();
+        assert_eq!(send_from_grant(ctx, echo, &granted), b"dcba");
+
+        // Dawdle until the client gives up on us.
+        ctx.set_timer(Some(100), 1);
+        ctx.recv(&ctx.buf(0), 1, Some(TaskId::KERNEL)).unwrap();
+        send_from_grant(ctx, echo, &granted);
+    });
+    sim.task(3, move |ctx| {
+        let buf = ctx.aligned_buf(32, 32);
+        buf.set(&[b"abcd".as_slice(), &[0; 28]].concat());
+        let (rc, _) = ctx.send_with_timeout(
+            server,
+            0,
+            &ctx.buf(0),
+            &ctx.buf(0),
+            &[(LeaseAttributes::READ, buf)],
+            50,
+        );
+        assert_eq!(rc, abi::TIMED_OUT);
+    });
+    let outcome = sim.run();
+    assert!(matches!(
+        outcome.state(2),
+        TaskState::Faulted {
+            fault: FaultInfo::MemoryAccess { .. },
+            ..
+        }
+    ));
+    assert_eq!(outcome.state(3), TaskState::Healthy(SchedState::Stopped));
+}
+
+/// Reads the kernel trace, oldest first, as (event, task, arg) triples.
+#[cfg(feature = "trace")]
+fn read_trace() -> Vec<(abi::TraceEvent, usize, u32)> {
+    let mut events = vec![];
+    crate::trace::for_each(|r| {
+        events.push((r.event, usize::from(r.task), r.arg));
+    });
+    events
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn trace_records_ipc_round_trip() {
+    use abi::TraceEvent::{ContextSwitch, SyscallEnter, SyscallExit};
+
+    let mut sim = Sim::new();
+    sim.task(0, idle_supervisor);
+    let server = sim.task(1, reversing_server);
+    sim.task(2, move |ctx| {
+        ctx.send(server, 1, &ctx.buf_from(b"hi"), &ctx.buf(2), &[]);
+        let events = read_trace();
+        let send = (SyscallEnter, 2, Sysnum::Send as u32);
+        let start = events.iter().position(|e| *e == send).unwrap();
+        assert_eq!(
+            events[start..],
+            [
+                send,
+                (SyscallExit, 2, Sysnum::Send as u32),
+                (ContextSwitch, 1, 0),
+                (SyscallEnter, 1, Sysnum::Reply as u32),
+                (SyscallExit, 1, Sysnum::Reply as u32),
+                (SyscallEnter, 1, Sysnum::Recv as u32),
+                (SyscallExit, 1, Sysnum::Recv as u32),
+                (ContextSwitch, 2, 0),
+            ]
+        );
+    });
+    sim.run();
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn trace_records_fault_and_supervisor_notification() {
+    use abi::TraceEvent::{Fault, Post};
+
+    let mut sim = Sim::new();
+    sim.task(0, |ctx| {
+        let buf = ctx.buf(0);
+        ctx.recv(&buf, HUBRIS_FAULT_NOTIFICATION, None).unwrap();
+        let events = read_trace()
+            .into_iter()
+            .filter(|e| matches!(e.0, Fault | Post))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [(Fault, 1, 8), (Post, 0, HUBRIS_FAULT_NOTIFICATION)]
+        );
+    });
+    sim.task(1, |ctx| ctx.panic(b"oops"));
+    let outcome = sim.run();
+    assert_eq!(outcome.state(0), TaskState::Healthy(SchedState::Stopped));
+}
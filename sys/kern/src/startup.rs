@@ -79,6 +79,25 @@ pub unsafe fn start_kernel(tick_divisor: u32) -> ! {
         crate::arch::reinitialize(task);
     }
 
+    // Tell the scheduler which tasks start out runnable.
+    crate::sched::reset(task_table, &HUBRIS_TIME_SLICES);
+    crate::time::set_tickless(HUBRIS_TICKLESS);
+
+    // Deep sleep can stop the kernel's timer, so rather than let time drift,
+    // refuse to start without a clock that keeps going, and whose interrupt
+    // is the kernel's alone.
+    if HUBRIS_TICKLESS == Some(crate::time::SleepDepth::DeepSleep) {
+        let clock = crate::time::deep_sleep_clock().unwrap_or_else(|| {
+            panic!("tickless deep sleep needs a DeepSleepClock")
+        });
+        if HUBRIS_IRQ_TASK_LOOKUP
+            .get(abi::InterruptNum(clock.irq))
+            .is_some()
+        {
+            panic!("DeepSleepClock IRQ {} is assigned to a task", clock.irq);
+        }
+    }
+
     // Great! Pick our first task. We'll act like we're scheduling after the
     // last task, which will cause a scan from 0 on.
     let first_task_index =
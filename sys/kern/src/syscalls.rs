@@ -30,13 +30,15 @@
 use core::convert::TryFrom;
 
 use abi::{
-    FaultInfo, LeaseAttributes, SchedState, Sysnum, TaskId, TaskState, ULease,
-    UsageError,
+    FaultInfo, FaultSource, LeaseAttributes, SchedState, Sysnum, TaskId,
+    TaskState, ULease, UsageError,
 };
 use unwrap_lite::UnwrapLite;
 
 use crate::arch;
+use crate::descs::{RegionAttributes, RegionDesc};
 use crate::err::{InteractFault, UserError};
+use crate::grant::Grant;
 use crate::startup::with_task_table;
 use crate::task::{self, current_id, ArchState, NextTask, Task};
 use crate::time::Timestamp;
@@ -94,10 +96,17 @@ pub unsafe extern "C" fn syscall_entry(nr: u32, task: *mut Task) {
 }
 
 /// Factored out of `syscall_entry` to encapsulate the bits that don't need
-/// unsafe.
-fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
+/// unsafe. The hosted simulator also enters the kernel here.
+pub(crate) fn safe_syscall_entry(
+    nr: u32,
+    current: usize,
+    tasks: &mut [Task],
+) -> NextTask {
+    tasks[current].note_syscall();
+    crate::trace::syscall_enter(current, nr);
+
     let res = match Sysnum::try_from(nr) {
-        Ok(Sysnum::Send) => send(tasks, current),
+        Ok(Sysnum::Send) => send(tasks, current, false),
         Ok(Sysnum::Recv) => recv(tasks, current).map_err(UserError::from),
         Ok(Sysnum::Reply) => reply(tasks, current).map_err(UserError::from),
         Ok(Sysnum::SetTimer) => Ok(set_timer(&mut tasks[current], arch::now())),
@@ -112,12 +121,15 @@ fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
         Ok(Sysnum::ReplyFault) => {
             reply_fault(tasks, current).map_err(UserError::from)
         }
+        Ok(Sysnum::SendWithTimeout) => send(tasks, current, true),
+        Ok(Sysnum::Idle) => Ok(idle(tasks, current)),
+        Ok(Sysnum::BorrowMap) => borrow_map(tasks, current),
         Err(_) => {
             // Bogus syscall number! That's a fault.
             Err(FaultInfo::SyscallUsage(UsageError::BadSyscallNumber).into())
         }
     };
-    match res {
+    let hint = match res {
         Ok(nt) => nt,
         Err(UserError::Recoverable(code, hint)) => {
             tasks[current].save_mut().set_error_response(code);
@@ -126,32 +138,66 @@ fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
         Err(UserError::Unrecoverable(fault)) => {
             task::force_fault(tasks, current, fault)
         }
+    };
+
+    // If that ended a grant to the caller (say, by replying to the lender),
+    // make sure it loses access to the memory before it gets back to work.
+    if tasks[current].prune_grant() {
+        arch::apply_memory_protection(&tasks[current]);
     }
+    crate::trace::syscall_exit(current, nr);
+    hint
 }
 
 /// Implementation of the SEND IPC primitive.
 ///
 /// `caller` is a valid task index (i.e. not directly from user code).
 ///
+/// If `timeout` is true, this is a `SEND_WITH_TIMEOUT`: the caller's timer
+/// doubles as the deadline for the whole operation, and if it fires before a
+/// reply arrives, the caller is unblocked with `abi::TIMED_OUT` (see
+/// `process_timers`). A timer that isn't armed is treated as a deadline that
+/// has already passed.
+///
 /// # Panics
 ///
 /// If `caller` is out of range for `tasks`.
-fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
+fn send(
+    tasks: &mut [Task],
+    caller: usize,
+    timeout: bool,
+) -> Result<NextTask, UserError> {
+    if timeout && tasks[caller].timer().0.is_none() {
+        return Err(UserError::Recoverable(abi::TIMED_OUT, NextTask::Same));
+    }
+    tasks[caller].set_timer_cancels_send(timeout);
+
     // Extract callee.
     let callee_id = tasks[caller].save().as_send_args().callee;
 
-    // Check IPC filter - TODO
-    // Open question: should out-of-range task IDs be handled by faulting below,
-    // or by failing the IPC filter? Either condition will fault...
-
     // Route kernel messages.
     if callee_id == TaskId::KERNEL {
         return crate::kipc::handle_kernel_message(tasks, caller);
     }
 
     // Verify the given callee ID, converting it into a table index on success.
+    // Out-of-range IDs fault here, before the IPC filter, since no
+    // configuration could make them valid.
     let callee = task::check_task_id_against_table(tasks, callee_id)?;
 
+    // Check IPC filter. A forbidden send is refused outright, without the
+    // callee ever hearing about it.
+    if !tasks[callee].allows_caller(caller) {
+        return Err(UserError::Recoverable(abi::ACCESS_DENIED, NextTask::Same));
+    }
+
+    // If the caller gave up on a message that the callee received, the
+    // callee's reply to it is still to come, and we'd have no way to tell it
+    // apart from a reply to this one. Refuse until it has arrived.
+    if tasks[caller].has_abandoned_reply(callee) {
+        return Err(UserError::Recoverable(abi::TIMED_OUT, NextTask::Same));
+    }
+
     // Check for ready peer.
     let mut next_task = NextTask::Same;
     let caller_id = current_id(tasks, caller);
@@ -323,6 +369,7 @@ fn reply(tasks: &mut [Task], caller: usize) -> Result<NextTask, FaultInfo> {
         // Huh. The target task is off doing something else. This can happen if
         // application-specific supervisory logic unblocks it before we've had a
         // chance to reply (e.g. to implement timeouts).
+        tasks[callee].discard_reply_from(caller_id);
         return Ok(NextTask::Same);
     }
 
@@ -414,6 +461,35 @@ fn get_timer(task: &mut Task, now: Timestamp) -> NextTask {
     NextTask::Same
 }
 
+/// Implementation of the `IDLE` syscall.
+///
+/// In tickless mode, this stops the periodic kernel tick and puts the
+/// processor into the configured sleep state until the next timer deadline of
+/// any task, so that when the caller waits for an interrupt, nothing wakes it
+/// up for no reason. Whatever interrupt does wake the processor, the kernel's
+/// notion of time is corrected before anything else happens.
+///
+/// This only makes sense when the caller is the only runnable task -- which
+/// the idle task always is -- and otherwise does nothing. It also does
+/// nothing if tickless mode is off.
+fn idle(tasks: &mut [Task], caller: usize) -> NextTask {
+    let depth = match crate::time::tickless() {
+        Some(depth) => depth,
+        None => return NextTask::Same,
+    };
+
+    // If the processor was already asleep and was woken by something that
+    // didn't take care of it (unlikely), fix the time first.
+    arch::wake_from_idle_sleep(tasks, Some(caller));
+
+    if crate::sched::select(caller) != Some(caller) {
+        return NextTask::Same;
+    }
+
+    let deadline = tasks.iter().filter_map(|t| t.timer().0).min();
+    arch::idle_sleep(tasks, caller, deadline, depth)
+}
+
 fn borrow_read(
     tasks: &mut [Task],
     caller: usize,
@@ -517,6 +593,82 @@ fn borrow_info(
     Ok(NextTask::Same)
 }
 
+/// Implementation of the `BORROW_MAP` syscall: grants a lease to the caller
+/// (see `crate::grant`).
+///
+/// `caller` is a valid task index (i.e. not directly from user code).
+fn borrow_map(
+    tasks: &mut [Task],
+    caller: usize,
+) -> Result<NextTask, UserError> {
+    // Collect parameters from caller.
+    let args = tasks[caller].save().as_borrow_args();
+
+    let lender = task::check_task_id_against_table(tasks, args.lender)?;
+
+    let lease = borrow_lease(tasks, caller, lender, args.lease_number, 0)?;
+
+    let mut atts = RegionAttributes::empty();
+    if lease.attributes.contains(LeaseAttributes::READ) {
+        atts |= RegionAttributes::READ;
+    }
+    if lease.attributes.contains(LeaseAttributes::WRITE) {
+        atts |= RegionAttributes::WRITE;
+    }
+
+    // Check that the lender is lending memory it has, like the copying
+    // borrows do.
+    let lent = USlice::<u8>::from_raw(
+        lease.base_address as usize,
+        lease.length as usize,
+    );
+    match lent {
+        Ok(slice) if tasks[lender].can_grant(&slice, atts) => (),
+        _ => {
+            let wake_hint = task::force_fault(
+                tasks,
+                lender,
+                FaultInfo::MemoryAccess {
+                    address: Some(lease.base_address),
+                    source: FaultSource::Kernel,
+                },
+            );
+            return Err(UserError::Recoverable(abi::DEFECT, wake_hint));
+        }
+    }
+
+    // Then, check that we can actually map it.
+    let slot = tasks[caller].grant_slot();
+    let slot = match slot {
+        Some(slot)
+            if tasks[caller].grant().is_none()
+                && arch::is_valid_grant(lease.base_address, lease.length) =>
+        {
+            slot
+        }
+        _ => {
+            return Err(UserError::Recoverable(
+                abi::UNGRANTABLE,
+                NextTask::Same,
+            ))
+        }
+    };
+
+    let region = RegionDesc {
+        base: lease.base_address,
+        size: lease.length,
+        attributes: atts,
+    };
+    let task = &mut tasks[caller];
+    task.set_grant(Grant::new(lender, slot, region));
+    // We're returning to the caller, so its memory protection has to be
+    // updated now.
+    arch::apply_memory_protection(task);
+    task.save_mut()
+        .set_borrow_map_result(lease.base_address, lease.length);
+    Ok(NextTask::Same)
+}
+
 fn borrow_lease(
     tasks: &mut [Task],
     caller: usize,
@@ -803,6 +955,7 @@ fn reply_fault(
         // Huh. The target task is off doing something else. This can happen if
         // application-specific supervisory logic unblocks it before we've had a
         // chance to reply (e.g. to implement timeouts).
+        tasks[callee].discard_reply_from(caller_id);
         return Ok(NextTask::Same);
     }
 
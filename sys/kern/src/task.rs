@@ -8,8 +8,8 @@ use core::convert::TryFrom;
 use core::ops::Range;
 
 use abi::{
-    FaultInfo, FaultSource, Generation, ReplyFaultReason, SchedState, TaskId,
-    TaskState, ULease, UsageError,
+    FaultInfo, FaultSource, Generation, ReplyFaultReason, SchedState,
+    StackUsage, TaskId, TaskState, TaskStats, ULease, UsageError,
 };
 use zerocopy::FromBytes;
 
@@ -18,10 +18,16 @@ use crate::descs::{
     REGIONS_PER_TASK,
 };
 use crate::err::UserError;
+use crate::grant::Grant;
+use crate::sched::MAX_TASKS;
 use crate::startup::HUBRIS_FAULT_NOTIFICATION;
 use crate::time::Timestamp;
 use crate::umem::USlice;
 
+/// Pattern painted over the unused part of a task's stack whenever the task
+/// is started, so that we can later tell how deep the stack has gone.
+pub const STACK_PAINT: u32 = 0xbaddcafe;
+
 /// Internal representation of a task.
 ///
 /// The fields of this struct are private to this module so that we can maintain
@@ -47,6 +53,19 @@ pub struct Task {
     /// Notification status.
     notifications: u32,
 
+    /// Execution statistics, maintained across restarts.
+    stats: TaskStats,
+    /// Deepest stack usage we've observed, in bytes, maintained across
+    /// restarts. This is only updated when someone asks (see `stack_usage`),
+    /// but we do make sure to ask before the task is restarted or dumped.
+    stack_high_water: u32,
+    /// Lease granted to this task by a client, if any. See `crate::grant`.
+    grant: Option<Grant>,
+    /// Bitmap, by task index, of servers holding a message that this task
+    /// gave up waiting for a reply to (see `cancel_send`), until each of them
+    /// replies to it or is restarted.
+    abandoned_replies: [u32; MAX_TASKS / 32],
+
     /// Pointer to the ROM descriptor used to create this task, so it can be
     /// restarted.
     descriptor: &'static TaskDesc,
@@ -68,6 +87,10 @@ impl Task {
 
             generation: 0,
             notifications: 0,
+            stats: TaskStats::default(),
+            stack_high_water: 0,
+            grant: None,
+            abandoned_replies: [0; MAX_TASKS / 32],
             save: crate::arch::SavedState::default(),
             timer: crate::task::TimerState::default(),
         }
@@ -222,7 +245,7 @@ impl Task {
             return true;
         }
         let forbidden = forbidden | RegionAttributes::DEVICE;
-        self.region_table().iter().any(|region| {
+        self.mpu_regions().any(|region| {
             region.covers(slice)
                 && region.attributes.contains(desired)
                 && !region.attributes.intersects(forbidden)
@@ -239,6 +262,7 @@ impl Task {
     /// its own global ID, which it does not.
     #[must_use]
     pub fn post(&mut self, n: NotificationSet) -> bool {
+        crate::trace::post(usize::from(self.descriptor.index), n.0);
         self.notifications |= n.0;
 
         // We only need to check the mask, and make updates, if the task is
@@ -248,7 +272,7 @@ impl Task {
                 // A bit the task is interested in has newly become set!
                 // Interrupt it.
                 self.save.set_recv_result(TaskId::KERNEL, firing, 0, 0, 0);
-                self.state = TaskState::Healthy(SchedState::Runnable);
+                self.set_state(TaskState::Healthy(SchedState::Runnable));
                 return true;
             }
         }
@@ -308,6 +332,59 @@ impl Task {
         (self.timer.deadline, self.timer.to_post)
     }
 
+    /// Controls whether this task's timer, when it fires, abandons any SEND
+    /// the task is blocked in (see `cancel_send`). This is set on every entry
+    /// to SEND, so it only has an effect for the send in progress.
+    pub fn set_timer_cancels_send(&mut self, cancels: bool) {
+        self.timer.cancels_send = cancels;
+    }
+
+    /// If this task is blocked in SEND, either waiting for the callee to
+    /// receive or waiting for its reply, abandons the operation and makes the
+    /// task runnable with a response code of `abi::TIMED_OUT`. Returns `true`
+    /// if the task was unblocked, `false` if it wasn't sending.
+    ///
+    /// If the callee has already received the message, it is not informed:
+    /// its attempts to borrow from this task will fail as though the task had
+    /// defected, and its eventual reply will be discarded. So that the late
+    /// reply can't be mistaken for the reply to a new message, this task can't
+    /// send the callee anything more until it has replied (see
+    /// `has_abandoned_reply`). This holds for every callee the task has
+    /// abandoned a message with, not just the most recent.
+    #[must_use]
+    fn cancel_send(&mut self) -> bool {
+        match self.state {
+            TaskState::Healthy(SchedState::InSend(_)) => {}
+            TaskState::Healthy(SchedState::InReply(callee)) => {
+                let i = callee.index();
+                self.abandoned_replies[i / 32] |= 1 << (i % 32);
+            }
+            _ => return false,
+        }
+        self.save.set_send_response_and_length(abi::TIMED_OUT, 0);
+        self.set_state(TaskState::Healthy(SchedState::Runnable));
+        true
+    }
+
+    /// Checks whether the server at task index `server` still holds a message
+    /// this task abandoned waiting for a reply to. SEND to that server fails
+    /// with `abi::TIMED_OUT` until it replies.
+    pub fn has_abandoned_reply(&self, server: usize) -> bool {
+        self.abandoned_replies[server / 32] & 1 << (server % 32) != 0
+    }
+
+    /// Notes that `server` has replied to a message this task wasn't waiting
+    /// for, which settles any message the task abandoned with it.
+    pub fn discard_reply_from(&mut self, server: TaskId) {
+        self.forget_abandoned_reply(server.index());
+    }
+
+    /// Forgets any message this task abandoned with the server at task index
+    /// `server`, because the server has been restarted and won't reply to it.
+    pub fn forget_abandoned_reply(&mut self, server: usize) {
+        self.abandoned_replies[server / 32] &= !(1 << (server % 32));
+    }
+
     /// Rewrites this task's state back to its initial form, to effect a task
     /// reboot.
     ///
@@ -322,11 +399,74 @@ impl Task {
         self.generation = self.generation.wrapping_add(1);
         self.timer = TimerState::default();
         self.notifications = 0;
-        self.state = TaskState::default();
+        self.grant = None;
+        self.abandoned_replies = [0; MAX_TASKS / 32];
+        self.set_state(TaskState::default());
+
+        // Record how deep the outgoing incarnation got before its stack is
+        // repainted.
+        let _ = self.stack_usage();
 
         crate::arch::reinitialize(self);
     }
 
+    /// Returns the memory region containing this task's stack -- the one that
+    /// contains its initial stack pointer -- and the stack's size in words.
+    /// The stack runs from the base of the region up to the initial stack
+    /// pointer.
+    fn stack_region(&self) -> Option<(&'static RegionDesc, usize)> {
+        let initial_stack = self.descriptor.initial_stack as usize;
+        self.region_table()
+            .iter()
+            .find(|region| region.contains(initial_stack))
+            .map(|region| {
+                (*region, (initial_stack - region.base as usize) >> 2)
+            })
+    }
+
+    /// Fills this task's stack with `STACK_PAINT`, from the bottom up to (but
+    /// not including) address `top`. Anything the architecture needs on the
+    /// stack to start the task should live above `top`.
+    pub fn paint_stack(&mut self, top: usize) {
+        if let Some((region, _)) = self.stack_region() {
+            let words = top.saturating_sub(region.base as usize) >> 2;
+            if let Ok(mut uslice) =
+                USlice::from_raw(region.base as usize, words)
+            {
+                if let Ok(stack) = self.try_write(&mut uslice) {
+                    stack.fill(STACK_PAINT);
+                }
+            }
+        }
+    }
+
+    /// Measures this task's stack usage by finding the lowest word that no
+    /// longer holds `STACK_PAINT`, and updates the high-water mark.
+    ///
+    /// If the task's stack can't be found, this reports all zeroes.
+    pub fn stack_usage(&mut self) -> StackUsage {
+        let untouched = self.stack_region().and_then(|(region, words)| {
+            let uslice: USlice<u32> =
+                USlice::from_raw(region.base as usize, words).ok()?;
+            let stack = self.try_read(&uslice).ok()?;
+            let untouched =
+                stack.iter().take_while(|&&w| w == STACK_PAINT).count();
+            Some((words, untouched))
+        });
+        let (words, untouched) = match untouched {
+            Some(x) => x,
+            None => return StackUsage::default(),
+        };
+
+        let depth = ((words - untouched) * 4) as u32;
+        self.stack_high_water = self.stack_high_water.max(depth);
+        StackUsage {
+            size: (words * 4) as u32,
+            depth,
+            high_water: self.stack_high_water,
+        }
+    }
+
     /// Returns a reference to the `TaskDesc` that was used to initially create
     /// this task.
     pub fn descriptor(&self) -> &'static TaskDesc {
@@ -338,6 +478,73 @@ impl Task {
         &self.descriptor.regions
     }
 
+    /// Returns the regions memory protection should currently allow this task
+    /// to access: its region table, with its grant (if it has one that's still
+    /// in force) in place of the entry it replaced.
+    pub fn mpu_regions(&self) -> impl Iterator<Item = &RegionDesc> {
+        let grant = self.grant.as_ref().filter(|g| g.is_live());
+        self.region_table()
+            .iter()
+            .enumerate()
+            .map(move |(i, region)| match grant {
+                Some(g) if g.slot() == i => g.region(),
+                _ => *region,
+            })
+    }
+
+    /// Returns the memory granted to this task, if it has a grant that's
+    /// still in force.
+    pub fn grant(&self) -> Option<&RegionDesc> {
+        self.grant
+            .as_ref()
+            .filter(|g| g.is_live())
+            .map(Grant::region)
+    }
+
+    /// Records a grant to this task, replacing any other.
+    pub fn set_grant(&mut self, grant: Grant) {
+        self.grant = Some(grant);
+    }
+
+    /// Forgets this task's grant if it's no longer in force, returning `true`
+    /// if there was one, meaning memory protection needs to be reapplied.
+    pub fn prune_grant(&mut self) -> bool {
+        match self.grant {
+            Some(g) if !g.is_live() => {
+                self.grant = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Finds an entry in this task's region table that can be replaced by a
+    /// grant: one that confers no access, usually the null region. Returns its
+    /// index.
+    pub fn grant_slot(&self) -> Option<usize> {
+        self.region_table()
+            .iter()
+            .rposition(|region| region.attributes.is_empty())
+    }
+
+    /// Tests whether this task could lend `slice` to another task as a grant
+    /// with `atts` access: it must be normal, non-`DMA` memory the task itself
+    /// can access that way, according to its region table. (Memory granted to
+    /// the task doesn't count, since it can't be granted onward.)
+    #[must_use]
+    pub fn can_grant(
+        &self,
+        slice: &USlice<u8>,
+        atts: RegionAttributes,
+    ) -> bool {
+        let forbidden = RegionAttributes::DEVICE | RegionAttributes::DMA;
+        self.region_table().iter().any(|region| {
+            region.covers(slice)
+                && region.attributes.contains(atts)
+                && !region.attributes.intersects(forbidden)
+        })
+    }
+
     /// Returns this task's current generation number.
     pub fn generation(&self) -> Generation {
         const MASK: u8 = ((1u32 << (16 - TaskId::INDEX_BITS)) - 1) as u8;
@@ -367,12 +574,40 @@ impl Task {
     ///
     /// If you attempt to use this to bring a task out of fault state.
     pub fn set_healthy_state(&mut self, s: SchedState) {
-        let last = core::mem::replace(&mut self.state, s.into());
+        let last = self.set_state(s.into());
         if let TaskState::Faulted { .. } = last {
             panic!();
         }
     }
 
+    /// Replaces this task's state, returning the old one, and keeps the
+    /// scheduler's ready set and this task's lend epoch (see `crate::grant`) up
+    /// to date. All state changes must go through here.
+    fn set_state(&mut self, state: TaskState) -> TaskState {
+        let runnable = TaskState::Healthy(SchedState::Runnable);
+        let last = core::mem::replace(&mut self.state, state);
+        let index = usize::from(self.descriptor.index);
+        if let TaskState::Healthy(SchedState::InReply(_)) = last {
+            if state != last {
+                crate::grant::revoke_lent(index);
+            }
+        }
+        if (last == runnable) != (state == runnable) {
+            crate::sched::set_ready(index, self.priority, state == runnable);
+        }
+        last
+    }
+
+    /// Checks whether the application allows the task at index `caller` to
+    /// send messages to this one.
+    pub fn allows_caller(&self, caller: usize) -> bool {
+        match self.descriptor.allowed_callers {
+            None => true,
+            Some(callers) => u16::try_from(caller)
+                .map_or(false, |c| callers.binary_search(&c).is_ok()),
+        }
+    }
+
     /// Returns a reference to the saved machine state for the task.
     pub fn save(&self) -> &crate::arch::SavedState {
         &self.save
@@ -382,6 +617,31 @@ impl Task {
     pub fn save_mut(&mut self) -> &mut crate::arch::SavedState {
         &mut self.save
     }
+
+    /// Returns the task's execution statistics.
+    pub fn stats(&self) -> &TaskStats {
+        &self.stats
+    }
+
+    /// Records that `ticks` timer ticks elapsed while this task was running.
+    pub fn note_ticks(&mut self, ticks: u32) {
+        self.stats.ticks = self.stats.ticks.wrapping_add(u64::from(ticks));
+    }
+
+    /// Records that the kernel has switched to this task from another.
+    pub fn note_scheduled(&mut self) {
+        self.stats.schedules = self.stats.schedules.wrapping_add(1);
+    }
+
+    /// Records that this task has entered the kernel with a syscall.
+    pub fn note_syscall(&mut self) {
+        self.stats.syscalls = self.stats.syscalls.wrapping_add(1);
+    }
+
+    /// Records that a hardware interrupt was delivered to this task.
+    pub fn note_irq(&mut self) {
+        self.stats.irqs = self.stats.irqs.wrapping_add(1);
+    }
 }
 
 /// Interface that must be implemented by the `arch::SavedState` type. This
@@ -596,6 +856,13 @@ pub trait ArchState: Default {
         self.ret1(len as u32);
     }
 
+    /// Sets the response code and results of a successful BORROW_MAP.
+    fn set_borrow_map_result(&mut self, base: u32, len: u32) {
+        self.ret0(0);
+        self.ret1(base);
+        self.ret2(len);
+    }
+
     /// Sets the response code and info returned from BORROW_INFO.
     fn set_borrow_info(&mut self, atts: u32, len: usize) {
         self.ret0(0);
@@ -713,6 +980,9 @@ pub struct TimerState {
     /// Set of notification bits to post to the owning task when this timer
     /// fires.
     to_post: NotificationSet,
+    /// If `true`, this timer firing also abandons any SEND the owning task is
+    /// blocked in. Used to implement `SEND_WITH_TIMEOUT`.
+    cancels_send: bool,
 }
 
 /// Collection of bits that may be posted to a task's notification word.
@@ -764,7 +1034,9 @@ pub fn process_timers(tasks: &mut [Task], current_time: Timestamp) -> NextTask {
         if let Some(deadline) = task.timer.deadline {
             if deadline <= current_time {
                 task.timer.deadline = None;
-                let task_hint = if task.post(task.timer.to_post) {
+                let woken = task.post(task.timer.to_post);
+                let cancelled = task.timer.cancels_send && task.cancel_send();
+                let task_hint = if woken || cancelled {
                     NextTask::Specific(index)
                 } else {
                     NextTask::Same
@@ -776,6 +1048,31 @@ pub fn process_timers(tasks: &mut [Task], current_time: Timestamp) -> NextTask {
     sched_hint
 }
 
+/// Does the architecture-independent work of a kernel timer tick at
+/// `current_time`: charges the `elapsed` ticks since the last one to the task
+/// it interrupted, `current` (if any), processes timers, and enforces time
+/// slices.
+///
+/// `elapsed` is normally 1, but can be more if the tick was stopped while the
+/// processor slept (see `crate::syscalls::idle`). Those ticks only count once
+/// against a time slice, since nothing else could have run during them.
+pub fn tick(
+    tasks: &mut [Task],
+    current: Option<usize>,
+    elapsed: u32,
+    current_time: Timestamp,
+) -> NextTask {
+    let mut hint = process_timers(tasks, current_time);
+    if let Some(current) = current {
+        let task = &mut tasks[current];
+        task.note_ticks(elapsed);
+        if crate::sched::charge_slice(current, task.priority) {
+            hint = hint.combine(NextTask::Other);
+        }
+    }
+    hint
+}
+
 /// Checks a user-provided `TaskId` for validity against `table`.
 ///
 /// On success, returns an index that can be used to dereference `table` without
@@ -804,10 +1101,13 @@ pub fn check_task_id_against_table(
 
 /// Selects a new task to run after `previous`. Tries to be fair, kind of.
 ///
+/// This makes the same choice as `priority_scan` looking for runnable tasks
+/// would, but in constant time; see the `sched` module.
+///
 /// If no tasks are runnable, the kernel panics.
 pub fn select(previous: usize, tasks: &[Task]) -> usize {
-    priority_scan(previous, tasks, |t| t.is_runnable())
-        .expect("no tasks runnable")
+    uassert!(previous < tasks.len());
+    crate::sched::select(previous).expect("no tasks runnable")
 }
 
 /// Scans `tasks` for the next task, after `previous`, that satisfies `pred`. If
@@ -870,8 +1170,9 @@ pub fn force_fault(
     index: usize,
     fault: FaultInfo,
 ) -> NextTask {
+    crate::trace::fault(index, &fault);
     let task = &mut tasks[index];
-    task.state = match task.state {
+    let state = match task.state {
         TaskState::Healthy(sched) => TaskState::Faulted {
             original_state: sched,
             fault,
@@ -885,6 +1186,7 @@ pub fn force_fault(
             }
         }
     };
+    task.set_state(state);
     let supervisor_awoken =
         tasks[0].post(NotificationSet(HUBRIS_FAULT_NOTIFICATION));
     if supervisor_awoken {
@@ -4,6 +4,8 @@
 
 //! Implementation of kernel time.
 
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
 /// In-kernel timestamp representation.
 ///
 /// This is currently measured in an arbitrary "tick" unit.
@@ -28,3 +30,83 @@ impl From<Timestamp> for u64 {
         v.0
     }
 }
+
+/// How deeply to put the processor to sleep while the idle task waits for the
+/// next timer deadline, in tickless mode.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SleepDepth {
+    /// Ordinary sleep: the core stops, but its clocks keep running.
+    Sleep,
+    /// The processor's deep sleep state, whose details are chip-specific. The
+    /// system timer may stop in it, so the kernel keeps time with a
+    /// `DeepSleepClock` instead, which the board has to provide.
+    DeepSleep,
+}
+
+/// Tickless mode configuration: 0 if the kernel keeps ticking while idle,
+/// otherwise one more than the `SleepDepth` to use.
+static TICKLESS: AtomicU8 = AtomicU8::new(0);
+
+/// Configures tickless mode: if `depth` is given, the idle task can ask the
+/// kernel to stop its periodic tick and sleep that deeply until the next timer
+/// deadline.
+pub fn set_tickless(depth: Option<SleepDepth>) {
+    let v = match depth {
+        None => 0,
+        Some(SleepDepth::Sleep) => 1,
+        Some(SleepDepth::DeepSleep) => 2,
+    };
+    TICKLESS.store(v, Ordering::Relaxed);
+}
+
+/// Returns the tickless mode sleep depth, or `None` if the kernel keeps
+/// ticking while idle.
+pub fn tickless() -> Option<SleepDepth> {
+    match TICKLESS.load(Ordering::Relaxed) {
+        1 => Some(SleepDepth::Sleep),
+        2 => Some(SleepDepth::DeepSleep),
+        _ => None,
+    }
+}
+
+/// Hooks that board setup code must provide to use tickless deep sleep.
+///
+/// The kernel's timer may stop in deep sleep -- on the LPC55 and STM32H7,
+/// `SysTick` does -- so it needs a counter that keeps going, such as the
+/// LPC55's OS event timer or an STM32H7 low-power timer, both to wake the
+/// processor at the next deadline and to tell how long it slept.
+pub struct DeepSleepClock {
+    /// The interrupt that `wake_at` arranges. The kernel handles it, so it
+    /// must not be assigned to any task; it's only enabled while we deep
+    /// sleep.
+    pub irq: u32,
+    /// Returns the counter, in kernel ticks, wrapping around at `u32::MAX`.
+    /// It's only read around deep sleeps, but has to keep counting in
+    /// between, so that the parts of a tick it rounds off don't add up.
+    pub now: fn() -> u32,
+    /// Arranges for `irq` to go off when `now` gets to the given count.
+    pub wake_at: fn(u32),
+    /// Called on waking, by whatever interrupt: cancels any wakeup that
+    /// `wake_at` arranged, and clears its interrupt.
+    pub cancel: fn(),
+}
+
+/// Supplies the kernel with a `DeepSleepClock`. This has to be done before
+/// starting the kernel if the application uses tickless deep sleep.
+pub fn configure_deep_sleep_clock(clock: &'static DeepSleepClock) {
+    DEEP_SLEEP_CLOCK.store(clock as *const _ as *mut _, Ordering::Relaxed);
+}
+
+/// Pointer written by `configure_deep_sleep_clock`, or null if there's no
+/// clock. Like the profiling events table, it's only ever written from a
+/// `&'static`.
+static DEEP_SLEEP_CLOCK: AtomicPtr<DeepSleepClock> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// Returns the configured `DeepSleepClock`, if any.
+pub fn deep_sleep_clock() -> Option<&'static DeepSleepClock> {
+    let p = DEEP_SLEEP_CLOCK.load(Ordering::Relaxed);
+    // Safety: we only write this pointer from a valid `&'static`, and we're
+    // handing out a shared reference.
+    unsafe { p.as_ref() }
+}
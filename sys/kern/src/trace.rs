@@ -0,0 +1,175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Kernel event trace.
+//!
+//! With the `trace` feature, the kernel records scheduling-related events --
+//! context switches, syscall entry and exit, interrupt dispatch, notification
+//! posts, and faults -- in a small ring buffer, each stamped with the kernel
+//! time. This is meant for working out what happened after the fact: the ring
+//! is a plain static, `HUBRIS_TRACE`, so a debugger can read it out of a live
+//! or halted system and decode it using the types in the ELF, and the
+//! supervisor can include it in task dumps (see `Kipcnum::GetTraceRegion`).
+//!
+//! The format is described on `abi::TraceRecord`. Timestamps are in ticks, so
+//! many events will share a timestamp; their order in the ring is the order
+//! in which they happened.
+//!
+//! Unlike the `profiling` hooks, this needs no support from the board, at the
+//! cost of a few stores per event and `TRACE_RECORDS` records of kernel RAM.
+//! Without the `trace` feature, the hooks compile to nothing.
+//!
+//! # Concurrency
+//!
+//! The ring is only written from kernel context, which can't be preempted or
+//! reentered, so we get away with a `static mut`.
+
+use abi::{FaultInfo, TraceEvent, TraceRecord};
+
+/// Number of records in the ring. This must be a power of two, so that the
+/// record count can wrap without skipping slots.
+pub const TRACE_RECORDS: usize = 64;
+
+const _: () = assert!(TRACE_RECORDS.is_power_of_two());
+
+/// The trace ring, as laid out in memory.
+#[repr(C)]
+pub struct TraceRing {
+    /// Number of records ever written, wrapping.
+    count: u32,
+    _pad: u32,
+    records: [TraceRecord; TRACE_RECORDS],
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "trace")] {
+        #[used]
+        #[no_mangle]
+        static mut HUBRIS_TRACE: TraceRing = TraceRing {
+            count: 0,
+            _pad: 0,
+            records: [TraceRecord::EMPTY; TRACE_RECORDS],
+        };
+
+        fn record(event: TraceEvent, task: usize, arg: u32) {
+            // Safety: we're only called from kernel context, so nothing else
+            // can be looking at the ring.
+            let ring = unsafe { &mut HUBRIS_TRACE };
+            let slot = ring.count as usize % TRACE_RECORDS;
+            ring.records[slot] = TraceRecord {
+                timestamp: crate::arch::now().into(),
+                arg,
+                task: task as u16,
+                event,
+                _pad: 0,
+            };
+            ring.count = ring.count.wrapping_add(1);
+        }
+
+        /// Returns the address and size of the ring in memory, or `None` if
+        /// tracing isn't compiled in.
+        pub fn region() -> Option<(usize, usize)> {
+            // Safety: we're only taking the address.
+            let ring = unsafe { core::ptr::addr_of!(HUBRIS_TRACE) };
+            Some((ring as usize, core::mem::size_of::<TraceRing>()))
+        }
+
+        /// Copies the raw bytes of the ring, starting `offset` bytes in, into
+        /// `dest`, returning the number of bytes copied.
+        pub fn read(offset: usize, dest: &mut [u8]) -> usize {
+            // Safety: we're only called from kernel context, so nothing else
+            // can be writing the ring. It's `repr(C)` with no padding, so
+            // every byte of it is initialized.
+            let ring: &[u8; core::mem::size_of::<TraceRing>()] =
+                unsafe { core::mem::transmute(&HUBRIS_TRACE) };
+            let src = ring.get(offset..).unwrap_or(&[]);
+            let len = dest.len().min(src.len());
+            dest[..len].copy_from_slice(&src[..len]);
+            len
+        }
+
+        /// Calls `f` with each record in the ring, oldest first.
+        pub fn for_each(mut f: impl FnMut(&TraceRecord)) {
+            // Safety: we're only called from kernel context, so nothing else
+            // can be writing the ring.
+            let ring = unsafe { &HUBRIS_TRACE };
+            let len = (ring.count as usize).min(TRACE_RECORDS);
+            for i in 0..len {
+                let n = ring.count.wrapping_sub((len - i) as u32);
+                f(&ring.records[n as usize % TRACE_RECORDS]);
+            }
+        }
+
+        /// Empties the ring. The simulator does this before each run, since
+        /// it boots the kernel many times in one process.
+        pub fn reset() {
+            // Safety: we're only called from kernel context, so nothing else
+            // can be looking at the ring.
+            let ring = unsafe { &mut HUBRIS_TRACE };
+            ring.count = 0;
+            ring.records = [TraceRecord::EMPTY; TRACE_RECORDS];
+        }
+    } else {
+        #[inline(always)]
+        fn record(_event: TraceEvent, _task: usize, _arg: u32) {}
+
+        /// Returns the address and size of the ring in memory, or `None` if
+        /// tracing isn't compiled in.
+        pub fn region() -> Option<(usize, usize)> {
+            None
+        }
+
+        /// Empties the ring, if there is one.
+        pub fn reset() {}
+    }
+}
+
+/// Records a switch to the task at `index`.
+#[inline(always)]
+pub fn context_switch(index: usize) {
+    record(TraceEvent::ContextSwitch, index, 0);
+}
+
+/// Records the task at `index` entering the kernel with syscall `nr`.
+#[inline(always)]
+pub fn syscall_enter(index: usize, nr: u32) {
+    record(TraceEvent::SyscallEnter, index, nr);
+}
+
+/// Records the kernel finishing syscall `nr` for the task at `index`.
+#[inline(always)]
+pub fn syscall_exit(index: usize, nr: u32) {
+    record(TraceEvent::SyscallExit, index, nr);
+}
+
+/// Records interrupt `irq` being dispatched to the task at `index`.
+#[inline(always)]
+pub fn irq(index: usize, irq: u32) {
+    record(TraceEvent::Irq, index, irq);
+}
+
+/// Records notification `bits` being posted to the task at `index`.
+#[inline(always)]
+pub fn post(index: usize, bits: u32) {
+    record(TraceEvent::Post, index, bits);
+}
+
+/// Records the task at `index` taking `fault`.
+#[inline(always)]
+pub fn fault(index: usize, fault: &FaultInfo) {
+    let kind = match fault {
+        FaultInfo::MemoryAccess { .. } => 0,
+        FaultInfo::StackOverflow { .. } => 1,
+        FaultInfo::BusError { .. } => 2,
+        FaultInfo::DivideByZero => 3,
+        FaultInfo::IllegalText => 4,
+        FaultInfo::IllegalInstruction => 5,
+        FaultInfo::InvalidOperation(_) => 6,
+        FaultInfo::SyscallUsage(_) => 7,
+        FaultInfo::Panic => 8,
+        FaultInfo::Injected(_) => 9,
+        FaultInfo::FromServer(..) => 10,
+    };
+    record(TraceEvent::Fault, index, kind);
+}
@@ -12,9 +12,9 @@ use core::marker::PhantomData;
 use zerocopy::{AsBytes, FromBytes, LayoutVerified};
 
 use crate::{
-    sys_borrow_info, sys_borrow_read, sys_borrow_write, sys_get_timer,
-    sys_recv, sys_recv_closed, sys_recv_open, sys_reply, sys_set_timer,
-    BorrowInfo, ClosedRecvError, FromPrimitive,
+    sys_borrow_info, sys_borrow_map, sys_borrow_read, sys_borrow_write,
+    sys_get_timer, sys_recv, sys_recv_closed, sys_recv_open, sys_reply,
+    sys_set_timer, BorrowInfo, ClosedRecvError, FromPrimitive,
 };
 
 const INTERNAL_TIMER_NOTIFICATION: u32 = 1 << 31;
@@ -412,6 +412,111 @@ impl Borrow<'_> {
             Some(())
         }
     }
+
+    /// Maps this borrow into our own memory, so that it can be accessed
+    /// without a syscall each time, until we reply to the caller.
+    ///
+    /// This is a wrapper for the `sys_borrow_map` syscall.
+    ///
+    /// This returns `None` if the borrow can't be mapped -- because it isn't
+    /// sized and aligned to suit the memory protection unit, or we already
+    /// have a borrow mapped -- as well as if it doesn't exist. In the former
+    /// case, the borrow can still be accessed through `read_fully_at` and
+    /// friends, so servers should fall back on those.
+    ///
+    /// Note that if the caller gives up waiting for us (if it used
+    /// `sys_send_with_timeout`) or is restarted, the mapping goes away
+    /// immediately, and touching it will fault *us*. Only map borrows from
+    /// callers you trust.
+    pub fn map(&self) -> Option<Grant<'_>> {
+        let info = self.info()?;
+        let mapped = sys_borrow_map(self.id, self.index).ok()?;
+        Some(Grant {
+            base: mapped.base,
+            len: mapped.len,
+            attributes: info.attributes,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// A borrow mapped into our memory by `Borrow::map`.
+///
+/// The handle borrows the `Borrow` (and so the `Caller`) to keep you from
+/// using the mapping after you reply to the caller, which revokes it.
+pub struct Grant<'borrow> {
+    base: *mut u8,
+    len: usize,
+    attributes: abi::LeaseAttributes,
+    _phantom: PhantomData<&'borrow ()>,
+}
+
+impl Grant<'_> {
+    /// Length of the mapped memory, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks whether the mapped memory is empty (it never is, in practice).
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Attributes of the underlying lease.
+    pub fn attributes(&self) -> abi::LeaseAttributes {
+        self.attributes
+    }
+
+    /// Address of the mapped memory, for handing to code that wants to work
+    /// on it in place. The usual caveats about raw pointers apply.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.base
+    }
+
+    /// Starting at offset `offset` within the mapping, reads exactly
+    /// `dest.len()` bytes into `dest`.
+    ///
+    /// This fails if the lease doesn't allow reading, or you're trying to read
+    /// off the end.
+    pub fn read_fully_at(&self, offset: usize, dest: &mut [u8]) -> Option<()> {
+        if !self.attributes.contains(abi::LeaseAttributes::READ)
+            || offset.checked_add(dest.len())? > self.len
+        {
+            return None;
+        }
+        // Safety: the kernel has mapped `len` bytes at `base` for us, which we
+        // just checked covers the source range, and nothing else can be using
+        // it while the caller is blocked.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.base.add(offset),
+                dest.as_mut_ptr(),
+                dest.len(),
+            );
+        }
+        Some(())
+    }
+
+    /// Starting at offset `offset` within the mapping, writes all of `src`.
+    ///
+    /// This fails if the lease doesn't allow writing, or you're trying to write
+    /// past the end.
+    pub fn write_fully_at(&self, offset: usize, src: &[u8]) -> Option<()> {
+        if !self.attributes.contains(abi::LeaseAttributes::WRITE)
+            || offset.checked_add(src.len())? > self.len
+        {
+            return None;
+        }
+        // Safety: as in `read_fully_at`, with the destination range.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                src.as_ptr(),
+                self.base.add(offset),
+                src.len(),
+            );
+        }
+        Some(())
+    }
 }
 
 /// Suspends the calling task until the kernel time is `>= time`.
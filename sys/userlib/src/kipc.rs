@@ -24,6 +24,36 @@ pub fn read_task_status(task: usize) -> abi::TaskState {
     ssmarshal::deserialize(&response[..len]).unwrap_lite().0
 }
 
+pub fn read_task_stats(task: usize) -> abi::TaskStats {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::TaskStats>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadTaskStats as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+pub fn read_stack_usage(task: usize) -> abi::StackUsage {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::StackUsage>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadStackUsage as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
 pub fn get_task_dump_region(
     task: usize,
     region: usize,
@@ -44,6 +74,19 @@ pub fn get_task_dump_region(
     ssmarshal::deserialize(&response[..len]).unwrap_lite().0
 }
 
+pub fn get_trace_region() -> Option<abi::TaskDumpRegion> {
+    let mut response = [0; core::mem::size_of::<Option<abi::TaskDumpRegion>>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::GetTraceRegion as u16,
+        &[],
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
 pub fn read_task_dump_region(
     task: usize,
     region: abi::TaskDumpRegion,
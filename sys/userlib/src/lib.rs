@@ -219,6 +219,128 @@ unsafe extern "C" fn sys_send_stub(_args: &mut SendArgs<'_>) -> RcLen {
     }
 }
 
+/// Sends a message like `sys_send`, but gives up if no reply has arrived by
+/// `deadline` (in kernel ticks, as for `sys_set_timer`), returning
+/// `abi::TIMED_OUT` with a zero-length response.
+///
+/// The deadline covers both waiting for `target` to receive the message and
+/// waiting for it to reply. If `target` has already received the message when
+/// the deadline passes, it isn't told: any later attempts to access `leases`
+/// will fail, and its reply will be discarded. Until that reply arrives, any
+/// further message to `target` (with or without a timeout) fails straight away
+/// with `abi::TIMED_OUT`, so that the late reply can't be mistaken for the
+/// reply to a new one.
+///
+/// This borrows the task's timer for the duration of the call. Any timer you
+/// had set is restored afterwards, and if its deadline passed in the
+/// meantime, its notification will be posted as usual.
+pub fn sys_send_with_timeout(
+    target: TaskId,
+    operation: u16,
+    outgoing: &[u8],
+    incoming: &mut [u8],
+    leases: &[Lease<'_>],
+    deadline: u64,
+) -> (u32, usize) {
+    let previous = sys_get_timer();
+    if deadline <= previous.now {
+        return (TIMED_OUT, 0);
+    }
+    // Arm the timer without any notification bits; the kernel interrupts the
+    // send itself when it fires. (If it manages to expire before we get to the
+    // send, the kernel will notice that it's no longer armed.)
+    sys_set_timer(Some(deadline), 0);
+
+    let mut args = SendArgs {
+        packed_target_operation: u32::from(target.0) << 16
+            | u32::from(operation),
+        outgoing_ptr: outgoing.as_ptr(),
+        outgoing_len: outgoing.len(),
+        incoming_ptr: incoming.as_mut_ptr(),
+        incoming_len: incoming.len(),
+        lease_ptr: leases.as_ptr(),
+        lease_len: leases.len(),
+    };
+    let result = unsafe { sys_send_with_timeout_stub(&mut args).into() };
+
+    sys_set_timer(previous.deadline, previous.on_dl);
+    result
+}
+
+/// Core implementation of the SEND_WITH_TIMEOUT syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_send_with_timeout_stub(
+    _args: &mut SendArgs<'_>,
+) -> RcLen {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r7, lr}}
+                mov r4, r8
+                mov r5, r9
+                mov r6, r10
+                mov r7, r11
+                push {{r4-r7}}
+                @ Load the constant syscall number.
+                eors r4, r4
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Load in args from the struct.
+                ldm r0!, {{r4-r7}}
+                ldm r0, {{r0-r2}}
+                mov r8, r0
+                mov r9, r1
+                mov r10, r2
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the two results back into their return positions.
+                mov r0, r4
+                mov r1, r5
+                @ Restore the registers we used.
+                pop {{r4-r7}}
+                mov r8, r4
+                mov r9, r5
+                mov r10, r6
+                mov r11, r7
+                pop {{r4-r7, pc}}
+                ",
+                sysnum = const Sysnum::SendWithTimeout as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r11}}
+                @ Load in args from the struct.
+                ldm r0, {{r4-r10}}
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the two results back into their return positions.
+                mov r0, r4
+                mov r1, r5
+                @ Restore the registers we used.
+                pop {{r4-r11}}
+                @ Fin.
+                bx lr
+                ",
+                sysnum = const Sysnum::SendWithTimeout as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_send_with_timeout_stub for ARM profile");
+        }
+    }
+}
+
 /// Performs an "open" RECV that will accept messages from any task or
 /// notifications from the kernel.
 ///
@@ -909,6 +1031,122 @@ unsafe extern "C" fn sys_borrow_info_stub(
     }
 }
 
+/// Asks the kernel to grant us lease number `index` from `lender`: to map the
+/// leased memory into our address space, for as long as `lender` is waiting
+/// for our reply. On success, returns the address and length of the memory.
+///
+/// On failure, returns a response code: `abi::UNGRANTABLE` if the lease
+/// can't be mapped (in which case it can still be accessed with
+/// `sys_borrow_read` and `sys_borrow_write`), or the usual codes for a
+/// defecting or dead lender.
+#[inline(always)]
+pub fn sys_borrow_map(
+    lender: TaskId,
+    index: usize,
+) -> Result<MappedBorrow, u32> {
+    use core::mem::MaybeUninit;
+
+    let mut raw = MaybeUninit::<RawBorrowMap>::uninit();
+    unsafe {
+        sys_borrow_map_stub(lender.0 as u32, index, raw.as_mut_ptr());
+    }
+    // Safety: stub completely initializes record
+    let raw = unsafe { raw.assume_init() };
+
+    if raw.rc == 0 {
+        Ok(MappedBorrow {
+            base: raw.base,
+            len: raw.length,
+        })
+    } else {
+        Err(raw.rc)
+    }
+}
+
+#[repr(C)]
+struct RawBorrowMap {
+    rc: u32,
+    base: *mut u8,
+    length: usize,
+}
+
+/// Location of memory granted by `sys_borrow_map`.
+pub struct MappedBorrow {
+    /// Address of the granted memory.
+    pub base: *mut u8,
+    /// Length of the granted memory, in bytes.
+    pub len: usize,
+}
+
+/// Core implementation of the BORROW_MAP syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_borrow_map_stub(
+    _lender: u32,
+    _index: usize,
+    _out: *mut RawBorrowMap,
+) {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r6, lr}}
+                mov r4, r11
+                push {{r4}}
+
+                @ Load the constant syscall number.
+                eors r4, r4
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Move register arguments into place.
+                mov r4, r0
+                mov r5, r1
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the results into place.
+                stm r2!, {{r4-r6}}
+
+                @ Restore the registers we used and return.
+                pop {{r4}}
+                mov r11, r4
+                pop {{r4-r6, pc}}
+                ",
+                sysnum = const Sysnum::BorrowMap as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r6, r11}}
+
+                @ Move register arguments into place.
+                mov r4, r0
+                mov r5, r1
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the results into place.
+                stm r2, {{r4-r6}}
+
+                @ Restore the registers we used and return.
+                pop {{r4-r6, r11}}
+                bx lr
+                ",
+                sysnum = const Sysnum::BorrowMap as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_borrow_map_stub for ARM profile")
+        }
+    }
+}
+
 #[inline(always)]
 pub fn sys_irq_control(mask: u32, enable: bool) {
     unsafe {
@@ -1652,3 +1890,70 @@ unsafe extern "C" fn sys_reply_fault_stub(_tid: u32, _reason: u32) {
         }
     }
 }
+
+/// Tells the kernel that the caller is about to idle the processor, so it can
+/// stop its periodic tick until the next timer deadline, if the application
+/// has enabled tickless mode. The caller should wait for an interrupt right
+/// afterwards.
+///
+/// This is for the idle task; in other tasks, it does nothing useful.
+#[inline(always)]
+pub fn sys_idle() {
+    unsafe { sys_idle_stub() }
+}
+
+/// Core implementation of the IDLE syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_idle_stub() {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the register we're about to use.
+                push {{r4, lr}}
+                mov r4, r11
+                push {{r4}}
+
+                @ Load the constant syscall number.
+                movs r4, #0
+                adds r4, #{sysnum}
+                mov r11, r4
+
+                @ To the kernel!
+                svc #0
+
+                @ This syscall has no results.
+
+                @ Restore the registers we used and return.
+                pop {{r4}}
+                mov r11, r4
+                pop {{r4, pc}}
+                ",
+                sysnum = const Sysnum::Idle as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the register we're about to use.
+                push {{r11, lr}}
+
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ This syscall has no results.
+
+                @ Restore the registers we used and return.
+                pop {{r11, pc}}
+                ",
+                sysnum = const Sysnum::Idle as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_idle_stub for ARM profile")
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the RoT attestation task.
+//!
+//! The attestation task keeps a log of measurements: the RoT's own FWID, from
+//! its DICE alias cert, and whatever other tasks record in it (e.g. the SP's
+//! measurement, from `sp_measure`). Measurements from the host aren't logged
+//! individually; instead, they're extended into a measurement register (see
+//! `measurement-register`), which appears in the log as a single `Host`
+//! entry. On request, the task signs the log, along with a caller-supplied
+//! nonce, with the DICE alias key, producing a [`Quote`].
+//!
+//! To check a quote, hubpack-serialize its `attestation` and verify
+//! `signature` over the result with the public key from the RoT's alias cert.
+//! The task also hands out the alias cert's chain, and those of the RoT's
+//! other DICE certs, a cert at a time; see [`CertChain`] and
+//! [`Attest::cert`].
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+use userlib::*;
+
+/// Size of the nonce that's signed along with the log.
+pub const NONCE_SIZE: usize = 32;
+
+/// Size of a measurement: a SHA3-256 digest.
+pub const DIGEST_SIZE: usize = 32;
+
+/// Size of an ed25519 signature.
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Maximum number of measurements in the log.
+pub const MAX_MEASUREMENTS: usize = 8;
+
+/// Maximum number of certs in a cert chain: the leaf, DeviceId and PersistId
+/// certs, and the intermediate cert, if the PersistId cert wasn't signed by a
+/// root.
+pub const MAX_CERT_CHAIN_LEN: u32 = 4;
+
+/// A cert chain the RoT hands out, named for its leaf cert. Every leaf is
+/// signed by the DeviceId cert, so the chains only differ in their first
+/// cert.
+///
+/// These are passed through sprot from the SP, so variants may only be added,
+/// at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum CertChain {
+    /// The alias cert, whose key signs quotes.
+    Alias,
+    /// The SpMeasure cert, for the key stage0 derives for `sp_measure`.
+    SpMeasure,
+    /// The TrustQuorumDhe cert, for the key used in trust quorum key
+    /// exchanges.
+    TrustQuorumDhe,
+}
+
+/// What a measurement is of.
+///
+/// These are part of quotes, which are checked by parties outside the RoT, so
+/// variants may only be added, at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum MeasurementKind {
+    /// The RoT's own firmware, as measured by stage0.
+    RotFwid,
+    /// The SP's flash, as measured by `sp_measure`.
+    Sp,
+    /// The host measurement register.
+    Host,
+    /// The SP's flash didn't match any of the measurements `sp_measure`
+    /// allows, and its policy is to flag that in the log. The digest is the
+    /// same as the `Sp` entry's.
+    SpPolicyMismatch,
+}
+
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct Measurement {
+    pub kind: MeasurementKind,
+    pub digest: [u8; DIGEST_SIZE],
+}
+
+/// The state of the host measurement register.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct HostMeasurements {
+    pub value: [u8; DIGEST_SIZE],
+    /// How many measurements the register has been extended with.
+    pub count: u32,
+}
+
+/// What `sp_measure` does when the SP's flash doesn't match any of the
+/// measurements it allows.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum SpMismatchAction {
+    /// Halt the SP's core over SWD, so the image doesn't run.
+    Halt,
+    /// Let the SP run, but add a `SpPolicyMismatch` entry to the log, so
+    /// quotes show it.
+    Flag,
+    /// Let the SP run, and only report the mismatch in
+    /// [`SpMeasureStatus`].
+    Alert,
+}
+
+/// The result of `sp_measure` checking the SP against its policy.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum SpMeasureStatus {
+    /// `sp_measure` hasn't finished measuring the SP (or isn't running).
+    NotMeasured,
+    /// The SP's flash matched one of the allowed measurements.
+    Allowed,
+    /// The SP's flash didn't match, and this action was taken.
+    Mismatch(SpMismatchAction),
+}
+
+/// The part of a quote that's signed.
+#[derive(Clone, Debug, Serialize, Deserialize, SerializedSize)]
+pub struct Attestation {
+    pub nonce: [u8; NONCE_SIZE],
+    /// The log, with at most one measurement of each kind, in the order each
+    /// kind was first recorded, followed by `None`s.
+    pub log: [Option<Measurement>; MAX_MEASUREMENTS],
+}
+
+/// A signed attestation.
+#[derive(Clone, Debug, Serialize, Deserialize, SerializedSize)]
+pub struct Quote {
+    pub attestation: Attestation,
+    /// Signature over the hubpack-serialized `attestation`, by the RoT's
+    /// alias key.
+    #[serde(with = "BigArray")]
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+/// Errors from the attestation task.
+///
+/// These are passed through sprot to the SP, so the values of existing
+/// variants must not change.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    IdolError,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub enum AttestError {
+    /// The log has no room for another measurement.
+    LogFull = 1,
+    /// A measurement wasn't `DIGEST_SIZE` bytes.
+    BadDigestSize = 2,
+    /// Measurements of this kind can't be recorded by other tasks.
+    ReservedKind = 3,
+    /// Stage0 didn't hand off an alias key, so we can't sign quotes.
+    NoAliasKey = 4,
+    /// The lease for the quote is too small.
+    QuoteBufferTooSmall = 5,
+    /// The host measurement register can't be extended any further.
+    RegisterFull = 6,
+    /// Stage0 didn't hand off the cert chain, or its leaf cert.
+    NoCerts = 7,
+    /// There's no cert at this index in the chain.
+    BadCertIndex = 8,
+    /// The offset is past the end of the cert.
+    BadCertOffset = 9,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
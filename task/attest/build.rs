@@ -0,0 +1,15 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    const IDL: &str = "../../idl/attest.idol";
+    idol::server::build_restricted_server_support(
+        IDL,
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+        &build_util::task_allowed_callers(IDL)?,
+    )?;
+
+    Ok(())
+}
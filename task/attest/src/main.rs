@@ -0,0 +1,346 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! RoT attestation task
+//!
+//! Keeps the measurement log and signs quotes over it; see `attest-api`.
+//!
+//! The alias key and the RoT's FWID come from the DICE artifacts that stage0
+//! hands off in the `dice_alias` region, along with the TrustQuorumDhe cert.
+//! The rest of the cert chains come from the `dice_certs` region, and the
+//! SpMeasure cert from the `dice_spmeasure` region, so this task needs to use
+//! all three, e.g.:
+//!
+//! ```toml
+//! [tasks.attest]
+//! uses = ["dice_alias", "dice_certs", "dice_spmeasure"]
+//! ```
+
+#![no_std]
+#![no_main]
+
+use attest_api::*;
+use dice::{
+    AliasCert, AliasData, Cert, CertData, SeedBuf, SpMeasureCert,
+    SpMeasureData, TrustQuorumDheCert,
+};
+use hubpack::SerializedSize;
+use idol_runtime::{Leased, LenLimit, RequestError, R, W};
+use measurement_log::{LogFull, MeasurementLog};
+use measurement_register::{MeasurementRegister, RegisterFull};
+use ringbuf::*;
+use salty::signature::Keypair;
+use stage0_handoff::HandoffData;
+use userlib::*;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    NoAliasData,
+    NoCertData,
+    NoSpMeasureData,
+    Recorded(MeasurementKind),
+    LogFull(MeasurementKind),
+    HostExtended(u32),
+    HostRegisterFull,
+    SpMeasureStatus(SpMeasureStatus),
+    Quote,
+    None,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    log: MeasurementLog<Measurement, MAX_MEASUREMENTS>,
+    host: MeasurementRegister,
+    alias_keypair: Option<Keypair>,
+    alias_cert: Option<AliasCert>,
+    tqdhe_cert: Option<TrustQuorumDheCert>,
+    spmeasure_cert: Option<SpMeasureCert>,
+    certs: Option<CertData>,
+    sp_measure_status: SpMeasureStatus,
+}
+
+impl ServerImpl {
+    /// Adds `measurement` to the log, in place of any earlier measurement of
+    /// the same kind. A new SP measurement also drops any policy mismatch
+    /// flagged against the last one. So measuring the SP again, e.g. after
+    /// it's released from a halt or restored, doesn't use up the log, and
+    /// quotes only ever show its latest measurement.
+    fn put(&mut self, measurement: Measurement) -> Result<(), AttestError> {
+        let kind = measurement.kind;
+        if kind == MeasurementKind::Sp {
+            self.log
+                .remove(|m| m.kind == MeasurementKind::SpPolicyMismatch);
+        }
+        match self.log.put(measurement, |m| m.kind == kind) {
+            Ok(()) => {
+                ringbuf_entry!(Trace::Recorded(kind));
+                Ok(())
+            }
+            Err(LogFull) => {
+                ringbuf_entry!(Trace::LogFull(kind));
+                Err(AttestError::LogFull)
+            }
+        }
+    }
+
+    /// Extends the host measurement register with `digest`, and brings its
+    /// entry in the log up to date, adding it if this is the first host
+    /// measurement.
+    fn extend_host(
+        &mut self,
+        digest: &[u8; DIGEST_SIZE],
+    ) -> Result<(), AttestError> {
+        // Make sure there's somewhere to put the register before we change
+        // it.
+        let entry = self
+            .log
+            .slot(|m| m.kind == MeasurementKind::Host)
+            .map_err(|LogFull| {
+                ringbuf_entry!(Trace::LogFull(MeasurementKind::Host));
+                AttestError::LogFull
+            })?;
+
+        self.host.extend(digest).map_err(|RegisterFull| {
+            ringbuf_entry!(Trace::HostRegisterFull);
+            AttestError::RegisterFull
+        })?;
+        *entry = Some(Measurement {
+            kind: MeasurementKind::Host,
+            digest: self.host.value(),
+        });
+        ringbuf_entry!(Trace::HostExtended(self.host.count()));
+        Ok(())
+    }
+
+    /// Returns the DER-encoded leaf cert of `chain`.
+    fn leaf_cert(&self, chain: CertChain) -> Result<&[u8], AttestError> {
+        let cert = match chain {
+            CertChain::Alias => self.alias_cert.as_ref().map(Cert::as_bytes),
+            CertChain::SpMeasure => {
+                self.spmeasure_cert.as_ref().map(Cert::as_bytes)
+            }
+            CertChain::TrustQuorumDhe => {
+                self.tqdhe_cert.as_ref().map(Cert::as_bytes)
+            }
+        };
+        cert.ok_or(AttestError::NoCerts)
+    }
+
+    /// Returns the rest of the cert chains, which they all share.
+    fn certs(&self) -> Result<&CertData, AttestError> {
+        self.certs.as_ref().ok_or(AttestError::NoCerts)
+    }
+
+    /// Returns the DER-encoded cert at `index` in `chain`, leaf first.
+    fn chain_cert(
+        &self,
+        chain: CertChain,
+        index: u32,
+    ) -> Result<&[u8], AttestError> {
+        let leaf = self.leaf_cert(chain)?;
+        let certs = self.certs()?;
+        match index {
+            0 => Ok(leaf),
+            1 => Ok(certs.deviceid_cert.as_bytes()),
+            2 => Ok(certs.persistid_cert.as_bytes()),
+            3 => match &certs.intermediate_cert {
+                Some(cert) => Ok(cert.as_bytes()),
+                None => Err(AttestError::BadCertIndex),
+            },
+            _ => Err(AttestError::BadCertIndex),
+        }
+    }
+}
+
+impl idl::InOrderAttestImpl for ServerImpl {
+    fn record(
+        &mut self,
+        _msg: &RecvMessage,
+        kind: MeasurementKind,
+        digest: LenLimit<Leased<R, [u8]>, DIGEST_SIZE>,
+    ) -> Result<(), RequestError<AttestError>> {
+        // The RoT's own FWID comes from stage0, and nobody else gets to
+        // claim otherwise.
+        if kind == MeasurementKind::RotFwid {
+            return Err(AttestError::ReservedKind.into());
+        }
+        if digest.len() != DIGEST_SIZE {
+            return Err(AttestError::BadDigestSize.into());
+        }
+
+        let mut measurement = Measurement {
+            kind,
+            digest: [0; DIGEST_SIZE],
+        };
+        digest
+            .read_range(0..DIGEST_SIZE, &mut measurement.digest)
+            .map_err(|_| RequestError::went_away())?;
+
+        if kind == MeasurementKind::Host {
+            self.extend_host(&measurement.digest)?;
+        } else {
+            self.put(measurement)?;
+        }
+        Ok(())
+    }
+
+    fn host_measurements(
+        &mut self,
+        _msg: &RecvMessage,
+    ) -> Result<HostMeasurements, RequestError<core::convert::Infallible>> {
+        Ok(HostMeasurements {
+            value: self.host.value(),
+            count: self.host.count(),
+        })
+    }
+
+    fn set_sp_measure_status(
+        &mut self,
+        _msg: &RecvMessage,
+        status: SpMeasureStatus,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        ringbuf_entry!(Trace::SpMeasureStatus(status));
+        self.sp_measure_status = status;
+        Ok(())
+    }
+
+    fn sp_measure_status(
+        &mut self,
+        _msg: &RecvMessage,
+    ) -> Result<SpMeasureStatus, RequestError<core::convert::Infallible>> {
+        Ok(self.sp_measure_status)
+    }
+
+    fn quote(
+        &mut self,
+        _msg: &RecvMessage,
+        nonce: [u8; NONCE_SIZE],
+        quote: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<AttestError>> {
+        let keypair =
+            self.alias_keypair.as_ref().ok_or(AttestError::NoAliasKey)?;
+
+        let attestation = Attestation {
+            nonce,
+            log: *self.log.entries(),
+        };
+        let mut buf = [0; Quote::MAX_SIZE];
+        let n = hubpack::serialize(&mut buf, &attestation).unwrap_lite();
+        let signature = keypair.sign(&buf[..n]).to_bytes();
+
+        let n = hubpack::serialize(
+            &mut buf,
+            &Quote {
+                attestation,
+                signature,
+            },
+        )
+        .unwrap_lite();
+        if quote.len() < n {
+            return Err(AttestError::QuoteBufferTooSmall.into());
+        }
+        quote
+            .write_range(0..n, &buf[..n])
+            .map_err(|_| RequestError::went_away())?;
+
+        ringbuf_entry!(Trace::Quote);
+        Ok(n as u32)
+    }
+
+    fn cert_chain_len(
+        &mut self,
+        _msg: &RecvMessage,
+        chain: CertChain,
+    ) -> Result<u32, RequestError<AttestError>> {
+        self.leaf_cert(chain)?;
+        let certs = self.certs()?;
+        Ok(if certs.intermediate_cert.is_some() {
+            MAX_CERT_CHAIN_LEN
+        } else {
+            MAX_CERT_CHAIN_LEN - 1
+        })
+    }
+
+    fn cert_len(
+        &mut self,
+        _msg: &RecvMessage,
+        chain: CertChain,
+        index: u32,
+    ) -> Result<u32, RequestError<AttestError>> {
+        Ok(self.chain_cert(chain, index)?.len() as u32)
+    }
+
+    fn cert(
+        &mut self,
+        _msg: &RecvMessage,
+        chain: CertChain,
+        index: u32,
+        offset: u32,
+        dest: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<AttestError>> {
+        let cert = self.chain_cert(chain, index)?;
+        let rest = cert
+            .get(offset as usize..)
+            .ok_or(AttestError::BadCertOffset)?;
+        let n = rest.len().min(dest.len());
+        dest.write_range(0..n, &rest[..n])
+            .map_err(|_| RequestError::went_away())?;
+        Ok(n as u32)
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let mut server = ServerImpl {
+        log: MeasurementLog::new(),
+        host: MeasurementRegister::new(),
+        alias_keypair: None,
+        alias_cert: None,
+        tqdhe_cert: None,
+        spmeasure_cert: None,
+        certs: None,
+        sp_measure_status: SpMeasureStatus::NotMeasured,
+    };
+
+    // Without the alias data we can still keep the log, but we have no FWID
+    // to start it with and can't sign quotes.
+    match AliasData::load() {
+        Ok(alias) => {
+            server.alias_keypair =
+                Some(Keypair::from(alias.alias_seed.as_bytes()));
+            let mut digest = [0; DIGEST_SIZE];
+            digest.copy_from_slice(alias.alias_cert.get_fwid());
+            server
+                .put(Measurement {
+                    kind: MeasurementKind::RotFwid,
+                    digest,
+                })
+                .unwrap_lite();
+            server.alias_cert = Some(alias.alias_cert);
+            server.tqdhe_cert = Some(alias.tqdhe_cert);
+        }
+        Err(_) => ringbuf_entry!(Trace::NoAliasData),
+    }
+    match CertData::load() {
+        Ok(certs) => server.certs = Some(certs),
+        Err(_) => ringbuf_entry!(Trace::NoCertData),
+    }
+    // We only want the SpMeasure cert; its key is for `sp_measure`.
+    match SpMeasureData::load() {
+        Ok(spmeasure) => server.spmeasure_cert = Some(spmeasure.spmeasure_cert),
+        Err(_) => ringbuf_entry!(Trace::NoSpMeasureData),
+    }
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -10,6 +10,7 @@ use derive_idol_err::IdolError;
 use serde::{Deserialize, Serialize};
 use userlib::*;
 
+pub use drv_sprot_api::{AttestOrSprotError, CertChain};
 pub use host_sp_messages::HostStartupOptions;
 pub use oxide_barcode::ParseError as BarcodeParseError;
 pub use oxide_barcode::VpdIdentity;
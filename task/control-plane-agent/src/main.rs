@@ -5,7 +5,10 @@
 #![no_std]
 #![no_main]
 
-use drv_sprot_api::SprotError;
+use drv_sprot_api::{
+    AttestError, AttestOrSprotError, CertChain, SpRot, SprotError,
+    MAX_BLOB_SIZE,
+};
 use gateway_messages::{
     sp_impl, IgnitionCommand, MgsError, PowerState, SpComponent, SpPort,
     UpdateId,
@@ -44,6 +47,7 @@ use self::mgs_handler::MgsHandler;
 task_slot!(JEFE, jefe);
 task_slot!(NET, net);
 task_slot!(SYS, sys);
+task_slot!(SPROT, sprot);
 
 #[allow(dead_code)] // Not all cases are used by all variants
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -59,6 +63,7 @@ enum Log {
     SerialConsoleSend { buffered: usize },
     UpdatePartial { bytes_written: u32 },
     UpdateComplete,
+    UpdateResumed { component: SpComponent, offset: u32 },
     HostFlashSectorsErased { num_sectors: usize },
     ExpectedRspTimeout,
     RotReset(SprotError),
@@ -166,15 +171,24 @@ fn main() {
 struct ServerImpl {
     mgs_handler: MgsHandler,
     net_handler: NetHandler,
+    sprot: SpRot,
+    // Holds what we fetch from the RoT on behalf of our callers, since we
+    // can't lend their leases on to `sprot`.
+    rot_buf: &'static mut [u8; MAX_BLOB_SIZE],
 }
 
 impl ServerImpl {
     fn claim_static_resources() -> Self {
         let net_handler = NetHandler::claim_static_resources();
         let base_mac_address = net_handler.net.get_mac_address();
+        let rot_buf = mutable_statics! {
+            static mut ROT_BUF: [u8; MAX_BLOB_SIZE] = [|| 0; _];
+        };
         Self {
             mgs_handler: MgsHandler::claim_static_resources(base_mac_address),
             net_handler,
+            sprot: SpRot::from(SPROT.get_task_id()),
+            rot_buf,
         }
     }
 
@@ -297,6 +311,57 @@ impl idl::InOrderControlPlaneAgentImpl for ServerImpl {
         Err(RequestError::Fail(ClientError::BadMessageContents))
     }
 
+    fn rot_attest(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        nonce: [u8; 32],
+        quote: LenLimit<Leased<idol_runtime::W, [u8]>, MAX_BLOB_SIZE>,
+    ) -> Result<u32, RequestError<AttestOrSprotError>> {
+        let len = self.sprot.attest(nonce, &mut self.rot_buf[..])? as usize;
+        if quote.len() < len {
+            return Err(AttestOrSprotError::Attest(
+                AttestError::QuoteBufferTooSmall,
+            )
+            .into());
+        }
+        quote
+            .write_range(0..len, &self.rot_buf[..len])
+            .map_err(|()| RequestError::went_away())?;
+        Ok(len as u32)
+    }
+
+    fn rot_cert_chain_len(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        chain: CertChain,
+    ) -> Result<u32, RequestError<AttestOrSprotError>> {
+        Ok(self.sprot.cert_chain_len(chain)?)
+    }
+
+    fn rot_cert_len(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        chain: CertChain,
+        index: u32,
+    ) -> Result<u32, RequestError<AttestOrSprotError>> {
+        Ok(self.sprot.cert_len(chain, index)?)
+    }
+
+    fn rot_cert(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        chain: CertChain,
+        index: u32,
+        offset: u32,
+        dest: LenLimit<Leased<idol_runtime::W, [u8]>, MAX_BLOB_SIZE>,
+    ) -> Result<u32, RequestError<AttestOrSprotError>> {
+        let buf = &mut self.rot_buf[..dest.len()];
+        let len = self.sprot.cert(chain, index, offset, buf)? as usize;
+        dest.write_range(0..len, &buf[..len])
+            .map_err(|()| RequestError::went_away())?;
+        Ok(len as u32)
+    }
+
     #[cfg(feature = "gimlet")]
     fn get_uart_client(
         &mut self,
@@ -493,7 +558,11 @@ impl NetHandler {
     ) {
         ringbuf_entry!(Log::Rx(meta));
 
-        let Address::Ipv6(addr) = meta.addr;
+        // MGS only talks to us over IPv6, so anything else isn't from MGS.
+        let addr = match meta.addr {
+            Address::Ipv6(addr) => addr,
+            Address::Ipv4(_) => return,
+        };
         let sender = gateway_messages::sp_impl::SocketAddrV6 {
             ip: addr.into(),
             port: meta.port,
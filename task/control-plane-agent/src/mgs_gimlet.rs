@@ -4,8 +4,9 @@
 
 use crate::{
     mgs_common::MgsCommon, notifications, update::host_flash::HostFlashUpdate,
-    update::rot::RotUpdate, update::sp::SpUpdate, update::ComponentUpdater,
-    usize_max, vlan_id_from_sp_port, Log, MgsMessage, SYS,
+    update::journal::Journal, update::rot::RotUpdate, update::sp::SpUpdate,
+    update::ComponentUpdater, usize_max, vlan_id_from_sp_port, Log, MgsMessage,
+    SYS,
 };
 use core::sync::atomic::{AtomicBool, Ordering};
 use core::time::Duration;
@@ -130,6 +131,8 @@ pub(crate) struct MgsHandler {
     serial_console_write_offset: u64,
     next_message_id: u32,
     installinator_image_id: &'static mut InstallinatorImageIdBuf,
+    /// Whether we have yet to check for an interrupted update to resume.
+    resume_pending: bool,
 }
 
 impl MgsHandler {
@@ -151,6 +154,42 @@ impl MgsHandler {
             serial_console_write_offset: 0,
             next_message_id: 0,
             installinator_image_id: claim_installinator_image_id_static(),
+            // Resuming can mean waiting on flash erases, so we leave it to
+            // the main loop (see `handle_timer_fired`), rather than holding
+            // up startup.
+            resume_pending: true,
+        }
+    }
+
+    /// Picks up the update that was in progress when we were last reset, if
+    /// the update journal has one.
+    fn resume_update(&mut self) {
+        let journal = match Journal::load() {
+            Some(journal) => journal,
+            None => return,
+        };
+        let component = journal.component();
+        let offset = journal.offset();
+
+        let result = match component {
+            SpComponent::SP_ITSELF => {
+                self.sp_update.resume(&UPDATE_MEMORY, journal)
+            }
+            SpComponent::HOST_CPU_BOOT_FLASH => {
+                self.host_flash_update.resume(&UPDATE_MEMORY, journal)
+            }
+            SpComponent::ROT | SpComponent::STAGE0 => {
+                self.rot_update.resume(&UPDATE_MEMORY, journal)
+            }
+            _ => Err(SpError::RequestUnsupportedForComponent),
+        };
+
+        match result {
+            Ok(()) => ringbuf_entry!(Log::UpdateResumed { component, offset }),
+            // MGS started another update before we got to this one; starting
+            // it has replaced the journal already.
+            Err(SpError::OtherComponentUpdateInProgress(_)) => (),
+            Err(_) => Journal::clear(),
         }
     }
 
@@ -170,8 +209,10 @@ impl MgsHandler {
         // need to be erased, but we break that work up across multiple steps to
         // avoid blocking while the entire erase happens. If we're in that case,
         // set our timer for 1 tick from now to give a window for other
-        // interrupts/notifications to arrive.
-        if self.host_flash_update.is_preparing()
+        // interrupts/notifications to arrive. We do the same at startup, to
+        // check for an update to resume.
+        if self.resume_pending
+            || self.host_flash_update.is_preparing()
             || self.sp_update.is_preparing()
         {
             Some(sys_get_timer().now + 1)
@@ -189,6 +230,11 @@ impl MgsHandler {
     }
 
     pub(crate) fn handle_timer_fired(&mut self) {
+        if self.resume_pending {
+            self.resume_pending = false;
+            self.resume_update();
+        }
+
         // We use a shared update buffer, so at most one of these updates can be
         // active at a time. For any inactive update, `step_preparation()` is a
         // no-op.
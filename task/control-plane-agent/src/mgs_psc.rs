@@ -3,8 +3,8 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    mgs_common::MgsCommon, update::rot::RotUpdate, update::sp::SpUpdate,
-    update::ComponentUpdater, Log, MgsMessage,
+    mgs_common::MgsCommon, update::journal::Journal, update::rot::RotUpdate,
+    update::sp::SpUpdate, update::ComponentUpdater, Log, MgsMessage,
 };
 use drv_user_leds_api::UserLeds;
 use gateway_messages::sp_impl::{
@@ -47,6 +47,8 @@ pub(crate) struct MgsHandler {
     sp_update: SpUpdate,
     rot_update: RotUpdate,
     user_leds: UserLeds,
+    /// Whether we have yet to check for an interrupted update to resume.
+    resume_pending: bool,
 }
 
 impl MgsHandler {
@@ -58,6 +60,39 @@ impl MgsHandler {
             sp_update: SpUpdate::new(),
             rot_update: RotUpdate::new(),
             user_leds: UserLeds::from(USER_LEDS.get_task_id()),
+            // Resuming can mean waiting on flash erases, so we leave it to
+            // the main loop (see `handle_timer_fired`), rather than holding
+            // up startup.
+            resume_pending: true,
+        }
+    }
+
+    /// Picks up the update that was in progress when we were last reset, if
+    /// the update journal has one.
+    fn resume_update(&mut self) {
+        let journal = match Journal::load() {
+            Some(journal) => journal,
+            None => return,
+        };
+        let component = journal.component();
+        let offset = journal.offset();
+
+        let result = match component {
+            SpComponent::SP_ITSELF => {
+                self.sp_update.resume(&UPDATE_MEMORY, journal)
+            }
+            SpComponent::ROT | SpComponent::STAGE0 => {
+                self.rot_update.resume(&UPDATE_MEMORY, journal)
+            }
+            _ => Err(SpError::RequestUnsupportedForComponent),
+        };
+
+        match result {
+            Ok(()) => ringbuf_entry!(Log::UpdateResumed { component, offset }),
+            // MGS started another update before we got to this one; starting
+            // it has replaced the journal already.
+            Err(SpError::OtherComponentUpdateInProgress(_)) => (),
+            Err(_) => Journal::clear(),
         }
     }
 
@@ -69,7 +104,7 @@ impl MgsHandler {
     /// `main()` is responsible for calling this method and actually setting the
     /// timer.
     pub(crate) fn timer_deadline(&self) -> Option<u64> {
-        if self.sp_update.is_preparing() {
+        if self.resume_pending || self.sp_update.is_preparing() {
             Some(sys_get_timer().now + 1)
         } else {
             None
@@ -77,6 +112,11 @@ impl MgsHandler {
     }
 
     pub(crate) fn handle_timer_fired(&mut self) {
+        if self.resume_pending {
+            self.resume_pending = false;
+            self.resume_update();
+        }
+
         // This is a no-op if we're not preparing for an SP update.
         self.sp_update.step_preparation();
     }
@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use super::journal::{self, Journal};
 use super::{common::CurrentUpdate, ComponentUpdater};
 use crate::mgs_handler::{BorrowedUpdateBuffer, UpdateBuffer};
 use core::ops::Range;
@@ -17,6 +18,10 @@ use gateway_messages::{
 
 userlib::task_slot!(HOST_FLASH, hf);
 
+/// How much of the host flash to read back per step when checking it against
+/// the journal of an update that we're resuming.
+const VERIFY_BYTES_PER_STEP: u32 = 16 * PAGE_SIZE_BYTES as u32;
+
 pub(crate) struct HostFlashUpdate {
     task: HostFlash,
     current: Option<CurrentUpdate<State>>,
@@ -74,6 +79,97 @@ impl HostFlashUpdate {
             Ok(())
         }
     }
+
+    /// Returns the sectors that need to be erased for an update of
+    /// `total_size` bytes to the active slot.
+    fn sectors_to_erase(&self, total_size: u32) -> Result<Range<u32>, SpError> {
+        // What is the total capacity of the device?
+        let capacity = self
+            .task
+            .capacity()
+            .map_err(|err| SpError::UpdateFailed(err as u32))?;
+
+        if total_size as usize > capacity {
+            return Err(SpError::UpdateIsTooLarge);
+        }
+
+        // How many total sectors do we need to erase? For gimlet, we know that
+        // capacity is an exact multiple of the sector size, which is probably
+        // a safe assumption for future parts as well. We'll fail here if that's
+        // untrue, which will require reworking how we erase the target slot.
+        if capacity % SECTOR_SIZE_BYTES != 0 {
+            // We don't have an error case for "our assumptions are wrong", so
+            // we'll fill in an easily-greppable update failure code. In case it
+            // shows up in logs in base 10, 0x1de_0001 == 31326209.
+            return Err(SpError::UpdateFailed(0x1de_0001));
+        }
+        let num_sectors = (capacity / SECTOR_SIZE_BYTES) as u32;
+
+        // Note that we preserve sector 0, which is used for Hubris-level
+        // persistent data.
+        Ok(1..num_sectors)
+    }
+
+    /// Hashes the next part of the host flash for `State::Verifying`,
+    /// returning the state to move to.
+    fn step_verification(
+        &self,
+        buffer: BorrowedUpdateBuffer,
+        sectors_to_erase: Range<u32>,
+        journal: Journal,
+        mut offset: u32,
+        mut hash: u32,
+    ) -> State {
+        let mut page = [0; PAGE_SIZE_BYTES];
+        let end = journal.offset().min(offset + VERIFY_BYTES_PER_STEP);
+        while offset < end {
+            let len = (end - offset).min(PAGE_SIZE_BYTES as u32) as usize;
+            let page = &mut page[..len];
+
+            // We never write sector 0 (see `ingest_chunk()`), so its part of
+            // the image is all 0xFF, whatever the flash actually holds.
+            if (offset as usize) < SECTOR_SIZE_BYTES {
+                page.fill(0xFF);
+            } else if let Err(err) = self.task.read(offset, page) {
+                Journal::clear();
+                return State::Failed(err);
+            }
+            hash = journal::hash(hash, page);
+            offset += len as u32;
+        }
+
+        if offset < journal.offset() {
+            State::Verifying {
+                buffer,
+                sectors_to_erase,
+                journal,
+                offset,
+                hash,
+            }
+        } else if hash == journal.hash() {
+            State::AcceptingData {
+                buffer,
+                next_write_offset: offset,
+                journal,
+            }
+        } else {
+            // The flash doesn't hold what we thought we'd written; start over
+            // from the beginning, with a fresh journal.
+            Journal::clear();
+            State::ErasingSectors {
+                buffer,
+                sectors_to_erase,
+                journal: Journal::new(
+                    journal.component(),
+                    journal.slot(),
+                    journal.id(),
+                    0,
+                    journal.total_size(),
+                    Self::BLOCK_SIZE as u32,
+                ),
+            }
+        }
+    }
 }
 
 // Ensure our `UpdateBuffer` type is sized large enough for us.
@@ -92,6 +188,7 @@ impl ComponentUpdater for HostFlashUpdate {
         // Do we have an update already in progress?
         match self.current.as_ref().map(CurrentUpdate::state) {
             Some(State::ErasingSectors { .. })
+            | Some(State::Verifying { .. })
             | Some(State::AcceptingData { .. }) => {
                 return Err(SpError::UpdateInProgress(self.status()));
             }
@@ -115,36 +212,54 @@ impl ComponentUpdater for HostFlashUpdate {
         // persist those changes to non-volatile memory.
         self.set_active_slot(update.slot, false)?;
 
-        // What is the total capacity of the device?
-        let capacity = self
-            .task
-            .capacity()
-            .map_err(|err| SpError::UpdateFailed(err as u32))?;
+        let sectors_to_erase = self.sectors_to_erase(update.total_size)?;
 
-        if update.total_size as usize > capacity {
-            return Err(SpError::UpdateIsTooLarge);
-        }
-
-        // How many total sectors do we need to erase? For gimlet, we know that
-        // capacity is an exact multiple of the sector size, which is probably
-        // a safe assumption for future parts as well. We'll fail here if that's
-        // untrue, which will require reworking how we erase the target slot.
-        if capacity % SECTOR_SIZE_BYTES != 0 {
-            // We don't have an error case for "our assumptions are wrong", so
-            // we'll fill in an easily-greppable update failure code. In case it
-            // shows up in logs in base 10, 0x1de_0001 == 31326209.
-            return Err(SpError::UpdateFailed(0x1de_0001));
-        }
-        let num_sectors = (capacity / SECTOR_SIZE_BYTES) as u32;
-
-        // Note that we preserve sector 0, which is used for Hubris-level
-        // persistent data.
+        Journal::clear();
         self.current = Some(CurrentUpdate::new(
             update.id,
             update.total_size,
             State::ErasingSectors {
                 buffer,
-                sectors_to_erase: 1..num_sectors,
+                sectors_to_erase,
+                journal: Journal::new(
+                    update.component,
+                    update.slot,
+                    update.id,
+                    0,
+                    update.total_size,
+                    Self::BLOCK_SIZE as u32,
+                ),
+            },
+        ));
+
+        Ok(())
+    }
+
+    fn resume(
+        &mut self,
+        buffer: &'static UpdateBuffer,
+        journal: Journal,
+    ) -> Result<(), SpError> {
+        let buffer = buffer
+            .borrow(SpComponent::HOST_CPU_BOOT_FLASH, Self::BLOCK_SIZE)
+            .map_err(|component| {
+                SpError::OtherComponentUpdateInProgress(component)
+            })?;
+
+        self.set_active_slot(journal.slot(), false)?;
+        let sectors_to_erase = self.sectors_to_erase(journal.total_size())?;
+
+        // Before we pick up where we left off, we check that the flash holds
+        // what the journal says we wrote to it.
+        self.current = Some(CurrentUpdate::new(
+            journal.id(),
+            journal.total_size(),
+            State::Verifying {
+                buffer,
+                sectors_to_erase,
+                journal,
+                offset: 0,
+                hash: journal::HASH_INIT,
             },
         ));
 
@@ -153,7 +268,8 @@ impl ComponentUpdater for HostFlashUpdate {
 
     fn is_preparing(&self) -> bool {
         match self.current.as_ref().map(CurrentUpdate::state) {
-            Some(State::ErasingSectors { .. }) => true,
+            Some(State::ErasingSectors { .. })
+            | Some(State::Verifying { .. }) => true,
             Some(State::AcceptingData { .. })
             | Some(State::Complete)
             | Some(State::Failed(_))
@@ -169,11 +285,27 @@ impl ComponentUpdater for HostFlashUpdate {
         };
 
         current.update_state(|state| {
-            let (buffer, mut sectors_to_erase) = match state {
+            let (buffer, mut sectors_to_erase, journal) = match state {
                 State::ErasingSectors {
                     buffer,
                     sectors_to_erase,
-                } => (buffer, sectors_to_erase),
+                    journal,
+                } => (buffer, sectors_to_erase, journal),
+                State::Verifying {
+                    buffer,
+                    sectors_to_erase,
+                    journal,
+                    offset,
+                    hash,
+                } => {
+                    return self.step_verification(
+                        buffer,
+                        sectors_to_erase,
+                        journal,
+                        offset,
+                        hash,
+                    );
+                }
                 State::AcceptingData { .. }
                 | State::Complete
                 | State::Failed(_)
@@ -195,11 +327,13 @@ impl ComponentUpdater for HostFlashUpdate {
                         State::AcceptingData {
                             buffer,
                             next_write_offset: 0,
+                            journal,
                         }
                     } else {
                         State::ErasingSectors {
                             buffer,
                             sectors_to_erase,
+                            journal,
                         }
                     }
                 }
@@ -224,9 +358,19 @@ impl ComponentUpdater for HostFlashUpdate {
                     total: sectors_to_erase.end,
                 }),
             }),
+            State::Verifying {
+                journal, offset, ..
+            } => UpdateStatus::Preparing(UpdatePreparationStatus {
+                id: current.id(),
+                progress: Some(UpdatePreparationProgress {
+                    current: *offset,
+                    total: journal.offset(),
+                }),
+            }),
             State::AcceptingData {
                 buffer,
                 next_write_offset,
+                ..
             } => UpdateStatus::InProgress(UpdateInProgressStatus {
                 id: current.id(),
                 bytes_received: next_write_offset + buffer.len() as u32,
@@ -254,14 +398,16 @@ impl ComponentUpdater for HostFlashUpdate {
         let current_id = current.id();
         let total_size = current.total_size();
 
-        let (buffer, next_write_offset) = match current.state_mut() {
+        let (buffer, next_write_offset, journal) = match current.state_mut() {
             State::AcceptingData {
                 buffer,
                 next_write_offset,
-            } => (buffer, next_write_offset),
-            State::ErasingSectors { .. } | State::Complete | State::Aborted => {
-                return Err(SpError::UpdateNotPrepared)
-            }
+                journal,
+            } => (buffer, next_write_offset, journal),
+            State::ErasingSectors { .. }
+            | State::Verifying { .. }
+            | State::Complete
+            | State::Aborted => return Err(SpError::UpdateNotPrepared),
             State::Failed(err) => {
                 return Err(SpError::UpdateFailed(*err as u32))
             }
@@ -303,6 +449,7 @@ impl ComponentUpdater for HostFlashUpdate {
 
                 if buffer[0..skip_bytes].iter().any(|b| *b != 0xFF) {
                     let err = HfError::Sector0IsReserved;
+                    Journal::clear();
                     *current.state_mut() = State::Failed(err);
                     return Err(SpError::UpdateFailed(err as u32));
                 }
@@ -313,11 +460,15 @@ impl ComponentUpdater for HostFlashUpdate {
                         HfProtectMode::ProtectSector0,
                         &buffer[skip_bytes..],
                     ) {
+                        Journal::clear();
                         *current.state_mut() = State::Failed(err);
                         return Err(SpError::UpdateFailed(err as u32));
                     }
                 }
 
+                // The skipped bytes are part of the journal's hash too: when
+                // we check it, we treat sector 0 as if it was erased.
+                journal.advance(buffer);
                 *next_write_offset += buffer.len() as u32;
                 buffer.clear();
             }
@@ -327,6 +478,7 @@ impl ComponentUpdater for HostFlashUpdate {
         // Should we set the device back to what it was if we had to change it
         // to write this update?
         if *next_write_offset == total_size {
+            Journal::clear();
             *current.state_mut() = State::Complete;
         }
 
@@ -349,10 +501,12 @@ impl ComponentUpdater for HostFlashUpdate {
             // Active states - do any work necessary to abort (none for host
             // flash), then set our state to `Aborted`.
             State::ErasingSectors { .. }
+            | State::Verifying { .. }
             | State::AcceptingData { .. }
             | State::Failed(_) => {
                 // TODO should we erase the slot? TODO should we set_dev() back
                 // to what it was (if we changed it)?
+                Journal::clear();
                 *current.state_mut() = State::Aborted;
                 Ok(())
             }
@@ -370,10 +524,22 @@ enum State {
     ErasingSectors {
         buffer: BorrowedUpdateBuffer,
         sectors_to_erase: Range<u32>,
+        journal: Journal,
+    },
+    /// Checking the host flash against the journal of an update that we're
+    /// resuming; if it doesn't match, we'll start the update over by erasing
+    /// `sectors_to_erase`.
+    Verifying {
+        buffer: BorrowedUpdateBuffer,
+        sectors_to_erase: Range<u32>,
+        journal: Journal,
+        offset: u32,
+        hash: u32,
     },
     AcceptingData {
         buffer: BorrowedUpdateBuffer,
         next_write_offset: u32,
+        journal: Journal,
     },
     Complete,
     Aborted,
@@ -0,0 +1,183 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Persistent progress journal for component updates.
+//!
+//! Without a journal, everything we know about an update's progress is lost if
+//! we're reset partway through it, and MGS has to start over. So, as each
+//! updater writes its component's image, it keeps a compact journal -- the
+//! update ID, the total size, a bitmap of the segments of the image that have
+//! been written, and a running hash of the data in them -- and saves it each
+//! time a segment is finished. On startup, the MGS handler loads the journal
+//! and hands it to the matching updater, which picks the update back up,
+//! reporting the end of the last complete segment as the number of bytes
+//! received; MGS can then send the rest of the image from there.
+//!
+//! Only one update can be in progress at a time (they share an update buffer),
+//! so there's only one journal. It's kept by the SP's update server, in its
+//! flash (see that server's `update-journal` feature). If the update server
+//! can't keep it, saving the journal does nothing, and updates can't be
+//! resumed.
+//!
+//! The record format is in the `update-journal` crate.
+
+use drv_update_api::{Update, UpdateError, JOURNAL_RECORD_BYTES};
+use gateway_messages::{SpComponent, UpdateId};
+use ringbuf::{ringbuf, ringbuf_entry};
+use update_journal::Record;
+use zerocopy::{AsBytes, FromBytes};
+
+pub(super) use update_journal::{hash, HASH_INIT};
+
+userlib::task_slot!(UPDATE_SERVER, update_server);
+
+ringbuf!(Trace, 16, Trace::None);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    Loaded { id: UpdateId, offset: u32 },
+    Saved { id: UpdateId, offset: u32 },
+    SaveFailed(UpdateError),
+    Cleared,
+}
+
+static_assertions::const_assert_eq!(
+    core::mem::size_of::<Record>(),
+    JOURNAL_RECORD_BYTES
+);
+static_assertions::const_assert_eq!(
+    update_journal::COMPONENT_ID_LEN,
+    SpComponent::MAX_ID_LENGTH
+);
+
+fn save(record: &mut Record) -> Result<(), UpdateError> {
+    record.seal();
+    Update::from(UPDATE_SERVER.get_task_id())
+        .write_update_journal(record.as_bytes())
+}
+
+/// The journal of an update in progress.
+pub(crate) struct Journal {
+    record: Record,
+    // How much of the image has been written, and its hash. These run ahead of
+    // `record`, which is only brought up to date at the end of each segment.
+    offset: u32,
+    hash: u32,
+}
+
+impl Journal {
+    /// Starts a journal for the update `id`, of `total_size` bytes, to `slot`
+    /// of `component`. Its segments are a multiple of `unit` bytes, which
+    /// should be the granularity at which the target can resume.
+    pub(crate) fn new(
+        component: SpComponent,
+        slot: u16,
+        id: UpdateId,
+        aux_flash_size: u32,
+        total_size: u32,
+        unit: u32,
+    ) -> Self {
+        Self {
+            record: Record::new(
+                component.id,
+                id.0,
+                slot,
+                aux_flash_size,
+                total_size,
+                unit,
+            ),
+            offset: 0,
+            hash: HASH_INIT,
+        }
+    }
+
+    /// Loads the journal of the update that was in progress when we were
+    /// reset, if there was one and there's anything in it to resume.
+    pub(crate) fn load() -> Option<Self> {
+        let server = Update::from(UPDATE_SERVER.get_task_id());
+        let record = update_journal::select(|back| {
+            let mut record = Record::new_zeroed();
+            match server.read_update_journal(back, record.as_bytes_mut()) {
+                Ok(true) => Some(record),
+                Ok(false) | Err(_) => None,
+            }
+        })?;
+        let offset = record.resume_offset()?;
+        ringbuf_entry!(Trace::Loaded {
+            id: UpdateId(record.id),
+            offset
+        });
+        Some(Self {
+            hash: record.hash,
+            record,
+            offset,
+        })
+    }
+
+    /// Forgets the journal, if any. Updaters do this when an update is
+    /// started, completed, or abandoned.
+    pub(crate) fn clear() {
+        ringbuf_entry!(Trace::Cleared);
+        if let Err(err) = save(&mut Record::empty()) {
+            ringbuf_entry!(Trace::SaveFailed(err));
+        }
+    }
+
+    pub(crate) fn component(&self) -> SpComponent {
+        SpComponent {
+            id: self.record.component,
+        }
+    }
+
+    pub(crate) fn slot(&self) -> u16 {
+        self.record.slot
+    }
+
+    pub(crate) fn id(&self) -> UpdateId {
+        UpdateId(self.record.id)
+    }
+
+    pub(crate) fn aux_flash_size(&self) -> u32 {
+        self.record.aux_flash_size
+    }
+
+    pub(crate) fn total_size(&self) -> u32 {
+        self.record.total_size
+    }
+
+    /// Returns how much of the image has been written, which, for a journal
+    /// that was just loaded, is where the update can be resumed.
+    pub(crate) fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the running hash of the image up to `offset()`.
+    #[allow(dead_code)] // not used by all configurations
+    pub(crate) fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    /// Records that `data`, which follows what's already been written, has
+    /// been written to the target, and saves the journal if that finishes a
+    /// segment.
+    ///
+    /// Failing to save the journal doesn't fail the update -- it just means
+    /// that it can't be resumed from here -- so it's only logged.
+    pub(crate) fn advance(&mut self, data: &[u8]) {
+        self.hash = hash(self.hash, data);
+        self.offset += data.len() as u32;
+
+        if !self.record.finish_segment(self.offset, self.hash) {
+            return;
+        }
+        match save(&mut self.record) {
+            Ok(()) => ringbuf_entry!(Trace::Saved {
+                id: self.id(),
+                offset: self.offset
+            }),
+            Err(err) => ringbuf_entry!(Trace::SaveFailed(err)),
+        }
+    }
+}
@@ -6,11 +6,13 @@ use crate::mgs_handler::UpdateBuffer;
 use gateway_messages::{
     ComponentUpdatePrepare, SpError, UpdateId, UpdateStatus,
 };
+use journal::Journal;
 
 #[cfg(feature = "gimlet")]
 pub(crate) mod host_flash;
 
 mod common;
+pub(crate) mod journal;
 pub(crate) mod rot;
 pub(crate) mod sp;
 
@@ -41,6 +43,15 @@ pub(crate) trait ComponentUpdater {
         update: ComponentUpdatePrepare,
     ) -> Result<(), SpError>;
 
+    /// Attempt to pick up the update described by `journal`, which was in
+    /// progress when we were reset, using `buffer` as the backing store for
+    /// incoming data.
+    fn resume(
+        &mut self,
+        buffer: &'static UpdateBuffer,
+        journal: Journal,
+    ) -> Result<(), SpError>;
+
     /// Returns true if this task needs `step_preparation()` called.
     fn is_preparing(&self) -> bool;
 
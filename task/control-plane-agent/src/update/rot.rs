@@ -2,9 +2,10 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use super::{common::CurrentUpdate, ComponentUpdater};
+use super::{common::CurrentUpdate, journal::Journal, ComponentUpdater};
 use crate::mgs_handler::{BorrowedUpdateBuffer, UpdateBuffer};
 use drv_sprot_api::{SpRot, SprotError};
+use drv_update_api::compressed::is_compressed;
 use drv_update_api::lpc55::BLOCK_SIZE_BYTES;
 use drv_update_api::UpdateTarget;
 use ringbuf::{ringbuf, ringbuf_entry};
@@ -24,6 +25,8 @@ enum Trace {
     IngestChunkInput { offset: u32, len: usize },
     IngestChunkState { offset: u32, len: usize },
     WriteOneBlock(u32, usize, usize),
+    WriteCompressed(u32, usize),
+    Resumed(u32),
 }
 
 pub(crate) struct RotUpdate {
@@ -40,10 +43,24 @@ impl RotUpdate {
     }
 }
 
+/// Returns the update target for `slot` of `component`.
+fn target(component: SpComponent, slot: u16) -> Result<UpdateTarget, SpError> {
+    match (component, slot) {
+        (SpComponent::ROT, 0) => Ok(UpdateTarget::ImageA),
+        (SpComponent::ROT, 1) => Ok(UpdateTarget::ImageB),
+        (SpComponent::STAGE0, 0) => Ok(UpdateTarget::Bootloader),
+        _ => Err(SpError::InvalidSlotForComponent),
+    }
+}
+
 enum State {
     AcceptingData {
         buffer: BorrowedUpdateBuffer,
         next_write_offset: u32,
+        journal: Journal,
+        /// Whether the image is compressed, which we find out from its first
+        /// block.
+        compressed: bool,
     },
     Complete,
     Aborted,
@@ -76,13 +93,9 @@ impl ComponentUpdater for RotUpdate {
             .map_err(SpError::OtherComponentUpdateInProgress)?;
 
         // Which target are we updating?
-        let target = match (update.component, update.slot) {
-            (SpComponent::ROT, 0) => UpdateTarget::ImageA,
-            (SpComponent::ROT, 1) => UpdateTarget::ImageB,
-            (SpComponent::STAGE0, 0) => UpdateTarget::Bootloader,
-            _ => return Err(SpError::InvalidSlotForComponent),
-        };
+        let target = target(update.component, update.slot)?;
 
+        Journal::clear();
         self.task.prep_image_update(target)?;
 
         self.current = Some(CurrentUpdate::new(
@@ -91,6 +104,48 @@ impl ComponentUpdater for RotUpdate {
             State::AcceptingData {
                 buffer,
                 next_write_offset: 0,
+                compressed: false,
+                journal: Journal::new(
+                    update.component,
+                    update.slot,
+                    update.id,
+                    0,
+                    update.total_size,
+                    Self::BLOCK_SIZE as u32,
+                ),
+            },
+        ));
+
+        Ok(())
+    }
+
+    fn resume(
+        &mut self,
+        buffer: &'static UpdateBuffer,
+        journal: Journal,
+    ) -> Result<(), SpError> {
+        let buffer = buffer
+            .borrow(journal.component(), Self::BLOCK_SIZE)
+            .map_err(SpError::OtherComponentUpdateInProgress)?;
+        let target = target(journal.component(), journal.slot())?;
+
+        // The RoT can resume from any block, so we expect it to agree with
+        // the journal.
+        let block_num = journal.offset() / Self::BLOCK_SIZE as u32;
+        if self.task.resume_image_update(target, block_num)? != block_num {
+            return Err(SpError::UpdateNotPrepared);
+        }
+        ringbuf_entry!(Trace::Resumed(block_num));
+
+        self.current = Some(CurrentUpdate::new(
+            journal.id(),
+            journal.total_size(),
+            State::AcceptingData {
+                buffer,
+                next_write_offset: journal.offset(),
+                journal,
+                // We don't journal compressed images.
+                compressed: false,
             },
         ));
 
@@ -117,6 +172,7 @@ impl ComponentUpdater for RotUpdate {
             State::AcceptingData {
                 buffer,
                 next_write_offset,
+                ..
             } => UpdateStatus::InProgress(UpdateInProgressStatus {
                 id: current.id(),
                 bytes_received: next_write_offset + buffer.len() as u32,
@@ -161,16 +217,19 @@ impl ComponentUpdater for RotUpdate {
         let current_id = current.id();
         let total_size = current.total_size();
 
-        let (buffer, next_write_offset) = match current.state_mut() {
-            State::AcceptingData {
-                buffer,
-                next_write_offset,
-            } => (buffer, next_write_offset),
-            State::Complete | State::Aborted => {
-                return Err(SpError::UpdateNotPrepared)
-            }
-            State::Failed(err) => return Err((*err).into()),
-        };
+        let (buffer, next_write_offset, journal, compressed) =
+            match current.state_mut() {
+                State::AcceptingData {
+                    buffer,
+                    next_write_offset,
+                    journal,
+                    compressed,
+                } => (buffer, next_write_offset, journal, compressed),
+                State::Complete | State::Aborted => {
+                    return Err(SpError::UpdateNotPrepared)
+                }
+                State::Failed(err) => return Err((*err).into()),
+            };
 
         ringbuf_entry!(Trace::IngestChunkState {
             offset: *next_write_offset,
@@ -200,18 +259,38 @@ impl ComponentUpdater for RotUpdate {
             if buffer.len() == buffer.capacity()
                 || *next_write_offset + buffer.len() as u32 == total_size
             {
-                let block_num = *next_write_offset / Self::BLOCK_SIZE as u32;
-                ringbuf_entry!(Trace::WriteOneBlock(
-                    block_num,
-                    buffer.len(),
-                    buffer.capacity()
-                ));
-                if let Err(err) = self.task.write_one_block(block_num, buffer) {
-                    *current.state_mut() = State::Failed(err);
+                if *next_write_offset == 0 {
+                    *compressed = is_compressed(buffer);
+                }
 
+                // A compressed image is passed on as it is, for the RoT to
+                // decompress; since it can't resume decompression, we don't
+                // journal it.
+                let result = if *compressed {
+                    ringbuf_entry!(Trace::WriteCompressed(
+                        *next_write_offset,
+                        buffer.len()
+                    ));
+                    self.task.write_compressed(*next_write_offset, buffer)
+                } else {
+                    let block_num =
+                        *next_write_offset / Self::BLOCK_SIZE as u32;
+                    ringbuf_entry!(Trace::WriteOneBlock(
+                        block_num,
+                        buffer.len(),
+                        buffer.capacity()
+                    ));
+                    self.task.write_one_block(block_num, buffer)
+                };
+                if let Err(err) = result {
+                    *current.state_mut() = State::Failed(err);
+                    Journal::clear();
                     return Err(err.into());
                 }
 
+                if !*compressed {
+                    journal.advance(buffer);
+                }
                 *next_write_offset += buffer.len() as u32;
                 buffer.clear();
             }
@@ -219,6 +298,7 @@ impl ComponentUpdater for RotUpdate {
 
         // Finish the update if we just wrote the last block.
         if *next_write_offset == total_size {
+            Journal::clear();
             if let Err(err) = self.task.finish_image_update() {
                 *current.state_mut() = State::Failed(err);
                 return Err(err.into());
@@ -244,6 +324,7 @@ impl ComponentUpdater for RotUpdate {
                 match self.task.abort_update() {
                     Ok(()) => {
                         *current.state_mut() = State::Aborted;
+                        Journal::clear();
                         Ok(())
                     }
                     Err(err) => Err(err.into()),
@@ -54,12 +54,18 @@
                                  └───────────────┘
 */
 
+// If we're reset while in `State::AcceptingData(_)`, the update can be resumed
+// from its journal (see `super::journal`), which goes straight back to that
+// state.
+
+use super::journal::Journal;
 use crate::mgs_handler::{BorrowedUpdateBuffer, UpdateBuffer};
 use cfg_if::cfg_if;
 use core::ops::{Deref, DerefMut};
 use drv_caboose::CabooseReader;
 use drv_sprot_api::SpRot;
-use drv_update_api::stm32h7::BLOCK_SIZE_BYTES;
+use drv_update_api::compressed::is_compressed;
+use drv_update_api::stm32h7::{BLOCK_SIZE_BYTES, SECTOR_SIZE_BYTES};
 use drv_update_api::{Update, UpdateError, UpdateTarget};
 use gateway_messages::{
     ImageVersion, SpComponent, SpError, SpUpdatePrepare, UpdateId,
@@ -163,7 +169,8 @@ impl SpUpdate {
             return Err(SpError::RequestUnsupportedForSp);
         }
 
-        // Attempt to prepare for an update (erases our flash).
+        // Attempt to prepare for an update (erases our flash, including any
+        // update journal).
         self.sp_task
             .prep_image_update(UpdateTarget::Alternate)
             .map_err(|err| SpError::UpdateFailed(err as u32))?;
@@ -178,6 +185,8 @@ impl SpUpdate {
             State::AcceptingData(AcceptingData {
                 buffer,
                 next_write_offset: 0,
+                journal: new_journal(update.id, 0, update.sp_image_size),
+                compressed: false,
             })
         };
 
@@ -191,6 +200,48 @@ impl SpUpdate {
         Ok(())
     }
 
+    pub(crate) fn resume(
+        &mut self,
+        buffer: &'static UpdateBuffer,
+        journal: Journal,
+    ) -> Result<(), SpError> {
+        let buffer = buffer
+            .borrow(SpComponent::SP_ITSELF, BLOCK_SIZE_BYTES)
+            .map_err(|component| {
+                SpError::OtherComponentUpdateInProgress(component)
+            })?;
+
+        // The update server resumes from the start of a sector, and our
+        // journal's segments are whole sectors, so we expect them to agree.
+        let block = journal.offset() as usize / BLOCK_SIZE_BYTES;
+        match self
+            .sp_task
+            .resume_image_update(UpdateTarget::Alternate, block)
+        {
+            Ok(b) if b == block => (),
+            Ok(_) => {
+                let _ = self.sp_task.abort_update();
+                return Err(SpError::UpdateNotPrepared);
+            }
+            Err(err) => return Err(SpError::UpdateFailed(err as u32)),
+        }
+
+        self.current = Some(CurrentUpdate::new(
+            journal.id(),
+            journal.aux_flash_size(),
+            journal.total_size(),
+            State::AcceptingData(AcceptingData {
+                buffer,
+                next_write_offset: journal.offset(),
+                journal,
+                // We don't journal compressed images.
+                compressed: false,
+            }),
+        ));
+
+        Ok(())
+    }
+
     pub(crate) fn is_preparing(&self) -> bool {
         match self.current.as_ref().map(|c| c.state()) {
             Some(State::AuxFlash(s)) => s.is_preparing(),
@@ -352,6 +403,12 @@ impl SpUpdate {
                     State::AcceptingData(AcceptingData {
                         buffer,
                         next_write_offset: 0,
+                        journal: new_journal(
+                            *id,
+                            aux_flash_size,
+                            sp_image_size,
+                        ),
+                        compressed: false,
                     })
                 }
             };
@@ -376,6 +433,8 @@ impl SpUpdate {
                 State::FoundMatchingAuxFlashChck { buffer } => AcceptingData {
                     buffer,
                     next_write_offset: 0,
+                    journal: new_journal(*id, aux_flash_size, sp_image_size),
+                    compressed: false,
                 },
                 State::AcceptingData(a) => a,
                 State::Complete | State::Aborted => {
@@ -419,6 +478,7 @@ impl SpUpdate {
                     // either way our caller is clear to start a new update.
                     Ok(()) | Err(UpdateError::UpdateNotStarted) => {
                         *current.state_mut() = State::Aborted;
+                        Journal::clear();
                         Ok(())
                     }
                     Err(other) => Err(SpError::UpdateFailed(other as u32)),
@@ -485,6 +545,26 @@ enum State {
 struct AcceptingData {
     buffer: BorrowedUpdateBuffer,
     next_write_offset: u32,
+    journal: Journal,
+    /// Whether the SP image is compressed, which we find out from its first
+    /// block.
+    compressed: bool,
+}
+
+/// Starts a journal for the SP image part of the update `id`.
+fn new_journal(
+    id: UpdateId,
+    aux_flash_size: u32,
+    sp_image_size: u32,
+) -> Journal {
+    Journal::new(
+        SpComponent::SP_ITSELF,
+        0,
+        id,
+        aux_flash_size,
+        sp_image_size,
+        SECTOR_SIZE_BYTES as u32,
+    )
 }
 
 impl AcceptingData {
@@ -514,14 +594,32 @@ impl AcceptingData {
                 || self.next_write_offset + self.buffer.len() as u32
                     == sp_image_size
             {
-                let block = self.next_write_offset as usize / BLOCK_SIZE_BYTES;
-                if let Err(err) = sp_task.write_one_block(block, &self.buffer) {
+                if self.next_write_offset == 0 {
+                    self.compressed = is_compressed(&self.buffer);
+                }
+
+                // A compressed image is passed on as it is, for the update
+                // server to decompress; since it can't resume decompression,
+                // we don't journal it.
+                let result = if self.compressed {
+                    sp_task
+                        .write_compressed(self.next_write_offset, &self.buffer)
+                } else {
+                    let block =
+                        self.next_write_offset as usize / BLOCK_SIZE_BYTES;
+                    sp_task.write_one_block(block, &self.buffer)
+                };
+                if let Err(err) = result {
+                    Journal::clear();
                     return (
                         State::Failed(err),
                         Err(SpError::UpdateFailed(err as u32)),
                     );
                 }
 
+                if !self.compressed {
+                    self.journal.advance(&self.buffer);
+                }
                 self.next_write_offset += self.buffer.len() as u32;
                 self.buffer.clear();
             }
@@ -529,6 +627,8 @@ impl AcceptingData {
 
         // Did we write the last block?
         if self.next_write_offset == sp_image_size {
+            Journal::clear();
+
             // Confirm that the image written is targeting the same board as our
             // current image.  If the current image doesn't have a caboose or a
             // `BORD` key, then we'll accept anything, but the incoming image
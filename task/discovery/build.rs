@@ -0,0 +1,35 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::Write;
+use std::net::Ipv6Addr;
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+
+    // We announce ourselves to the first multicast group that our socket
+    // joins, on its port.
+    let net_config = build_net::load_net_config()?;
+    let socket = net_config
+        .sockets
+        .get("discovery")
+        .ok_or_else(|| anyhow!("there's no discovery socket"))?;
+    let group: Ipv6Addr = socket
+        .multicast
+        .first()
+        .ok_or_else(|| anyhow!("the discovery socket joins no groups"))?
+        .parse()?;
+
+    let version: u32 = build_util::env_var("HUBRIS_BUILD_VERSION")?.parse()?;
+
+    let out = build_util::out_dir();
+    let mut consts = File::create(out.join("consts.rs"))?;
+    writeln!(consts, "const GROUP: [u8; 16] = {:?};", group.octets())?;
+    writeln!(consts, "const PORT: u16 = {};", socket.port)?;
+    writeln!(consts, "const HUBRIS_BUILD_VERSION: u32 = {};", version)?;
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Discovery responder.
+//!
+//! This lets tools on the management network find SPs without knowing where
+//! to look. Our socket joins a multicast group (the first one listed for the
+//! `discovery` socket in the app.toml), and a query sent to that group, on
+//! the socket's port, gets a reply from every SP listening. Each reply is an
+//! `Announcement` saying who the SP is and what it's running; we also send
+//! one to the group on every VLAN when we start, so that listeners hear about
+//! us without asking.
+//!
+//! A query is any packet that starts with `QUERY_MAGIC`.
+
+#![no_std]
+#![no_main]
+
+use hubpack::SerializedSize;
+use serde::Serialize;
+use task_net_api::*;
+use task_packrat_api::{Packrat, VpdIdentity};
+use userlib::*;
+
+task_slot!(NET, net);
+task_slot!(PACKRAT, packrat);
+
+const SOCKET: SocketName = SocketName::discovery;
+
+const QUERY_MAGIC: [u8; 4] = *b"SPD?";
+
+#[derive(Debug, Clone, Copy, Serialize, SerializedSize)]
+struct Announcement {
+    /// Always `Announcement::MAGIC`, to tell these apart from queries (which
+    /// other listeners on the group see too).
+    magic: [u8; 4],
+
+    // Version for this data structure; adding new fields to the end is okay,
+    // but changing the order, size, or meaning of existing fields should result
+    // in a version bump.
+    version: u32,
+
+    mac_address: [u8; 6],
+    image_id: [u8; 8],
+    build_version: u32,
+
+    // If true, we have identity from our VPD, and the following three fields
+    // are populated. If false, we have no VPD or failed to read it, and the
+    // following three fields will be all zero.
+    identity_valid: bool,
+    part_number: [u8; VpdIdentity::PART_NUMBER_LEN],
+    revision: u32,
+    serial: [u8; VpdIdentity::SERIAL_LEN],
+}
+
+impl Announcement {
+    const MAGIC: [u8; 4] = *b"SPD!";
+    const CURRENT_VERSION: u32 = 1;
+}
+
+// Ensure our serialized size doesn't change unexpectedly: if you land here
+// because compilation has failed, consider whether you need to update
+// `Announcement::CURRENT_VERSION`!
+//
+// Current size is 53 bytes:
+// magic (4)
+// version (4)
+// mac_address (6)
+// image_id (8)
+// build_version (4)
+// identity_valid (1)
+// part_number (11)
+// revision (4)
+// serial (11)
+static_assertions::const_assert_eq!(Announcement::MAX_SIZE, 53);
+
+#[export_name = "main"]
+fn main() -> ! {
+    let net = NET.get_task_id();
+    let net = Net::from(net);
+
+    let packrat = PACKRAT.get_task_id();
+    let packrat = Packrat::from(packrat);
+
+    // Ask `net` for our mac address first; this also serves as a useful wait
+    // for `packrat` to be loaded by the sequencer if we're on a board with VPD.
+    let mac_address = net.get_mac_address().0;
+
+    let mut out = [0u8; Announcement::MAX_SIZE];
+    let n = announce(&packrat, mac_address, &mut out);
+    let group = Address::Ipv6(Ipv6Address(GROUP));
+    #[cfg(feature = "vlan")]
+    for vid in VLAN_RANGE {
+        let meta = UdpMetadata {
+            addr: group,
+            port: PORT,
+            size: n as u32,
+            vid,
+        };
+        send(&net, meta, &out[..n]);
+    }
+    #[cfg(not(feature = "vlan"))]
+    send(
+        &net,
+        UdpMetadata {
+            addr: group,
+            port: PORT,
+            size: n as u32,
+        },
+        &out[..n],
+    );
+
+    loop {
+        // Queries are tiny; anything that doesn't fit here isn't one.
+        let mut rx_data_buf = [0u8; 16];
+        match net.recv_packet(
+            SOCKET,
+            LargePayloadBehavior::Discard,
+            &mut rx_data_buf,
+        ) {
+            Ok(meta) => {
+                if rx_data_buf[..meta.size as usize].starts_with(&QUERY_MAGIC) {
+                    QUERY_COUNT
+                        .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                    // Look our identity up again, in case it's turned up
+                    // since we started.
+                    let n = announce(&packrat, mac_address, &mut out);
+                    let meta = UdpMetadata {
+                        size: n as u32,
+                        ..meta
+                    };
+                    send(&net, meta, &out[..n]);
+                }
+            }
+            Err(RecvError::QueueEmpty) => {
+                // Our incoming queue is empty. Wait for more packets.
+                sys_recv_closed(
+                    &mut [],
+                    notifications::SOCKET_MASK,
+                    TaskId::KERNEL,
+                )
+                .unwrap();
+            }
+            Err(RecvError::ServerRestarted) => {
+                // `net` restarted (probably due to the watchdog); just retry.
+            }
+            Err(RecvError::NotYours) => panic!(),
+            Err(RecvError::Other) => panic!(),
+        }
+    }
+}
+
+/// Serializes our `Announcement` into `out`, returning its length.
+fn announce(packrat: &Packrat, mac_address: [u8; 6], out: &mut [u8]) -> usize {
+    // If we're on a board with no VPD or VPD reading failed, we'll construct a
+    // default (all 0) identity and set `identity_valid` to false.
+    let identity = packrat.get_identity().ok();
+    let identity_valid = identity.is_some();
+    let identity = identity.unwrap_or_default();
+
+    let data = Announcement {
+        magic: Announcement::MAGIC,
+        version: Announcement::CURRENT_VERSION,
+        mac_address,
+        image_id: kipc::read_image_id().to_le_bytes(),
+        build_version: HUBRIS_BUILD_VERSION,
+        identity_valid,
+        part_number: identity.part_number,
+        revision: identity.revision,
+        serial: identity.serial,
+    };
+    hubpack::serialize(out, &data).unwrap_lite()
+}
+
+/// Sends a reply or announcement. This is best effort: if our tx queue is
+/// full, whoever asked can ask again.
+fn send(net: &Net, meta: UdpMetadata, payload: &[u8]) {
+    match net.send_packet(SOCKET, meta, payload) {
+        Ok(()) => (),
+        Err(SendError::QueueFull | SendError::ServerRestarted) => {
+            SEND_ERROR_COUNT
+                .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        }
+        Err(
+            SendError::NotYours | SendError::InvalidVLan | SendError::Other,
+        ) => panic!(),
+    }
+}
+
+static QUERY_COUNT: core::sync::atomic::AtomicU32 =
+    core::sync::atomic::AtomicU32::new(0);
+static SEND_ERROR_COUNT: core::sync::atomic::AtomicU32 =
+    core::sync::atomic::AtomicU32::new(0);
+
+include!(concat!(env!("OUT_DIR"), "/consts.rs"));
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -10,14 +10,18 @@ use drv_stm32h7_usart as drv_usart;
 
 use drv_gimlet_hf_api::{HfDevSelect, HostFlash};
 use drv_gimlet_seq_api::{PowerState, SeqError, Sequencer};
+use drv_sprot_api::{AttestError, AttestOrSprotError, HostMeasurements, SpRot};
 use drv_stm32xx_sys_api as sys_api;
 use drv_usart::Usart;
 use enum_map::Enum;
 use heapless::Vec;
 use host_sp_messages::{
-    Bsu, DecodeFailureReason, Header, HostToSp, Key, KeyLookupResult, SpToHost,
-    Status, MAX_MESSAGE_SIZE, MIN_SP_TO_HOST_FILL_DATA_LEN,
+    Bsu, DecodeFailureReason, Header, HostToSp, Key, KeyLookupResult,
+    RotAttestationResult, RotRequest, RotRequestError, RotResponse, SpToHost,
+    Status, HOST_MEASUREMENT_SIZE, MAX_MESSAGE_SIZE,
+    MIN_SP_TO_HOST_FILL_DATA_LEN,
 };
+use hubpack::SerializedSize;
 use idol_runtime::{NotificationHandler, RequestError};
 use multitimer::{Multitimer, Repeat};
 use mutable_statics::mutable_statics;
@@ -42,6 +46,7 @@ task_slot!(GIMLET_SEQ, gimlet_seq);
 task_slot!(HOST_FLASH, hf);
 task_slot!(PACKRAT, packrat);
 task_slot!(NET, net);
+task_slot!(SPROT, sprot);
 task_slot!(SYS, sys);
 
 // TODO: When rebooting the host, we need to wait for the relevant power rails
@@ -100,6 +105,7 @@ enum Trace {
         sequence: u64,
         message: SpToHost,
     },
+    RotRequestFailed(AttestOrSprotError),
 }
 
 ringbuf!(Trace, 16, Trace::None);
@@ -164,6 +170,7 @@ struct ServerImpl {
     net: Net,
     cp_agent: ControlPlaneAgent,
     packrat: Packrat,
+    sprot: SpRot,
     reboot_state: Option<RebootState>,
 
     last_host_boot_fail: &'static mut [u8; MAX_HOST_FAIL_MESSAGE_LEN],
@@ -204,6 +211,7 @@ impl ServerImpl {
                 CONTROL_PLANE_AGENT.get_task_id(),
             ),
             packrat: Packrat::from(PACKRAT.get_task_id()),
+            sprot: SpRot::from(SPROT.get_task_id()),
             reboot_state: None,
             last_host_boot_fail,
             last_host_panic,
@@ -700,12 +708,34 @@ impl ServerImpl {
                 Some(SpToHost::Alert { action: 0 })
             }
             HostToSp::RotRequest => {
-                // TODO forward request to RoT
-                Some(SpToHost::RotResponse)
+                match hubpack::deserialize::<RotRequest>(data) {
+                    Ok((request, _)) => {
+                        let response =
+                            forward_rot_request(&self.sprot, request);
+                        encode_rot_response(
+                            &mut self.tx_buf,
+                            header.sequence,
+                            &response,
+                        );
+                        None
+                    }
+                    Err(err) => Some(SpToHost::DecodeFailure(err.into())),
+                }
             }
             HostToSp::RotAddHostMeasurements => {
-                // TODO forward request to RoT
-                Some(SpToHost::Ack)
+                match host_sp_messages::host_measurements(data) {
+                    Ok(measurements) => {
+                        let response =
+                            add_host_measurements(&self.sprot, measurements);
+                        encode_rot_response(
+                            &mut self.tx_buf,
+                            header.sequence,
+                            &response,
+                        );
+                        None
+                    }
+                    Err(err) => Some(SpToHost::DecodeFailure(err)),
+                }
             }
             HostToSp::GetPhase2Data { hash, offset } => {
                 // We don't have a response to transmit now, but need to avoid
@@ -744,6 +774,16 @@ impl ServerImpl {
                 }
                 Err(err) => Some(SpToHost::KeyLookupResult(err)),
             },
+            HostToSp::GetRotAttestation { nonce } => {
+                match self.get_rot_attestation(header.sequence, nonce) {
+                    Ok(()) => {
+                        // get_rot_attestation() encodes the response directly
+                        // when it succeeds, so we have nothing else to do.
+                        None
+                    }
+                    Err(err) => Some(SpToHost::RotAttestation(err)),
+                }
+            }
         };
 
         if let Some(response) = response {
@@ -856,6 +896,49 @@ impl ServerImpl {
 
         Ok(())
     }
+
+    /// On success, we will have already filled `self.tx_buf` with our response.
+    /// On failure, our caller should respond with
+    /// `SpToHost::RotAttestation(err)` with the error we return.
+    fn get_rot_attestation(
+        &mut self,
+        sequence: u64,
+        nonce: [u8; 32],
+    ) -> Result<(), RotAttestationResult> {
+        // Borrow `sprot` to avoid borrowing `self` in the closure below.
+        let sprot = &self.sprot;
+
+        // As with the installinator image ID, optimistically serialize a
+        // successful response with the quote fetched directly into our
+        // outgoing buffer, and replace it with an error if that fails.
+        let mut result = Ok(());
+        self.tx_buf.encode_response(
+            sequence,
+            &SpToHost::RotAttestation(RotAttestationResult::Ok),
+            |buf| match sprot.attest(nonce, buf) {
+                Ok(len) => len as usize,
+                Err(err) => {
+                    result = Err(err);
+                    0
+                }
+            },
+        );
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                self.tx_buf.reset();
+                Err(match err {
+                    AttestOrSprotError::Attest(_) => {
+                        RotAttestationResult::Failed
+                    }
+                    AttestOrSprotError::Sprot(_) => {
+                        RotAttestationResult::RotUnavailable
+                    }
+                })
+            }
+        }
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -1005,6 +1088,72 @@ fn handle_tx_periodic_zero_byte_timer(
     }
 }
 
+// This is conceptually a method on `ServerImpl`, but it takes a reference to
+// `sprot` instead of `self` to avoid borrow checker issues (the request is
+// still borrowed from `rx_buf`).
+fn forward_rot_request(sprot: &SpRot, request: RotRequest) -> RotResponse {
+    match request {
+        RotRequest::ReadHostMeasurements => {
+            rot_response(sprot.read_host_measurements())
+        }
+    }
+}
+
+// This is conceptually a method on `ServerImpl`, but it takes a reference to
+// `sprot` instead of `self` to avoid borrow checker issues (the measurements
+// are still borrowed from `rx_buf`).
+//
+// Measurements are sent to the RoT one at a time, in order; we stop at the
+// first failure, leaving the earlier ones in the register.
+fn add_host_measurements<'a>(
+    sprot: &SpRot,
+    measurements: impl Iterator<Item = &'a [u8; HOST_MEASUREMENT_SIZE]>,
+) -> RotResponse {
+    let mut response = None;
+    for digest in measurements {
+        let result = sprot.extend_host_measurements(*digest);
+        let failed = result.is_err();
+        response = Some(rot_response(result));
+        if failed {
+            break;
+        }
+    }
+    // `host_sp_messages::host_measurements()` rejects empty blobs, so we
+    // always have a response.
+    response.unwrap_lite()
+}
+
+fn rot_response(
+    result: Result<HostMeasurements, AttestOrSprotError>,
+) -> RotResponse {
+    match result {
+        Ok(HostMeasurements { value, count }) => {
+            RotResponse::HostMeasurements { value, count }
+        }
+        Err(err) => {
+            ringbuf_entry!(Trace::RotRequestFailed(err));
+            RotResponse::Error(match err {
+                AttestOrSprotError::Attest(AttestError::RegisterFull) => {
+                    RotRequestError::RegisterFull
+                }
+                AttestOrSprotError::Attest(_) => RotRequestError::Failed,
+                AttestOrSprotError::Sprot(_) => RotRequestError::RotUnavailable,
+            })
+        }
+    }
+}
+
+fn encode_rot_response(
+    tx_buf: &mut TxBuf,
+    sequence: u64,
+    response: &RotResponse,
+) {
+    const_assert!(MIN_SP_TO_HOST_FILL_DATA_LEN >= RotResponse::MAX_SIZE);
+    tx_buf.encode_response(sequence, &SpToHost::RotResponse, |buf| {
+        hubpack::serialize(buf, response).unwrap_lite()
+    });
+}
+
 impl idl::InOrderHostSpCommsImpl for ServerImpl {
     fn set_status(
         &mut self,
@@ -23,8 +23,11 @@ fn main() -> ! {
             // So, do not get clever and remove this.
             cortex_m::asm::nop();
         } else {
-            // Wait For Interrupt to pause the processor until an ISR arrives,
-            // which could wake some higher-priority task.
+            // Give the kernel a chance to stop its tick until the next timer
+            // deadline (if the app is configured for that), then Wait For
+            // Interrupt to pause the processor until an ISR arrives, which
+            // could wake some higher-priority task.
+            userlib::sys_idle();
             cortex_m::asm::wfi();
         }
     }
@@ -25,6 +25,9 @@ pub enum ResetReason {
     ExitStandby,
     Other(u32),
     Unknown, // TODO remove and use `Option<ResetReason>` once we switch to hubpack
+    /// The startup code reset into this image because the other one used up
+    /// its boot attempts (this many) without declaring itself healthy.
+    BootFallback(u32),
 }
 
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
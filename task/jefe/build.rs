@@ -10,11 +10,33 @@ use std::io::Write;
 fn main() -> Result<()> {
     let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
 
-    let allowed_callers = build_util::task_ids()
-        .remap_allowed_caller_names_to_ids(&cfg.allowed_callers)?;
+    // Start from the app-wide IPC permission table, and let Jefe's own
+    // per-operation restrictions override it.
+    const IDL: &str = "../../idl/jefe.idol";
+    let mut allowed_callers = build_util::task_allowed_callers(IDL)?;
+    allowed_callers.extend(
+        build_util::task_ids()
+            .remap_allowed_caller_names_to_ids(&cfg.allowed_callers)?,
+    );
+
+    // A task that can't reach `check_in` can never meet its heartbeat
+    // deadline, so catch that here rather than in a restart loop.
+    if let Some(ids) = allowed_callers.get("check_in") {
+        for name in cfg.heartbeats.keys() {
+            let id = build_util::task_ids()
+                .get(name)
+                .with_context(|| format!("unknown heartbeat task {name}"))?;
+            if !ids.contains(&id) {
+                anyhow::bail!(
+                    "task {name} has a heartbeat, but isn't allowed to call \
+                     jefe's check_in operation"
+                );
+            }
+        }
+    }
 
     idol::server::build_restricted_server_support(
-        "../../idl/jefe.idol",
+        IDL,
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
         &allowed_callers,
@@ -57,11 +79,106 @@ fn main() -> Result<()> {
         writeln!(out, "];")?;
     }
 
+    {
+        let count = cfg.heartbeats.len();
+        writeln!(
+            out,
+            "pub(crate) const HEARTBEATS: [crate::heartbeat::Heartbeat; {count}] = [",
+        )?;
+        for (name, hb) in &cfg.heartbeats {
+            if hb.timeout_ms == 0 {
+                anyhow::bail!("heartbeat timeout for {name} must be nonzero");
+            }
+            let dump = match hb.action {
+                HeartbeatAction::Restart => false,
+                HeartbeatAction::DumpAndRestart => {
+                    if !cfg!(feature = "dump") {
+                        anyhow::bail!(
+                            "heartbeat action for {name} is dump-and-restart, \
+                             but jefe isn't built with the dump feature"
+                        );
+                    }
+                    true
+                }
+            };
+            writeln!(
+                out,
+                "    crate::heartbeat::Heartbeat {{
+        task: {task}::{name} as usize,
+        timeout: {},
+        dump: {dump},
+        critical: {},
+    }},",
+                hb.timeout_ms, hb.critical,
+            )?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    match &cfg.watchdog {
+        Some(w) => {
+            if cfg.heartbeats.values().all(|hb| !hb.critical) {
+                anyhow::bail!(
+                    "jefe is configured to pet a watchdog, but no heartbeat \
+                     is marked critical"
+                );
+            }
+            writeln!(
+                out,
+                "pub(crate) const WATCHDOG: Option<({task}, u32)> = \
+                 Some(({task}::{}, crate::notifications::{}::{}_MASK));",
+                w.task,
+                w.task,
+                w.notification.to_ascii_uppercase().replace("-", "_"),
+            )?;
+        }
+        None => {
+            writeln!(
+                out,
+                "pub(crate) const WATCHDOG: Option<({task}, u32)> = None;"
+            )?;
+        }
+    }
+
+    match cfg.max_boot_attempts {
+        Some(n) => {
+            if !cfg!(feature = "boot-attempts") {
+                anyhow::bail!(
+                    "jefe is configured with max-boot-attempts, but isn't \
+                     built with the boot-attempts feature"
+                );
+            }
+            writeln!(out, "pub(crate) const MAX_BOOT_ATTEMPTS: u32 = {n};")?;
+        }
+        None => {
+            // Count boots, but never fall back.
+            if cfg!(feature = "boot-attempts") {
+                writeln!(out, "pub(crate) const MAX_BOOT_ATTEMPTS: u32 = 0;")?;
+            }
+        }
+    }
+
+    #[cfg(feature = "boot-attempts")]
+    if !build_util::task_full_config_toml()?
+        .extern_regions
+        .iter()
+        .any(|r| r == BOOT_RECORD_REGION)
+    {
+        anyhow::bail!(
+            "jefe is built with the boot-attempts feature, but doesn't have \
+             the {BOOT_RECORD_REGION} extern region"
+        );
+    }
+
     #[cfg(feature = "dump")]
     output_dump_areas(&mut out)?;
     Ok(())
 }
 
+/// Extern region holding the boot record that the startup code keeps; see
+/// the `boot-attempts` crate.
+const BOOT_RECORD_REGION: &str = "bkpsram";
+
 /// Jefe task-level configuration.
 #[derive(Deserialize, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
@@ -77,6 +194,51 @@ struct Config {
     /// failure, unless overridden at runtime through Humility.
     #[serde(default)]
     tasks_to_hold: BTreeSet<String>,
+    /// Map of names of tasks that must check in periodically to their
+    /// heartbeat settings.
+    #[serde(default)]
+    heartbeats: BTreeMap<String, Heartbeat>,
+    /// Task that pets the hardware watchdog on our behalf, when all critical
+    /// tasks are healthy.
+    #[serde(default)]
+    watchdog: Option<Watchdog>,
+    /// Number of boots that the image gets to declare itself healthy (with
+    /// `mark_boot_healthy`) before the startup code falls back to the other
+    /// image. Requires the `boot-attempts` feature.
+    #[serde(default)]
+    max_boot_attempts: Option<u32>,
+}
+
+/// Liveness requirement for a single task.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Heartbeat {
+    /// Longest the task may go between check-ins, in milliseconds.
+    timeout_ms: u64,
+    /// What to do when the task misses its deadline.
+    #[serde(default)]
+    action: HeartbeatAction,
+    /// Whether the hardware watchdog depends on this task.
+    #[serde(default)]
+    critical: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+enum HeartbeatAction {
+    #[default]
+    Restart,
+    DumpAndRestart,
+}
+
+/// Where to send watchdog pets.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Watchdog {
+    /// Name of the task that owns the watchdog.
+    task: String,
+    /// Name of the notification (in that task) that tells it to pet.
+    notification: String,
 }
 
 #[cfg(feature = "dump")]
@@ -95,7 +257,9 @@ struct DumpRegion {
 ///
 #[cfg(feature = "dump")]
 fn output_dump_areas(out: &mut std::fs::File) -> Result<()> {
-    let dump_regions = build_util::task_extern_regions::<DumpRegion>()?;
+    // The boot record's region, if we have it, isn't for dumps.
+    let mut dump_regions = build_util::task_extern_regions::<DumpRegion>()?;
+    dump_regions.shift_remove(BOOT_RECORD_REGION);
 
     if dump_regions.len() == 0 {
         anyhow::bail!(
@@ -110,11 +274,13 @@ fn output_dump_areas(out: &mut std::fs::File) -> Result<()> {
             "jefe is configured for task dumping, but can't find dump_agent",
         )?;
 
-    if me.extern_regions != dump_agent.extern_regions {
+    let mut my_dump_regions = me.extern_regions.clone();
+    my_dump_regions.retain(|r| r != BOOT_RECORD_REGION);
+    if my_dump_regions != dump_agent.extern_regions {
         anyhow::bail!(
             "jefe is configured for task dumping, but extern regions for \
              jefe ({:?}) do not match extern regions for dump agent ({:?})",
-            me.extern_regions,
+            my_dump_regions,
             dump_agent.extern_regions
         );
     }
@@ -0,0 +1,41 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Keeping up the boot record that the startup code uses to count attempts
+//! to boot this image; see the `boot-attempts` crate.
+
+use boot_attempts::BootRecord;
+
+extern "C" {
+    static mut __REGION_BKPSRAM_BASE: [u32; 0];
+}
+
+fn record() -> *mut BootRecord {
+    // Safety: populated by the linker; we only take the address.
+    unsafe { __REGION_BKPSRAM_BASE.as_mut_ptr() as *mut BootRecord }
+}
+
+/// Sets the number of attempts an image gets from our config. If the
+/// startup code fell back to this image, returns the number of attempts the
+/// other image had.
+pub(crate) fn init() -> Option<u32> {
+    // Safety: the region is ours, and the startup code is done with it.
+    let mut r = unsafe { BootRecord::load(record()) };
+    let fell_back_after = r.fell_back_after;
+    r.fell_back_after = 0;
+    r.max_attempts = crate::generated::MAX_BOOT_ATTEMPTS;
+    unsafe { r.store(record()) };
+
+    if fell_back_after != 0 {
+        Some(fell_back_after)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn mark_healthy() {
+    let mut r = unsafe { BootRecord::load(record()) };
+    r.mark_healthy();
+    unsafe { r.store(record()) };
+}
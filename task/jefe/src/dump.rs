@@ -251,31 +251,48 @@ pub fn dump_task(base: u32, task: usize) -> Result<u8, DumpAgentError> {
         match kipc::get_task_dump_region(task, ndx) {
             None => break,
             Some(region) if !in_dump_area(region.base, region.size) => {
-                ringbuf_entry!(Trace::DumpRegion(region));
-
-                // SAFETY: we have configured memory so that humpty
-                // should only read headers which are properly initialized and
-                // readable by this task, and should only write memory which is
-                // writeable by this task (i.e. the dump areas).
-                if let Err(e) = humpty::add_dump_segment_header(
-                    area.region.address,
-                    region.base,
-                    region.size,
-                    |addr, buf, _| unsafe { humpty::from_mem(addr, buf) },
-                    |addr, buf| unsafe { humpty::to_mem(addr, buf) },
-                ) {
-                    ringbuf_entry!(Trace::DumpRegionsFailed(e));
-                    return Err(DumpAgentError::BadSegmentAdd);
-                }
+                add_dump_segment(&area, region)?;
             }
             Some(_) => {}
         }
     }
 
+    //
+    // If the kernel keeps an event trace, include it too: it's often the best
+    // clue as to how the task got into whatever state it's in.
+    //
+    if let Some(region) = kipc::get_trace_region() {
+        add_dump_segment(&area, region)?;
+    }
+
     dump_task_run(area.region.address, task)?;
     Ok(area.index)
 }
 
+/// Adds a segment header for `region` to the dump in `area`
+fn add_dump_segment(
+    area: &DumpArea,
+    region: TaskDumpRegion,
+) -> Result<(), DumpAgentError> {
+    ringbuf_entry!(Trace::DumpRegion(region));
+
+    // SAFETY: we have configured memory so that humpty should only read
+    // headers which are properly initialized and readable by this task, and
+    // should only write memory which is writeable by this task (i.e. the
+    // dump areas).
+    if let Err(e) = humpty::add_dump_segment_header(
+        area.region.address,
+        region.base,
+        region.size,
+        |addr, buf, _| unsafe { humpty::from_mem(addr, buf) },
+        |addr, buf| unsafe { humpty::to_mem(addr, buf) },
+    ) {
+        ringbuf_entry!(Trace::DumpRegionsFailed(e));
+        return Err(DumpAgentError::BadSegmentAdd);
+    }
+    Ok(())
+}
+
 /// Dumps a specific region from the given task
 pub fn dump_task_region(
     base: u32,
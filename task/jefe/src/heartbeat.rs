@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Heartbeat supervision for Jefe
+//!
+//! Faulting is only one way for a task to fail: it can also get stuck in a
+//! loop, or blocked waiting for something that will never come. To catch
+//! those, a task can be given a heartbeat in `app.toml`:
+//!
+//! ```toml
+//! [tasks.jefe.config.heartbeats]
+//! net = { timeout-ms = 2000, action = "dump-and-restart", critical = true }
+//! ```
+//!
+//! A task with a heartbeat must call Jefe's `check_in` operation at least once
+//! every `timeout-ms` milliseconds. If it doesn't, Jefe logs it and restarts
+//! the task (after dumping it, for `dump-and-restart`) -- or, if the task is
+//! being held, injects a fault so that it stays put to be inspected. Deadlines
+//! are only checked on Jefe's periodic timer, so they're enforced to within
+//! `TIMER_INTERVAL`.
+//!
+//! Jefe can also keep a hardware watchdog fed, by posting a notification to
+//! the task that owns it, for only as long as every _critical_ task is
+//! healthy: that is, it has checked in since it was last started, and hasn't
+//! missed a deadline since. A critical task that stops checking in will
+//! therefore eventually reset the system, even if restarting it doesn't help
+//! -- so the watchdog's own timeout needs to allow for the critical tasks'
+//! first check-in after boot.
+
+use crate::generated::{HEARTBEATS, WATCHDOG};
+use userlib::*;
+
+pub use heartbeats::Heartbeat;
+
+/// Heartbeat state for every task that has one.
+pub type Heartbeats = heartbeats::Heartbeats<{ HEARTBEATS.len() }>;
+
+/// Starts every task's first deadline at `now`.
+pub fn init(now: u64) -> Heartbeats {
+    Heartbeats::new(HEARTBEATS, now)
+}
+
+/// Asks the task that owns the hardware watchdog, if any, to pet it.
+pub fn pet_watchdog() {
+    if let Some((task, mask)) = WATCHDOG {
+        let id = TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+        sys_post(sys_refresh_task_id(id), mask);
+    }
+}
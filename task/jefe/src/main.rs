@@ -8,12 +8,13 @@
 //!
 //! - Maintaining the system console output (currently via semihosting).
 //! - Monitoring tasks for failures and restarting them.
+//! - Restarting tasks that stop checking in, and feeding a watchdog timer
+//!   while critical tasks are healthy (see the `heartbeat` module).
 //!
 //! It will probably become responsible for:
 //!
 //! - Evacuating kernel log information.
 //! - Coordinating certain shared resources, such as the RCC and GPIO muxing.
-//! - Managing a watchdog timer.
 //!
 //! It's unwise for the supervisor to use `SEND`, ever, except to talk to the
 //! kernel. This is because a `SEND` to a misbehaving task could block forever,
@@ -26,16 +27,19 @@
 #![no_std]
 #![no_main]
 
+#[cfg(feature = "boot-attempts")]
+mod boot_attempts;
 #[cfg(feature = "dump")]
 mod dump;
 
 mod external;
+mod heartbeat;
 
 use core::convert::Infallible;
 
 use hubris_num_tasks::NUM_TASKS;
 use humpty::DumpArea;
-use idol_runtime::RequestError;
+use idol_runtime::{ClientError, RequestError};
 use task_jefe_api::{DumpAgentError, ResetReason};
 use userlib::*;
 
@@ -126,17 +130,32 @@ fn main() -> ! {
         task_states[held_task as usize].disposition = Disposition::Hold;
     }
 
-    let deadline = sys_get_timer().now + TIMER_INTERVAL;
+    let now = sys_get_timer().now;
+    let deadline = now + TIMER_INTERVAL;
 
     sys_set_timer(Some(deadline), notifications::TIMER_MASK);
 
     external::set_ready();
 
+    // A fallback says more about why we're here than the reset the startup
+    // code did to get here.
+    #[cfg(feature = "boot-attempts")]
+    let reset_reason = match boot_attempts::init() {
+        Some(attempts) => {
+            sys_log!("Fell back from an image that failed {} boots", attempts);
+            ResetReason::BootFallback(attempts)
+        }
+        None => ResetReason::Unknown,
+    };
+    #[cfg(not(feature = "boot-attempts"))]
+    let reset_reason = ResetReason::Unknown;
+
     let mut server = ServerImpl {
         state: 0,
         deadline,
         task_states: &mut task_states,
-        reset_reason: ResetReason::Unknown,
+        heartbeats: heartbeat::init(now),
+        reset_reason,
         #[cfg(feature = "dump")]
         dump_areas: dump::initialize_dump_areas(),
     };
@@ -151,11 +170,55 @@ struct ServerImpl<'s> {
     state: u32,
     task_states: &'s mut [TaskStatus; NUM_TASKS],
     deadline: u64,
+    heartbeats: heartbeat::Heartbeats,
     reset_reason: ResetReason,
     #[cfg(feature = "dump")]
     dump_areas: u32,
 }
 
+impl ServerImpl<'_> {
+    /// Deals with tasks that have stopped checking in.
+    fn check_heartbeats(&mut self, now: u64) {
+        while let Some(hb) = self.heartbeats.next_missed(now) {
+            let i = hb.task;
+            match kipc::read_task_status(i) {
+                // A task that isn't running can't be expected to check in,
+                // and faults are dealt with separately. Either way, the task
+                // counts as unhealthy until it's back and checking in, and
+                // has a fresh deadline for when it is.
+                abi::TaskState::Healthy(abi::SchedState::Stopped)
+                | abi::TaskState::Faulted { .. } => continue,
+                _ => (),
+            }
+
+            sys_log!("Task #{} missed its heartbeat", i);
+
+            let status = &mut self.task_states[i];
+            if status.disposition == Disposition::Hold {
+                // Stop it where it is, so it can be inspected.
+                kipc::fault_task(i);
+                status.holding_fault = true;
+                continue;
+            }
+
+            if hb.dump {
+                #[cfg(feature = "dump")]
+                {
+                    // As with faults, we ignore any failure to dump.
+                    _ = dump::dump_task(self.dump_areas, i);
+                }
+            }
+
+            // `next_missed` has already given the task a fresh deadline.
+            kipc::restart_task(i, true);
+        }
+
+        if self.heartbeats.critical_tasks_healthy() {
+            heartbeat::pet_watchdog();
+        }
+    }
+}
+
 impl idl::InOrderJefeImpl for ServerImpl<'_> {
     fn request_reset(
         &mut self,
@@ -178,7 +241,53 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
         _msg: &userlib::RecvMessage,
         reason: ResetReason,
     ) -> Result<(), RequestError<Infallible>> {
-        self.reset_reason = reason;
+        // Don't let the hardware's view of the last reset hide a fallback.
+        if !matches!(self.reset_reason, ResetReason::BootFallback(_)) {
+            self.reset_reason = reason;
+        }
+        Ok(())
+    }
+
+    fn mark_boot_healthy(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<Infallible>> {
+        #[cfg(feature = "boot-attempts")]
+        boot_attempts::mark_healthy();
+        Ok(())
+    }
+
+    fn get_task_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<TaskStats, RequestError<Infallible>> {
+        // The kernel faults us if we ask about a task that doesn't exist, so
+        // pass that along to the client instead.
+        if task_index as usize >= self.task_states.len() {
+            return Err(RequestError::Fail(ClientError::BadMessageContents));
+        }
+        Ok(kipc::read_task_stats(task_index as usize))
+    }
+
+    fn get_stack_usage(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<StackUsage, RequestError<Infallible>> {
+        // As in `get_task_stats`, don't let a bad index fault us.
+        if task_index as usize >= self.task_states.len() {
+            return Err(RequestError::Fail(ClientError::BadMessageContents));
+        }
+        Ok(kipc::read_stack_usage(task_index as usize))
+    }
+
+    fn check_in(
+        &mut self,
+        msg: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<Infallible>> {
+        self.heartbeats
+            .check_in(msg.sender.index(), sys_get_timer().now);
         Ok(())
     }
 
@@ -347,9 +456,11 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
 
         if bits & notifications::TIMER_MASK != 0 {
             // If our timer went off, we need to reestablish it
-            if sys_get_timer().now >= self.deadline {
+            let now = sys_get_timer().now;
+            if now >= self.deadline {
                 self.deadline += TIMER_INTERVAL;
                 sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
+                self.check_heartbeats(now);
             }
         }
 
@@ -386,6 +497,7 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
                         if status.disposition == Disposition::Restart {
                             // Stand it back up
                             kipc::restart_task(i, true);
+                            self.heartbeats.restarted(i, sys_get_timer().now);
                         } else {
                             // Mark this one off so we don't revisit it until
                             // requested.
@@ -32,6 +32,17 @@ pub enum SendError {
     ServerRestarted = 5,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum StatsError {
+    /// The specified VID is not in the configured range (or isn't 0, without
+    /// VLAN support)
+    InvalidVLan = 1,
+
+    #[idol(server_death)]
+    ServerRestarted = 2,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
 #[repr(u32)]
 pub enum RecvError {
@@ -47,6 +58,36 @@ pub enum RecvError {
     ServerRestarted = 4,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum TcpError {
+    /// The selected socket is not owned by this task
+    NotYours = 1,
+
+    /// The socket isn't listening for connections (or stopped when its owner
+    /// restarted); call `tcp_listen` first
+    NotListening = 2,
+
+    /// The socket doesn't have an accepted connection
+    NotConnected = 3,
+
+    /// The socket already has an accepted connection, which must be closed
+    /// before another can be accepted
+    AlreadyConnected = 4,
+
+    /// There's no connection waiting to be accepted, no data to read, or no
+    /// room to write, depending on the operation; wait for the socket's
+    /// notification and retry
+    WouldBlock = 5,
+
+    /// The other end has closed the connection and everything it sent has
+    /// been read, or the connection was reset
+    Closed = 6,
+
+    #[idol(server_death)]
+    ServerRestarted = 7,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
 #[repr(u32)]
 pub enum PhyError {
@@ -191,13 +232,83 @@ pub struct UdpMetadata {
     pub vid: u16,
 }
 
+/// Describes the other end of an accepted TCP connection.
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct TcpMetadata {
+    pub addr: Address,
+    pub port: u16,
+
+    #[cfg(feature = "vlan")]
+    pub vid: u16,
+}
+
+/// Counters for a socket, summed over all VLANs. Counters wrap around once
+/// they overflow.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct SocketStats {
+    /// Packets handed to the owner by `recv_packet`
+    pub rx_packets: u32,
+    /// Bytes handed to the owner, by `recv_packet` or `tcp_read`
+    pub rx_bytes: u64,
+    /// Packets queued by the owner with `send_packet`
+    pub tx_packets: u32,
+    /// Bytes queued by the owner, with `send_packet` or `tcp_write`
+    pub tx_bytes: u64,
+    /// Calls to `send_packet` that failed with `SendError::QueueFull`
+    pub tx_queue_full: u32,
+    /// Packets discarded by `recv_packet` because they were too big for the
+    /// owner's buffer (with `LargePayloadBehavior::Discard`)
+    pub rx_truncated: u32,
+    /// Times the socket was reset because its tx queue seemed to be stuck
+    pub queue_resets: u32,
+}
+
+/// Counters for an interface (i.e. a VLAN, or the whole port without VLAN
+/// support). Counters wrap around once they overflow.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct InterfaceStats {
+    /// Frames received from the MAC
+    pub rx_packets: u32,
+    /// Bytes in those frames
+    pub rx_bytes: u64,
+    /// Frames handed to the MAC to send
+    pub tx_packets: u32,
+    /// Bytes in those frames
+    pub tx_bytes: u64,
+    /// Frames that the MAC dropped because their checksum was wrong. The MAC
+    /// can't tell which VLAN these were on, so this counts them on all
+    /// interfaces.
+    pub rx_crc_errors: u32,
+}
+
+/// Counts of packets that a socket's filter has dropped, by reason.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct FilterDrops {
+    /// Packets from a source address that isn't allowed
+    pub source: u32,
+    /// Packets on a VLAN that isn't allowed
+    pub vlan: u32,
+    /// Packets over the socket's rate limit
+    pub rate: u32,
+}
+
 #[cfg(feature = "use-smoltcp")]
-impl From<UdpMetadata> for smoltcp::wire::IpEndpoint {
-    fn from(m: UdpMetadata) -> Self {
-        Self {
-            addr: m.addr.into(),
+impl TryFrom<UdpMetadata> for smoltcp::wire::IpEndpoint {
+    type Error = AddressUnsupported;
+
+    fn try_from(m: UdpMetadata) -> Result<Self, Self::Error> {
+        Ok(Self {
+            addr: m.addr.try_into()?,
             port: m.port,
-        }
+        })
     }
 }
 
@@ -209,13 +320,22 @@ impl From<UdpMetadata> for smoltcp::wire::IpEndpoint {
 #[repr(C)]
 pub enum Address {
     Ipv6(Ipv6Address),
+    // This is here even if the net task doesn't do IPv4, so that all tasks
+    // agree on how big an `Address` is without having to share a feature.
+    Ipv4(Ipv4Address),
 }
 
 #[cfg(feature = "use-smoltcp")]
-impl From<Address> for smoltcp::wire::IpAddress {
-    fn from(a: Address) -> Self {
+impl TryFrom<Address> for smoltcp::wire::IpAddress {
+    type Error = AddressUnsupported;
+
+    fn try_from(a: Address) -> Result<Self, Self::Error> {
         match a {
-            Address::Ipv6(a) => Self::Ipv6(a.into()),
+            Address::Ipv6(a) => Ok(Self::Ipv6(a.into())),
+            #[cfg(feature = "ipv4")]
+            Address::Ipv4(a) => Ok(Self::Ipv4(a.into())),
+            #[cfg(not(feature = "ipv4"))]
+            Address::Ipv4(_) => Err(AddressUnsupported),
         }
     }
 }
@@ -229,6 +349,8 @@ impl TryFrom<smoltcp::wire::IpAddress> for Address {
 
         match a {
             IpAddress::Ipv6(a) => Ok(Self::Ipv6(a.into())),
+            #[cfg(feature = "ipv4")]
+            IpAddress::Ipv4(a) => Ok(Self::Ipv4(a.into())),
         }
     }
 }
@@ -236,6 +358,10 @@ impl TryFrom<smoltcp::wire::IpAddress> for Address {
 #[cfg(feature = "use-smoltcp")]
 pub struct AddressUnspecified;
 
+/// The address is of a kind the net task hasn't been built to support.
+#[cfg(feature = "use-smoltcp")]
+pub struct AddressUnsupported;
+
 #[derive(
     Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
 )]
@@ -256,5 +382,25 @@ impl From<Ipv6Address> for smoltcp::wire::Ipv6Address {
     }
 }
 
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+#[serde(transparent)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+#[cfg(all(feature = "use-smoltcp", feature = "ipv4"))]
+impl From<smoltcp::wire::Ipv4Address> for Ipv4Address {
+    fn from(a: smoltcp::wire::Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
+#[cfg(all(feature = "use-smoltcp", feature = "ipv4"))]
+impl From<Ipv4Address> for smoltcp::wire::Ipv4Address {
+    fn from(a: Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 include!(concat!(env!("OUT_DIR"), "/net_config.rs"));
@@ -3,9 +3,13 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use anyhow::{anyhow, bail, Result};
-use build_net::{BufSize, NetConfig, SocketConfig};
+use build_net::{
+    BufSize, FilterConfig, Ipv4Config, NetConfig, SocketConfig, SocketKind,
+};
 use proc_macro2::TokenStream;
+use std::collections::BTreeSet;
 use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 fn main() -> Result<()> {
     idol::server::build_server_support(
@@ -16,6 +20,26 @@ fn main() -> Result<()> {
     .map_err(|e| anyhow!(e))?;
 
     let net_config = build_net::load_net_config()?;
+    let has_tcp = net_config
+        .sockets
+        .values()
+        .any(|s| s.kind == SocketKind::Tcp);
+    if has_tcp && !build_util::has_feature("tcp") {
+        bail!("TCP sockets are configured, but the tcp feature is disabled");
+    }
+    let has_filter = net_config.sockets.values().any(|s| s.filter.is_some());
+    if has_filter != build_util::has_feature("filter") {
+        bail!("the filter feature must be enabled iff any socket has a filter");
+    }
+    match (build_util::has_feature("ipv4"), net_config.ipv4.is_some()) {
+        (true, false) => {
+            bail!("IPv4 feature is enabled, but ipv4 is missing from config")
+        }
+        (false, true) => {
+            bail!("IPv4 feature is disabled, but ipv4 is present in config")
+        }
+        _ => (),
+    }
 
     generate_net_config(&net_config)?;
     build_util::expose_target_board();
@@ -41,10 +65,20 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
             pub const SOCKET_COUNT: usize = #socket_count;
         }
     )?;
+    if build_util::has_feature("tcp") {
+        writeln!(out, "use smoltcp::socket::tcp;")?;
+    }
 
     if build_util::has_feature("vlan") {
         build_net::generate_vlan_consts(config, &mut out)?;
     }
+    if let Some(ipv4) = &config.ipv4 {
+        writeln!(
+            out,
+            "{}",
+            generate_ipv4_consts(ipv4, config.vlan.is_some())?
+        )?;
+    }
 
     for (name, socket) in &config.sockets {
         writeln!(
@@ -61,6 +95,11 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     writeln!(out, "{}", generate_constructor(config)?)?;
     writeln!(out, "{}", generate_owner_info(config)?)?;
     writeln!(out, "{}", generate_port_table(config)?)?;
+    writeln!(out, "{}", generate_kind_table(config)?)?;
+    if build_util::has_feature("filter") {
+        writeln!(out, "{}", generate_filter_table(config)?)?;
+    }
+    writeln!(out, "{}", generate_multicast_table(config)?)?;
 
     build_net::generate_socket_enum(config, &mut out)?;
 
@@ -71,6 +110,49 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     Ok(())
 }
 
+fn generate_ipv4_consts(
+    config: &Ipv4Config,
+    vlan: bool,
+) -> Result<TokenStream> {
+    let vpd = build_util::has_feature("vpd-ipv4");
+    if vlan && (config.address.is_some() || vpd) {
+        bail!("a static IPv4 address can't be shared between VLANs");
+    }
+    if config.address.is_none() && !vpd && !config.dhcp {
+        bail!("IPv4 needs a static address, the vpd-ipv4 feature, or DHCP");
+    }
+
+    let address = match &config.address {
+        Some(a) => {
+            let (addr, prefix_len) = a
+                .split_once('/')
+                .ok_or_else(|| anyhow!("IPv4 address {a} has no prefix"))?;
+            let octets = addr.parse::<Ipv4Addr>()?.octets();
+            let prefix_len: u8 = prefix_len.parse()?;
+            if prefix_len > 32 {
+                bail!("IPv4 address {a} has a bad prefix length");
+            }
+            quote::quote! { Some(([ #( #octets ),* ], #prefix_len)) }
+        }
+        None => quote::quote! { None },
+    };
+    let gateway = match (&config.gateway, &config.address) {
+        (Some(g), Some(_)) => {
+            let octets = g.parse::<Ipv4Addr>()?.octets();
+            quote::quote! { Some([ #( #octets ),* ]) }
+        }
+        (Some(_), None) => bail!("IPv4 gateway is set without an address"),
+        (None, _) => quote::quote! { None },
+    };
+    let dhcp = config.dhcp;
+
+    Ok(quote::quote! {
+        pub(crate) const IPV4_ADDRESS: Option<([u8; 4], u8)> = #address;
+        pub(crate) const IPV4_GATEWAY: Option<[u8; 4]> = #gateway;
+        pub(crate) const IPV4_DHCP: bool = #dhcp;
+    })
+}
+
 fn generate_port_table(config: &NetConfig) -> Result<TokenStream> {
     let consts = config.sockets.values().map(|socket| {
         let port = socket.port;
@@ -86,6 +168,130 @@ fn generate_port_table(config: &NetConfig) -> Result<TokenStream> {
     })
 }
 
+fn generate_kind_table(config: &NetConfig) -> Result<TokenStream> {
+    let consts = config.sockets.values().map(|socket| match socket.kind {
+        SocketKind::Udp => quote::quote! { crate::server::SocketKind::Udp },
+        SocketKind::Tcp => quote::quote! { crate::server::SocketKind::Tcp },
+    });
+
+    let n = config.sockets.len();
+
+    Ok(quote::quote! {
+        pub(crate) const SOCKET_KINDS: [crate::server::SocketKind; #n] = [
+            #( #consts ),*
+        ];
+    })
+}
+
+fn generate_filter_table(config: &NetConfig) -> Result<TokenStream> {
+    let consts = config
+        .sockets
+        .values()
+        .map(|socket| match &socket.filter {
+            Some(filter) => {
+                let filter = generate_filter(filter)?;
+                Ok(quote::quote! { Some(#filter) })
+            }
+            None => Ok(quote::quote! { None }),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let n = config.sockets.len();
+
+    Ok(quote::quote! {
+        pub(crate) const SOCKET_FILTERS: [Option<crate::filter::Filter>; #n] = [
+            #( #consts ),*
+        ];
+    })
+}
+
+fn generate_filter(filter: &FilterConfig) -> Result<TokenStream> {
+    let sources = filter
+        .sources
+        .iter()
+        .map(|p| {
+            let (addr, prefix_len) = p
+                .split_once('/')
+                .ok_or_else(|| anyhow!("source prefix {p} has no length"))?;
+            let prefix_len: u8 = prefix_len.parse()?;
+            match addr.parse::<IpAddr>()? {
+                IpAddr::V6(a) if prefix_len <= 128 => {
+                    let octets = a.octets();
+                    Ok(quote::quote! {
+                        crate::filter::Prefix::Ipv6(
+                            [ #( #octets ),* ], #prefix_len
+                        )
+                    })
+                }
+                IpAddr::V4(a) if prefix_len <= 32 => {
+                    if !build_util::has_feature("ipv4") {
+                        bail!("source prefix {p} is IPv4, which is disabled");
+                    }
+                    let octets = a.octets();
+                    Ok(quote::quote! {
+                        crate::filter::Prefix::Ipv4(
+                            [ #( #octets ),* ], #prefix_len
+                        )
+                    })
+                }
+                _ => bail!("source prefix {p} has a bad length"),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let vlans = &filter.vlans;
+    let rate = match filter.rate {
+        Some(rate) => {
+            let per_sec = rate.packets_per_sec;
+            let burst = rate.burst;
+            quote::quote! {
+                Some(crate::filter::Rate { per_sec: #per_sec, burst: #burst })
+            }
+        }
+        None => quote::quote! { None },
+    };
+
+    Ok(quote::quote! {
+        crate::filter::Filter {
+            sources: &[ #( #sources ),* ],
+            vlans: &[ #( #vlans ),* ],
+            rate: #rate,
+        }
+    })
+}
+
+/// Most multicast groups we can join, so that an MLD report listing them all
+/// fits in one frame.
+const MAX_MULTICAST_GROUPS: usize = 64;
+
+fn generate_multicast_table(config: &NetConfig) -> Result<TokenStream> {
+    // Membership is reported per interface, so sockets that share a group
+    // only need it reported once.
+    let mut groups = BTreeSet::new();
+    for socket in config.sockets.values() {
+        for g in &socket.multicast {
+            let addr: Ipv6Addr = g.parse()?;
+            if !addr.is_multicast() {
+                bail!("{g} is not a multicast address");
+            }
+            groups.insert(addr.octets());
+        }
+    }
+    if groups.len() > MAX_MULTICAST_GROUPS {
+        bail!("can't join more than {MAX_MULTICAST_GROUPS} multicast groups");
+    }
+
+    let consts = groups.iter().map(|octets| {
+        quote::quote! { [ #( #octets ),* ] }
+    });
+    let n = groups.len();
+
+    Ok(quote::quote! {
+        pub(crate) const MULTICAST_GROUPS: [[u8; 16]; #n] = [
+            #( #consts ),*
+        ];
+    })
+}
+
 fn generate_owner_info(config: &NetConfig) -> Result<TokenStream> {
     let consts: Vec<_> = config
         .sockets
@@ -123,12 +329,8 @@ fn generate_socket_state(
     config: &SocketConfig,
     vlan_count: usize,
 ) -> Result<TokenStream> {
-    if config.kind != "udp" {
-        bail!("unsupported socket kind");
-    }
-
-    let tx = generate_buffers(name, "TX", &config.tx, vlan_count);
-    let rx = generate_buffers(name, "RX", &config.rx, vlan_count);
+    let tx = generate_buffers(name, "TX", config.kind, &config.tx, vlan_count);
+    let rx = generate_buffers(name, "RX", config.kind, &config.rx, vlan_count);
     Ok(quote::quote! {
         #tx
         #rx
@@ -138,71 +340,122 @@ fn generate_socket_state(
 fn generate_buffers(
     name: &str,
     dir: &str,
+    kind: SocketKind,
     config: &BufSize,
     vlan_count: usize,
 ) -> TokenStream {
-    let pktcnt = config.packets;
     let bytecnt = config.bytes;
     let upname = name.to_ascii_uppercase();
-    let hdrname: syn::Ident =
-        syn::parse_str(&format!("SOCK_{}_HDR_{}", dir, upname)).unwrap();
     let bufname: syn::Ident =
         syn::parse_str(&format!("SOCK_{}_DAT_{}", dir, upname)).unwrap();
-    quote::quote! {
-        static mut #hdrname: [[udp::PacketMetadata; #pktcnt]; #vlan_count] = [
-            [udp::PacketMetadata::EMPTY; #pktcnt]; #vlan_count
-        ];
+    let buf = quote::quote! {
         static mut #bufname: [[u8; #bytecnt]; #vlan_count] = [[0u8; #bytecnt]; #vlan_count];
+    };
+    match kind {
+        SocketKind::Udp => {
+            // load_net_config checks that UDP sockets have a packet count.
+            let pktcnt = config.packets.unwrap();
+            let hdrname: syn::Ident =
+                syn::parse_str(&format!("SOCK_{}_HDR_{}", dir, upname))
+                    .unwrap();
+            quote::quote! {
+                static mut #hdrname: [[udp::PacketMetadata; #pktcnt]; #vlan_count] = [
+                    [udp::PacketMetadata::EMPTY; #pktcnt]; #vlan_count
+                ];
+                #buf
+            }
+        }
+        // TCP sockets only need the bytes.
+        SocketKind::Tcp => buf,
     }
 }
 
 fn generate_state_struct(config: &NetConfig) -> TokenStream {
-    let n = config.sockets.len();
+    let count = |kind: SocketKind| {
+        config.sockets.values().filter(|s| s.kind == kind).count()
+    };
+    let udp = count(SocketKind::Udp);
+    let tcp = if build_util::has_feature("tcp") {
+        let n = count(SocketKind::Tcp);
+        quote::quote! {
+            pub tcp: [[tcp::Socket<'a>; #n]; N],
+        }
+    } else {
+        quote::quote! {}
+    };
     quote::quote! {
-        pub(crate) struct Sockets<'a, const N: usize>(pub [[udp::Socket<'a>; #n]; N]);
+        /// Sockets of each kind, in the order they appear in `SocketName`.
+        pub(crate) struct Sockets<'a, const N: usize> {
+            pub udp: [[udp::Socket<'a>; #udp]; N],
+            #tcp
+        }
     }
 }
 
 fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
-    let name_to_sockets = |name: &String, i: usize| {
+    let name_to_socket = |name: &String, kind: SocketKind, i: usize| {
         let upname = name.to_ascii_uppercase();
-        let rxhdrs: syn::Ident =
-            syn::parse_str(&format!("SOCK_RX_HDR_{}", upname)).unwrap();
         let rxbytes: syn::Ident =
             syn::parse_str(&format!("SOCK_RX_DAT_{}", upname)).unwrap();
-        let txhdrs: syn::Ident =
-            syn::parse_str(&format!("SOCK_TX_HDR_{}", upname)).unwrap();
         let txbytes: syn::Ident =
             syn::parse_str(&format!("SOCK_TX_DAT_{}", upname)).unwrap();
 
-        quote::quote! {
-            udp::Socket::new(
-                udp::PacketBuffer::new(
-                    unsafe { &mut #rxhdrs[#i][..] },
-                    unsafe { &mut #rxbytes[#i][..] },
-                ),
-                udp::PacketBuffer::new(
-                    unsafe { &mut #txhdrs[#i][..] },
-                    unsafe { &mut #txbytes[#i][..] },
-                ),
-            )
+        match kind {
+            SocketKind::Udp => {
+                let rxhdrs: syn::Ident =
+                    syn::parse_str(&format!("SOCK_RX_HDR_{}", upname)).unwrap();
+                let txhdrs: syn::Ident =
+                    syn::parse_str(&format!("SOCK_TX_HDR_{}", upname)).unwrap();
+                quote::quote! {
+                    udp::Socket::new(
+                        udp::PacketBuffer::new(
+                            unsafe { &mut #rxhdrs[#i][..] },
+                            unsafe { &mut #rxbytes[#i][..] },
+                        ),
+                        udp::PacketBuffer::new(
+                            unsafe { &mut #txhdrs[#i][..] },
+                            unsafe { &mut #txbytes[#i][..] },
+                        ),
+                    )
+                }
+            }
+            SocketKind::Tcp => quote::quote! {
+                tcp::Socket::new(
+                    tcp::SocketBuffer::new(unsafe { &mut #rxbytes[#i][..] }),
+                    tcp::SocketBuffer::new(unsafe { &mut #txbytes[#i][..] }),
+                )
+            },
         }
     };
     let vlan_count = config.vlan.map(|v| v.count).unwrap_or(1);
-    let sockets = (0..vlan_count)
-        .map(|i| {
-            let s = config
-                .sockets
-                .keys()
-                .map(|n| name_to_sockets(n, i))
-                .collect::<Vec<_>>();
-            quote::quote! {
-                [
-                    #( #s ),*
-                ]
-            }
-        })
-        .collect::<Vec<_>>();
+    let sockets_of_kind = |kind: SocketKind| {
+        (0..vlan_count)
+            .map(|i| {
+                let s = config
+                    .sockets
+                    .iter()
+                    .filter(|(_, s)| s.kind == kind)
+                    .map(|(n, _)| name_to_socket(n, kind, i))
+                    .collect::<Vec<_>>();
+                quote::quote! {
+                    [
+                        #( #s ),*
+                    ]
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let udp = sockets_of_kind(SocketKind::Udp);
+    let tcp = if build_util::has_feature("tcp") {
+        let tcp = sockets_of_kind(SocketKind::Tcp);
+        quote::quote! {
+            tcp: [
+                #( #tcp ),*
+            ],
+        }
+    } else {
+        quote::quote! {}
+    };
     Ok(quote::quote! {
         static CTOR_FLAG: AtomicBool = AtomicBool::new(false);
         pub(crate) fn construct_sockets() -> Sockets<'static, #vlan_count> {
@@ -211,9 +464,12 @@ fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
 
             // Now that we're confident we're not aliasing, we can touch these
             // static muts.
-            Sockets([
-                #( #sockets ),*
-            ])
+            Sockets {
+                udp: [
+                    #( #udp ),*
+                ],
+                #tcp
+            }
         }
     })
 }
@@ -0,0 +1,283 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-socket packet filters.
+//!
+//! A UDP socket in the app.toml can restrict where its packets come from (by
+//! source prefix and VLAN) and how fast they arrive. Packets that don't pass
+//! are dropped as they come in from the MAC, before smoltcp queues them into
+//! the socket, so a host flooding a socket can't fill up its rx buffer and
+//! crowd out everyone else. Each socket counts what it has dropped.
+//!
+//! Filtering happens in `Filtered`, which wraps a device for the duration of
+//! a call to `Interface::poll`.
+
+use crate::generated::{self, SOCKET_COUNT};
+use crate::server::SocketKind;
+
+use smoltcp::phy::{Device, DeviceCapabilities, RxToken};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetProtocol, IpProtocol, UdpPacket};
+use task_net_api::FilterDrops;
+
+/// A socket's filter, as configured in the app.toml. Packets must pass all
+/// of its checks.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Filter {
+    /// Prefixes that the source address must be in, or empty for any
+    pub sources: &'static [Prefix],
+    /// VLANs that the packet must arrive on, or empty for any
+    pub vlans: &'static [u16],
+    /// Limit on the rate at which packets arrive
+    pub rate: Option<Rate>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Prefix {
+    Ipv6([u8; 16], u8),
+    #[cfg(feature = "ipv4")]
+    Ipv4([u8; 4], u8),
+}
+
+impl Prefix {
+    fn contains(&self, addr: &[u8]) -> bool {
+        let (prefix, len) = match self {
+            Prefix::Ipv6(p, len) => (&p[..], *len),
+            #[cfg(feature = "ipv4")]
+            Prefix::Ipv4(p, len) => (&p[..], *len),
+        };
+        if addr.len() != prefix.len() {
+            return false;
+        }
+        let whole = usize::from(len / 8);
+        let bits = len % 8;
+        if addr[..whole] != prefix[..whole] {
+            return false;
+        }
+        // Compare the leftover bits, if the length isn't a whole number of
+        // bytes.
+        bits == 0 || {
+            let mask = !(0xFFu8 >> bits);
+            addr[whole] & mask == prefix[whole] & mask
+        }
+    }
+}
+
+/// A token bucket rate limit.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Rate {
+    /// Packets per second that the bucket refills at
+    pub per_sec: u32,
+    /// Size of the bucket, in packets
+    pub burst: u32,
+}
+
+/// Tokens are counted in thousandths of a packet, so that a bucket refills a
+/// little every millisecond.
+const TOKENS_PER_PACKET: u64 = 1000;
+
+#[derive(Copy, Clone, Debug)]
+struct Bucket {
+    tokens: u64,
+    last_refill: u64,
+}
+
+/// Filter state for every socket, shared between VLANs.
+pub(crate) struct Filters {
+    buckets: [Bucket; SOCKET_COUNT],
+    drops: [FilterDrops; SOCKET_COUNT],
+}
+
+impl Filters {
+    pub(crate) fn new() -> Self {
+        let mut buckets = [Bucket {
+            tokens: 0,
+            last_refill: 0,
+        }; SOCKET_COUNT];
+        for (b, f) in buckets.iter_mut().zip(generated::SOCKET_FILTERS) {
+            if let Some(rate) = f.and_then(|f| f.rate) {
+                b.tokens = u64::from(rate.burst) * TOKENS_PER_PACKET;
+            }
+        }
+        Self {
+            buckets,
+            drops: [FilterDrops::default(); SOCKET_COUNT],
+        }
+    }
+
+    pub(crate) fn drops(&self, socket_index: usize) -> FilterDrops {
+        self.drops[socket_index]
+    }
+
+    /// Decides whether to let `frame`, which arrived on VLAN `vid` at `now`
+    /// (in milliseconds), through to smoltcp. Frames that aren't UDP, or are
+    /// for sockets without filters, always pass.
+    fn allows(&mut self, frame: &[u8], vid: Option<u16>, now: u64) -> bool {
+        let (socket_index, filter, src) = match classify(frame) {
+            Some(c) => c,
+            None => return true,
+        };
+        let drops = &mut self.drops[socket_index];
+
+        if !filter.sources.is_empty()
+            && !filter.sources.iter().any(|p| p.contains(src))
+        {
+            drops.source = drops.source.wrapping_add(1);
+            return false;
+        }
+        if let Some(vid) = vid {
+            if !filter.vlans.is_empty() && !filter.vlans.contains(&vid) {
+                drops.vlan = drops.vlan.wrapping_add(1);
+                return false;
+            }
+        }
+        if let Some(rate) = filter.rate {
+            let bucket = &mut self.buckets[socket_index];
+            let elapsed = now.saturating_sub(bucket.last_refill);
+            bucket.last_refill = now;
+            // `per_sec` packets a second is `per_sec` tokens a millisecond.
+            bucket.tokens = bucket
+                .tokens
+                .saturating_add(elapsed.saturating_mul(rate.per_sec.into()))
+                .min(u64::from(rate.burst) * TOKENS_PER_PACKET);
+            if bucket.tokens < TOKENS_PER_PACKET {
+                drops.rate = drops.rate.wrapping_add(1);
+                return false;
+            }
+            bucket.tokens -= TOKENS_PER_PACKET;
+        }
+        true
+    }
+}
+
+/// Works out which filtered socket `frame` is for, if any, returning its
+/// index and filter along with the frame's source address.
+fn classify(frame: &[u8]) -> Option<(usize, Filter, &[u8])> {
+    // Any VLAN tag has already been stripped by the MAC, so the ethertype is
+    // at 12..14.
+    let ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+    let packet = &frame[14..];
+    let (src, protocol, payload) = match EthernetProtocol::from(ethertype) {
+        EthernetProtocol::Ipv6 => {
+            // Fixed header: next header at 6, source address at 8..24
+            let header = packet.get(..40)?;
+            let mut protocol = IpProtocol::from(header[6]);
+            let mut payload = &packet[40..];
+            // smoltcp accepts a hop-by-hop options header before the payload,
+            // so we have to look past one too.
+            if protocol == IpProtocol::HopByHop {
+                let len = (usize::from(*payload.get(1)?) + 1) * 8;
+                protocol = IpProtocol::from(payload[0]);
+                payload = payload.get(len..)?;
+            }
+            (&header[8..24], protocol, payload)
+        }
+        #[cfg(feature = "ipv4")]
+        EthernetProtocol::Ipv4 => {
+            // Source address at 12..16, after a header of variable length
+            let len = (usize::from(*packet.first()? & 0xF) * 4).max(20);
+            let header = packet.get(..len)?;
+            // Only the first fragment has the UDP header; smoltcp drops the
+            // rest anyway.
+            let frag_offset = u16::from_be_bytes([header[6], header[7]]);
+            if frag_offset & 0x1FFF != 0 {
+                return None;
+            }
+            (&header[12..16], IpProtocol::from(header[9]), &packet[len..])
+        }
+        _ => return None,
+    };
+    if protocol != IpProtocol::Udp {
+        return None;
+    }
+    let port = UdpPacket::new_checked(payload).ok()?.dst_port();
+
+    let socket_index = (0..SOCKET_COUNT).find(|&i| {
+        generated::SOCKET_KINDS[i] == SocketKind::Udp
+            && generated::SOCKET_PORTS[i] == port
+    })?;
+    let filter = generated::SOCKET_FILTERS[socket_index]?;
+    Some((socket_index, filter, src))
+}
+
+/// Wraps a device on VLAN `vid` (if it's on one), filtering what it
+/// receives.
+pub(crate) struct Filtered<'a, D> {
+    device: &'a mut D,
+    filters: &'a mut Filters,
+    vid: Option<u16>,
+}
+
+impl<'a, D: Device> Filtered<'a, D> {
+    pub(crate) fn new(
+        device: &'a mut D,
+        filters: &'a mut Filters,
+        vid: Option<u16>,
+    ) -> Self {
+        Self {
+            device,
+            filters,
+            vid,
+        }
+    }
+}
+
+impl<'a, D: Device> Device for Filtered<'a, D> {
+    type RxToken<'b> = FilteredRxToken<'b, D::RxToken<'b>> where Self: 'b;
+    type TxToken<'b> = D::TxToken<'b> where Self: 'b;
+
+    fn receive(
+        &mut self,
+        timestamp: Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.device.receive(timestamp)?;
+        Some((
+            FilteredRxToken {
+                rx,
+                filters: &mut *self.filters,
+                vid: self.vid,
+                now: timestamp.total_millis() as u64,
+            },
+            tx,
+        ))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.device.transmit(timestamp)
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.device.capabilities()
+    }
+}
+
+pub(crate) struct FilteredRxToken<'a, T> {
+    rx: T,
+    filters: &'a mut Filters,
+    vid: Option<u16>,
+    now: u64,
+}
+
+impl<T: RxToken> RxToken for FilteredRxToken<'_, T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let Self {
+            rx,
+            filters,
+            vid,
+            now,
+        } = self;
+        rx.consume(|frame| {
+            if filters.allows(frame, vid, now) {
+                f(frame)
+            } else {
+                // We have to hand smoltcp _something_, and it quietly drops
+                // frames too short to be Ethernet.
+                f(&mut [])
+            }
+        })
+    }
+}
@@ -0,0 +1,116 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! IPv4 addressing.
+//!
+//! Each interface gets an IPv4 address alongside its link-local IPv6 one. We
+//! use a static address if there's one in the VPD (with the `vpd-ipv4`
+//! feature) or in `[config.net.ipv4]`, in that order, and otherwise ask for
+//! one with DHCP, if that's enabled.
+
+use crate::generated;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::socket::dhcpv4;
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+use userlib::UnwrapLite;
+
+/// A static IPv4 configuration.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct StaticConfig {
+    cidr: Ipv4Cidr,
+    gateway: Option<Ipv4Address>,
+}
+
+/// Finds our static IPv4 configuration, if we have one.
+pub(crate) fn static_config() -> Option<StaticConfig> {
+    #[cfg(feature = "vpd-ipv4")]
+    {
+        use task_packrat_api::Packrat;
+        let packrat = Packrat::from(crate::PACKRAT.get_task_id());
+        if let Ok(config) = packrat.get_ipv4_config() {
+            let gateway = Ipv4Address(config.gateway);
+            return Some(StaticConfig {
+                cidr: Ipv4Cidr::new(
+                    Ipv4Address(config.address),
+                    config.prefix_len,
+                ),
+                gateway: (!gateway.is_unspecified()).then_some(gateway),
+            });
+        }
+    }
+
+    let (address, prefix_len) = generated::IPV4_ADDRESS?;
+    Some(StaticConfig {
+        cidr: Ipv4Cidr::new(Ipv4Address(address), prefix_len),
+        gateway: generated::IPV4_GATEWAY.map(Ipv4Address),
+    })
+}
+
+/// Sets up IPv4 on `iface`. If there's a static configuration, this applies
+/// it; otherwise, if DHCP is enabled, this adds a DHCP client to `sockets`
+/// and returns its handle, for `poll_dhcp`.
+pub(crate) fn configure(
+    iface: &mut Interface,
+    sockets: &mut SocketSet<'static>,
+    config: Option<StaticConfig>,
+) -> Option<SocketHandle> {
+    match config {
+        Some(config) => {
+            set_address(iface, Some(config.cidr), config.gateway);
+            None
+        }
+        None if generated::IPV4_DHCP => {
+            Some(sockets.add(dhcpv4::Socket::new()))
+        }
+        None => None,
+    }
+}
+
+/// Checks whether the DHCP client has gained or lost a lease, and updates
+/// `iface` to match. Returns `true` if anything changed.
+pub(crate) fn poll_dhcp(
+    iface: &mut Interface,
+    sockets: &mut SocketSet<'static>,
+    dhcp: SocketHandle,
+) -> bool {
+    match sockets.get_mut::<dhcpv4::Socket<'_>>(dhcp).poll() {
+        None => false,
+        Some(dhcpv4::Event::Configured(config)) => {
+            set_address(iface, Some(config.address), config.router);
+            true
+        }
+        Some(dhcpv4::Event::Deconfigured) => {
+            set_address(iface, None, None);
+            true
+        }
+    }
+}
+
+/// Replaces the IPv4 address and default route of `iface`.
+fn set_address(
+    iface: &mut Interface,
+    cidr: Option<Ipv4Cidr>,
+    gateway: Option<Ipv4Address>,
+) {
+    iface.update_ip_addrs(|addrs| {
+        if let Some(i) = addrs.iter().position(|a| matches!(a, IpCidr::Ipv4(_)))
+        {
+            addrs.swap_remove(i);
+        }
+        if let Some(cidr) = cidr {
+            addrs.push(IpCidr::Ipv4(cidr)).unwrap_lite();
+        }
+    });
+    match gateway {
+        Some(gateway) => {
+            iface
+                .routes_mut()
+                .add_default_ipv4_route(gateway)
+                .unwrap_lite();
+        }
+        None => {
+            iface.routes_mut().remove_default_ipv4_route();
+        }
+    }
+}
@@ -10,6 +10,7 @@ pub mod pins;
 mod bsp_support;
 mod buf;
 mod miim_bridge;
+mod mld;
 mod server;
 
 // Select the BSP based on the target board
@@ -46,11 +47,19 @@ mod server_impl;
 #[cfg(feature = "mgmt")]
 pub(crate) mod mgmt;
 
+#[cfg(feature = "ipv4")]
+mod ipv4;
+
+#[cfg(feature = "filter")]
+mod filter;
+
 mod idl {
     use task_net_api::{
-        KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-        MacAddressBlock, ManagementCounters, ManagementLinkStatus, MgmtError,
-        PhyError, RecvError, SendError, SocketName, UdpMetadata,
+        FilterDrops, InterfaceStats, KszError, KszMacTableEntry,
+        LargePayloadBehavior, MacAddress, MacAddressBlock, ManagementCounters,
+        ManagementLinkStatus, MgmtError, PhyError, RecvError, SendError,
+        SocketName, SocketStats, StatsError, TcpError, TcpMetadata,
+        UdpMetadata,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -75,7 +84,7 @@ use crate::bsp_support::Bsp;
 
 task_slot!(SYS, sys);
 
-#[cfg(feature = "vpd-mac")]
+#[cfg(any(feature = "vpd-mac", feature = "vpd-ipv4"))]
 task_slot!(PACKRAT, packrat);
 
 /////////////////////////////////////////////////////////////////////////////
@@ -210,11 +219,12 @@ fn main() -> ! {
     // Turn on our IRQ.
     userlib::sys_irq_control(notifications::ETH_IRQ_MASK, true);
 
-    // We use two timers:
+    // We use three timers:
     #[derive(Copy, Clone, Enum)]
     enum Timers {
         Wake,
         Watchdog,
+        Poll,
     }
     let mut multitimer =
         Multitimer::<Timers>::new(notifications::WAKE_TIMER_BIT);
@@ -266,6 +276,17 @@ fn main() -> ! {
                         // timer is set to auto-repeat
                     }
                     Timers::Watchdog => panic!("MAC RX watchdog"),
+                    // We poll at the top of the loop anyway.
+                    Timers::Poll => (),
+                }
+            }
+            // Make sure we come back around in time for anything the IP
+            // stack has scheduled, like TCP retransmissions, even if nothing
+            // else happens.
+            match server.poll_at(now) {
+                Some(t) => multitimer.set_timer(Timers::Poll, t, None),
+                None => {
+                    multitimer.clear_timer(Timers::Poll);
                 }
             }
             let mut msgbuf = [0u8; idl::INCOMING_SIZE];
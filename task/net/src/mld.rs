@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Multicast Listener Discovery (MLDv2, RFC 3810).
+//!
+//! Our MAC is promiscuous, and smoltcp hands a UDP socket any multicast
+//! packet sent to its port, so receiving multicast needs nothing from us.
+//! What it does need is for the network to send those packets our way:
+//! switches that snoop on MLD only forward a group's traffic to ports that
+//! have reported listening to it.
+//!
+//! smoltcp doesn't speak MLD, so we build the reports ourselves, with the
+//! `mld-report` crate, listing the groups that sockets join in
+//! `[config.net.sockets]`. Rather than listening for queries, we send a
+//! report for every interface periodically, which keeps switches'
+//! membership from timing out just as well.
+
+use crate::generated::MULTICAST_GROUPS;
+use crate::link_local_iface_addr;
+
+use smoltcp::phy::{Device, TxToken};
+use smoltcp::time::Instant;
+use smoltcp::wire::EthernetAddress;
+
+/// How often to report our groups. Switches forget about a listener after
+/// about four minutes by default, so this leaves room for a lost report.
+pub(crate) const REPORT_INTERVAL_MS: u64 = 60_000;
+
+/// How soon to try again if we couldn't send a report.
+pub(crate) const RETRY_INTERVAL_MS: u64 = 100;
+
+const FRAME_LEN: usize = mld_report::frame_len(MULTICAST_GROUPS.len());
+
+/// Sends a report listing our groups out of `device`, whose MAC address is
+/// `mac`. Returns `false` if there was no room to send it.
+pub(crate) fn send_report<D: Device + ?Sized>(
+    device: &mut D,
+    mac: EthernetAddress,
+    timestamp: Instant,
+) -> bool {
+    match device.transmit(timestamp) {
+        Some(tx) => {
+            tx.consume(FRAME_LEN, |buf| {
+                let src = link_local_iface_addr(mac).0;
+                mld_report::fill_report(buf, mac.0, src, &MULTICAST_GROUPS)
+            });
+            true
+        }
+        None => false,
+    }
+}
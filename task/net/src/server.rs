@@ -13,9 +13,10 @@ use crate::generated::VLAN_RANGE;
 use drv_stm32h7_eth as eth;
 use idol_runtime::{ClientError, RequestError};
 use task_net_api::{
-    KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError, RecvError,
-    SendError, SocketName, UdpMetadata,
+    FilterDrops, InterfaceStats, KszError, KszMacTableEntry,
+    LargePayloadBehavior, MacAddress, ManagementCounters, ManagementLinkStatus,
+    MgmtError, PhyError, RecvError, SendError, SocketName, SocketStats,
+    StatsError, TcpError, TcpMetadata, UdpMetadata,
 };
 
 use core::iter::zip;
@@ -26,6 +27,10 @@ use smoltcp::wire::{EthernetAddress, Ipv6Cidr};
 use userlib::{sys_post, sys_refresh_task_id, UnwrapLite};
 use zerocopy::byteorder::U16;
 
+mod stats;
+#[cfg(feature = "tcp")]
+mod tcp;
+
 /// Implementation of the Net Idol interface.
 impl<B, E, const N: usize> idl::InOrderNetImpl for GenServerImpl<'_, B, E, N>
 where
@@ -52,6 +57,105 @@ where
         self.net_send_packet(msg, socket, metadata, payload)
     }
 
+    ////////////////////////////////////////////////////////////////////////////
+    // TCP functions, if TCP is disabled. There can't be any TCP sockets, so
+    // asking for one is a client error.
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_listen(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_accept(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+    ) -> Result<TcpMetadata, RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_read(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _data: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_write(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _data: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_close(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Main TCP functions
+    #[cfg(feature = "tcp")]
+    fn tcp_listen(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        self.net_tcp_listen(msg, socket)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_accept(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<TcpMetadata, RequestError<TcpError>> {
+        self.net_tcp_accept(msg, socket)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_read(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        data: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        self.net_tcp_read(msg, socket, data)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_write(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        data: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        self.net_tcp_write(msg, socket, data)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_close(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        self.net_tcp_close(msg, socket)
+    }
+
     fn smi_read(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -104,6 +208,57 @@ where
         Ok(())
     }
 
+    fn socket_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<SocketStats, RequestError<core::convert::Infallible>> {
+        Ok(self.socket_stats[socket as usize])
+    }
+
+    fn interface_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        vid: u16,
+    ) -> Result<InterfaceStats, RequestError<StatsError>> {
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            if !VLAN_RANGE.contains(&vid) {
+                return Err(StatsError::InvalidVLan.into());
+            }
+            usize::from(vid - VLAN_RANGE.start)
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = {
+            if vid != 0 {
+                return Err(StatsError::InvalidVLan.into());
+            }
+            0
+        };
+
+        let mut stats = self.vlan_state[vlan_index].stats;
+        stats.rx_crc_errors = self.eth.rx_crc_errors();
+        Ok(stats)
+    }
+
+    #[cfg(not(feature = "filter"))]
+    fn get_filter_drops(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+    ) -> Result<FilterDrops, RequestError<core::convert::Infallible>> {
+        Ok(FilterDrops::default())
+    }
+
+    #[cfg(feature = "filter")]
+    fn get_filter_drops(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<FilterDrops, RequestError<core::convert::Infallible>> {
+        Ok(self.filters.drops(socket as usize))
+    }
+
     fn get_mac_address(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -250,6 +405,23 @@ pub trait DeviceExt: smoltcp::phy::Device {
         size: usize,
         addr: task_net_api::Address,
     ) -> UdpMetadata;
+
+    fn make_tcp_meta(
+        &self,
+        port: u16,
+        addr: task_net_api::Address,
+    ) -> TcpMetadata;
+
+    /// Returns the VID of the VLAN this device is on, if it's on one.
+    fn vid(&self) -> Option<u16>;
+}
+
+/// The kinds of socket that can be configured in `[config.net.sockets]`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SocketKind {
+    Udp,
+    #[cfg(feature = "tcp")]
+    Tcp,
 }
 
 /// State for the running network server
@@ -261,6 +433,13 @@ where
 
     vlan_state: [VLanState<E>; N],
     client_waiting_to_send: [bool; SOCKET_COUNT],
+    socket_stats: [SocketStats; SOCKET_COUNT],
+    /// When to next report our multicast groups, if we have any
+    next_mld_report: u64,
+    #[cfg(feature = "tcp")]
+    tcp_state: [tcp::TcpState; SOCKET_COUNT],
+    #[cfg(feature = "filter")]
+    filters: crate::filter::Filters,
     bsp: B,
 
     mac: EthernetAddress,
@@ -275,9 +454,18 @@ where
     socket_set: smoltcp::iface::SocketSet<'static>,
     iface: &'static mut Interface,
     device: E,
+    mac: EthernetAddress,
+
+    /// DHCP client for IPv4, if we're using one
+    #[cfg(feature = "ipv4")]
+    dhcp: Option<SocketHandle>,
 
     /// Used to detect stuck queues (due to smoltcp#594)
     queue_watchdog: [QueueWatchdog; SOCKET_COUNT],
+
+    /// Counters for this interface (except `rx_crc_errors`, which is kept by
+    /// the MAC)
+    stats: InterfaceStats,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -300,21 +488,25 @@ impl<E: DeviceExt> VLanState<E> {
         self.socket_handles.get(index).cloned()
     }
 
-    /// Gets the socket `index`. If `index` is out of range, returns
-    /// `None`.
-    ///
-    /// Sockets are currently assumed to be UDP.
+    /// Gets the socket `index`. If `index` is out of range, or isn't a UDP
+    /// socket, returns `None`.
     pub(crate) fn get_socket_mut(
         &mut self,
         index: usize,
     ) -> Option<&mut udp::Socket<'static>> {
+        if *generated::SOCKET_KINDS.get(index)? != SocketKind::Udp {
+            return None;
+        }
         Some(
             self.socket_set
                 .get_mut::<udp::Socket<'_>>(self.get_handle(index)?),
         )
     }
 
-    pub(crate) fn check_socket_watchdog(&mut self) -> bool {
+    pub(crate) fn check_socket_watchdog(
+        &mut self,
+        socket_stats: &mut [SocketStats; SOCKET_COUNT],
+    ) -> bool {
         let mut changed = false;
         for socket_index in 0..SOCKET_COUNT {
             if self.queue_watchdog[socket_index]
@@ -329,6 +521,8 @@ impl<E: DeviceExt> VLanState<E> {
                 s.close();
                 s.bind(e).unwrap_lite();
                 changed = true;
+                let stats = &mut socket_stats[socket_index];
+                stats.queue_resets = stats.queue_resets.wrapping_add(1);
 
                 // Reset the watchdog, so it doesn't fire right away
                 self.queue_watchdog[socket_index] = QueueWatchdog::Nominal;
@@ -359,14 +553,18 @@ where
         assert!(mac_address_block.count.get() as usize >= N);
         let mut mac: [u8; 6] = mac_address_block.base_mac;
 
+        #[cfg(feature = "tcp")]
+        let mut tcp_sockets = sockets.tcp.into_iter();
+
+        #[cfg(feature = "ipv4")]
+        let ipv4_config = crate::ipv4::static_config();
+
         // Each of these is replicated once per VID. Loop over them in lockstep.
-        for (i, (sockets, storage)) in zip(sockets.0, storage).enumerate() {
+        for (i, (udp_sockets, storage)) in zip(sockets.udp, storage).enumerate()
+        {
             let mac_addr = EthernetAddress::from_bytes(&mac);
             let ipv6_addr = link_local_iface_addr(mac_addr);
 
-            // Make some types explicit to try and make this clearer.
-            let sockets: [udp::Socket<'_>; SOCKET_COUNT] = sockets;
-
             let mut config = smoltcp::iface::Config::new();
             config.hardware_addr = Some(mac_addr.into());
             let mut device = mkdevice(i);
@@ -376,25 +574,57 @@ where
                 ip_addrs.push(Ipv6Cidr::new(ipv6_addr, 64).into()).unwrap()
             });
 
-            // Associate sockets with this interface.
+            // Associate sockets with this interface. The sockets of each kind
+            // are in the same order as their names, so we can hand them out
+            // as we go.
             let mut socket_set =
                 smoltcp::iface::SocketSet::new(storage.sockets.as_mut_slice());
-            let socket_handles = sockets.map(|s| socket_set.add(s));
-            // Bind sockets to their ports.
-            for (&h, port) in zip(&socket_handles, generated::SOCKET_PORTS) {
-                socket_set
-                    .get_mut::<udp::Socket<'_>>(h)
-                    .bind((ipv6_addr, port))
-                    .unwrap_lite();
+            let mut udp_sockets = udp_sockets.into_iter();
+            #[cfg(feature = "tcp")]
+            let mut tcp_sockets = tcp_sockets.next().unwrap_lite().into_iter();
+            let socket_handles = generated::SOCKET_KINDS.map(|k| match k {
+                SocketKind::Udp => {
+                    socket_set.add(udp_sockets.next().unwrap_lite())
+                }
+                #[cfg(feature = "tcp")]
+                SocketKind::Tcp => {
+                    socket_set.add(tcp_sockets.next().unwrap_lite())
+                }
+            });
+            // Bind UDP sockets to their ports. TCP sockets listen on theirs
+            // when their owners ask. With IPv4, we may not know our address
+            // yet, so bind to the port on any address.
+            for ((&h, port), kind) in zip(
+                zip(&socket_handles, generated::SOCKET_PORTS),
+                generated::SOCKET_KINDS,
+            ) {
+                if kind == SocketKind::Udp {
+                    #[cfg(feature = "ipv4")]
+                    let endpoint = port;
+                    #[cfg(not(feature = "ipv4"))]
+                    let endpoint = (ipv6_addr, port);
+                    socket_set
+                        .get_mut::<udp::Socket<'_>>(h)
+                        .bind(endpoint)
+                        .unwrap_lite();
+                }
             }
 
+            #[cfg(feature = "ipv4")]
+            let dhcp =
+                crate::ipv4::configure(iface, &mut socket_set, ipv4_config);
+
             vlan_state
                 .push(VLanState {
                     socket_handles,
                     iface,
                     device,
+                    mac: mac_addr,
                     socket_set,
                     queue_watchdog: [QueueWatchdog::Nominal; SOCKET_COUNT],
+                    stats: InterfaceStats::default(),
+                    #[cfg(feature = "ipv4")]
+                    dhcp,
                 })
                 .unwrap_lite();
 
@@ -428,6 +658,12 @@ where
         Self {
             eth,
             client_waiting_to_send: [false; SOCKET_COUNT],
+            socket_stats: [SocketStats::default(); SOCKET_COUNT],
+            next_mld_report: 0,
+            #[cfg(feature = "tcp")]
+            tcp_state: [tcp::TcpState::default(); SOCKET_COUNT],
+            #[cfg(feature = "filter")]
+            filters: crate::filter::Filters::new(),
             vlan_state: vlan_state.into_array().unwrap_lite(),
             bsp,
             mac: EthernetAddress::from_bytes(&mac_address_block.base_mac),
@@ -445,20 +681,68 @@ where
         // we really do want to poll all of them.
         let mut ip = false;
         let mut mac_rx = false;
+        let report_mld = !generated::MULTICAST_GROUPS.is_empty()
+            && t >= self.next_mld_report;
+        let mut mld_sent = true;
         for vlan in &mut self.vlan_state {
-            ip |= vlan.iface.poll(
-                instant,
-                &mut vlan.device,
-                &mut vlan.socket_set,
+            #[cfg(feature = "filter")]
+            let vid = vlan.device.vid();
+            let device =
+                &mut stats::Counted::new(&mut vlan.device, &mut vlan.stats);
+            // Filter inside the counter, so that the interface counts what
+            // arrived rather than what passed.
+            #[cfg(feature = "filter")]
+            let device = &mut crate::filter::Filtered::new(
+                device,
+                &mut self.filters,
+                vid,
             );
+            ip |= vlan.iface.poll(instant, device, &mut vlan.socket_set);
+            if report_mld {
+                mld_sent &= crate::mld::send_report(device, vlan.mac, instant);
+            }
             // Test and clear our receive activity flag.
             mac_rx |= vlan.device.read_and_clear_activity_flag();
-            ip |= vlan.check_socket_watchdog();
+            ip |= vlan.check_socket_watchdog(&mut self.socket_stats);
+            #[cfg(feature = "ipv4")]
+            if let Some(dhcp) = vlan.dhcp {
+                ip |= crate::ipv4::poll_dhcp(
+                    vlan.iface,
+                    &mut vlan.socket_set,
+                    dhcp,
+                );
+            }
+        }
+        #[cfg(feature = "tcp")]
+        {
+            ip |= self.tcp_housekeeping();
+        }
+        if report_mld {
+            self.next_mld_report = t + if mld_sent {
+                crate::mld::REPORT_INTERVAL_MS
+            } else {
+                crate::mld::RETRY_INTERVAL_MS
+            };
         }
 
         crate::Activity { ip, mac_rx }
     }
 
+    /// Returns the time at which the IP stack next needs to be polled even if
+    /// nothing else happens (e.g., to retransmit on a TCP connection, or to
+    /// report our multicast groups), if there is one.
+    pub(crate) fn poll_at(&mut self, t: u64) -> Option<u64> {
+        let instant = smoltcp::time::Instant::from_millis(t as i64);
+        let mld = (!generated::MULTICAST_GROUPS.is_empty())
+            .then_some(self.next_mld_report);
+        self.vlan_state
+            .iter_mut()
+            .filter_map(|v| v.iface.poll_at(instant, &v.socket_set))
+            .map(|i| i.total_millis() as u64)
+            .chain(mld)
+            .min()
+    }
+
     /// Iterate over sockets, waking any that can do work.
     ///
     /// A task can do work if...
@@ -469,21 +753,30 @@ where
     ///   across all VLANs can accept an outgoing packet. (The "all" is
     ///   important here since we don't keep track of which one it's trying to
     ///   send through.)
+    ///
+    /// TCP sockets are handled by `tcp_wants_wake`.
     pub fn wake_sockets(&mut self) {
         for i in 0..SOCKET_COUNT {
-            // recv wake depends only on the state of the sockets.
-            let recv_wake = self
-                .vlan_state
-                .iter_mut()
-                .any(|v| v.get_socket_mut(i).unwrap().can_recv());
-            // send wake only happens if the wait flag is set.
-            let send_wake = self.client_waiting_to_send[i]
-                && self
-                    .vlan_state
-                    .iter_mut()
-                    .all(|v| v.get_socket_mut(i).unwrap().can_send());
-
-            if recv_wake || send_wake {
+            let wake = match generated::SOCKET_KINDS[i] {
+                SocketKind::Udp => {
+                    // recv wake depends only on the state of the sockets.
+                    let recv_wake = self
+                        .vlan_state
+                        .iter_mut()
+                        .any(|v| v.get_socket_mut(i).unwrap().can_recv());
+                    // send wake only happens if the wait flag is set.
+                    let send_wake = self.client_waiting_to_send[i]
+                        && self
+                            .vlan_state
+                            .iter_mut()
+                            .all(|v| v.get_socket_mut(i).unwrap().can_send());
+                    recv_wake || send_wake
+                }
+                #[cfg(feature = "tcp")]
+                SocketKind::Tcp => self.tcp_wants_wake(i),
+            };
+
+            if wake {
                 let (task_id, notification) = generated::SOCKET_OWNERS[i];
                 let task_id = sys_refresh_task_id(task_id);
                 sys_post(task_id, notification);
@@ -533,12 +826,17 @@ where
             loop {
                 match socket.recv() {
                     Ok((body, endp)) => {
+                        let stats = &mut self.socket_stats[socket_index];
                         if payload.len() < body.len() {
                             match large_payload_behavior {
-                                LargePayloadBehavior::Discard => continue,
                                 // If we add a `::Fail` case, we will need to
-                                // allow for caller retries (possibly by peeking
-                                // on the socket instead of recving)
+                                // allow for caller retries (possibly by
+                                // peeking on the socket instead of recving)
+                                LargePayloadBehavior::Discard => {
+                                    stats.rx_truncated =
+                                        stats.rx_truncated.wrapping_add(1);
+                                    continue;
+                                }
                             }
                         }
                         payload
@@ -547,6 +845,9 @@ where
 
                         // Release borrow on self/socket
                         let body_len = body.len();
+                        stats.rx_packets = stats.rx_packets.wrapping_add(1);
+                        stats.rx_bytes =
+                            stats.rx_bytes.wrapping_add(body_len as u64);
 
                         return Ok(vlan.device.make_meta(
                             endp.port,
@@ -595,13 +896,22 @@ where
         let socket = vlan
             .get_socket_mut(socket_index)
             .ok_or(RequestError::Fail(ClientError::BadMessageContents))?;
-        match socket.send(payload.len(), metadata.into()) {
+        // The address can only be one we don't support if the client made it
+        // up.
+        let endpoint = metadata
+            .try_into()
+            .map_err(|_| RequestError::Fail(ClientError::BadMessageContents))?;
+        match socket.send(payload.len(), endpoint) {
             Ok(buf) => {
                 payload
                     .read_range(0..payload.len(), buf)
                     .map_err(|_| RequestError::went_away())?;
                 self.client_waiting_to_send[socket_index] = false;
                 vlan.queue_watchdog[socket_index] = QueueWatchdog::Nominal;
+                let stats = &mut self.socket_stats[socket_index];
+                stats.tx_packets = stats.tx_packets.wrapping_add(1);
+                stats.tx_bytes =
+                    stats.tx_bytes.wrapping_add(payload.len() as u64);
                 Ok(())
             }
             Err(udp::SendError::BufferFull) => {
@@ -625,6 +935,8 @@ where
                     QueueWatchdog::QueueFullTimeout => (),
                 }
                 self.client_waiting_to_send[socket_index] = true;
+                let stats = &mut self.socket_stats[socket_index];
+                stats.tx_queue_full = stats.tx_queue_full.wrapping_add(1);
                 Err(SendError::QueueFull.into())
             }
             Err(_e) => {
@@ -654,8 +966,12 @@ where
     }
 }
 
+/// Sockets we make for ourselves, beyond the ones in the config: a DHCP
+/// client, with IPv4.
+const INTERNAL_SOCKET_COUNT: usize = if cfg!(feature = "ipv4") { 1 } else { 0 };
+
 pub struct Storage {
-    sockets: [SocketStorage<'static>; SOCKET_COUNT],
+    sockets: [SocketStorage<'static>; SOCKET_COUNT + INTERNAL_SOCKET_COUNT],
     iface: core::mem::MaybeUninit<Interface>,
 }
 
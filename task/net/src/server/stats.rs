@@ -0,0 +1,86 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Interface counters.
+//!
+//! smoltcp doesn't count what goes through an interface, so we count it
+//! ourselves, by wrapping each VLAN's device in a `Counted` while polling it.
+
+use core::cell::Cell;
+use smoltcp::phy::{Device, DeviceCapabilities, RxToken, TxToken};
+use smoltcp::time::Instant;
+use task_net_api::InterfaceStats;
+
+/// Wraps a device, counting the frames that go through it.
+pub(super) struct Counted<'a, D> {
+    device: &'a mut D,
+    stats: &'a mut InterfaceStats,
+}
+
+impl<'a, D: Device> Counted<'a, D> {
+    pub(super) fn new(
+        device: &'a mut D,
+        stats: &'a mut InterfaceStats,
+    ) -> Self {
+        Self { device, stats }
+    }
+}
+
+impl<'a, D: Device> Device for Counted<'a, D> {
+    type RxToken<'b> = CountedRxToken<'b, D::RxToken<'b>> where Self: 'b;
+    type TxToken<'b> = CountedTxToken<'b, D::TxToken<'b>> where Self: 'b;
+
+    fn receive(
+        &mut self,
+        timestamp: Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.device.receive(timestamp)?;
+        // smoltcp only ever consumes one of these, so they can both count
+        // into the same place, with a little help from `Cell`.
+        let stats = Cell::from_mut(&mut *self.stats);
+        Some((CountedRxToken(rx, stats), CountedTxToken(tx, stats)))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        let tx = self.device.transmit(timestamp)?;
+        Some(CountedTxToken(tx, Cell::from_mut(self.stats)))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.device.capabilities()
+    }
+}
+
+pub(super) struct CountedRxToken<'a, T>(T, &'a Cell<InterfaceStats>);
+
+impl<T: RxToken> RxToken for CountedRxToken<'_, T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let stats = self.1;
+        self.0.consume(|frame| {
+            let mut s = stats.get();
+            s.rx_packets = s.rx_packets.wrapping_add(1);
+            s.rx_bytes = s.rx_bytes.wrapping_add(frame.len() as u64);
+            stats.set(s);
+            f(frame)
+        })
+    }
+}
+
+pub(super) struct CountedTxToken<'a, T>(T, &'a Cell<InterfaceStats>);
+
+impl<T: TxToken> TxToken for CountedTxToken<'_, T> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut s = self.1.get();
+        s.tx_packets = s.tx_packets.wrapping_add(1);
+        s.tx_bytes = s.tx_bytes.wrapping_add(len as u64);
+        self.1.set(s);
+        self.0.consume(len, f)
+    }
+}
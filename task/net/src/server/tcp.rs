@@ -0,0 +1,344 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! TCP sockets.
+//!
+//! A TCP socket in the app.toml is a listener, which its owner starts with
+//! `tcp_listen`. Like UDP sockets, it's replicated on each VLAN; a connection
+//! can come in on any of them, and once the owner accepts one, the others
+//! stop listening until it's closed. So a socket has at most one accepted
+//! connection, which is all our clients want.
+//!
+//! If the owner restarts, whatever it was doing with the socket is forgotten:
+//! the connection is reset and the socket stops listening, so that the new
+//! owner starts from scratch.
+
+use super::{DeviceExt, GenServerImpl, SocketKind, VLanState};
+use crate::bsp_support;
+use crate::generated;
+
+use idol_runtime::{ClientError, RequestError};
+use smoltcp::socket::tcp;
+use smoltcp::time::Duration;
+use task_net_api::{SocketName, TcpError, TcpMetadata};
+use userlib::{sys_refresh_task_id, Generation, UnwrapLite};
+
+/// How often to check that the other end of an idle connection is still
+/// there.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// How long to wait for the other end of a connection to respond before
+/// giving up on it. Without this, a connection to a peer that's gone away
+/// without closing it would tie up its socket forever.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What we know about a TCP socket beyond what smoltcp does.
+#[derive(Copy, Clone, Debug, Default)]
+pub(super) struct TcpState {
+    /// Generation of the owner that started the socket listening, if it has.
+    owner_gen: Option<Generation>,
+
+    /// Index of the VLAN whose copy of the socket has the connection that
+    /// the owner accepted, if any.
+    conn: Option<usize>,
+}
+
+impl<E: DeviceExt> VLanState<E> {
+    /// Gets the socket `index`. If `index` is out of range, or isn't a TCP
+    /// socket, returns `None`.
+    fn get_tcp_socket_mut(
+        &mut self,
+        index: usize,
+    ) -> Option<&mut tcp::Socket<'static>> {
+        if *generated::SOCKET_KINDS.get(index)? != SocketKind::Tcp {
+            return None;
+        }
+        Some(
+            self.socket_set
+                .get_mut::<tcp::Socket<'_>>(self.get_handle(index)?),
+        )
+    }
+}
+
+impl<B, E, const N: usize> GenServerImpl<'_, B, E, N>
+where
+    B: bsp_support::Bsp,
+    E: DeviceExt,
+{
+    /// Checks that `socket` is a TCP socket belonging to the sender of `msg`,
+    /// and returns its index.
+    ///
+    /// If the socket was being used by an earlier incarnation of the sender,
+    /// this resets it first, in case we haven't noticed the restart yet.
+    fn tcp_socket_index(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<usize, RequestError<TcpError>> {
+        let socket_index = socket as usize;
+        if generated::SOCKET_KINDS[socket_index] != SocketKind::Tcp {
+            return Err(RequestError::Fail(ClientError::BadMessageContents));
+        }
+        if generated::SOCKET_OWNERS[socket_index].0.index()
+            != msg.sender.index()
+        {
+            return Err(TcpError::NotYours.into());
+        }
+        if let Some(gen) = self.tcp_state[socket_index].owner_gen {
+            if gen != msg.sender.generation() {
+                self.tcp_reset(socket_index);
+            }
+        }
+        Ok(socket_index)
+    }
+
+    /// Gets the copy of TCP socket `socket_index` with the accepted
+    /// connection.
+    fn tcp_connection(
+        &mut self,
+        socket_index: usize,
+    ) -> Result<&mut tcp::Socket<'static>, RequestError<TcpError>> {
+        let state = self.tcp_state[socket_index];
+        if state.owner_gen.is_none() {
+            return Err(TcpError::NotListening.into());
+        }
+        let vlan = state.conn.ok_or(TcpError::NotConnected)?;
+        Ok(self.vlan_state[vlan]
+            .get_tcp_socket_mut(socket_index)
+            .unwrap_lite())
+    }
+
+    /// Resets every copy of TCP socket `socket_index`, dropping any
+    /// connections, and stops it listening.
+    fn tcp_reset(&mut self, socket_index: usize) {
+        for vlan in &mut self.vlan_state {
+            vlan.get_tcp_socket_mut(socket_index).unwrap_lite().abort();
+        }
+        self.tcp_state[socket_index] = TcpState::default();
+        self.client_waiting_to_send[socket_index] = false;
+    }
+
+    /// Starts listening on any copies of TCP socket `socket_index` that
+    /// aren't doing anything else. Returns `true` if there were any.
+    fn tcp_relisten(&mut self, socket_index: usize) -> bool {
+        let port = generated::SOCKET_PORTS[socket_index];
+        let mut changed = false;
+        for vlan in &mut self.vlan_state {
+            let socket = vlan.get_tcp_socket_mut(socket_index).unwrap_lite();
+            if !socket.is_open() {
+                socket.listen(port).unwrap_lite();
+                socket.set_keep_alive(Some(KEEP_ALIVE));
+                socket.set_timeout(Some(TIMEOUT));
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Cleans up after the owners of TCP sockets: forgets about sockets whose
+    /// owners have restarted, and starts listening again once a closed
+    /// connection has finished closing. Returns `true` if anything changed.
+    pub(super) fn tcp_housekeeping(&mut self) -> bool {
+        let mut changed = false;
+        for socket_index in 0..generated::SOCKET_COUNT {
+            if let Some(gen) = self.tcp_state[socket_index].owner_gen {
+                let (task_id, _) = generated::SOCKET_OWNERS[socket_index];
+                if sys_refresh_task_id(task_id).generation() != gen {
+                    self.tcp_reset(socket_index);
+                    changed = true;
+                } else if self.tcp_state[socket_index].conn.is_none() {
+                    changed |= self.tcp_relisten(socket_index);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Checks whether the owner of TCP socket `socket_index` can do work:
+    /// accept a connection, read from its connection (or find out that it's
+    /// closed), or write to it, if it was waiting to.
+    pub(super) fn tcp_wants_wake(&mut self, socket_index: usize) -> bool {
+        let state = self.tcp_state[socket_index];
+        if state.owner_gen.is_none() {
+            return false;
+        }
+        match state.conn {
+            None => self.vlan_state.iter_mut().any(|v| {
+                v.get_tcp_socket_mut(socket_index).unwrap_lite().may_send()
+            }),
+            Some(vlan) => {
+                let socket = self.vlan_state[vlan]
+                    .get_tcp_socket_mut(socket_index)
+                    .unwrap_lite();
+                socket.can_recv()
+                    || !socket.may_recv()
+                    || (self.client_waiting_to_send[socket_index]
+                        && socket.can_send())
+            }
+        }
+    }
+
+    pub(super) fn net_tcp_listen(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        let socket_index = self.tcp_socket_index(msg, socket)?;
+        if self.tcp_state[socket_index].owner_gen.is_none() {
+            self.tcp_state[socket_index].owner_gen =
+                Some(msg.sender.generation());
+            self.tcp_relisten(socket_index);
+        }
+        Ok(())
+    }
+
+    pub(super) fn net_tcp_accept(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<TcpMetadata, RequestError<TcpError>> {
+        let socket_index = self.tcp_socket_index(msg, socket)?;
+        let state = self.tcp_state[socket_index];
+        if state.owner_gen.is_none() {
+            return Err(TcpError::NotListening.into());
+        }
+        if state.conn.is_some() {
+            return Err(TcpError::AlreadyConnected.into());
+        }
+
+        // A copy of the socket has a connection to accept once it's
+        // established; the other end may already have closed its half.
+        let vlan_index = self
+            .vlan_state
+            .iter_mut()
+            .position(|v| {
+                v.get_tcp_socket_mut(socket_index).unwrap_lite().may_send()
+            })
+            .ok_or(TcpError::WouldBlock)?;
+
+        // Only one connection at a time, so stop listening on the others.
+        for vlan in &mut self.vlan_state {
+            let socket = vlan.get_tcp_socket_mut(socket_index).unwrap_lite();
+            if socket.is_listening() {
+                socket.close();
+            }
+        }
+        self.tcp_state[socket_index].conn = Some(vlan_index);
+
+        let vlan = &mut self.vlan_state[vlan_index];
+        let endp = vlan
+            .get_tcp_socket_mut(socket_index)
+            .unwrap_lite()
+            .remote_endpoint()
+            .unwrap_lite();
+        Ok(vlan.device.make_tcp_meta(
+            endp.port,
+            endp.addr.try_into().map_err(|_| ()).unwrap(),
+        ))
+    }
+
+    /// Copies as much data as is waiting on the accepted connection of
+    /// `socket` into loaned memory at `data`, returning how much that was.
+    pub(super) fn net_tcp_read(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        data: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let socket_index = self.tcp_socket_index(msg, socket)?;
+        let socket = self.tcp_connection(socket_index)?;
+
+        // The rx buffer is a ring, so what's waiting may be in two pieces.
+        let mut n = 0;
+        while n < data.len() {
+            let r = socket.recv(|buf| {
+                let len = buf.len().min(data.len() - n);
+                match data.write_range(n..n + len, &buf[..len]) {
+                    Ok(()) => (len, Ok(len)),
+                    Err(()) => (0, Err(())),
+                }
+            });
+            match r {
+                Ok(Ok(0)) => break,
+                Ok(Ok(len)) => n += len,
+                Ok(Err(())) => return Err(RequestError::went_away()),
+                // The other end closed the connection (or reset it), and
+                // we've read everything it sent.
+                Err(_) if n == 0 => return Err(TcpError::Closed.into()),
+                Err(_) => break,
+            }
+        }
+
+        let stats = &mut self.socket_stats[socket_index];
+        stats.rx_bytes = stats.rx_bytes.wrapping_add(n as u64);
+        if n == 0 {
+            Err(TcpError::WouldBlock.into())
+        } else {
+            Ok(n as u32)
+        }
+    }
+
+    /// Copies as much of the loaned memory at `data` as will fit into the tx
+    /// buffer of the accepted connection of `socket`, returning how much that
+    /// was.
+    pub(super) fn net_tcp_write(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        data: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let socket_index = self.tcp_socket_index(msg, socket)?;
+        let socket = self.tcp_connection(socket_index)?;
+
+        // Like the rx buffer, the free space in the tx buffer may be in two
+        // pieces.
+        let mut n = 0;
+        while n < data.len() {
+            let r = socket.send(|buf| {
+                let len = buf.len().min(data.len() - n);
+                match data.read_range(n..n + len, &mut buf[..len]) {
+                    Ok(()) => (len, Ok(len)),
+                    Err(()) => (0, Err(())),
+                }
+            });
+            match r {
+                Ok(Ok(0)) => break,
+                Ok(Ok(len)) => n += len,
+                Ok(Err(())) => return Err(RequestError::went_away()),
+                // The connection was reset.
+                Err(_) => return Err(TcpError::Closed.into()),
+            }
+        }
+
+        let stats = &mut self.socket_stats[socket_index];
+        stats.tx_bytes = stats.tx_bytes.wrapping_add(n as u64);
+
+        // If it didn't all fit, let the owner know when there's room.
+        let blocked = n < data.len();
+        self.client_waiting_to_send[socket_index] = blocked;
+        if n == 0 && blocked {
+            Err(TcpError::WouldBlock.into())
+        } else {
+            Ok(n as u32)
+        }
+    }
+
+    pub(super) fn net_tcp_close(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        let socket_index = self.tcp_socket_index(msg, socket)?;
+        if let Some(vlan) = self.tcp_state[socket_index].conn.take() {
+            // This sends anything still queued before closing; the socket
+            // starts listening again once that's done, in `tcp_housekeeping`.
+            self.vlan_state[vlan]
+                .get_tcp_socket_mut(socket_index)
+                .unwrap_lite()
+                .close();
+            self.client_waiting_to_send[socket_index] = false;
+        }
+        Ok(())
+    }
+}
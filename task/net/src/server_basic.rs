@@ -16,7 +16,7 @@ use crate::{
 };
 use core::cell::Cell;
 use mutable_statics::mutable_statics;
-use task_net_api::UdpMetadata;
+use task_net_api::{TcpMetadata, UdpMetadata};
 
 /// Grabs references to the server storage arrays.  Can only be called once!
 fn claim_server_storage_statics() -> &'static mut [Storage; 1] {
@@ -150,4 +150,16 @@ impl DeviceExt for Smol<'_> {
             addr,
         }
     }
+
+    fn make_tcp_meta(
+        &self,
+        port: u16,
+        addr: task_net_api::Address,
+    ) -> TcpMetadata {
+        TcpMetadata { port, addr }
+    }
+
+    fn vid(&self) -> Option<u16> {
+        None
+    }
 }
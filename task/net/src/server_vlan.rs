@@ -11,7 +11,7 @@ use drv_stm32h7_eth as eth;
 
 use core::cell::Cell;
 use mutable_statics::mutable_statics;
-use task_net_api::UdpMetadata;
+use task_net_api::{TcpMetadata, UdpMetadata};
 
 use crate::bsp_support;
 use crate::generated::{self, VLAN_COUNT, VLAN_RANGE};
@@ -86,6 +86,22 @@ impl DeviceExt for VLanEthernet<'_> {
             vid: self.vid,
         }
     }
+
+    fn make_tcp_meta(
+        &self,
+        port: u16,
+        addr: task_net_api::Address,
+    ) -> TcpMetadata {
+        TcpMetadata {
+            port,
+            addr,
+            vid: self.vid,
+        }
+    }
+
+    fn vid(&self) -> Option<u16> {
+        Some(self.vid)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -25,6 +25,18 @@ pub struct MacAddressBlock {
     pub stride: u8,
 }
 
+/// A static IPv4 configuration for the management network, for boards whose
+/// VPD has one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromBytes, AsBytes, Default)]
+#[repr(C)]
+pub struct Ipv4Config {
+    pub address: [u8; 4],
+    /// Length of the subnet prefix, in bits
+    pub prefix_len: u8,
+    /// Default gateway, or all zeroes if there isn't one
+    pub gateway: [u8; 4],
+}
+
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 pub enum CacheGetError {
     ValueNotSet = 1,
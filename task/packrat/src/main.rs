@@ -34,8 +34,8 @@ use idol_runtime::{Leased, LenLimit, RequestError};
 use mutable_statics::mutable_statics;
 use ringbuf::{ringbuf, ringbuf_entry};
 use task_packrat_api::{
-    CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
-    VpdIdentity,
+    CacheGetError, CacheSetError, HostStartupOptions, Ipv4Config,
+    MacAddressBlock, VpdIdentity,
 };
 use userlib::RecvMessage;
 
@@ -48,6 +48,7 @@ enum Trace {
     None,
     MacAddressBlockSet(TraceSet<MacAddressBlock>),
     VpdIdentitySet(TraceSet<VpdIdentity>),
+    Ipv4ConfigSet(TraceSet<Ipv4Config>),
     SetNextBootHostStartupOptions(HostStartupOptions),
     SpdDataUpdate {
         index: u8,
@@ -69,6 +70,12 @@ impl From<TraceSet<VpdIdentity>> for Trace {
     }
 }
 
+impl From<TraceSet<Ipv4Config>> for Trace {
+    fn from(value: TraceSet<Ipv4Config>) -> Self {
+        Self::Ipv4ConfigSet(value)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum TraceSet<T> {
     // Initial set (always succeeds)
@@ -84,15 +91,17 @@ ringbuf!(Trace, 16, Trace::None);
 
 #[export_name = "main"]
 fn main() -> ! {
-    let (mac_address_block, identity) = mutable_statics! {
+    let (mac_address_block, identity, ipv4_config) = mutable_statics! {
         static mut MAC_ADDRESS_BLOCK: [Option<MacAddressBlock>; 1]
             = [|| None; _];
         static mut IDENTITY: [Option<VpdIdentity>; 1] = [|| None; _];
+        static mut IPV4_CONFIG: [Option<Ipv4Config>; 1] = [|| None; _];
     };
 
     let mut server = ServerImpl {
         mac_address_block: &mut mac_address_block[0],
         identity: &mut identity[0],
+        ipv4_config: &mut ipv4_config[0],
         #[cfg(feature = "gimlet")]
         gimlet_data: gimlet::GimletData::claim_static_resources(),
     };
@@ -106,6 +115,7 @@ fn main() -> ! {
 struct ServerImpl {
     mac_address_block: &'static mut Option<MacAddressBlock>,
     identity: &'static mut Option<VpdIdentity>,
+    ipv4_config: &'static mut Option<Ipv4Config>,
     #[cfg(feature = "gimlet")]
     gimlet_data: gimlet::GimletData,
 }
@@ -180,6 +190,22 @@ impl idl::InOrderPackratImpl for ServerImpl {
         Self::set_once(&mut self.identity, identity).map_err(Into::into)
     }
 
+    fn get_ipv4_config(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<Ipv4Config, RequestError<CacheGetError>> {
+        let config = self.ipv4_config.ok_or(CacheGetError::ValueNotSet)?;
+        Ok(config)
+    }
+
+    fn set_ipv4_config(
+        &mut self,
+        _: &RecvMessage,
+        config: Ipv4Config,
+    ) -> Result<(), RequestError<CacheSetError>> {
+        Self::set_once(&mut self.ipv4_config, config).map_err(Into::into)
+    }
+
     #[cfg(feature = "gimlet")]
     fn get_next_boot_host_startup_options(
         &mut self,
@@ -292,8 +318,8 @@ impl idl::InOrderPackratImpl for ServerImpl {
 
 mod idl {
     use super::{
-        CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
-        VpdIdentity,
+        CacheGetError, CacheSetError, HostStartupOptions, Ipv4Config,
+        MacAddressBlock, VpdIdentity,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
@@ -2,9 +2,38 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+//! Measures the SP's flash over SWD and checks it against a policy.
+//!
+//! The measurement always goes in the attestation log. If it isn't one of
+//! the allowed measurements, the task takes the action configured with
+//! `on_mismatch` (see `SpMismatchAction`). Either way, it reports the result
+//! to the `attest` task, where the SP can query it over sprot.
+//!
+//! An SP that's halted for a mismatch stays halted through resets, but not
+//! through a power-on reset, or a restore from its staged image (which
+//! resets it into the restored image). So the task keeps watching it, and
+//! measures it again if it's running.
+//!
+//! The policy is fixed at build time, in the task's config:
+//!
+//! ```toml
+//! [tasks.sp_measure.config]
+//! # The image we expect the SP to run
+//! binary_path = "../../target/gimlet-c/dist/default/final.bin"
+//! # Other images it may run, e.g. the previous release
+//! allowed_binary_paths = ["../../gimlet-c-v1.0.0.bin"]
+//! # Measurements of images that aren't available at build time
+//! allowed_measurements = ["5e1f...", ...]
+//! # What to do on a mismatch: "halt", "flag", or "alert" (the default)
+//! on_mismatch = "halt"
+//! ```
+
 #![no_std]
 #![no_main]
 
+use attest_api::{
+    Attest, AttestError, MeasurementKind, SpMeasureStatus, SpMismatchAction,
+};
 use drv_sp_ctrl_api::*;
 use ringbuf::*;
 use sha3::{Digest, Sha3_256};
@@ -14,7 +43,11 @@ const READ_SIZE: usize = 256;
 
 const TRANSACTION_SIZE: u32 = 1024;
 
+/// How often to check that an SP we've halted is still halted, in ms.
+const HOLD_POLL_INTERVAL: u64 = 100;
+
 task_slot!(SP_CTRL, swd);
+task_slot!(ATTEST, attest);
 
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
@@ -22,6 +55,9 @@ enum Trace {
     End(u64),
     ShaGood,
     ShaBad,
+    RecordFailed(AttestError),
+    Mismatch(SpMismatchAction),
+    Released,
     None,
 }
 
@@ -29,9 +65,11 @@ ringbuf!(Trace, 16, Trace::None);
 
 #[export_name = "main"]
 fn main() -> ! {
+    let sp_ctrl = SpCtrl::from(SP_CTRL.get_task_id());
+    let attest = Attest::from(ATTEST.get_task_id());
+
     loop {
         let mut sha = Sha3_256::new();
-        let sp_ctrl = SpCtrl::from(SP_CTRL.get_task_id());
 
         if sp_ctrl.setup().is_err() {
             panic!();
@@ -62,10 +100,44 @@ fn main() -> ! {
 
         let end = sys_get_timer().now;
         ringbuf_entry!(Trace::End(end));
-        if sha_out.as_slice() == EXPECTED.as_slice() {
+
+        // Whether or not it's allowed, the measurement goes in the
+        // attestation log, for whoever asks for a quote to judge.
+        if let Err(e) = attest.record(MeasurementKind::Sp, sha_out.as_slice()) {
+            ringbuf_entry!(Trace::RecordFailed(e));
+        }
+
+        let status = if ALLOWED.iter().any(|m| m == sha_out.as_slice()) {
             ringbuf_entry!(Trace::ShaGood);
+            SpMeasureStatus::Allowed
         } else {
             ringbuf_entry!(Trace::ShaBad);
+            ringbuf_entry!(Trace::Mismatch(ON_MISMATCH));
+            match ON_MISMATCH {
+                SpMismatchAction::Halt => {
+                    if sp_ctrl.halt().is_err() {
+                        panic!();
+                    }
+                }
+                SpMismatchAction::Flag => {
+                    if let Err(e) = attest.record(
+                        MeasurementKind::SpPolicyMismatch,
+                        sha_out.as_slice(),
+                    ) {
+                        ringbuf_entry!(Trace::RecordFailed(e));
+                    }
+                }
+                SpMismatchAction::Alert => (),
+            }
+            SpMeasureStatus::Mismatch(ON_MISMATCH)
+        };
+        attest.set_sp_measure_status(status);
+
+        if status == SpMeasureStatus::Mismatch(SpMismatchAction::Halt) {
+            hold(&sp_ctrl);
+            ringbuf_entry!(Trace::Released);
+            attest.set_sp_measure_status(SpMeasureStatus::NotMeasured);
+            continue;
         }
 
         // Wait for a notification that will never come, politer than
@@ -76,4 +148,18 @@ fn main() -> ! {
     }
 }
 
+/// Returns once the SP we've halted is running again, or may be: if it's had
+/// a power-on reset, we have to set up its debug port again before we can
+/// tell.
+fn hold(sp_ctrl: &SpCtrl) {
+    loop {
+        hl::sleep_for(HOLD_POLL_INTERVAL);
+        match sp_ctrl.is_halted() {
+            // A restore resets the SP when it's done, so wait for that.
+            Ok(true) | Err(SpCtrlError::RecoveryInProgress) => (),
+            Ok(false) | Err(_) => return,
+        }
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/expected.rs"));
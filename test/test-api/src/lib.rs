@@ -36,6 +36,26 @@ pub enum AssistOp {
     RefreshTaskIdOffByOne = 21,
     RefreshTaskIdOffByMany = 22,
     ReadNotifications = 23,
+    NoReply = 24,
+    /// Replies, sleeps for a tick, then spins for `msg` ticks without
+    /// blocking.
+    WakeAndSpin = 25,
+}
+
+/// Spins, without blocking, until `ticks` ticks have passed. Returns the
+/// longest stretch, in ticks, for which we were preempted along the way.
+pub fn spin(ticks: u64) -> u64 {
+    let start = userlib::sys_get_timer().now;
+    let mut last = start;
+    let mut longest_gap = 0;
+    while last < start + ticks {
+        let now = userlib::sys_get_timer().now;
+        // Seeing the clock move by one tick is just the clock moving; more
+        // than that means someone else ran in between.
+        longest_gap = longest_gap.max((now - last).saturating_sub(1));
+        last = now;
+    }
+    longest_gap
 }
 
 /// Operations that are performed by the test-suite
@@ -278,6 +278,17 @@ fn main() -> ! {
                     AssistOp::ReadNotifications => {
                         caller.reply(core::mem::replace(posted_bits, 0));
                     }
+                    AssistOp::NoReply => {
+                        // Leave the caller hanging; it's expected to give up
+                        // on its own via a send timeout.
+                        drop(caller);
+                    }
+                    AssistOp::WakeAndSpin => {
+                        let ticks = *msg;
+                        caller.reply(0);
+                        hl::sleep_for(1);
+                        spin(u64::from(ticks));
+                    }
                     _ => {
                         // Anything else should be fatal
                         for (which, func) in &fatalops {
@@ -79,6 +79,14 @@ impl idl::InOrderIdolTestImpl for ServerImpl {
     ) -> Result<u16, RequestError<IdolTestError>> {
         Ok(b.vid)
     }
+    fn spin(
+        &mut self,
+        _: &RecvMessage,
+        ticks: u32,
+    ) -> Result<u32, RequestError<IdolTestError>> {
+        let gap = test_api::spin(u64::from(ticks));
+        Ok(u32::try_from(gap).unwrap_or(u32::MAX))
+    }
 }
 
 #[export_name = "main"]
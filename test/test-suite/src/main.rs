@@ -94,6 +94,11 @@ test_cases! {
     test_timer_advance,
     test_timer_notify,
     test_timer_notify_past,
+    test_send_timeout,
+    test_send_timeout_expired,
+    test_send_timeout_reply,
+    test_sched_preempt,
+    test_sched_time_slice,
     test_task_config,
     test_task_status,
     test_task_fault_injection,
@@ -1187,6 +1192,134 @@ fn test_timer_notify_past() {
     assert_eq!(rm.lease_count, 0);
 }
 
+/// Tests that a send with a timeout gives up if the peer never replies.
+fn test_send_timeout() {
+    let assist = assist_task_id();
+    let mut response = 0_u32;
+
+    let deadline = sys_get_timer().now + 2;
+    let (rc, len) = sys_send_with_timeout(
+        assist,
+        AssistOp::NoReply as u16,
+        &0_u32.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+        deadline,
+    );
+    assert_eq!(rc, TIMED_OUT);
+    assert_eq!(len, 0);
+    assert!(sys_get_timer().now >= deadline);
+
+    // The assistant should still be up and willing to answer us.
+    let (rc, _) = sys_send(
+        assist,
+        AssistOp::JustReply as u16,
+        &0_u32.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+/// Tests that a send with a deadline in the past fails without being
+/// delivered.
+fn test_send_timeout_expired() {
+    let assist = assist_task_id();
+    let mut response = 0_u32;
+
+    let deadline = sys_get_timer().now;
+    let (rc, len) = sys_send_with_timeout(
+        assist,
+        AssistOp::Store as u16,
+        &0xDEAD_u32.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+        deadline,
+    );
+    assert_eq!(rc, TIMED_OUT);
+    assert_eq!(len, 0);
+
+    // Had the message been delivered, the assistant would have stored the
+    // value above; instead it should still hold the value from below.
+    let (rc, _) = sys_send(
+        assist,
+        AssistOp::Store as u16,
+        &0_u32.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+    assert_ne!(response, 0xDEAD);
+}
+
+/// Tests that a send with a timeout completes normally if the peer replies in
+/// time, and that our own timer setting survives it.
+fn test_send_timeout_reply() {
+    const ARBITRARY_NOTIFICATION: u32 = 1 << 16;
+
+    let assist = assist_task_id();
+    let challenge = 0xDEADBEEF_u32;
+    let mut response = 0_u32;
+
+    let start_time = sys_get_timer().now;
+    let timer = start_time + 1_000_000;
+    sys_set_timer(Some(timer), ARBITRARY_NOTIFICATION);
+
+    let (rc, len) = sys_send_with_timeout(
+        assist,
+        AssistOp::JustReply as u16,
+        &challenge.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+        start_time + 1000,
+    );
+    assert_eq!(rc, 0);
+    assert_eq!(len, 4);
+    assert_eq!(response, !challenge);
+
+    let state = sys_get_timer();
+    assert_eq!(state.deadline, Some(timer));
+    assert_eq!(state.on_dl, ARBITRARY_NOTIFICATION);
+    sys_set_timer(None, 0);
+}
+
+/// How long the assistant spins for in the scheduling tests, in ticks.
+const ASSIST_SPIN_TICKS: u32 = 10;
+
+/// Asks the assistant to wake up shortly and hog the CPU for a while.
+fn assist_wake_and_spin() {
+    let assist = assist_task_id();
+    let mut response = 0_u32;
+    let (rc, _) = sys_send(
+        assist,
+        AssistOp::WakeAndSpin as u16,
+        &ASSIST_SPIN_TICKS.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+/// Tests that a more important task preempts us as soon as its timer fires,
+/// even though we never block.
+fn test_sched_preempt() {
+    assist_wake_and_spin();
+    let gap = spin(u64::from(ASSIST_SPIN_TICKS) * 2);
+    assert!(gap >= u64::from(ASSIST_SPIN_TICKS) - 1, "gap was {gap}");
+}
+
+/// Tests that tasks at a time sliced priority take turns. The assistant and
+/// the Idol server share a priority, which the test images time slice at two
+/// ticks.
+fn test_sched_time_slice() {
+    assist_wake_and_spin();
+    let gap = idol_handle().spin(ASSIST_SPIN_TICKS * 2).unwrap();
+    // Without time slicing, the server would be stuck for the assistant's
+    // entire spin.
+    assert!(gap > 0, "server was never preempted");
+    assert!(gap < ASSIST_SPIN_TICKS / 2, "gap was {gap}");
+}
+
 /// Tests that floating point registers are properly saved and restored
 #[cfg(any(armv7m, armv8m))]
 fn test_floating_point(highregs: bool) {